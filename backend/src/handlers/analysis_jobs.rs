@@ -0,0 +1,353 @@
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+    Extension,
+};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use crate::handlers::auth::{AppState, ApiResponse};
+use crate::handlers::errors::{bad_request, database_error, ApiError};
+use crate::handlers::streaming::get_authenticated_streaming_service;
+use crate::models::{
+    AnalysisJobResponseDto, AnalysisTrackDto, EnqueueAnalysisDto, EnqueueCollectionDto, PlaylistEntity,
+    PlaylistItemEntity, SavedAlbumEntity, UserResponseDto,
+};
+use crate::services::analysis_jobs::{BatchProgress, EnqueueOutcome, JobError, StreamUrlResolver, ALL_JOB_KINDS};
+use crate::services::fingerprint::{find_duplicates, DuplicateGroup, Fingerprint, FingerprintedTrack};
+use crate::services::library_ingest::{RootIngestStatus, ScanTrigger};
+use crate::services::track_analysis_store::TrackAnalysisStore;
+use crate::services::streaming::{confine_to_music_dir, default_music_dir, StreamingService};
+
+/// Resolves stream URLs of queued tracks with the queueing user's streaming accounts
+pub struct StreamingUrlResolver {
+    db: DatabaseConnection,
+}
+
+impl StreamingUrlResolver {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl StreamUrlResolver for StreamingUrlResolver {
+    async fn resolve(&self, user_id: Option<Uuid>, source: &str, track_id: &str) -> Result<String, JobError> {
+        // Local library tracks carry their file path in the id, which must stay inside the library
+        if let Some(path) = track_id.strip_prefix("server_") {
+            return confine_to_music_dir(&default_music_dir(), std::path::Path::new(path))
+                .map(|path| path.to_string_lossy().to_string())
+                .ok_or_else(|| JobError::Fatal(format!("Track {} is not in the music library", track_id)));
+        }
+        if source == "spotify" {
+            return Err(JobError::Fatal("Spotify does not provide full-length audio for analysis".to_string()));
+        }
+        let user_id = user_id.ok_or_else(|| JobError::Fatal(format!("No account to stream {} tracks with", source)))?;
+
+        let service = get_authenticated_streaming_service(source, user_id, &self.db)
+            .await
+            .map_err(JobError::Fatal)?;
+        service
+            .get_stream_url(track_id, None)
+            .await
+            .map_err(|e| JobError::Retryable(format!("Could not get stream URL: {}", e)))
+    }
+}
+
+#[derive(Serialize)]
+pub struct EnqueueAnalysisResponse {
+    pub batch_id: Uuid,
+    pub jobs: Vec<AnalysisJobResponseDto>,
+    pub already_analyzed: usize,
+    pub errors: Vec<String>, // Parts of a collection that could not be listed
+}
+
+#[derive(Deserialize)]
+pub struct ListAnalysisJobsQuery {
+    pub status: Option<String>,
+    pub batch_id: Option<Uuid>,
+}
+
+// Requested analysis kinds, all of them by default
+fn job_kinds(kinds: Option<Vec<String>>) -> Result<Vec<String>, ApiError> {
+    let kinds = kinds
+        .filter(|kinds| !kinds.is_empty())
+        .unwrap_or_else(|| ALL_JOB_KINDS.iter().map(|kind| kind.to_string()).collect());
+
+    if let Some(unknown) = kinds.iter().find(|kind| !ALL_JOB_KINDS.contains(&kind.as_str())) {
+        return Err(bad_request(&format!(
+            "Unknown analysis kind '{}', expected one of {}",
+            unknown,
+            ALL_JOB_KINDS.join(", ")
+        )));
+    }
+    Ok(kinds)
+}
+
+fn enqueue_response(outcome: EnqueueOutcome, errors: Vec<String>) -> Json<ApiResponse<EnqueueAnalysisResponse>> {
+    Json(ApiResponse::success(EnqueueAnalysisResponse {
+        batch_id: outcome.batch_id,
+        jobs: outcome.jobs.into_iter().map(AnalysisJobResponseDto::from).collect(),
+        already_analyzed: outcome.already_analyzed,
+        errors,
+    }))
+}
+
+/// Queue analyses of individual tracks
+pub async fn enqueue_analysis_jobs(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Json(request): Json<EnqueueAnalysisDto>,
+) -> Result<Json<ApiResponse<EnqueueAnalysisResponse>>, ApiError> {
+    let kinds = job_kinds(request.kinds)?;
+    if request.tracks.is_empty() {
+        return Err(bad_request("No tracks given"));
+    }
+
+    let outcome = state
+        .analysis_jobs
+        .enqueue(Some(user.id), request.tracks, &kinds, request.force.unwrap_or(false))
+        .await
+        .map_err(database_error)?;
+    Ok(enqueue_response(outcome, Vec::new()))
+}
+
+/// Queue analyses of all tracks of one of the user's playlists
+pub async fn enqueue_playlist_analysis(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(playlist_id): Path<Uuid>,
+    Json(request): Json<EnqueueCollectionDto>,
+) -> Result<Json<ApiResponse<EnqueueAnalysisResponse>>, ApiError> {
+    let kinds = job_kinds(request.kinds)?;
+
+    PlaylistEntity::find_by_id(playlist_id)
+        .filter(crate::models::playlist::Column::UserId.eq(user.id))
+        .one(state.db())
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error("Playlist not found".to_string())),
+            )
+        })?;
+
+    let items = PlaylistItemEntity::find()
+        .filter(crate::models::playlist_item::Column::PlaylistId.eq(playlist_id))
+        .filter(crate::models::playlist_item::Column::ItemType.eq("track"))
+        .order_by_asc(crate::models::playlist_item::Column::Position)
+        .all(state.db())
+        .await
+        .map_err(database_error)?;
+
+    let tracks: Vec<AnalysisTrackDto> = items
+        .into_iter()
+        .filter_map(|item| {
+            item.source.map(|source| AnalysisTrackDto {
+                track_id: item.item_id,
+                source,
+                title: item.title,
+                stream_url: None,
+                content_hash: None,
+            })
+        })
+        .collect();
+    debug!("Queueing analysis of {} tracks of playlist {}", tracks.len(), playlist_id);
+
+    let outcome = state
+        .analysis_jobs
+        .enqueue(Some(user.id), tracks, &kinds, request.force.unwrap_or(false))
+        .await
+        .map_err(database_error)?;
+    Ok(enqueue_response(outcome, Vec::new()))
+}
+
+/// Queue analyses of all tracks of the user's saved albums
+pub async fn enqueue_saved_albums_analysis(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Json(request): Json<EnqueueCollectionDto>,
+) -> Result<Json<ApiResponse<EnqueueAnalysisResponse>>, ApiError> {
+    let kinds = job_kinds(request.kinds)?;
+
+    let albums = SavedAlbumEntity::find()
+        .filter(crate::models::SavedAlbumColumn::UserId.eq(user.id))
+        .all(state.db())
+        .await
+        .map_err(database_error)?;
+
+    // Album track lists come from the streaming services, one service instance per source
+    let mut services: HashMap<String, Result<Box<dyn StreamingService>, String>> = HashMap::new();
+    let mut tracks = Vec::new();
+    let mut errors = Vec::new();
+    for album in albums {
+        if !services.contains_key(&album.source) {
+            let service = get_authenticated_streaming_service(&album.source, user.id, state.db()).await;
+            services.insert(album.source.clone(), service);
+        }
+        let service = match &services[&album.source] {
+            Ok(service) => service,
+            Err(e) => {
+                errors.push(format!("{} ({}): {}", album.title, album.source, e));
+                continue;
+            }
+        };
+
+        match service.get_album_tracks(&album.album_id).await {
+            Ok(album_tracks) => tracks.extend(album_tracks.into_iter().map(|track| AnalysisTrackDto {
+                track_id: track.id,
+                source: album.source.clone(),
+                title: Some(track.title),
+                stream_url: None,
+                content_hash: None,
+            })),
+            Err(e) => errors.push(format!("{} ({}): {}", album.title, album.source, e)),
+        }
+    }
+    debug!("Queueing analysis of {} tracks from saved albums ({} albums failed)", tracks.len(), errors.len());
+
+    let outcome = state
+        .analysis_jobs
+        .enqueue(Some(user.id), tracks, &kinds, request.force.unwrap_or(false))
+        .await
+        .map_err(database_error)?;
+    Ok(enqueue_response(outcome, errors))
+}
+
+pub async fn list_analysis_jobs(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Query(query): Query<ListAnalysisJobsQuery>,
+) -> Result<Json<ApiResponse<Vec<AnalysisJobResponseDto>>>, ApiError> {
+    let jobs = state
+        .analysis_jobs
+        .list(Some(user.id), query.status.as_deref(), query.batch_id)
+        .await
+        .map_err(database_error)?;
+    Ok(Json(ApiResponse::success(jobs.into_iter().map(AnalysisJobResponseDto::from).collect())))
+}
+
+/// Status and progress of one job
+pub async fn get_analysis_job(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(job_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<AnalysisJobResponseDto>>, ApiError> {
+    let job = state
+        .analysis_jobs
+        .get(user.id, job_id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error("Analysis job not found".to_string())),
+            )
+        })?;
+    Ok(Json(ApiResponse::success(job.into())))
+}
+
+/// Progress of a batch of jobs queued together
+pub async fn get_analysis_batch(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(batch_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<BatchProgress>>, ApiError> {
+    let progress = state
+        .analysis_jobs
+        .batch_progress(Some(user.id), batch_id)
+        .await
+        .map_err(database_error)?;
+    if progress.total == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("Analysis batch not found".to_string())),
+        ));
+    }
+    Ok(Json(ApiResponse::success(progress)))
+}
+
+pub async fn cancel_analysis_job(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(job_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<u64>>, ApiError> {
+    let cancelled = state
+        .analysis_jobs
+        .cancel(user.id, Some(job_id), None)
+        .await
+        .map_err(database_error)?;
+    Ok(Json(ApiResponse::success(cancelled)))
+}
+
+pub async fn cancel_analysis_batch(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(batch_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<u64>>, ApiError> {
+    let cancelled = state
+        .analysis_jobs
+        .cancel(user.id, None, Some(batch_id))
+        .await
+        .map_err(database_error)?;
+    Ok(Json(ApiResponse::success(cancelled)))
+}
+
+/// Scan and analysis progress of each local library root
+pub async fn get_library_ingest_status(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<RootIngestStatus>>>, ApiError> {
+    let status = state.library_ingest.status().await.map_err(database_error)?;
+    Ok(Json(ApiResponse::success(status)))
+}
+
+/// Scan the local library for new and changed files now; returns false when a scan is already running.
+/// Scans may be requested at most once a minute.
+pub async fn scan_library(State(state): State<AppState>) -> Result<Json<ApiResponse<bool>>, ApiError> {
+    match state.library_ingest.trigger_scan() {
+        ScanTrigger::Started => Ok(Json(ApiResponse::success(true))),
+        ScanTrigger::AlreadyRunning => Ok(Json(ApiResponse::success(false))),
+        ScanTrigger::TooSoon(wait) => Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ApiResponse::<()>::error(format!(
+                "The library was scanned moments ago, try again in {} seconds",
+                wait.as_secs() + 1
+            ))),
+        )),
+    }
+}
+
+/// Groups of local library files holding the same recording, with the best copy of each marked
+pub async fn get_library_duplicates(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<DuplicateGroup>>>, ApiError> {
+    let analyses = TrackAnalysisStore::find_by_source(state.db(), "server").await.map_err(database_error)?;
+    let tracks: Vec<FingerprintedTrack> = analyses
+        .iter()
+        .filter_map(|analysis| {
+            let stored = analysis.track_fingerprint()?;
+            let fingerprint = Fingerprint::decode(&stored.fingerprint)
+                .inspect_err(|e| debug!("Skipping unreadable fingerprint of {}: {}", analysis.track_id, e))
+                .ok()?;
+            Some(FingerprintedTrack {
+                source: analysis.source.clone(),
+                track_id: analysis.track_id.clone(),
+                fingerprint,
+                duration: stored.duration,
+                quality: stored.quality,
+            })
+        })
+        .collect();
+
+    let groups = tokio::task::spawn_blocking(move || find_duplicates(&tracks)).await.map_err(|e| {
+        error!("Duplicate search failed: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error("Duplicate search failed".to_string())))
+    })?;
+    Ok(Json(ApiResponse::success(groups)))
+}
@@ -1,27 +1,39 @@
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    response::Json,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+    Extension,
 };
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set};
 use serde::{Deserialize, Serialize};
-use sea_orm::{EntityTrait, ColumnTrait, QueryFilter, Set, ActiveModelTrait};
 
 use crate::handlers::auth::{AppState, ApiResponse};
-use crate::services::{SpectrogramBpmAnalysisService, KeyAnalysisService};
-use crate::models::saved_track::{Entity as SavedTrack, ActiveModel as SavedTrackActiveModel};
+use crate::handlers::streaming::get_authenticated_streaming_service;
+use crate::services::{AnalysisArtifacts, ArtifactKind, KeyCandidate, KeyProfile, KeySegment, ChordSegment, ChordSummary, TrackAnalysisStore, TrackStructure, BeatGrid, BpmCandidate, BpmRange, resolve_octave};
+use crate::services::{LoudnessHistogram, TrackKey, replaygain_gain};
+use crate::services::{WaveformBand, is_remote};
+use crate::services::streaming::{confine_to_music_dir, default_music_dir};
+use crate::models::{TrackAnalysisModel, UserResponseDto, BpmRangeEntity, BpmRangeActiveModel, BpmRangeColumn, BpmRangeDto, AnalysisJobResponseDto, AnalysisTrackDto, LoudnessDto};
+use crate::models::analysis_job::{JOB_KIND_BPM, JOB_KIND_KEY, JOB_KIND_LOUDNESS, JOB_KIND_SPECTROGRAM};
+use crate::models::bpm_range::DEFAULT_GENRE;
 
 #[derive(Deserialize)]
 pub struct AnalyzeBpmQuery {
     pub track_id: String,
     pub source: String,
-    pub stream_url: Option<String>,
+    pub force: Option<bool>, // Re-analyze even if a current result is stored
+    pub genre: Option<String>, // Picks the user's BPM range for this genre to resolve half/double tempo
+    pub images: Option<bool>, // Spectrogram endpoint only: render the spectrogram and visualization images
 }
 
 #[derive(Serialize)]
 pub struct BpmAnalysisResponse {
     pub track_id: String,
     pub source: String,
-    pub bpm: f32,
+    pub bpm: Option<f32>, // None when the tempo could not be determined
+    pub confidence: f32,
+    pub candidates: Vec<BpmCandidate>,
+    pub undetermined: bool,
     pub analysis_time_ms: u64,
 }
 
@@ -29,17 +41,21 @@ pub struct BpmAnalysisResponse {
 pub struct SpectrogramBpmAnalysisResponse {
     pub track_id: String,
     pub source: String,
-    pub bpm: f32,
+    pub bpm: Option<f32>,
+    pub confidence: f32,
+    pub candidates: Vec<BpmCandidate>,
+    pub undetermined: bool,
     pub analysis_time_ms: u64,
-    pub spectrogram_path: String,
-    pub analysis_visualization_path: String,
+    pub spectrogram_url: Option<String>, // Set when images were rendered, served by /api/audio/artifacts
+    pub analysis_visualization_url: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct AnalyzeKeyQuery {
     pub track_id: String,
     pub source: String,
-    pub stream_url: Option<String>,
+    pub force: Option<bool>, // Re-analyze even if a current result is stored
+    pub profile: Option<String>, // "temperley" (default), "krumhansl" or "shaath"/"edm"
 }
 
 #[derive(Serialize)]
@@ -50,146 +66,115 @@ pub struct KeyAnalysisResponse {
     pub camelot: String,
     pub confidence: f32,
     pub is_major: bool,
+    pub profile: String,
+    pub runner_up: Option<KeyCandidate>,
+    pub timeline: Vec<KeySegment>,
     pub analysis_time_ms: u64,
 }
 
-/// Analyze BPM of a track and save it to the database
+impl KeyAnalysisResponse {
+    fn from_stored(track_id: String, source: String, analysis: &TrackAnalysisModel, profile: KeyProfile, analysis_time_ms: u64) -> Self {
+        let camelot = analysis.camelot.clone().unwrap_or_default();
+        Self {
+            track_id,
+            source,
+            key_name: analysis.key_name.clone().unwrap_or_default(),
+            is_major: camelot.ends_with('B'),
+            camelot,
+            confidence: analysis.key_confidence.unwrap_or(0.0),
+            profile: profile.name().to_string(),
+            runner_up: analysis.key_runner_up(),
+            timeline: analysis.key_timeline(),
+            analysis_time_ms,
+        }
+    }
+}
+
+/// BPM of a track from the shared analysis results. Tracks without a current result are queued
+/// for analysis and answered with the job (202 Accepted).
 pub async fn analyze_track_bpm(
     State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
     Query(query): Query<AnalyzeBpmQuery>,
-) -> Result<Json<ApiResponse<BpmAnalysisResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+) -> Result<Response, (StatusCode, Json<ApiResponse<()>>)> {
     let start_time = std::time::Instant::now();
-    
-    // Log at ERROR level to ensure it shows up
-    tracing::error!("=== BPM ANALYSIS ENDPOINT CALLED ===");
-    tracing::error!("BPM analysis request received - Track ID: {}, Source: {}, Stream URL: {:?}", 
-                   query.track_id, query.source, query.stream_url);
-    println!("=== BPM ANALYSIS ENDPOINT CALLED ===");
-    println!("BPM analysis request received - Track ID: {}, Source: {}, Stream URL: {:?}", 
-             query.track_id, query.source, query.stream_url);
-    
-    // Get the stream URL for the track
-    let stream_url = match query.stream_url {
-        Some(url) => {
-            tracing::debug!("Using provided stream URL: {}", url);
-            url
-        },
-        None => {
-            tracing::debug!("No stream URL provided, attempting to resolve for source: {}", query.source);
-            // Try to get stream URL from streaming service
-            match get_stream_url_for_track(&state, &query.track_id, &query.source).await {
-                Ok(url) => {
-                    tracing::debug!("Resolved stream URL: {}", url);
-                    url
-                },
-                Err(e) => {
-                    tracing::error!("Failed to get stream URL for track {} ({}): {}", 
-                                   query.track_id, query.source, e);
-                    return Err((
-                        StatusCode::BAD_REQUEST,
-                        Json(ApiResponse::<()>::error(format!("Could not get stream URL: {}", e))),
-                    ));
-                }
-            }
+    tracing::info!("BPM analysis request received - Track ID: {}, Source: {}", query.track_id, query.source);
+
+    // Analysis results are shared between users, so a track only has to be analyzed once
+    if !query.force.unwrap_or(false) {
+        let stored = find_stored_analysis(&state, &query.track_id, &query.source).await?;
+        if let Some(analysis) = stored.filter(|analysis| analysis.has_current_bpm()) {
+            tracing::info!("Using stored BPM for track {} ({}): {:?}", query.track_id, query.source, analysis.bpm);
+            let range = preferred_bpm_range(&state, user.id, query.genre.as_deref()).await;
+            let candidates = analysis.bpm_candidates();
+            return Ok(Json(ApiResponse::success(BpmAnalysisResponse {
+                track_id: query.track_id,
+                source: query.source,
+                bpm: bpm_in_range(analysis.bpm, &candidates, range),
+                confidence: analysis.bpm_confidence.unwrap_or_default(),
+                undetermined: analysis.bpm.is_none(),
+                candidates,
+                analysis_time_ms: start_time.elapsed().as_millis() as u64,
+            }))
+            .into_response());
         }
-    };
+    }
 
-    // Create spectrogram analysis service (now the only BPM analysis method)
-    let analysis_service = SpectrogramBpmAnalysisService::new();
+    queue_analysis(&state, user.id, &query.source, &query.track_id, JOB_KIND_BPM).await
+}
 
-    // Analyze BPM using spectrogram approach
-    let track_id = query.track_id.clone();
-    let source = query.source.clone();
-    
-    tracing::info!("Starting spectrogram BPM analysis task for track: {} ({})", track_id, source);
-    
-    let bpm = if stream_url.starts_with("http://") || stream_url.starts_with("https://") {
-        tracing::debug!("Analyzing remote file with spectrogram: {}", stream_url);
-        match analysis_service.analyze_remote_file_spectrogram(&stream_url).await {
-            Ok((bpm, _, _)) => {
-                tracing::info!("Remote spectrogram analysis successful: {} BPM", bpm);
-                bpm
-            },
-            Err(e) => {
-                tracing::error!("Remote spectrogram analysis failed for {}: {}", stream_url, e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<()>::error(format!("BPM analysis failed: {}", e))),
-                ));
-            }
-        }
-    } else {
-        tracing::debug!("Analyzing local file with spectrogram: {}", stream_url);
-        match analysis_service.analyze_bpm_with_spectrogram(&stream_url).await {
-            Ok((bpm, _, _)) => {
-                tracing::info!("Spectrogram analysis successful: {} BPM", bpm);
-                bpm
-            },
-            Err(e) => {
-                tracing::error!("Spectrogram analysis failed for {}: {}", stream_url, e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<()>::error(format!("BPM analysis failed: {}", e))),
-                ));
-            }
-        }
-    };
+/// Look up the shared analysis results of a track
+async fn find_stored_analysis(
+    state: &AppState,
+    track_id: &str,
+    source: &str,
+) -> Result<Option<TrackAnalysisModel>, (StatusCode, Json<ApiResponse<()>>)> {
+    TrackAnalysisStore::find(state.db(), source, track_id).await.map_err(|e| {
+        tracing::error!("Database error when finding track analysis: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error("Database error".to_string())),
+        )
+    })
+}
 
-    // Update all saved tracks with this track_id and source to include the BPM
-    let db = state.db();
-    
-    tracing::debug!("Searching for saved tracks to update with BPM - Track ID: {}, Source: {}", 
-                    query.track_id, query.source);
-    
-    let saved_tracks = SavedTrack::find()
-        .filter(crate::models::saved_track::Column::TrackId.eq(&query.track_id))
-        .filter(crate::models::saved_track::Column::Source.eq(&query.source))
-        .all(db)
+/// Queue an analysis of a track for the user and answer with its job (202 Accepted). The worker
+/// resolves the audio on the server, so nothing the client passes is analyzed or stored.
+async fn queue_analysis(
+    state: &AppState,
+    user_id: uuid::Uuid,
+    source: &str,
+    track_id: &str,
+    kind: &str,
+) -> Result<Response, (StatusCode, Json<ApiResponse<()>>)> {
+    let track = AnalysisTrackDto {
+        track_id: track_id.to_string(),
+        source: source.to_string(),
+        title: None,
+        stream_url: None,
+        content_hash: None,
+    };
+    // Callers queue only when no current result is stored, so the analysis is always wanted
+    let outcome = state
+        .analysis_jobs
+        .enqueue(Some(user_id), vec![track], &[kind.to_string()], true)
         .await
         .map_err(|e| {
-            tracing::error!("Database error when finding saved tracks: {}", e);
+            tracing::error!("Failed to queue {} analysis of track {} ({}): {}", kind, track_id, source, e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ApiResponse::<()>::error("Database error".to_string())),
             )
         })?;
-
-    tracing::info!("Found {} saved tracks to update with BPM: {}", saved_tracks.len(), bpm);
-
-    // Update each saved track with the BPM
-    let mut updated_count = 0;
-    for saved_track in saved_tracks {
-        let track_uuid = saved_track.id;
-        let mut active_model: SavedTrackActiveModel = saved_track.into();
-        active_model.bpm = Set(Some(bpm));
-        
-        match active_model.update(db).await {
-            Ok(_) => {
-                updated_count += 1;
-                tracing::debug!("Updated saved track {} with BPM: {}", track_uuid, bpm);
-            },
-            Err(e) => {
-                tracing::error!("Failed to update saved track {} with BPM: {}", track_uuid, e);
-                // Continue with other tracks even if one fails
-            }
-        }
-    }
-    
-    tracing::info!("Successfully updated {} saved tracks with BPM: {}", updated_count, bpm);
-
-    let analysis_time = start_time.elapsed();
-    
-    let response = BpmAnalysisResponse {
-        track_id: query.track_id,
-        source: query.source,
-        bpm,
-        analysis_time_ms: analysis_time.as_millis() as u64,
-    };
-
-    tracing::info!("BPM analysis completed for track {} ({}): {} BPM in {}ms", 
-                   response.track_id, response.source, response.bpm, response.analysis_time_ms);
-
-    Ok(Json(ApiResponse::success(response)))
+    let job = outcome.jobs.into_iter().next().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error("Analysis could not be queued".to_string())),
+        )
+    })?;
+    tracing::info!("Queued {} analysis of track {} ({}) as job {}", kind, track_id, source, job.id);
+
+    Ok((StatusCode::ACCEPTED, Json(ApiResponse::success(AnalysisJobResponseDto::from(job)))).into_response())
 }
 
 /// Helper function to get stream URL for a track
@@ -271,6 +256,7 @@ async fn get_stream_url_for_track(
 pub struct GetBpmQuery {
     pub track_id: String,
     pub source: String,
+    pub genre: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -278,302 +264,628 @@ pub struct BpmResponse {
     pub track_id: String,
     pub source: String,
     pub bpm: Option<f32>,
+    pub confidence: Option<f32>,
+    pub candidates: Vec<BpmCandidate>,
+    pub undetermined: bool, // Analyzed, but no tempo could be determined
 }
 
 /// Get the BPM for a track if it has been analyzed
 pub async fn get_track_bpm(
     State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
     Query(query): Query<GetBpmQuery>,
 ) -> Result<Json<ApiResponse<BpmResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
-    let db = state.db();
-    
-    let saved_track = SavedTrack::find()
-        .filter(crate::models::saved_track::Column::TrackId.eq(&query.track_id))
-        .filter(crate::models::saved_track::Column::Source.eq(&query.source))
-        .one(db)
+    let analysis = find_stored_analysis(&state, &query.track_id, &query.source).await?;
+    let range = preferred_bpm_range(&state, user.id, query.genre.as_deref()).await;
+
+    let response = match analysis {
+        Some(analysis) => {
+            let candidates = analysis.bpm_candidates();
+            BpmResponse {
+                track_id: query.track_id,
+                source: query.source,
+                bpm: bpm_in_range(analysis.bpm, &candidates, range),
+                confidence: analysis.bpm_confidence,
+                undetermined: analysis.bpm.is_none() && analysis.bpm_analyzed_at.is_some(),
+                candidates,
+            }
+        }
+        None => BpmResponse {
+            track_id: query.track_id,
+            source: query.source,
+            bpm: None,
+            confidence: None,
+            candidates: Vec::new(),
+            undetermined: false,
+        },
+    };
+
+    Ok(Json(ApiResponse::success(response)))
+}
+
+/// Stored tempos are resolved with the default range; move them to the octave inside the user's range
+fn bpm_in_range(bpm: Option<f32>, candidates: &[BpmCandidate], range: Option<BpmRange>) -> Option<f32> {
+    match (bpm, range) {
+        (Some(bpm), Some(range)) => Some(resolve_octave(bpm, candidates, range)),
+        _ => bpm,
+    }
+}
+
+/// The user's BPM range for a genre, falling back to their default range
+async fn preferred_bpm_range(state: &AppState, user_id: uuid::Uuid, genre: Option<&str>) -> Option<BpmRange> {
+    let genre = genre.map(|genre| genre.trim().to_lowercase()).unwrap_or_default();
+    let ranges = BpmRangeEntity::find()
+        .filter(BpmRangeColumn::UserId.eq(user_id))
+        .filter(BpmRangeColumn::Genre.is_in([genre.as_str(), DEFAULT_GENRE]))
+        .all(state.db())
+        .await;
+
+    match ranges {
+        Ok(ranges) => ranges
+            .iter()
+            .find(|range| range.genre == genre)
+            .or_else(|| ranges.iter().find(|range| range.genre == DEFAULT_GENRE))
+            .map(|range| range.range()),
+        Err(e) => {
+            tracing::error!("Failed to load BPM ranges: {}", e);
+            None
+        }
+    }
+}
+
+/// List the user's preferred BPM ranges
+pub async fn get_bpm_ranges(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+) -> Result<Json<ApiResponse<Vec<BpmRangeDto>>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let ranges = BpmRangeEntity::find()
+        .filter(BpmRangeColumn::UserId.eq(user.id))
+        .order_by_asc(BpmRangeColumn::Genre)
+        .all(state.db())
         .await
-        .map_err(|e| {
-            tracing::error!("Database error when finding saved track: {}", e);
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(format!("Database error: {}", e)))))?;
+
+    Ok(Json(ApiResponse::success(ranges.into_iter().map(BpmRangeDto::from).collect())))
+}
+
+/// Set the preferred BPM range for a genre, or the default range when no genre is given
+pub async fn update_bpm_range(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Json(request): Json<BpmRangeDto>,
+) -> Result<Json<ApiResponse<BpmRangeDto>>, (StatusCode, Json<ApiResponse<()>>)> {
+    if !request.min_bpm.is_finite() || !request.max_bpm.is_finite() || request.min_bpm <= 0.0 || request.min_bpm >= request.max_bpm {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error("min_bpm must be positive and below max_bpm".to_string())),
+        ));
+    }
+
+    let genre = request.genre.as_deref().map(|genre| genre.trim().to_lowercase()).unwrap_or_default();
+    let now = chrono::Utc::now().naive_utc();
+    let existing = BpmRangeEntity::find()
+        .filter(BpmRangeColumn::UserId.eq(user.id))
+        .filter(BpmRangeColumn::Genre.eq(genre.as_str()))
+        .one(state.db())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(format!("Database error: {}", e)))))?;
+
+    let result = match existing {
+        Some(model) => {
+            let mut active: BpmRangeActiveModel = model.into();
+            active.min_bpm = Set(request.min_bpm);
+            active.max_bpm = Set(request.max_bpm);
+            active.updated_at = Set(now);
+            active.update(state.db()).await
+        }
+        None => BpmRangeActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            user_id: Set(user.id),
+            genre: Set(genre),
+            min_bpm: Set(request.min_bpm),
+            max_bpm: Set(request.max_bpm),
+            updated_at: Set(now),
+        }
+        .insert(state.db())
+        .await,
+    };
+
+    let saved = result
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(format!("Failed to save BPM range: {}", e)))))?;
+    Ok(Json(ApiResponse::success(BpmRangeDto::from(saved))))
+}
+
+#[derive(Deserialize)]
+pub struct DeleteBpmRangeQuery {
+    pub genre: Option<String>,
+}
+
+/// Remove the preferred BPM range of a genre, or the default range when no genre is given
+pub async fn delete_bpm_range(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Query(query): Query<DeleteBpmRangeQuery>,
+) -> Result<Json<ApiResponse<()>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let genre = query.genre.as_deref().map(|genre| genre.trim().to_lowercase()).unwrap_or_default();
+    BpmRangeEntity::delete_many()
+        .filter(BpmRangeColumn::UserId.eq(user.id))
+        .filter(BpmRangeColumn::Genre.eq(genre))
+        .exec(state.db())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(format!("Database error: {}", e)))))?;
+
+    Ok(Json(ApiResponse::success(())))
+}
+
+#[derive(Serialize)]
+pub struct KeyTimelineResponse {
+    pub track_id: String,
+    pub source: String,
+    pub profile: Option<String>,
+    pub timeline: Vec<KeySegment>,
+}
+
+/// Get the key changes of a track if its key has been analyzed
+pub async fn get_track_key_timeline(
+    State(state): State<AppState>,
+    Query(query): Query<GetBpmQuery>,
+) -> Result<Json<ApiResponse<KeyTimelineResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let analysis = find_stored_analysis(&state, &query.track_id, &query.source)
+        .await?
+        .filter(|analysis| analysis.key_timeline.is_some())
+        .ok_or_else(|| {
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error("Database error".to_string())),
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error("No key timeline for this track, analyze its key first".to_string())),
             )
         })?;
 
-    let bpm = saved_track.and_then(|track| track.bpm);
-    
-    let response = BpmResponse {
+    Ok(Json(ApiResponse::success(KeyTimelineResponse {
         track_id: query.track_id,
         source: query.source,
-        bpm,
-    };
+        timeline: analysis.key_timeline(),
+        profile: analysis.key_profile,
+    })))
+}
 
-    Ok(Json(ApiResponse::success(response)))
+#[derive(Serialize)]
+pub struct ChordsResponse {
+    pub track_id: String,
+    pub source: String,
+    pub timeline: Vec<ChordSegment>,
+    pub summary: ChordSummary,
 }
 
-/// Analyze BPM using spectrogram approach and save spectrogram image
-pub async fn analyze_track_bpm_spectrogram(
+/// Get the chords of a track if its key has been analyzed
+pub async fn get_track_chords(
     State(state): State<AppState>,
-    Query(query): Query<AnalyzeBpmQuery>,
-) -> Result<Json<ApiResponse<SpectrogramBpmAnalysisResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
-    let start_time = std::time::Instant::now();
-    
-    tracing::info!("=== SPECTROGRAM BPM ANALYSIS ENDPOINT CALLED ===");
-    tracing::info!("Spectrogram BPM analysis request - Track ID: {}, Source: {}, Stream URL: {:?}", 
-                   query.track_id, query.source, query.stream_url);
-    
-    // Get the stream URL for the track
-    let stream_url = match query.stream_url {
-        Some(url) => {
-            tracing::debug!("Using provided stream URL: {}", url);
-            url
-        },
-        None => {
-            tracing::debug!("No stream URL provided, attempting to resolve for source: {}", query.source);
-            match get_stream_url_for_track(&state, &query.track_id, &query.source).await {
-                Ok(url) => {
-                    tracing::debug!("Resolved stream URL: {}", url);
-                    url
-                },
-                Err(e) => {
-                    tracing::error!("Failed to get stream URL for track {} ({}): {}", 
-                                   query.track_id, query.source, e);
-                    return Err((
-                        StatusCode::BAD_REQUEST,
-                        Json(ApiResponse::<()>::error(format!("Could not get stream URL: {}", e))),
-                    ));
-                }
-            }
-        }
-    };
+    Query(query): Query<GetBpmQuery>,
+) -> Result<Json<ApiResponse<ChordsResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let analysis = find_stored_analysis(&state, &query.track_id, &query.source)
+        .await?
+        .filter(|analysis| analysis.chord_timeline.is_some())
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error("No chords for this track, analyze its key first".to_string())),
+            )
+        })?;
 
-    // Create spectrogram analysis service
-    let analysis_service = SpectrogramBpmAnalysisService::new();
+    let timeline = analysis.chord_timeline();
+    Ok(Json(ApiResponse::success(ChordsResponse {
+        track_id: query.track_id,
+        source: query.source,
+        summary: ChordSummary::of(&timeline),
+        timeline,
+    })))
+}
 
-    // Analyze BPM with spectrogram
-    let track_id = query.track_id.clone();
-    let source = query.source.clone();
-    
-    tracing::info!("Starting spectrogram BPM analysis task for track: {} ({})", track_id, source);
-    
-    let (bpm, spectrogram_path, analysis_visualization_path) = if stream_url.starts_with("http://") || stream_url.starts_with("https://") {
-        tracing::debug!("Analyzing remote file with spectrogram: {}", stream_url);
-        match analysis_service.analyze_remote_file_spectrogram(&stream_url).await {
-            Ok((bpm, spec_path, viz_path)) => {
-                tracing::info!("Remote spectrogram analysis successful: {} BPM, spectrogram: {}, visualization: {}", bpm, spec_path, viz_path);
-                (bpm, spec_path, viz_path)
-            },
-            Err(e) => {
-                tracing::error!("Remote spectrogram analysis failed for {}: {}", stream_url, e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<()>::error(format!("Remote spectrogram analysis failed: {}", e))),
-                ));
-            }
-        }
-    } else {
-        tracing::debug!("Analyzing local file with spectrogram: {}", stream_url);
-        match analysis_service.analyze_bpm_with_spectrogram(&stream_url).await {
-            Ok((bpm, spec_path, viz_path)) => {
-                tracing::info!("Spectrogram analysis successful: {} BPM, spectrogram: {}, visualization: {}", bpm, spec_path, viz_path);
-                (bpm, spec_path, viz_path)
-            },
-            Err(e) => {
-                tracing::error!("Spectrogram analysis failed for {}: {}", stream_url, e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<()>::error(format!("Spectrogram analysis failed: {}", e))),
-                ));
-            }
-        }
-    };
+#[derive(Serialize)]
+pub struct BeatGridResponse {
+    pub track_id: String,
+    pub source: String,
+    pub beat_grid: BeatGrid,
+}
 
-    let analysis_duration = start_time.elapsed();
-    
-    // Save BPM to database
-    let db = state.db();
-    
-    // Find existing saved track or create new one
-    let existing_track = SavedTrack::find()
-        .filter(crate::models::saved_track::Column::TrackId.eq(&track_id))
-        .filter(crate::models::saved_track::Column::Source.eq(&source))
-        .one(db)
-        .await
-        .map_err(|e| {
-            tracing::error!("Database error when finding saved track: {}", e);
+/// Get the beat grid of a track if its BPM has been analyzed
+pub async fn get_track_beat_grid(
+    State(state): State<AppState>,
+    Query(query): Query<GetBpmQuery>,
+) -> Result<Json<ApiResponse<BeatGridResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let beat_grid = find_stored_analysis(&state, &query.track_id, &query.source)
+        .await?
+        .and_then(|analysis| analysis.beat_grid())
+        .ok_or_else(|| {
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error("Database error".to_string())),
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error("No beat grid for this track, analyze its BPM first".to_string())),
             )
         })?;
 
-    match existing_track {
-        Some(track) => {
-            // Update existing track
-            let mut active_track: SavedTrackActiveModel = track.into();
-            active_track.bpm = Set(Some(bpm));
-            
-            active_track.update(db).await.map_err(|e| {
-                tracing::error!("Failed to update track BPM in database: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<()>::error("Failed to save BPM".to_string())),
-                )
-            })?;
-            
-            tracing::info!("Updated existing track {} ({}) with BPM: {}", track_id, source, bpm);
-        }
-        None => {
-            // Create new saved track entry
-            let new_track = SavedTrackActiveModel {
-                track_id: Set(track_id.clone()),
-                source: Set(source.clone()),
-                bpm: Set(Some(bpm)),
-                ..Default::default()
+    Ok(Json(ApiResponse::success(BeatGridResponse {
+        track_id: query.track_id,
+        source: query.source,
+        beat_grid,
+    })))
+}
+
+#[derive(Serialize)]
+pub struct TrackStructureResponse {
+    pub track_id: String,
+    pub source: String,
+    pub structure: TrackStructure,
+}
+
+/// Get silence, fades, intro/outro and suggested hot cues of a track if its BPM has been analyzed
+pub async fn get_track_structure(
+    State(state): State<AppState>,
+    Query(query): Query<GetBpmQuery>,
+) -> Result<Json<ApiResponse<TrackStructureResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let structure = find_stored_analysis(&state, &query.track_id, &query.source)
+        .await?
+        .and_then(|analysis| analysis.structure())
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error("No structure for this track, analyze its BPM first".to_string())),
+            )
+        })?;
+
+    Ok(Json(ApiResponse::success(TrackStructureResponse {
+        track_id: query.track_id,
+        source: query.source,
+        structure,
+    })))
+}
+
+/// BPM of a track with the spectrogram images of its analysis. Tracks without a current result, or
+/// without images when they are requested, are queued for analysis and answered with the job.
+pub async fn analyze_track_bpm_spectrogram(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Query(query): Query<AnalyzeBpmQuery>,
+) -> Result<Response, (StatusCode, Json<ApiResponse<()>>)> {
+    let start_time = std::time::Instant::now();
+    tracing::info!("Spectrogram BPM analysis request - Track ID: {}, Source: {}", query.track_id, query.source);
+
+    let images = query.images.unwrap_or(false);
+    let rendered = |kind: ArtifactKind| state.artifacts.path(&query.source, &query.track_id, kind).exists();
+    if !query.force.unwrap_or(false) {
+        let stored = find_stored_analysis(&state, &query.track_id, &query.source).await?;
+        if let Some(analysis) = stored.filter(|analysis| analysis.has_current_bpm())
+            && (!images || ArtifactKind::ALL.into_iter().all(rendered))
+        {
+            let range = preferred_bpm_range(&state, user.id, query.genre.as_deref()).await;
+            let candidates = analysis.bpm_candidates();
+            let artifact_url = |kind: ArtifactKind| {
+                rendered(kind).then(|| AnalysisArtifacts::url(&query.source, &query.track_id, kind))
             };
-            
-            new_track.insert(db).await.map_err(|e| {
-                tracing::error!("Failed to insert new track with BPM: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<()>::error("Failed to save BPM".to_string())),
-                )
-            })?;
-            
-            tracing::info!("Created new track entry {} ({}) with BPM: {}", track_id, source, bpm);
+            return Ok(Json(ApiResponse::success(SpectrogramBpmAnalysisResponse {
+                track_id: query.track_id.clone(),
+                source: query.source.clone(),
+                bpm: bpm_in_range(analysis.bpm, &candidates, range),
+                confidence: analysis.bpm_confidence.unwrap_or_default(),
+                undetermined: analysis.bpm.is_none(),
+                candidates,
+                analysis_time_ms: start_time.elapsed().as_millis() as u64,
+                spectrogram_url: artifact_url(ArtifactKind::Spectrogram),
+                analysis_visualization_url: artifact_url(ArtifactKind::Visualization),
+            }))
+            .into_response());
         }
     }
 
-    let response = SpectrogramBpmAnalysisResponse {
-        track_id,
-        source,
-        bpm,
-        analysis_time_ms: analysis_duration.as_millis() as u64,
-        spectrogram_path,
-        analysis_visualization_path,
-    };
-
-    tracing::info!("Spectrogram BPM analysis completed successfully: {} BPM in {}ms", 
-                   bpm, analysis_duration.as_millis());
-
-    Ok(Json(ApiResponse::success(response)))
+    let kind = if images { JOB_KIND_SPECTROGRAM } else { JOB_KIND_BPM };
+    queue_analysis(&state, user.id, &query.source, &query.track_id, kind).await
 }
 
-/// Analyze key of a track and save it to the database
+/// Key of a track from the shared analysis results. Tracks without a current result are queued
+/// for analysis and answered with the job (202 Accepted).
 pub async fn analyze_track_key(
     State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
     Query(query): Query<AnalyzeKeyQuery>,
-) -> Result<Json<ApiResponse<KeyAnalysisResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+) -> Result<Response, (StatusCode, Json<ApiResponse<()>>)> {
     let start_time = std::time::Instant::now();
     
     tracing::info!("Starting key analysis for track: {} ({})", query.track_id, query.source);
-    
-    // Get stream URL from query or fetch it
-    let stream_url = match query.stream_url {
-        Some(url) => url,
-        None => {
-            tracing::error!("No stream URL provided for key analysis");
-            return Err((
+
+    let profile = match query.profile.as_deref() {
+        Some(name) => KeyProfile::from_name(name).ok_or_else(|| {
+            (
                 StatusCode::BAD_REQUEST,
-                Json(ApiResponse::<()>::error("Stream URL is required for key analysis".to_string())),
-            ));
-        }
+                Json(ApiResponse::<()>::error(format!("Unknown key profile: {}", name))),
+            )
+        })?,
+        None => KeyProfile::default(),
     };
 
-    // Create key analysis service
-    let analysis_service = KeyAnalysisService::new();
-    
-    let track_id = query.track_id.clone();
-    let source = query.source.clone();
-    
-    tracing::info!("Starting key analysis task for track: {} ({})", track_id, source);
-    
-    let key_result = if stream_url.starts_with("http://") || stream_url.starts_with("https://") {
-        tracing::debug!("Analyzing remote file for key: {}", stream_url);
-        match analysis_service.analyze_remote_file_key(&stream_url).await {
-            Ok(key) => {
-                tracing::info!("Remote key analysis successful: {} ({}), confidence: {:.3}", 
-                              key.key_name, key.camelot, key.confidence);
-                key
-            },
-            Err(e) => {
-                tracing::error!("Remote key analysis failed for {}: {}", stream_url, e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<()>::error(format!("Remote key analysis failed: {}", e))),
-                ));
-            }
+    // The stored key is shared by all users, so tracks are only analyzed with the default profile
+    if profile != KeyProfile::default() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(format!(
+                "Keys are analyzed with the {} profile only",
+                KeyProfile::default().name()
+            ))),
+        ));
+    }
+
+    if !query.force.unwrap_or(false) {
+        let stored = find_stored_analysis(&state, &query.track_id, &query.source).await?;
+        if let Some(analysis) = stored.filter(|analysis| analysis.has_current_key(profile)) {
+            tracing::info!("Using stored key for track {} ({})", query.track_id, query.source);
+            let elapsed = start_time.elapsed().as_millis() as u64;
+            let response = KeyAnalysisResponse::from_stored(query.track_id, query.source, &analysis, profile, elapsed);
+            return Ok(Json(ApiResponse::success(response)).into_response());
         }
+    }
+
+    queue_analysis(&state, user.id, &query.source, &query.track_id, JOB_KIND_KEY).await
+}
+
+#[derive(Deserialize)]
+pub struct AnalyzeLoudnessQuery {
+    pub track_id: String,
+    pub source: String,
+    pub force: Option<bool>, // Re-analyze even if a current result is stored
+}
+
+#[derive(Serialize)]
+pub struct LoudnessAnalysisResponse {
+    pub track_id: String,
+    pub source: String,
+    pub loudness: LoudnessDto,
+    pub analysis_time_ms: u64,
+}
+
+/// EBU R128 loudness of a track from the shared analysis results. Tracks without a current result
+/// are queued for analysis and answered with the job (202 Accepted).
+pub async fn analyze_track_loudness(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Query(query): Query<AnalyzeLoudnessQuery>,
+) -> Result<Response, (StatusCode, Json<ApiResponse<()>>)> {
+    let start_time = std::time::Instant::now();
+    tracing::info!("Starting loudness analysis for track: {} ({})", query.track_id, query.source);
+
+    if !query.force.unwrap_or(false) {
+        let stored = find_stored_analysis(&state, &query.track_id, &query.source).await?;
+        if let Some(loudness) = stored.filter(|analysis| analysis.has_current_loudness()).and_then(|analysis| analysis.loudness()) {
+            tracing::info!("Using stored loudness for track {} ({})", query.track_id, query.source);
+            return Ok(Json(ApiResponse::success(LoudnessAnalysisResponse {
+                track_id: query.track_id,
+                source: query.source,
+                loudness,
+                analysis_time_ms: start_time.elapsed().as_millis() as u64,
+            }))
+            .into_response());
+        }
+    }
+
+    queue_analysis(&state, user.id, &query.source, &query.track_id, JOB_KIND_LOUDNESS).await
+}
+
+#[derive(Deserialize)]
+pub struct AlbumLoudnessQuery {
+    pub album_id: String, // Album id of the source, e.g. of a saved album or "server_album_{artist}_{album}"
+    pub source: String,
+}
+
+#[derive(Serialize)]
+pub struct AlbumTrackLoudness {
+    pub track_id: String,
+    pub title: String,
+    pub loudness: Option<LoudnessDto>, // None while the track is not measured yet
+}
+
+#[derive(Serialize)]
+pub struct AlbumLoudnessResponse {
+    pub album_id: String,
+    pub source: String,
+    pub album_loudness: Option<f32>, // LUFS
+    pub album_gain: Option<f32>, // dB to reach the ReplayGain reference
+    pub album_peak: Option<f32>, // Linear
+    pub complete: bool, // Whether all tracks contributed to the album values
+    pub queued_tracks: usize, // Tracks queued for loudness analysis
+    pub tracks: Vec<AlbumTrackLoudness>,
+}
+
+/// Album gain of an album from the loudness of its tracks. Tracks not yet measured are queued
+/// for analysis, and the album values are stored on each measured track.
+pub async fn get_album_loudness(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Query(query): Query<AlbumLoudnessQuery>,
+) -> Result<Json<ApiResponse<AlbumLoudnessResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let service = get_authenticated_streaming_service(&query.source, user.id, state.db())
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(e))))?;
+    let album_tracks = service.get_album_tracks(&query.album_id).await.map_err(|e| {
+        (
+            StatusCode::BAD_GATEWAY,
+            Json(ApiResponse::<()>::error(format!("Failed to get album tracks: {}", e))),
+        )
+    })?;
+
+    let keys: Vec<TrackKey> = album_tracks.iter().map(|track| (query.source.clone(), track.id.clone())).collect();
+    let mut analyses = TrackAnalysisStore::find_many(state.db(), &keys).await.map_err(|e| {
+        tracing::error!("Database error when finding album analyses: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error("Database error".to_string())),
+        )
+    })?;
+    analyses.retain(|_, analysis| analysis.has_current_loudness());
+
+    let missing: Vec<AnalysisTrackDto> = album_tracks
+        .iter()
+        .filter(|track| !analyses.contains_key(&(query.source.clone(), track.id.clone())))
+        .map(|track| AnalysisTrackDto {
+            track_id: track.id.clone(),
+            source: query.source.clone(),
+            title: Some(track.title.clone()),
+            stream_url: None,
+            content_hash: None,
+        })
+        .collect();
+    let queued_tracks = if missing.is_empty() {
+        0
     } else {
-        tracing::debug!("Analyzing local file for key: {}", stream_url);
-        match analysis_service.analyze_key(&stream_url).await {
-            Ok(key) => {
-                tracing::info!("Key analysis successful: {} ({}), confidence: {:.3}", 
-                              key.key_name, key.camelot, key.confidence);
-                key
-            },
+        let kinds = [JOB_KIND_LOUDNESS.to_string()];
+        match state.analysis_jobs.enqueue(Some(user.id), missing, &kinds, false).await {
+            Ok(outcome) => outcome.jobs.len(),
             Err(e) => {
-                tracing::error!("Key analysis failed for {}: {}", stream_url, e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<()>::error(format!("Key analysis failed: {}", e))),
-                ));
+                tracing::warn!("Failed to queue loudness analysis of album {} ({}): {}", query.album_id, query.source, e);
+                0
             }
         }
     };
+    let complete = analyses.len() == album_tracks.len();
+
+    // Album loudness gates the blocks of all tracks together, like one long track
+    let mut histogram = LoudnessHistogram::default();
+    let mut album_peak = 0.0f32;
+    for analysis in analyses.values() {
+        histogram.merge(&analysis.loudness_histogram().unwrap_or_default());
+        album_peak = album_peak.max(analysis.true_peak.unwrap_or_default());
+    }
+    let album_loudness = histogram.integrated();
+
+    // Partial albums are not stored, their values change once the remaining tracks are measured
+    if complete && !analyses.is_empty() {
+        let stored = TrackAnalysisStore::save_album_loudness(state.db(), analyses.into_values().collect(), album_loudness, album_peak)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to save album loudness of {} ({}): {}", query.album_id, query.source, e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::<()>::error("Failed to save analysis".to_string())),
+                )
+            })?;
+        analyses = stored
+            .into_iter()
+            .map(|analysis| ((analysis.source.clone(), analysis.track_id.clone()), analysis))
+            .collect();
+    }
 
-    let analysis_duration = start_time.elapsed();
-    
-    // Save key to database (update existing saved track if it exists)
-    let db = state.db();
-    
-    // Find existing saved track
-    let existing_track = SavedTrack::find()
-        .filter(crate::models::saved_track::Column::TrackId.eq(&track_id))
-        .filter(crate::models::saved_track::Column::Source.eq(&source))
-        .one(db)
-        .await
-        .map_err(|e| {
-            tracing::error!("Database error when finding saved track: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<()>::error("Database error".to_string())),
-            )
-        })?;
+    let tracks = album_tracks
+        .into_iter()
+        .map(|track| AlbumTrackLoudness {
+            loudness: analyses.get(&(query.source.clone(), track.id.clone())).and_then(|analysis| analysis.loudness()),
+            track_id: track.id,
+            title: track.title,
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(AlbumLoudnessResponse {
+        album_id: query.album_id,
+        source: query.source,
+        album_gain: album_loudness.map(replaygain_gain),
+        album_loudness,
+        album_peak: (album_peak > 0.0).then_some(album_peak),
+        complete,
+        queued_tracks,
+        tracks,
+    })))
+}
 
-    if let Some(track) = existing_track {
-        // Update existing track with key information
-        let mut track_update: SavedTrackActiveModel = track.into();
-        track_update.key_name = Set(Some(key_result.key_name.clone()));
-        track_update.camelot = Set(Some(key_result.camelot.clone()));
-        track_update.key_confidence = Set(Some(key_result.confidence));
-        
-        match track_update.update(db).await {
-            Ok(_) => {
-                tracing::info!("Updated existing saved track {} with key: {} ({})", 
-                              track_id, key_result.key_name, key_result.camelot);
-            }
-            Err(e) => {
-                tracing::error!("Failed to update saved track with key: {}", e);
-            }
-        }
-    } else {
-        tracing::debug!("No existing saved track found for key update: {} ({})", track_id, source);
+#[derive(Deserialize)]
+pub struct WaveformQuery {
+    pub track_id: String,
+    pub source: String,
+    pub stream_url: Option<String>, // Local path; remote streams have no waveform
+    pub format: Option<String>, // "json" (default) or "dat", both audiowaveform version 2
+    pub band: Option<String>, // "full" (default), "low", "mid", "high" or "split" for all three as channels
+    pub samples_per_pixel: Option<u32>, // Zoom level, rounded up to a multiple of 256
+    pub pixels: Option<u32>, // Target width when no zoom level is given
+    pub bits: Option<u8>, // 8 or 16 (default)
+}
+
+/// Waveform peaks of a local or cached track in audiowaveform's JSON or binary format
+pub async fn get_track_waveform(
+    State(state): State<AppState>,
+    Extension(_user): Extension<UserResponseDto>,
+    Query(query): Query<WaveformQuery>,
+) -> Result<Response, (StatusCode, Json<ApiResponse<()>>)> {
+    let bad_request = |message: String| (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(message)));
+
+    let bands = match query.band.as_deref().unwrap_or("full") {
+        "split" => WaveformBand::SPLIT.to_vec(),
+        name => vec![WaveformBand::from_name(name).ok_or_else(|| bad_request(format!("Unknown waveform band: {}", name)))?],
+    };
+    let bits = query.bits.unwrap_or(16);
+    if bits != 8 && bits != 16 {
+        return Err(bad_request("Waveform bits must be 8 or 16".to_string()));
+    }
+    let format = query.format.as_deref().unwrap_or("json");
+    if format != "json" && format != "dat" {
+        return Err(bad_request(format!("Unknown waveform format: {}", format)));
     }
 
-    let response = KeyAnalysisResponse {
-        track_id,
-        source,
-        key_name: key_result.key_name,
-        camelot: key_result.camelot,
-        confidence: key_result.confidence,
-        is_major: key_result.is_major,
-        analysis_time_ms: analysis_duration.as_millis() as u64,
+    let path = match query.stream_url {
+        Some(url) if is_remote(&url) => {
+            return Err(bad_request("Waveforms are only available for local or cached tracks".to_string()));
+        }
+        Some(path) => path,
+        None => get_stream_url_for_track(&state, &query.track_id, &query.source)
+            .await
+            .map_err(|e| (StatusCode::NOT_FOUND, Json(ApiResponse::<()>::error(e))))?,
     };
+    let path = waveform_source(&state, &path).await.ok_or_else(|| {
+        (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()>::error("Waveforms are only available for local or cached tracks".to_string())),
+        )
+    })?;
+
+    let waveform = state.waveforms.waveform(&path.to_string_lossy()).await.map_err(|e| {
+        tracing::error!("Waveform computation failed for track {} ({}): {}", query.track_id, query.source, e);
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ApiResponse::<()>::error(format!("Waveform computation failed: {}", e))),
+        )
+    })?;
+    let samples_per_pixel = waveform.resolution_for(query.samples_per_pixel, query.pixels);
+
+    Ok(match format {
+        "dat" => (
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            waveform.to_dat(&bands, samples_per_pixel, bits),
+        )
+            .into_response(),
+        _ => Json(waveform.to_json(&bands, samples_per_pixel, bits)).into_response(),
+    })
+}
 
-    tracing::info!("Key analysis completed successfully: {} ({}) with confidence {:.3} in {}ms", 
-                   response.key_name, response.camelot, response.confidence, analysis_duration.as_millis());
+// Files of the music library and tracks in the stream cache, nothing else on disk
+async fn waveform_source(state: &AppState, path: &str) -> Option<std::path::PathBuf> {
+    let path = std::path::Path::new(path.strip_prefix("file://").unwrap_or(path));
+    match confine_to_music_dir(&default_music_dir(), path) {
+        Some(path) => Some(path),
+        None => state.streaming_service.cached_file(path).await,
+    }
+}
 
-    Ok(Json(ApiResponse::success(response)))
+#[derive(Deserialize)]
+pub struct ArtifactQuery {
+    pub track_id: String,
+    pub source: String,
 }
 
+/// Serve an image rendered by an analysis of a track
+pub async fn get_analysis_artifact(
+    State(state): State<AppState>,
+    Path(kind): Path<String>,
+    Query(query): Query<ArtifactQuery>,
+) -> Result<Response, (StatusCode, Json<ApiResponse<()>>)> {
+    let not_found = |message: String| (StatusCode::NOT_FOUND, Json(ApiResponse::<()>::error(message)));
+    let kind = ArtifactKind::from_name(&kind).ok_or_else(|| not_found(format!("Unknown artifact: {}", kind)))?;
+
+    let path = state.artifacts.path(&query.source, &query.track_id, kind);
+    let bytes = tokio::fs::read(&path).await.map_err(|_| {
+        not_found(format!("No {} for this track, analyze it with images=true first", kind.name()))
+    })?;
+
+    Ok(([(header::CONTENT_TYPE, "image/png")], bytes).into_response())
+}
@@ -18,6 +18,12 @@ use crate::models::{CreateUserDto, LoginDto, UserResponseDto};
 pub struct AppState {
     pub auth_service: AuthService,
     pub streaming_service: Arc<crate::services::streaming_service::StreamingService>,
+    pub analysis_jobs: crate::services::analysis_jobs::AnalysisJobQueue,
+    pub library_ingest: crate::services::library_ingest::LibraryIngestService,
+    pub artifacts: crate::services::analysis_artifacts::AnalysisArtifacts,
+    pub waveforms: crate::services::waveform::WaveformService,
+    pub mix_exports: crate::services::mix_export::MixExportService,
+    pub samples: crate::services::sample_library::SampleLibraryService,
 }
 
 impl AppState {
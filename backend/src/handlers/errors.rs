@@ -0,0 +1,20 @@
+use axum::{http::StatusCode, response::Json};
+use tracing::error;
+
+use crate::handlers::auth::ApiResponse;
+
+/// Error response of the JSON API handlers
+pub type ApiError = (StatusCode, Json<ApiResponse<()>>);
+
+/// Log a database error and answer without its details
+pub fn database_error(e: impl std::fmt::Display) -> ApiError {
+    error!("Database error: {}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ApiResponse::<()>::error("Database error".to_string())),
+    )
+}
+
+pub fn bad_request(message: &str) -> ApiError {
+    (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(message.to_string())))
+}
@@ -0,0 +1,544 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+    Extension,
+};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use crate::handlers::auth::{AppState, ApiResponse};
+use crate::handlers::errors::{bad_request, database_error, ApiError};
+use crate::models::{
+    PlaylistEntity, PlaylistItemEntity, PlaylistItemModel, PlaylistModel, QueueItemEntity, SavedTrackEntity, TrackAnalysisModel,
+    UserResponseDto,
+};
+use crate::services::auto_dj::{order_set, EnergyCurve, SetOrder, SetTransition, MAX_SET_TRACKS};
+use crate::services::mix_export::{MixExport, MixExportService, MixExportStatus, MixFormat, MixSourceTrack, MAX_MIX_TRACKS};
+use crate::services::mix_render::MixSettings;
+use crate::services::harmonic_mixing::{suggest_next, MixOptions, MixTrack, NextTrackSuggestion, DEFAULT_PITCH_TOLERANCE};
+use crate::services::track_analysis_store::TrackAnalysisStore;
+
+const DEFAULT_SUGGESTIONS: usize = 10;
+const MAX_SUGGESTIONS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct NextTracksQuery {
+    pub track_id: String,
+    pub source: String,
+    pub from: Option<String>, // "saved" (default), "playlist" or "library"
+    pub playlist_id: Option<Uuid>, // Required with from=playlist
+    pub limit: Option<usize>,
+    pub pitch_tolerance: Option<f32>, // Percent, 6 by default
+    pub key_lock: Option<bool>, // true by default
+}
+
+#[derive(Serialize)]
+pub struct NextTracksResponse {
+    pub track_id: String,
+    pub source: String,
+    pub bpm: Option<f32>,
+    pub camelot: Option<String>,
+    pub suggestions: Vec<NextTrackSuggestion>,
+}
+
+fn mix_track(source: String, track_id: String, title: Option<String>, artist: Option<String>, analysis: Option<&TrackAnalysisModel>) -> MixTrack {
+    MixTrack {
+        source,
+        track_id,
+        title,
+        artist,
+        bpm: analysis.and_then(|analysis| analysis.bpm),
+        camelot: analysis.and_then(|analysis| analysis.camelot.clone()),
+        energy: analysis.and_then(|analysis| analysis.energy),
+    }
+}
+
+/// Candidates to play next: the user's saved tracks, one of their playlists or the local library
+async fn load_candidates(state: &AppState, user: &UserResponseDto, query: &NextTracksQuery) -> Result<Vec<MixTrack>, ApiError> {
+    let tracks: Vec<(String, String, Option<String>, Option<String>)> = match query.from.as_deref().unwrap_or("saved") {
+        "saved" => SavedTrackEntity::find()
+            .filter(crate::models::SavedTrackColumn::UserId.eq(user.id))
+            .all(state.db())
+            .await
+            .map_err(database_error)?
+            .into_iter()
+            .map(|track| (track.source, track.track_id, Some(track.title), Some(track.artist)))
+            .collect(),
+        "playlist" => {
+            let playlist_id = query.playlist_id.ok_or_else(|| bad_request("playlist_id is required with from=playlist"))?;
+            PlaylistEntity::find_by_id(playlist_id)
+                .filter(crate::models::playlist::Column::UserId.eq(user.id))
+                .one(state.db())
+                .await
+                .map_err(database_error)?
+                .ok_or_else(|| {
+                    (
+                        StatusCode::NOT_FOUND,
+                        Json(ApiResponse::<()>::error("Playlist not found".to_string())),
+                    )
+                })?;
+            PlaylistItemEntity::find()
+                .filter(crate::models::playlist_item::Column::PlaylistId.eq(playlist_id))
+                .filter(crate::models::playlist_item::Column::ItemType.eq("track"))
+                .order_by_asc(crate::models::playlist_item::Column::Position)
+                .all(state.db())
+                .await
+                .map_err(database_error)?
+                .into_iter()
+                .filter_map(|item| Some((item.source?, item.item_id, item.title, item.artist)))
+                .collect()
+        }
+        "library" => {
+            // Local files are only known through their analyses
+            let analyses = TrackAnalysisStore::find_by_source(state.db(), "server").await.map_err(database_error)?;
+            return Ok(analyses
+                .iter()
+                .map(|analysis| mix_track(analysis.source.clone(), analysis.track_id.clone(), None, None, Some(analysis)))
+                .collect());
+        }
+        _ => return Err(bad_request("from must be saved, playlist or library")),
+    };
+
+    let keys: Vec<_> = tracks.iter().map(|(source, track_id, ..)| (source.clone(), track_id.clone())).collect();
+    let analyses = TrackAnalysisStore::find_many(state.db(), &keys).await.map_err(database_error)?;
+    Ok(tracks
+        .into_iter()
+        .map(|(source, track_id, title, artist)| {
+            let analysis = analyses.get(&(source.clone(), track_id.clone()));
+            mix_track(source, track_id, title, artist, analysis)
+        })
+        .collect())
+}
+
+/// Tracks that mix well after a track, ranked by Camelot compatibility and BPM proximity
+pub async fn get_next_track_suggestions(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Query(query): Query<NextTracksQuery>,
+) -> Result<Json<ApiResponse<NextTracksResponse>>, ApiError> {
+    let pitch_tolerance = query.pitch_tolerance.unwrap_or(DEFAULT_PITCH_TOLERANCE);
+    if !(0.0..=50.0).contains(&pitch_tolerance) {
+        return Err(bad_request("pitch_tolerance must be between 0 and 50 percent"));
+    }
+
+    let analysis = TrackAnalysisStore::find(state.db(), &query.source, &query.track_id)
+        .await
+        .map_err(database_error)?
+        .filter(|analysis| analysis.bpm.is_some() && analysis.camelot.is_some())
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error("No BPM and key for this track, analyze it first".to_string())),
+            )
+        })?;
+    let current = mix_track(query.source.clone(), query.track_id.clone(), None, None, Some(&analysis));
+
+    let candidates = load_candidates(&state, &user, &query).await?;
+    let options = MixOptions { pitch_tolerance, key_lock: query.key_lock.unwrap_or(true) };
+    let limit = query.limit.unwrap_or(DEFAULT_SUGGESTIONS).clamp(1, MAX_SUGGESTIONS);
+    let suggestions = suggest_next(&current, &candidates, options, limit);
+    debug!("{} of {} candidates suggested after {} ({})", suggestions.len(), candidates.len(), query.track_id, query.source);
+
+    Ok(Json(ApiResponse::success(NextTracksResponse {
+        track_id: query.track_id,
+        source: query.source,
+        bpm: current.bpm,
+        camelot: current.camelot,
+        suggestions,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct AutoDjRequest {
+    pub curve: Option<String>, // "warm_up_peak_cool_down" (default), "rising" or "flat"
+    pub energy_curve: Option<Vec<f32>>, // Custom curve from 0.0 to 1.0 over the set, replaces `curve`
+    pub pitch_tolerance: Option<f32>, // Percent, 6 by default
+    pub key_lock: Option<bool>, // true by default
+    pub save_as: Option<String>, // Name of a new playlist to write the order to; only proposed without it
+}
+
+#[derive(Serialize)]
+pub struct AutoDjItem {
+    pub item_id: Uuid, // Playlist item of the original playlist
+    pub position: i32, // Proposed position
+    pub item_type: String,
+    pub track_id: String,
+    pub source: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub bpm: Option<f32>,
+    pub camelot: Option<String>,
+    pub energy: Option<f32>,
+    pub target_energy: Option<f32>, // On the set's energy scale, None for items left out of the set
+    pub transition: Option<SetTransition>, // From the previous track
+}
+
+#[derive(Serialize)]
+pub struct AutoDjResponse {
+    pub playlist_id: Uuid, // The new playlist when saved, the original one otherwise
+    pub saved: bool,
+    pub cost: f32,
+    pub original_cost: f32,
+    pub items: Vec<AutoDjItem>,
+}
+
+/// Propose a play order of a playlist's tracks for smooth transitions along an energy curve,
+/// optionally written to a new playlist. Nested playlists and tracks without a source keep
+/// their relative order after the set.
+pub async fn auto_dj_playlist(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(playlist_id): Path<Uuid>,
+    Json(request): Json<AutoDjRequest>,
+) -> Result<Json<ApiResponse<AutoDjResponse>>, ApiError> {
+    let curve = match (request.energy_curve, request.curve.as_deref()) {
+        (Some(points), _) if points.iter().any(|energy| !(0.0..=1.0).contains(energy)) => {
+            return Err(bad_request("energy_curve values must be between 0 and 1"));
+        }
+        (Some(points), _) => EnergyCurve::Custom(points),
+        (None, None) => EnergyCurve::WarmUpPeakCoolDown,
+        (None, Some(name)) => EnergyCurve::from_name(name)
+            .ok_or_else(|| bad_request("curve must be warm_up_peak_cool_down, rising or flat"))?,
+    };
+    let pitch_tolerance = request.pitch_tolerance.unwrap_or(DEFAULT_PITCH_TOLERANCE);
+    if !(0.0..=50.0).contains(&pitch_tolerance) {
+        return Err(bad_request("pitch_tolerance must be between 0 and 50 percent"));
+    }
+    let options = MixOptions { pitch_tolerance, key_lock: request.key_lock.unwrap_or(true) };
+
+    let playlist = PlaylistEntity::find_by_id(playlist_id)
+        .filter(crate::models::playlist::Column::UserId.eq(user.id))
+        .one(state.db())
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error("Playlist not found".to_string())),
+            )
+        })?;
+    let items = PlaylistItemEntity::find()
+        .filter(crate::models::playlist_item::Column::PlaylistId.eq(playlist_id))
+        .order_by_asc(crate::models::playlist_item::Column::Position)
+        .all(state.db())
+        .await
+        .map_err(database_error)?;
+
+    let (tracks, others): (Vec<_>, Vec<_>) = items
+        .into_iter()
+        .partition(|item| item.item_type == "track" && item.source.is_some());
+    if tracks.len() > MAX_SET_TRACKS {
+        return Err(bad_request(&format!("Auto-DJ orders at most {} tracks", MAX_SET_TRACKS)));
+    }
+    let keys: Vec<_> = tracks
+        .iter()
+        .filter_map(|item| item.source.clone().map(|source| (source, item.item_id.clone())))
+        .collect();
+    let analyses = TrackAnalysisStore::find_many(state.db(), &keys).await.map_err(database_error)?;
+    let set: Vec<MixTrack> = tracks
+        .iter()
+        .map(|item| {
+            let source = item.source.clone().unwrap_or_default();
+            let analysis = analyses.get(&(source.clone(), item.item_id.clone()));
+            mix_track(source, item.item_id.clone(), item.title.clone(), item.artist.clone(), analysis)
+        })
+        .collect();
+
+    let SetOrder { order, target_energies, transitions, cost, original_cost } = {
+        let set = set.clone();
+        tokio::task::spawn_blocking(move || order_set(&set, &curve, options)).await.map_err(|e| {
+            error!("Auto-DJ ordering failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error("Auto-DJ ordering failed".to_string())))
+        })?
+    };
+    debug!("Auto-DJ order of playlist {}: cost {:.2} (was {:.2})", playlist_id, cost, original_cost);
+
+    // The set in its new order, then the items left out of it
+    let mut transitions = transitions.into_iter();
+    let ordered: Vec<(&PlaylistItemModel, Option<&MixTrack>)> = order
+        .iter()
+        .map(|&index| (&tracks[index], Some(&set[index])))
+        .chain(others.iter().map(|item| (item, None)))
+        .collect();
+    let response_items: Vec<AutoDjItem> = ordered
+        .iter()
+        .enumerate()
+        .map(|(position, (item, track))| AutoDjItem {
+            item_id: item.id,
+            position: position as i32,
+            item_type: item.item_type.clone(),
+            track_id: item.item_id.clone(),
+            source: item.source.clone(),
+            title: item.title.clone().or_else(|| item.playlist_name.clone()),
+            artist: item.artist.clone(),
+            bpm: track.and_then(|track| track.bpm),
+            camelot: track.and_then(|track| track.camelot.clone()),
+            energy: track.and_then(|track| track.energy),
+            target_energy: track.map(|_| target_energies[position]),
+            transition: if track.is_some() && position > 0 { transitions.next() } else { None },
+        })
+        .collect();
+
+    let new_playlist = match request.save_as {
+        Some(name) => Some(save_ordered_copy(&state, &playlist, name, &ordered).await?),
+        None => None,
+    };
+    Ok(Json(ApiResponse::success(AutoDjResponse {
+        playlist_id: new_playlist.unwrap_or(playlist_id),
+        saved: new_playlist.is_some(),
+        cost,
+        original_cost,
+        items: response_items,
+    })))
+}
+
+// Write items in order to a new playlist of the same user
+async fn save_ordered_copy(
+    state: &AppState,
+    playlist: &PlaylistModel,
+    name: String,
+    ordered: &[(&PlaylistItemModel, Option<&MixTrack>)],
+) -> Result<Uuid, ApiError> {
+    // All or nothing, a failure half way must not leave a partial playlist behind
+    let txn = state.db().begin().await.map_err(database_error)?;
+    let now = chrono::Utc::now().naive_utc();
+    let copy = crate::models::playlist::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(playlist.user_id),
+        name: Set(name),
+        description: Set(Some(format!("Auto-DJ order of {}", playlist.name))),
+        is_public: Set(false),
+        created_at: Set(now),
+        updated_at: Set(now),
+    }
+    .insert(&txn)
+    .await
+    .map_err(database_error)?;
+
+    for (position, (item, _)) in ordered.iter().enumerate() {
+        crate::models::playlist_item::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            playlist_id: Set(copy.id),
+            item_type: Set(item.item_type.clone()),
+            item_id: Set(item.item_id.clone()),
+            position: Set(position as i32),
+            added_at: Set(now),
+            title: Set(item.title.clone()),
+            artist: Set(item.artist.clone()),
+            album: Set(item.album.clone()),
+            duration: Set(item.duration),
+            source: Set(item.source.clone()),
+            cover_url: Set(item.cover_url.clone()),
+            playlist_name: Set(item.playlist_name.clone()),
+        }
+        .insert(&txn)
+        .await
+        .map_err(database_error)?;
+    }
+    txn.commit().await.map_err(database_error)?;
+    debug!("Saved the auto-DJ order of playlist {} as playlist {}", playlist.id, copy.id);
+    Ok(copy.id)
+}
+
+#[derive(Deserialize)]
+pub struct MixExportRequest {
+    pub name: Option<String>,
+    pub playlist_id: Option<Uuid>, // The user's queue without it
+    pub format: Option<String>, // "flac" (default), "mp3", "opus" or "wav"
+    pub crossfade_secs: Option<f32>, // 8 by default, 0 for gapless
+    pub beat_match: Option<bool>, // true by default
+    pub max_tempo_change: Option<f32>, // Percent, 8 by default
+    pub normalize: Option<bool>, // true by default
+    pub target_lufs: Option<f32>, // -14 by default
+}
+
+fn export_not_found() -> ApiError {
+    (StatusCode::NOT_FOUND, Json(ApiResponse::<()>::error("Mix export not found".to_string())))
+}
+
+/// Render the user's queue or one of their playlists into a single mix file in the background
+pub async fn create_mix_export(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Json(request): Json<MixExportRequest>,
+) -> Result<Json<ApiResponse<MixExport>>, ApiError> {
+    let format = MixFormat::from_name(request.format.as_deref().unwrap_or("flac"))
+        .ok_or_else(|| bad_request("format must be flac, mp3, opus or wav"))?;
+    let defaults = MixSettings::default();
+    let settings = MixSettings {
+        crossfade_secs: request.crossfade_secs.unwrap_or(defaults.crossfade_secs),
+        beat_match: request.beat_match.unwrap_or(defaults.beat_match),
+        max_tempo_change: request.max_tempo_change.unwrap_or(defaults.max_tempo_change),
+        normalize: request.normalize.unwrap_or(defaults.normalize),
+        target_lufs: request.target_lufs.unwrap_or(defaults.target_lufs),
+    };
+    if !(0.0..=60.0).contains(&settings.crossfade_secs) {
+        return Err(bad_request("crossfade_secs must be between 0 and 60"));
+    }
+    if !(0.0..=50.0).contains(&settings.max_tempo_change) {
+        return Err(bad_request("max_tempo_change must be between 0 and 50 percent"));
+    }
+    if !(-40.0..=0.0).contains(&settings.target_lufs) {
+        return Err(bad_request("target_lufs must be between -40 and 0"));
+    }
+
+    let (default_name, tracks): (String, Vec<MixSourceTrack>) = match request.playlist_id {
+        Some(playlist_id) => {
+            let playlist = PlaylistEntity::find_by_id(playlist_id)
+                .filter(crate::models::playlist::Column::UserId.eq(user.id))
+                .one(state.db())
+                .await
+                .map_err(database_error)?
+                .ok_or_else(|| {
+                    (
+                        StatusCode::NOT_FOUND,
+                        Json(ApiResponse::<()>::error("Playlist not found".to_string())),
+                    )
+                })?;
+            let items = PlaylistItemEntity::find()
+                .filter(crate::models::playlist_item::Column::PlaylistId.eq(playlist_id))
+                .filter(crate::models::playlist_item::Column::ItemType.eq("track"))
+                .order_by_asc(crate::models::playlist_item::Column::Position)
+                .all(state.db())
+                .await
+                .map_err(database_error)?;
+            let tracks = items
+                .into_iter()
+                .filter_map(|item| {
+                    Some(MixSourceTrack { source: item.source?, track_id: item.item_id, title: item.title, artist: item.artist })
+                })
+                .collect();
+            (playlist.name, tracks)
+        }
+        None => {
+            let items = QueueItemEntity::find()
+                .filter(crate::models::queue_item::Column::UserId.eq(user.id))
+                .order_by_asc(crate::models::queue_item::Column::Position)
+                .all(state.db())
+                .await
+                .map_err(database_error)?;
+            let tracks = items
+                .into_iter()
+                .map(|item| MixSourceTrack {
+                    source: item.source,
+                    track_id: item.track_id,
+                    title: Some(item.title),
+                    artist: Some(item.artist),
+                })
+                .collect();
+            ("Queue".to_string(), tracks)
+        }
+    };
+    if tracks.is_empty() {
+        return Err(bad_request("No tracks to mix"));
+    }
+    if tracks.len() > MAX_MIX_TRACKS {
+        return Err(bad_request(&format!("Mixes are limited to {} tracks", MAX_MIX_TRACKS)));
+    }
+
+    debug!("Rendering a mix of {} tracks for user {}", tracks.len(), user.id);
+    let name = request.name.filter(|name| !name.trim().is_empty()).unwrap_or(default_name);
+    let export = state.mix_exports.export(user.id, name, tracks, settings, format).ok_or_else(|| {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ApiResponse::<()>::error("Wait for your other mix to finish rendering".to_string())),
+        )
+    })?;
+    Ok(Json(ApiResponse::success(export)))
+}
+
+pub async fn list_mix_exports(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+) -> Json<ApiResponse<Vec<MixExport>>> {
+    Json(ApiResponse::success(state.mix_exports.list(user.id)))
+}
+
+/// Status of a mix export, with its cues once rendered
+pub async fn get_mix_export(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(export_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<MixExport>>, ApiError> {
+    let export = state.mix_exports.get(user.id, export_id).ok_or_else(export_not_found)?;
+    Ok(Json(ApiResponse::success(export)))
+}
+
+pub async fn delete_mix_export(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(export_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<bool>>, ApiError> {
+    if !state.mix_exports.remove(user.id, export_id) {
+        return Err(export_not_found());
+    }
+    Ok(Json(ApiResponse::success(true)))
+}
+
+// A completed export, or a conflict while it is still rendering
+fn completed_export(state: &AppState, user: &UserResponseDto, export_id: Uuid) -> Result<MixExport, ApiError> {
+    let export = state.mix_exports.get(user.id, export_id).ok_or_else(export_not_found)?;
+    match export.status {
+        MixExportStatus::Completed => Ok(export),
+        MixExportStatus::Failed => Err((
+            StatusCode::CONFLICT,
+            Json(ApiResponse::<()>::error(format!("Mix export failed: {}", export.error.unwrap_or_default()))),
+        )),
+        _ => Err((StatusCode::CONFLICT, Json(ApiResponse::<()>::error("Mix export is not finished yet".to_string())))),
+    }
+}
+
+/// Download the rendered mix
+pub async fn download_mix_export(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(export_id): Path<Uuid>,
+) -> Result<Response, ApiError> {
+    let export = completed_export(&state, &user, export_id)?;
+    let path = state.mix_exports.file_path(export.id, export.format);
+    let file = tokio::fs::File::open(&path).await.map_err(|e| {
+        error!("Cannot open mix file {:?}: {}", path, e);
+        export_not_found()
+    })?;
+    let length = file.metadata().await.map(|metadata| metadata.len()).unwrap_or(0);
+
+    // Streamed in chunks, mixes can be large
+    let chunks = futures_util::stream::unfold(file, |mut file| async move {
+        use tokio::io::AsyncReadExt;
+        let mut buffer = vec![0u8; 256 * 1024];
+        match file.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((Ok::<_, std::io::Error>(buffer), file))
+            }
+            Err(e) => Some((Err(e), file)),
+        }
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, export.format.content_type().to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", MixExportService::file_name(&export)),
+            ),
+        ],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+/// CUE sheet with the track boundaries of a rendered mix
+pub async fn get_mix_cue_sheet(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(export_id): Path<Uuid>,
+) -> Result<Response, ApiError> {
+    let export = completed_export(&state, &user, export_id)?;
+    Ok(([(header::CONTENT_TYPE, "application/x-cue; charset=utf-8")], MixExportService::cue_sheet(&export)).into_response())
+}
@@ -6,6 +6,10 @@ pub mod saved_albums;
 pub mod queue;
 pub mod playlist;
 pub mod audio_analysis;
+pub mod analysis_jobs;
+pub mod errors;
+pub mod mixing;
+pub mod samples;
 
 pub use auth::*;
 pub use music::*;
@@ -15,3 +19,6 @@ pub use saved_albums::*;
 pub use queue::*;
 pub use playlist::*;
 pub use audio_analysis::*;
+pub use analysis_jobs::*;
+pub use mixing::*;
+pub use samples::*;
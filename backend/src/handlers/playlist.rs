@@ -16,8 +16,9 @@ use uuid::Uuid;
 use crate::handlers::auth::{AppState, ApiResponse};
 use crate::models::{
     PlaylistEntity, PlaylistItemEntity, PlaylistResponseDto, CreatePlaylistDto, UpdatePlaylistDto,
-    PlaylistItemResponseDto, AddPlaylistItemDto, ReorderPlaylistItemDto, UserResponseDto,
+    PlaylistItemResponseDto, AddPlaylistItemDto, ReorderPlaylistItemDto, UserResponseDto, TrackAnalysisDto,
 };
+use crate::services::TrackAnalysisStore;
 
 #[derive(Deserialize)]
 pub struct GetPlaylistsQuery {
@@ -262,7 +263,29 @@ pub async fn get_playlist_items(
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    let response_items: Vec<PlaylistItemResponseDto> = items.into_iter().map(|item| item.into()).collect();
+    // Attach shared analysis results to the track items
+    let keys: Vec<_> = items
+        .iter()
+        .filter(|item| item.item_type == "track")
+        .filter_map(|item| item.source.clone().map(|source| (source, item.item_id.clone())))
+        .collect();
+    let analyses = TrackAnalysisStore::find_many(&state.auth_service.db, &keys)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let response_items: Vec<PlaylistItemResponseDto> = items
+        .into_iter()
+        .map(|item| {
+            let analysis = item.source.clone()
+                .and_then(|source| analyses.get(&(source, item.item_id.clone())))
+                .map(TrackAnalysisDto::from)
+                .unwrap_or_default();
+            PlaylistItemResponseDto { analysis, ..item.into() }
+        })
+        .collect();
 
     Ok(Json(ApiResponse::success(response_items)))
 }
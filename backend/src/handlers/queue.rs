@@ -10,7 +10,8 @@ use uuid::Uuid;
 
 use crate::{
     handlers::auth::{AppState, ApiResponse},
-    models::{QueueItemEntity, AddToQueueDto, ReorderQueueDto, QueueItemResponseDto, UserResponseDto},
+    models::{QueueItemEntity, AddToQueueDto, ReorderQueueDto, QueueItemResponseDto, UserResponseDto, TrackAnalysisDto},
+    services::TrackAnalysisStore,
 };
 
 pub async fn get_queue(
@@ -29,9 +30,24 @@ pub async fn get_queue(
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
+    // Attach shared analysis results
+    let keys: Vec<_> = queue_items.iter().map(|item| (item.source.clone(), item.track_id.clone())).collect();
+    let analyses = TrackAnalysisStore::find_many(&state.auth_service.db, &keys)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
     let response_dtos: Vec<QueueItemResponseDto> = queue_items
         .into_iter()
-        .map(|item| item.into())
+        .map(|item| {
+            let analysis = analyses
+                .get(&(item.source.clone(), item.track_id.clone()))
+                .map(TrackAnalysisDto::from)
+                .unwrap_or_default();
+            QueueItemResponseDto { analysis, ..item.into() }
+        })
         .collect();
 
     Ok(Json(ApiResponse::success(response_dtos)))
@@ -0,0 +1,129 @@
+use axum::{
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+    Extension,
+};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use crate::handlers::auth::{AppState, ApiResponse};
+use crate::handlers::errors::{bad_request, database_error, ApiError};
+use crate::models::{CreateSampleDto, SampleResponseDto, UserResponseDto};
+use crate::services::loop_extraction::MAX_LOOP_BARS;
+use crate::services::mix_export::MixFormat;
+use crate::services::sample_library::{LoopRequest, SampleError, SampleLibraryService};
+
+const DEFAULT_LOOP_BARS: u32 = 4;
+const MAX_FADE_MS: u32 = 2000;
+
+fn sample_not_found() -> ApiError {
+    (StatusCode::NOT_FOUND, Json(ApiResponse::<()>::error("Sample not found".to_string())))
+}
+
+/// Cut a loop from a track, snapped to its beat grid, into the user's samples
+pub async fn create_sample(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Json(request): Json<CreateSampleDto>,
+) -> Result<Json<ApiResponse<SampleResponseDto>>, ApiError> {
+    let format = match request.format.as_deref().unwrap_or("wav") {
+        "wav" => MixFormat::Wav,
+        "flac" => MixFormat::Flac,
+        _ => return Err(bad_request("format must be wav or flac")),
+    };
+    let bars = request.bars.unwrap_or(DEFAULT_LOOP_BARS);
+    if !(1..=MAX_LOOP_BARS).contains(&bars) {
+        return Err(bad_request(&format!("bars must be between 1 and {}", MAX_LOOP_BARS)));
+    }
+    let fade_ms = request.fade_ms.unwrap_or(0);
+    if fade_ms > MAX_FADE_MS {
+        return Err(bad_request(&format!("fade_ms must be at most {}", MAX_FADE_MS)));
+    }
+
+    debug!("Cutting a {}-bar loop from beat {} of track {} ({})", bars, request.start_beat, request.track_id, request.source);
+    let loop_request = LoopRequest {
+        source: request.source,
+        track_id: request.track_id,
+        title: request.title,
+        artist: request.artist,
+        name: request.name.filter(|name| !name.trim().is_empty()),
+        start_beat: request.start_beat,
+        bars,
+        snap_to_bar: request.snap_to_bar.unwrap_or(false),
+        fade_ms,
+        format,
+    };
+    match state.samples.extract(user.id, loop_request).await {
+        Ok(sample) => Ok(Json(ApiResponse::success(sample.into()))),
+        Err(SampleError::Invalid(message)) => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ApiResponse::<()>::error(message)),
+        )),
+        Err(e @ SampleError::NotAnalyzed) => Err((StatusCode::CONFLICT, Json(ApiResponse::<()>::error(e.to_string())))),
+        Err(SampleError::Failed(message)) => {
+            error!("Failed to cut a loop for user {}: {}", user.id, message);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(format!("Failed to cut the loop: {}", message))),
+            ))
+        }
+    }
+}
+
+/// The user's samples, newest first
+pub async fn list_samples(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+) -> Result<Json<ApiResponse<Vec<SampleResponseDto>>>, ApiError> {
+    let samples = state.samples.list(user.id).await.map_err(database_error)?;
+    Ok(Json(ApiResponse::success(samples.into_iter().map(SampleResponseDto::from).collect())))
+}
+
+pub async fn get_sample(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(sample_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<SampleResponseDto>>, ApiError> {
+    let sample = state.samples.get(user.id, sample_id).await.map_err(database_error)?.ok_or_else(sample_not_found)?;
+    Ok(Json(ApiResponse::success(sample.into())))
+}
+
+/// Remove a sample and its file
+pub async fn delete_sample(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(sample_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<()>>, ApiError> {
+    if !state.samples.remove(user.id, sample_id).await.map_err(database_error)? {
+        return Err(sample_not_found());
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// Download the audio file of a sample
+pub async fn download_sample(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(sample_id): Path<Uuid>,
+) -> Result<Response, ApiError> {
+    let sample = state.samples.get(user.id, sample_id).await.map_err(database_error)?.ok_or_else(sample_not_found)?;
+    let path = state.samples.file_path(&sample);
+    // Loops are at most a few minutes, small enough to send in one piece
+    let content = tokio::fs::read(&path).await.map_err(|e| {
+        error!("Cannot read sample file {:?}: {}", path, e);
+        sample_not_found()
+    })?;
+    let content_type = MixFormat::from_name(&sample.format).unwrap_or(MixFormat::Wav).content_type();
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", SampleLibraryService::file_name(&sample)),
+            ),
+        ],
+        content,
+    )
+        .into_response())
+}
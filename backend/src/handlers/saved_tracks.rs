@@ -11,7 +11,8 @@ use uuid::Uuid;
 
 use crate::{
     handlers::auth::{AppState, ApiResponse},
-    models::{SavedTrackEntity, UserResponseDto},
+    models::{SavedTrackEntity, UserResponseDto, TrackAnalysisDto},
+    services::TrackAnalysisStore,
 };
 
 #[derive(Deserialize, Debug)]
@@ -35,7 +36,8 @@ pub struct SavedTrackResponse {
     pub duration: i32,
     pub source: String,
     pub cover_url: Option<String>,
-    pub bpm: Option<f32>,
+    #[serde(flatten)]
+    pub analysis: TrackAnalysisDto, // Shared analysis results of the track, if any
     pub created_at: chrono::NaiveDateTime,
 }
 
@@ -98,10 +100,6 @@ pub async fn save_track(
         duration: Set(request.duration),
         source: Set(request.source.clone()),
         cover_url: Set(request.cover_url.clone()),
-        bpm: Set(None), // BPM not available when saving track initially
-        key_name: Set(None), // Key not available when saving track initially
-        camelot: Set(None), // Camelot not available when saving track initially
-        key_confidence: Set(None), // Key confidence not available when saving track initially
         created_at: Set(chrono::Utc::now().naive_utc()),
     };
     debug!("SavedTrackActiveModel created successfully");
@@ -114,6 +112,14 @@ pub async fn save_track(
 
     debug!("Successfully inserted saved track with ID: {:?}", result.id);
 
+    // The track may have been analyzed before, by any user
+    let analysis = TrackAnalysisStore::find(state.db(), &result.source, &result.track_id)
+        .await
+        .map_err(|e| {
+            error!("Error loading track analysis: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
     let response = SavedTrackResponse {
         id: result.id,
         track_id: result.track_id,
@@ -123,7 +129,7 @@ pub async fn save_track(
         duration: result.duration,
         source: result.source,
         cover_url: result.cover_url,
-        bpm: result.bpm,
+        analysis: analysis.as_ref().map(TrackAnalysisDto::from).unwrap_or_default(),
         created_at: result.created_at,
     };
 
@@ -160,9 +166,18 @@ pub async fn get_saved_tracks(
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
+    let keys: Vec<_> = saved_tracks.iter().map(|track| (track.source.clone(), track.track_id.clone())).collect();
+    let analyses = TrackAnalysisStore::find_many(state.db(), &keys)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
     let tracks: Vec<SavedTrackResponse> = saved_tracks
         .into_iter()
         .map(|track| SavedTrackResponse {
+            analysis: analyses
+                .get(&(track.source.clone(), track.track_id.clone()))
+                .map(TrackAnalysisDto::from)
+                .unwrap_or_default(),
             id: track.id,
             track_id: track.track_id,
             title: track.title,
@@ -171,7 +186,6 @@ pub async fn get_saved_tracks(
             duration: track.duration,
             source: track.source,
             cover_url: track.cover_url,
-            bpm: track.bpm,
             created_at: track.created_at,
         })
         .collect();
@@ -1,28 +1,63 @@
 use axum::{
-    extract::{State, Query, Extension, Path},
+    extract::{State, Query, Extension, Path, RawQuery},
     http::{StatusCode, HeaderMap, header},
     response::{Json, Html, Response},
 };
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use sea_orm::{EntityTrait, Set, ActiveModelTrait, ColumnTrait, QueryFilter};
-
-use crate::services::streaming::{QobuzService, SpotifyService, LocalMusicService, StreamingService, SearchResults, StreamingTrack};
-use crate::services::streaming_service::StreamingService as BackendStreamingService;
-use crate::models::{UserResponseDto, SearchQuery, StreamingServiceEntity, StreamingServiceActiveModel, StreamingServiceColumn}; 
+use std::collections::{HashMap, HashSet};
+use sea_orm::{EntityTrait, Set, ActiveModelTrait, ColumnTrait, QueryFilter, TransactionTrait};
+
+use crate::services::streaming::{QobuzService, SpotifyService, LocalMusicService, StreamingService, SearchResults, StreamingTrack, StreamingAlbum, StreamingPlaylist};
+use crate::services::search_query::{TrackQuery, TrackAnalysisInfo, FilterSubject};
+use crate::services::track_analysis_store::TrackAnalysisStore;
+use crate::services::search_ranking::{SearchRankingService, SearchCursor, ProviderResults};
+use crate::services::streaming_service::{serve_file, StreamingService as BackendStreamingService};
+use crate::services::harmonic_mixing::Camelot;
+use crate::services::mix_render::MIX_SAMPLE_RATE;
+use crate::services::audio_decode::{AnalysisSpan, AudioSink, AudioStream, StreamResampler};
+use crate::services::time_stretch::{StretchSettings, Stretcher, MAX_SEMITONES, MAX_TEMPO, MIN_TEMPO};
+use crate::services::track_matching::{TrackMatchingService, TrackMatch, MatchMethod};
+use crate::models::{UserResponseDto, SearchQuery, StreamingServiceEntity, StreamingServiceActiveModel, StreamingServiceColumn, PlaylistResponseDto, SearchPreferenceEntity, SearchPreferenceActiveModel, SearchPreferenceColumn, SearchPreferencesDto, AnalysisTrackDto, LoudnessDto}; 
+use crate::models::analysis_job::JOB_KIND_LOUDNESS;
 use crate::handlers::auth::{AppState, ApiResponse};
 use std::sync::Arc;
 
+const SEARCH_PROVIDER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(8);
+const DEFAULT_SOURCE_ORDER: [&str; 3] = ["server", "qobuz", "spotify"];
+
 #[derive(Deserialize)]
 pub struct StreamingSearchQuery {
     pub q: String,
     pub limit: Option<u32>,
     pub offset: Option<u32>,
     pub service: Option<String>,
-    pub services: Option<Vec<String>>, // For multi-service search
-    pub r#type: Option<String>, // "track" or "playlist"
+    pub services: Option<String>, // For multi-service search, comma-separated
+    pub r#type: Option<String>, // "track", "album" or "playlist"
     pub library: Option<String>, // "true" for library search
+    pub cursor: Option<String>, // `next_cursor` of the previous page
+    pub prefer: Option<String>, // Comma-separated source order, overrides the saved preference
+}
+
+#[derive(Serialize)]
+pub struct ServiceSearchStatus {
+    pub service: String,
+    pub total: u32,
+    pub returned: usize,
+    pub error: Option<String>,
+    pub timed_out: bool,
+}
+
+#[derive(Serialize)]
+pub struct MergedSearchResults {
+    pub tracks: Vec<StreamingTrack>,
+    pub albums: Vec<StreamingAlbum>,
+    pub playlists: Vec<StreamingPlaylist>,
+    pub total: u32, // Sum of the provider totals, before deduplication
+    pub offset: u32,
+    pub limit: u32,
+    pub next_cursor: Option<String>,
+    pub services: Vec<ServiceSearchStatus>,
 }
 
 #[derive(Deserialize)]
@@ -60,7 +95,7 @@ fn get_streaming_service(service_name: &str) -> Result<Box<dyn StreamingService>
     }
 }
 
-async fn get_authenticated_streaming_service(
+pub(crate) async fn get_authenticated_streaming_service(
     service_name: &str, 
     user_id: uuid::Uuid, 
     db: &sea_orm::DatabaseConnection
@@ -125,7 +160,7 @@ async fn get_authenticated_streaming_service(
             let music_dir = std::env::current_dir()
                 .unwrap_or_else(|_| std::path::PathBuf::from("."))
                 .join("own_music");
-            let service = LocalMusicService::new(music_dir);
+            let service = LocalMusicService::new(music_dir).with_analysis(db.clone());
             Ok(Box::new(service))
         },
         _ => Err(format!("Unknown streaming service: {}", service_name)),
@@ -136,129 +171,350 @@ pub async fn search_music(
     State(state): State<AppState>,
     Extension(user): Extension<UserResponseDto>,
     Query(params): Query<StreamingSearchQuery>,
-) -> Result<Json<ApiResponse<SearchResults>>, (StatusCode, Json<ApiResponse<()>>)> {
-    // Determine which services to search
-    let services_to_search = if let Some(services) = &params.services {
-        if services.is_empty() {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(ApiResponse::<()>::error("No services specified for search".to_string())),
-            ));
-        }
-        services.clone()
-    } else if let Some(service) = &params.service {
-        vec![service.clone()]
-    } else {
-        vec!["qobuz".to_string()] // Default to qobuz
+    RawQuery(raw_query): RawQuery,
+) -> Result<Json<ApiResponse<MergedSearchResults>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let track_query = TrackQuery::parse(&params.q)
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(e.to_string()))))?;
+
+    // Determine which services to search; `source:` in the query overrides the selection
+    let services_to_search = match track_query.sources() {
+        Some(sources) => sources,
+        None => requested_services(&params, raw_query.as_deref()),
     };
-
-    let mut all_tracks = Vec::new();
-    let mut all_albums = Vec::new();
-    let mut all_playlists = Vec::new();
-    let mut total_results = 0;
-    let mut search_errors = Vec::new();
+    if services_to_search.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error("No services specified for search".to_string())),
+        ));
+    }
 
     // Determine search type and mode
     let search_type = params.r#type.as_deref().unwrap_or("track");
     let is_library_search = params.library.as_deref() == Some("true");
-    println!("Backend: Search type: {}, Library search: {}", search_type, is_library_search);
-    println!("Backend: Services to search: {:?}", services_to_search);
-
-    // Search each service
-    for service_name in &services_to_search {
-        match get_authenticated_streaming_service(service_name, user.id, state.db()).await {
-            Ok(service) => {
-                if is_library_search {
-                    // Library search
-                    println!("Backend: Searching library on {} for query: {} with type: {}", service_name, params.q, search_type);
-                    match service.search_library(&params.q, Some(search_type), params.limit, params.offset).await {
-                        Ok(results) => {
-                            all_tracks.extend(results.tracks);
-                            all_albums.extend(results.albums);
-                            all_playlists.extend(results.playlists);
-                            total_results += results.total;
-                        },
-                        Err(err) => {
-                            println!("Backend: Error searching library on {}: {}", service_name, err);
-                            search_errors.push(format!("{}: {}", service_name, err));
-                        }
-                    }
-                } else if search_type == "playlist" {
-                    // Search for playlists
-                    println!("Backend: Searching playlists on {} for query: {}", service_name, params.q);
-                    match service.search_playlists(&params.q, params.limit, params.offset).await {
-                        Ok(playlists) => {
-                            let playlist_count = playlists.len() as u32;
-                            println!("Backend: Found {} playlists on {}", playlist_count, service_name);
-                            all_playlists.extend(playlists);
-                            total_results += playlist_count;
-                        },
-                        Err(err) => {
-                            println!("Backend: Error searching playlists on {}: {}", service_name, err);
-                            search_errors.push(format!("{}: {}", service_name, err));
-                        }
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    debug!("Search type: {}, library search: {}, services: {:?}", search_type, is_library_search, services_to_search);
+
+    // Resume from the cursor if one was given, otherwise start every provider at `offset`
+    let fingerprint = SearchCursor::fingerprint_for(&params.q, &format!("{}:{}", search_type, is_library_search), &services_to_search);
+    let cursor = match params.cursor.as_deref() {
+        Some(encoded) => {
+            let cursor = SearchCursor::decode(encoded)
+                .map_err(|e| (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(e.to_string()))))?;
+            if cursor.fingerprint != fingerprint {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::<()>::error("Search cursor does not belong to this query".to_string())),
+                ));
+            }
+            cursor
+        }
+        None => SearchCursor {
+            fingerprint: fingerprint.clone(),
+            offsets: services_to_search.iter().map(|s| (s.clone(), params.offset.unwrap_or(0))).collect(),
+            exhausted: Default::default(),
+        },
+    };
+
+    let preferred_sources = preferred_source_order(&state, user.id, params.prefer.as_deref()).await;
+
+    // Query all providers concurrently, each with its own timeout
+    let filters_only = track_query.free_text.trim().is_empty() && !track_query.filters.is_empty();
+    let fetches = services_to_search
+        .iter()
+        .filter(|service_name| !cursor.exhausted.contains(*service_name))
+        .map(|service_name| {
+            let offset = cursor.offset_for(service_name);
+            // The local index evaluates filters itself; remote providers only understand free text
+            let query = if service_name == "server" { params.q.as_str() } else { track_query.free_text.as_str() };
+            let state = &state;
+            async move {
+                let fetch = async {
+                    if filters_only && service_name != "server" {
+                        return Err(format!("{} needs some free text besides field filters", service_name));
                     }
-                } else {
-                    // Search for tracks and albums
-                    match service.search(&params.q, params.limit, params.offset).await {
-                        Ok(results) => {
-                            all_tracks.extend(results.tracks);
-                            all_albums.extend(results.albums);
-                            all_playlists.extend(results.playlists);
-                            total_results += results.total;
-                        },
-                        Err(err) => {
-                            search_errors.push(format!("{}: {}", service_name, err));
-                        }
+                    let service = get_authenticated_streaming_service(service_name, user.id, state.db()).await?;
+                    if is_library_search {
+                        service.search_library(query, Some(search_type), Some(limit), Some(offset)).await
+                    } else if search_type == "playlist" {
+                        service.search_playlists(query, Some(limit), Some(offset)).await.map(|playlists| SearchResults {
+                            total: playlists.len() as u32,
+                            tracks: vec![],
+                            albums: vec![],
+                            playlists,
+                            offset,
+                            limit,
+                        })
+                    } else {
+                        service.search(query, Some(limit), Some(offset)).await
                     }
-                }
-            },
-            Err(err) => {
-                search_errors.push(format!("{}: {}", service_name, err));
+                    .map_err(|e| e.to_string())
+                };
+                (service_name.clone(), tokio::time::timeout(SEARCH_PROVIDER_TIMEOUT, fetch).await)
+            }
+        });
+    let outcomes = futures_util::future::join_all(fetches).await;
+
+    // Shared analysis results of the returned tracks, for the analysis-based filters
+    let analysis: HashMap<(String, String), TrackAnalysisInfo> = if track_query.needs_analysis() {
+        let keys: Vec<_> = outcomes
+            .iter()
+            .filter_map(|(_, outcome)| outcome.as_ref().ok()?.as_ref().ok())
+            .flat_map(|results| results.tracks.iter().map(|track| (track.source.clone(), track.id.clone())))
+            .collect();
+        TrackAnalysisStore::find_many(state.db(), &keys)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(format!("Database error: {}", e)))))?
+            .into_iter()
+            .map(|(key, analysis)| (key, TrackAnalysisInfo::from(&analysis)))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let mut statuses = Vec::new();
+    let mut track_lists = Vec::new();
+    let mut album_lists = Vec::new();
+    let mut playlist_lists = Vec::new();
+    let mut returned_counts = HashMap::new();
+    let mut kept_positions = HashMap::new();
+    for (service_name, outcome) in outcomes {
+        match outcome {
+            Ok(Ok(results)) => {
+                let returned = match search_type {
+                    "album" => results.albums.len(),
+                    "playlist" => results.playlists.len(),
+                    _ => results.tracks.len(),
+                };
+                returned_counts.insert(service_name.clone(), returned);
+                statuses.push(ServiceSearchStatus { service: service_name.clone(), total: results.total, returned, error: None, timed_out: false });
+
+                // Evaluate the field filters against what the provider returned
+                let (tracks, track_positions) = filter_provider_results(results.tracks, |track| {
+                    track_query.matches(&FilterSubject {
+                        title: Some(&track.title),
+                        artist: &track.artist,
+                        album: &track.album,
+                        year: None,
+                        source: &track.source,
+                        analysis: analysis.get(&(track.source.clone(), track.id.clone())),
+                    })
+                });
+                let (albums, album_positions) = filter_provider_results(results.albums, |album| {
+                    track_query.matches(&FilterSubject {
+                        title: None,
+                        artist: &album.artist,
+                        album: &album.title,
+                        year: album.release_date.as_deref().and_then(|date| date.get(..4)).and_then(|year| year.parse().ok()),
+                        source: &album.source,
+                        analysis: None,
+                    })
+                });
+                let (playlists, playlist_positions) =
+                    filter_provider_results(results.playlists, |_| !track_query.has_result_filters());
+                let positions = match search_type {
+                    "album" => album_positions,
+                    "playlist" => playlist_positions,
+                    _ => track_positions,
+                };
+                kept_positions.insert(service_name.clone(), positions);
+
+                track_lists.push(ProviderResults { service: service_name.clone(), items: tracks });
+                album_lists.push(ProviderResults { service: service_name.clone(), items: albums });
+                playlist_lists.push(ProviderResults { service: service_name, items: playlists });
+            }
+            Ok(Err(err)) => {
+                error!("Search failed on {}: {}", service_name, err);
+                statuses.push(ServiceSearchStatus { service: service_name, total: 0, returned: 0, error: Some(err), timed_out: false });
+            }
+            Err(_) => {
+                error!("Search timed out on {}", service_name);
+                statuses.push(ServiceSearchStatus {
+                    service: service_name,
+                    total: 0,
+                    returned: 0,
+                    error: Some(format!("Timed out after {}s", SEARCH_PROVIDER_TIMEOUT.as_secs())),
+                    timed_out: true,
+                });
             }
         }
     }
 
     // If all services failed, return an error
-    if all_tracks.is_empty() && all_albums.is_empty() && all_playlists.is_empty() && !search_errors.is_empty() {
+    if returned_counts.is_empty() && statuses.iter().any(|s| s.error.is_some()) {
+        let errors: Vec<String> = statuses.iter()
+            .filter_map(|s| s.error.as_ref().map(|e| format!("{}: {}", s.service, e)))
+            .collect();
         return Err((
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::<()>::error(format!("Search failed on all services: {}", search_errors.join(", ")))),
+            Json(ApiResponse::<()>::error(format!("Search failed on all services: {}", errors.join(", ")))),
         ));
     }
 
-    // Sort tracks by relevance (you could implement more sophisticated sorting)
-    all_tracks.sort_by(|a, b| a.title.cmp(&b.title));
-    
-    // Limit results if needed
-    let limit = params.limit.unwrap_or(20) as usize;
-    if all_tracks.len() > limit {
-        all_tracks.truncate(limit);
-    }
-    if all_playlists.len() > limit {
-        all_playlists.truncate(limit);
+    // Merge the paginated kind with cursor bookkeeping; the other kinds are merged per page only
+    let ranking = SearchRankingService::new();
+    let query = track_query.free_text.as_str();
+    let (tracks, albums, playlists, consumed) = match search_type {
+        "album" => {
+            let page = ranking.merge(query, album_lists, &preferred_sources, limit as usize);
+            (vec![], page.items, vec![], page.consumed)
+        }
+        "playlist" => {
+            let page = ranking.merge(query, playlist_lists, &preferred_sources, limit as usize);
+            (vec![], vec![], page.items, page.consumed)
+        }
+        _ => {
+            let page = ranking.merge(query, track_lists, &preferred_sources, limit as usize);
+            let albums = ranking.merge(query, album_lists, &preferred_sources, limit as usize).items;
+            let playlists = ranking.merge(query, playlist_lists, &preferred_sources, limit as usize).items;
+            (page.items, albums, playlists, page.consumed)
+        }
+    };
+
+    let mut next_cursor = SearchCursor { fingerprint, offsets: cursor.offsets.clone(), exhausted: cursor.exhausted.clone() };
+    for (service_name, returned) in &returned_counts {
+        let used = provider_advance(
+            kept_positions.get(service_name).map(Vec::as_slice).unwrap_or_default(),
+            *returned,
+            consumed.get(service_name).copied().unwrap_or(0),
+        );
+        next_cursor.offsets.insert(service_name.clone(), cursor.offset_for(service_name) + used as u32);
+        if *returned < limit as usize && used == *returned {
+            next_cursor.exhausted.insert(service_name.clone());
+        }
     }
+    let page_len = match search_type {
+        "album" => albums.len(),
+        "playlist" => playlists.len(),
+        _ => tracks.len(),
+    };
+    let has_more = page_len > 0 && services_to_search.iter().any(|s| !next_cursor.exhausted.contains(s));
 
-    let combined_results = SearchResults {
-        tracks: all_tracks,
-        albums: all_albums,
-        playlists: all_playlists.clone(),
-        total: total_results,
+    let combined_results = MergedSearchResults {
+        tracks,
+        albums,
+        playlists,
+        total: statuses.iter().map(|s| s.total).sum(),
         offset: params.offset.unwrap_or(0),
-        limit: params.limit.unwrap_or(20),
+        limit,
+        next_cursor: has_more.then(|| next_cursor.encode()),
+        services: statuses,
     };
 
-    println!("Backend: Returning search results - {} tracks, {} albums, {} playlists", 
-             combined_results.tracks.len(), combined_results.albums.len(), combined_results.playlists.len());
-    
-    // Debug: Print first playlist if any
-    if !all_playlists.is_empty() {
-        println!("Backend: First playlist: {:?}", &all_playlists[0]);
-    }
+    debug!("Returning search results - {} tracks, {} albums, {} playlists",
+           combined_results.tracks.len(), combined_results.albums.len(), combined_results.playlists.len());
 
     Ok(Json(ApiResponse::success(combined_results)))
 }
 
+// Drop results rejected by the query's filters, remembering the provider position of each kept one
+fn filter_provider_results<T>(items: Vec<T>, keep: impl Fn(&T) -> bool) -> (Vec<T>, Vec<usize>) {
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(_, item)| keep(item))
+        .map(|(position, item)| (item, position))
+        .unzip()
+}
+
+// How far a provider's offset moves when `consumed` of its kept results made it onto the page:
+// up to the first kept result left over, or past everything it returned
+fn provider_advance(kept_positions: &[usize], returned: usize, consumed: usize) -> usize {
+    kept_positions.get(consumed).copied().unwrap_or(returned)
+}
+
+// Services from `services=a,b`, `services[0]=a&services[1]=b` or `service=a`, defaulting to qobuz
+fn requested_services(params: &StreamingSearchQuery, raw_query: Option<&str>) -> Vec<String> {
+    let mut services: Vec<String> = params.services.as_deref()
+        .map(|list| list.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    if let Some(raw_query) = raw_query {
+        for (key, value) in url::form_urlencoded::parse(raw_query.as_bytes()) {
+            if key.starts_with("services[") && !value.trim().is_empty() {
+                services.push(value.trim().to_string());
+            }
+        }
+    }
+
+    if services.is_empty() {
+        services.push(params.service.clone().unwrap_or_else(|| "qobuz".to_string()));
+    }
+    // A service may be named more than once, and not necessarily in a row
+    let mut seen = HashSet::new();
+    services.retain(|service| seen.insert(service.clone()));
+    services
+}
+
+// Source order used to break ties and pick between duplicates: query override, saved preference, default
+async fn preferred_source_order(state: &AppState, user_id: uuid::Uuid, override_order: Option<&str>) -> Vec<String> {
+    if let Some(order) = override_order {
+        return order.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    }
+
+    match SearchPreferenceEntity::find()
+        .filter(SearchPreferenceColumn::UserId.eq(user_id))
+        .one(state.db())
+        .await
+    {
+        Ok(Some(preference)) => preference.source_order(),
+        Ok(None) => DEFAULT_SOURCE_ORDER.iter().map(|s| s.to_string()).collect(),
+        Err(e) => {
+            error!("Failed to load search preferences: {}", e);
+            DEFAULT_SOURCE_ORDER.iter().map(|s| s.to_string()).collect()
+        }
+    }
+}
+
+pub async fn get_search_preferences(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+) -> Result<Json<ApiResponse<SearchPreferencesDto>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let preferred_sources = preferred_source_order(&state, user.id, None).await;
+    Ok(Json(ApiResponse::success(SearchPreferencesDto { preferred_sources })))
+}
+
+pub async fn update_search_preferences(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Json(request): Json<SearchPreferencesDto>,
+) -> Result<Json<ApiResponse<SearchPreferencesDto>>, (StatusCode, Json<ApiResponse<()>>)> {
+    if let Some(unknown) = request.preferred_sources.iter().find(|s| !DEFAULT_SOURCE_ORDER.contains(&s.as_str())) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(format!("Unknown streaming service: {}", unknown))),
+        ));
+    }
+
+    let preferred_sources = request.preferred_sources.join(",");
+    let now = chrono::Utc::now().naive_utc();
+    let existing = SearchPreferenceEntity::find()
+        .filter(SearchPreferenceColumn::UserId.eq(user.id))
+        .one(state.db())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(format!("Database error: {}", e)))))?;
+
+    let result = match existing {
+        Some(model) => {
+            let mut active: SearchPreferenceActiveModel = model.into();
+            active.preferred_sources = Set(preferred_sources);
+            active.updated_at = Set(now);
+            active.update(state.db()).await
+        }
+        None => SearchPreferenceActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            user_id: Set(user.id),
+            preferred_sources: Set(preferred_sources),
+            updated_at: Set(now),
+        }
+        .insert(state.db())
+        .await,
+    };
+
+    let saved = result
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(format!("Failed to save preferences: {}", e)))))?;
+    Ok(Json(ApiResponse::success(SearchPreferencesDto { preferred_sources: saved.source_order() })))
+}
+
 pub async fn get_stream_url(
     State(state): State<AppState>,
     Extension(user): Extension<UserResponseDto>,
@@ -1557,14 +1813,54 @@ pub struct GetBackendStreamUrlQuery {
 pub struct BackendStreamUrlResponse {
     pub stream_url: String,
     pub is_cached: bool,
+    pub loudness: Option<LoudnessDto>, // For volume normalization, None until the track is measured
+}
+
+/// Stored loudness of a track; tracks not yet measured are queued for loudness analysis
+async fn stream_loudness(state: &AppState, user_id: uuid::Uuid, query: &GetBackendStreamUrlQuery) -> Option<LoudnessDto> {
+    let stored = match TrackAnalysisStore::find(state.db(), &query.source, &query.track_id).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            warn!("Failed to look up loudness of track {} ({}): {}", query.track_id, query.source, e);
+            return None;
+        }
+    };
+    if let Some(loudness) = stored.as_ref().and_then(|analysis| analysis.loudness()) {
+        return Some(loudness);
+    }
+
+    // Tracks are played again before their measurement finishes, one job is enough
+    match state.analysis_jobs.has_pending(&query.source, &query.track_id, JOB_KIND_LOUDNESS).await {
+        Ok(false) => {}
+        Ok(true) => return None,
+        Err(e) => {
+            warn!("Failed to look up loudness analyses of track {} ({}): {}", query.track_id, query.source, e);
+            return None;
+        }
+    }
+
+    // The worker resolves the audio through the track's provider, never from the requested URL
+    let track = AnalysisTrackDto {
+        track_id: query.track_id.clone(),
+        source: query.source.clone(),
+        title: query.title.clone(),
+        stream_url: None,
+        content_hash: None,
+    };
+    let kinds = [JOB_KIND_LOUDNESS.to_string()];
+    if let Err(e) = state.analysis_jobs.enqueue(Some(user_id), vec![track], &kinds, false).await {
+        warn!("Failed to queue loudness analysis of track {} ({}): {}", query.track_id, query.source, e);
+    }
+    None
 }
 
 pub async fn get_backend_stream_url(
     State(state): State<AppState>,
-    Extension(_user): Extension<UserResponseDto>,
+    Extension(user): Extension<UserResponseDto>,
     Query(query): Query<GetBackendStreamUrlQuery>,
 ) -> Result<Json<ApiResponse<BackendStreamUrlResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
     debug!("Getting backend stream URL for track {} from {}", query.track_id, query.source);
+    let loudness = stream_loudness(&state, user.id, &query).await;
 
     // Handle server source differently - don't cache local files
     if query.source == "server" {
@@ -1572,6 +1868,7 @@ pub async fn get_backend_stream_url(
         let response = BackendStreamUrlResponse {
             stream_url: query.url,
             is_cached: false, // Server files are not cached, they're served directly
+            loudness,
         };
         return Ok(Json(ApiResponse::success(response)));
     }
@@ -1585,6 +1882,7 @@ pub async fn get_backend_stream_url(
             let response = BackendStreamUrlResponse {
                 stream_url,
                 is_cached: true, // For now, assume it's always cached
+                loudness,
             };
             Ok(Json(ApiResponse::success(response)))
         }
@@ -1633,6 +1931,288 @@ pub struct GetPlaylistTracksQuery {
     pub offset: Option<u32>,
 }
 
+#[derive(Deserialize)]
+pub struct FindTrackMatchesQuery {
+    pub track_id: String,
+    pub service: String,
+    pub services: Option<String>, // Comma-separated target services, defaults to all others
+    pub limit: Option<usize>,     // Maximum matches per target service
+}
+
+#[derive(Serialize)]
+pub struct ServiceMatches {
+    pub service: String,
+    pub matches: Vec<TrackMatch>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TrackMatchesResponse {
+    pub track: StreamingTrack,
+    pub results: Vec<ServiceMatches>,
+}
+
+// Find the same recording on other streaming services
+pub async fn find_track_on_other_services(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Query(params): Query<FindTrackMatchesQuery>,
+) -> Result<Json<ApiResponse<TrackMatchesResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let source_service = get_authenticated_streaming_service(&params.service, user.id, state.db()).await
+        .map_err(|err| (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(err))))?;
+
+    let track = source_service.get_track(&params.track_id).await
+        .map_err(|err| (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("Failed to get track: {}", err))),
+        ))?;
+
+    let target_services: Vec<String> = match &params.services {
+        Some(services) => services.split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect(),
+        None => ["qobuz", "spotify", "server"].iter().map(|s| s.to_string()).collect(),
+    };
+    let limit = params.limit.unwrap_or(3);
+    let matcher = TrackMatchingService::new();
+
+    let mut results = Vec::new();
+    for service_name in target_services.into_iter().filter(|s| *s != params.service) {
+        let outcome = match get_authenticated_streaming_service(&service_name, user.id, state.db()).await {
+            Ok(service) => matcher.find_on_service(service.as_ref(), &track).await.map_err(|e| e.to_string()),
+            Err(err) => Err(err),
+        };
+
+        match outcome {
+            Ok(mut matches) => {
+                debug!("Found {} matches for {} on {}", matches.len(), params.track_id, service_name);
+                matches.truncate(limit);
+                results.push(ServiceMatches { service: service_name, matches, error: None });
+            }
+            Err(err) => {
+                error!("Failed to match {} on {}: {}", params.track_id, service_name, err);
+                results.push(ServiceMatches { service: service_name, matches: vec![], error: Some(err) });
+            }
+        }
+    }
+
+    Ok(Json(ApiResponse::success(TrackMatchesResponse { track, results })))
+}
+
+const SPOTIFY_PLAYLIST_PAGE_SIZE: u32 = 100;
+const MAX_RESOLVE_TRACKS: usize = 2000;
+const RESOLVE_CONCURRENCY: usize = 4;
+
+#[derive(Deserialize)]
+pub struct ResolveSpotifyPlaylistRequest {
+    pub targets: Option<Vec<String>>, // Services to resolve to, in order of preference
+    pub min_confidence: Option<f32>,
+    pub create_playlist: Option<bool>,
+    pub playlist_name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ResolvedPlaylistTrack {
+    pub position: usize,
+    pub spotify_track: StreamingTrack,
+    pub resolved: Option<TrackMatch>,
+    pub best_candidate: Option<TrackMatch>, // Closest match below the confidence threshold
+}
+
+#[derive(Serialize)]
+pub struct ResolveSpotifyPlaylistResponse {
+    pub playlist_id: String,
+    pub total: usize,
+    pub matched: usize,
+    pub resolved: Vec<ResolvedPlaylistTrack>,
+    pub unmatched: Vec<ResolvedPlaylistTrack>,
+    pub service_errors: Vec<String>,
+    pub created_playlist: Option<PlaylistResponseDto>,
+}
+
+// Resolve the tracks of a Spotify playlist to playable Qobuz or local copies.
+// Single tracks can be resolved on demand through find_track_on_other_services.
+pub async fn resolve_spotify_playlist(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserResponseDto>,
+    Path(playlist_id): Path<String>,
+    Json(request): Json<ResolveSpotifyPlaylistRequest>,
+) -> Result<Json<ApiResponse<ResolveSpotifyPlaylistResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+    use futures_util::StreamExt;
+
+    let spotify = get_authenticated_streaming_service("spotify", user.id, state.db()).await
+        .map_err(|err| (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(err))))?;
+
+    // Fetch the whole playlist page by page
+    let mut spotify_tracks = Vec::new();
+    loop {
+        let page = spotify
+            .get_playlist_tracks(&playlist_id, Some(SPOTIFY_PLAYLIST_PAGE_SIZE), Some(spotify_tracks.len() as u32))
+            .await
+            .map_err(|err| (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(format!("Failed to get playlist tracks: {}", err))),
+            ))?;
+        let page_len = page.len();
+        spotify_tracks.extend(page);
+        if page_len < SPOTIFY_PLAYLIST_PAGE_SIZE as usize || spotify_tracks.len() >= MAX_RESOLVE_TRACKS {
+            break;
+        }
+    }
+    spotify_tracks.truncate(MAX_RESOLVE_TRACKS);
+
+    let targets = request.targets.clone()
+        .unwrap_or_else(|| vec!["qobuz".to_string(), "server".to_string()]);
+    let min_confidence = request.min_confidence.unwrap_or(0.8).clamp(0.0, 1.0);
+    let matcher = TrackMatchingService::new();
+
+    // Connect the target services once; each track is then looked up with a bounded search per service,
+    // which for the local library goes through its search index
+    let mut service_errors = Vec::new();
+    let mut target_services = Vec::new();
+    for target in targets.iter().filter(|t| t.as_str() != "spotify") {
+        match get_authenticated_streaming_service(target, user.id, state.db()).await {
+            Ok(service) => target_services.push((target.clone(), service)),
+            Err(err) => service_errors.push(format!("{}: {}", target, err)),
+        }
+    }
+
+    let resolve_track = |position: usize, track: StreamingTrack| {
+        let matcher = &matcher;
+        let target_services = &target_services;
+        let targets = &targets;
+        async move {
+            let mut candidates: Vec<(usize, TrackMatch)> = Vec::new();
+            let mut errors = Vec::new();
+
+            for (name, service) in target_services {
+                match matcher.find_on_service(service.as_ref(), &track).await {
+                    Ok(matches) => {
+                        let rank = targets.iter().position(|t| t == name).unwrap_or(usize::MAX);
+                        candidates.extend(matches.into_iter().next().map(|m| (rank, m)));
+                    }
+                    Err(err) => errors.push(format!("{}: {}", name, err)),
+                }
+            }
+
+            // ISRC matches first, then confidence, then the caller's service preference
+            candidates.sort_by(|(rank_a, a), (rank_b, b)| {
+                (b.method == MatchMethod::Isrc).cmp(&(a.method == MatchMethod::Isrc))
+                    .then(b.confidence.partial_cmp(&a.confidence).unwrap_or(std::cmp::Ordering::Equal))
+                    .then(rank_a.cmp(rank_b))
+            });
+            let best = candidates.into_iter().next().map(|(_, m)| m);
+            let (resolved, best_candidate) = match best {
+                Some(m) if m.confidence >= min_confidence => (Some(m), None),
+                other => (None, other),
+            };
+
+            (ResolvedPlaylistTrack { position, spotify_track: track, resolved, best_candidate }, errors)
+        }
+    };
+
+    let outcomes: Vec<(ResolvedPlaylistTrack, Vec<String>)> = futures_util::stream::iter(spotify_tracks.into_iter().enumerate())
+        .map(|(position, track)| resolve_track(position, track))
+        .buffered(RESOLVE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let total = outcomes.len();
+    let mut resolved = Vec::new();
+    let mut unmatched = Vec::new();
+    for (track, errors) in outcomes {
+        for err in errors {
+            if !service_errors.contains(&err) {
+                service_errors.push(err);
+            }
+        }
+        if track.resolved.is_some() {
+            resolved.push(track);
+        } else {
+            unmatched.push(track);
+        }
+    }
+    debug!("Resolved {}/{} tracks of Spotify playlist {}", resolved.len(), total, playlist_id);
+
+    let created_playlist = if request.create_playlist.unwrap_or(false) && !resolved.is_empty() {
+        let name = request.playlist_name.clone()
+            .unwrap_or_else(|| format!("Spotify playlist {}", playlist_id));
+        let description = format!("Resolved from Spotify playlist {}: {}/{} tracks matched", playlist_id, resolved.len(), total);
+        Some(create_resolved_playlist(&state, user.id, name, description, &resolved).await
+            .map_err(|err| {
+                error!("Failed to create resolved playlist: {}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::<()>::error(format!("Failed to create playlist: {}", err))),
+                )
+            })?)
+    } else {
+        None
+    };
+
+    Ok(Json(ApiResponse::success(ResolveSpotifyPlaylistResponse {
+        playlist_id,
+        total,
+        matched: resolved.len(),
+        resolved,
+        unmatched,
+        service_errors,
+        created_playlist,
+    })))
+}
+
+async fn create_resolved_playlist(
+    state: &AppState,
+    user_id: uuid::Uuid,
+    name: String,
+    description: String,
+    tracks: &[ResolvedPlaylistTrack],
+) -> Result<PlaylistResponseDto, sea_orm::DbErr> {
+    // All or nothing, a failure half way must not leave a partial playlist behind
+    let txn = state.db().begin().await?;
+    let now = chrono::Utc::now().naive_utc();
+    let playlist = crate::models::playlist::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        user_id: Set(user_id),
+        name: Set(name),
+        description: Set(Some(description)),
+        is_public: Set(false),
+        created_at: Set(now),
+        updated_at: Set(now),
+    }
+    .insert(&txn)
+    .await?;
+
+    let mut position = 0;
+    for track in tracks.iter().filter_map(|t| t.resolved.as_ref()).map(|m| &m.track) {
+        crate::models::playlist_item::ActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            playlist_id: Set(playlist.id),
+            item_type: Set("track".to_string()),
+            item_id: Set(track.id.clone()),
+            position: Set(position),
+            added_at: Set(now),
+            title: Set(Some(track.title.clone())),
+            artist: Set(Some(track.artist.clone())),
+            album: Set(Some(track.album.clone())),
+            duration: Set(track.duration),
+            source: Set(Some(track.source.clone())),
+            cover_url: Set(track.cover_url.clone()),
+            playlist_name: Set(None),
+        }
+        .insert(&txn)
+        .await?;
+        position += 1;
+    }
+
+    txn.commit().await?;
+
+    let mut response: PlaylistResponseDto = playlist.into();
+    response.item_count = position;
+    Ok(response)
+}
+
 // Stream local music files
 pub async fn stream_local_file(
     axum::extract::Path(file_path_param): axum::extract::Path<String>,
@@ -1732,6 +2312,221 @@ pub async fn stream_local_file(
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
+#[derive(Deserialize)]
+pub struct StreamPreviewQuery {
+    pub bpm: Option<f32>, // Target tempo, the track's own when missing
+    pub key_lock: Option<bool>, // Keep the key at the new tempo, on by default
+    pub semitones: Option<i32>, // Pitch shift on top, -12 to 12
+    pub source_bpm: Option<f32>, // Tempo of the track when it has not been analyzed
+}
+
+// Stream a local music file time-stretched and pitch-shifted
+pub async fn stream_local_preview(
+    State(state): State<AppState>,
+    Path(file_path_param): Path<String>,
+    Query(query): Query<StreamPreviewQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let decoded_path = urlencoding::decode(&file_path_param)
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+        .into_owned();
+    if std::path::Path::new(&decoded_path).components().any(|c| matches!(c, std::path::Component::ParentDir)) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let music_dir = std::env::current_dir()
+        .unwrap_or_else(|_| std::path::PathBuf::from("."))
+        .join("own_music");
+    let file_path = music_dir.join(&decoded_path);
+    if !file_path.starts_with(&music_dir) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if !file_path.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    // Local library tracks carry their file path in the id
+    let track_id = format!("server_{}", file_path.to_string_lossy());
+    stream_preview(&state, file_path, "server", &track_id, &query, &headers).await
+}
+
+// Stream a cached track time-stretched and pitch-shifted
+pub async fn stream_cached_preview(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<StreamPreviewQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let cached = state.streaming_service.cached_track(&id).await.ok_or(StatusCode::NOT_FOUND)?;
+    if !cached.file_path.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    stream_preview(&state, cached.file_path, &cached.source, &cached.track_id, &query, &headers).await
+}
+
+// Render a preview, or reuse it for the player's range requests, and serve it as WAV with the
+// resulting tempo and key in X-Preview-* headers
+async fn stream_preview(
+    state: &AppState,
+    file_path: std::path::PathBuf,
+    source: &str,
+    track_id: &str,
+    query: &StreamPreviewQuery,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let analysis = match TrackAnalysisStore::find(state.db(), source, track_id).await {
+        Ok(analysis) => analysis,
+        Err(e) => {
+            warn!("Failed to look up analysis of track {} ({}): {}", track_id, source, e);
+            None
+        }
+    };
+    let original_bpm = query
+        .source_bpm
+        .or_else(|| analysis.as_ref().and_then(|analysis| analysis.bpm))
+        .filter(|bpm| *bpm > 0.0);
+
+    // A target tempo needs the track's own. Steps of 0.1% are inaudible and keep players that
+    // glide the tempo from rendering a preview for every value they pass.
+    let tempo = match query.bpm {
+        Some(bpm) => (bpm / original_bpm.ok_or(StatusCode::UNPROCESSABLE_ENTITY)? * 1000.0).round() / 1000.0,
+        None => 1.0,
+    };
+    let settings = StretchSettings {
+        tempo,
+        key_lock: query.key_lock.unwrap_or(true),
+        semitones: query.semitones.unwrap_or(0),
+    };
+    if !(MIN_TEMPO..=MAX_TEMPO).contains(&tempo) || settings.semitones.abs() > MAX_SEMITONES {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let key = {
+        use sha2::{Digest, Sha256};
+        let input = format!("{}|{:.3}|{}|{}", file_path.display(), tempo, settings.key_lock, settings.semitones);
+        format!("{:x}", Sha256::digest(input.as_bytes()))
+    };
+    let preview_path = state
+        .streaming_service
+        .preview(&key, move |target| render_preview(&file_path, &target, &settings))
+        .await
+        .map_err(|e| {
+            error!("Failed to render preview of track {} ({}): {}", track_id, source, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let range = headers.get(header::RANGE).and_then(|h| h.to_str().ok());
+    let mut response = serve_file(&preview_path, "audio/wav", range).await?;
+    let response_headers = response.headers_mut();
+    let mut report = |name: &'static str, value: String| {
+        if let Ok(value) = header::HeaderValue::from_str(&value) {
+            response_headers.insert(header::HeaderName::from_static(name), value);
+        }
+    };
+    if let Some(bpm) = original_bpm {
+        report("x-preview-bpm", format!("{:.2}", bpm * tempo));
+    }
+    report("x-preview-pitch-shift", format!("{:.2}", settings.pitch_shift()));
+    if let Some(key) = analysis.as_ref().and_then(|analysis| analysis.camelot.as_deref()).and_then(Camelot::parse) {
+        report("x-preview-camelot", settings.played_key(key).to_string());
+    }
+    Ok(response)
+}
+
+// Previews render only the beginning of longer tracks, bounding the work of a single request
+const MAX_PREVIEW_SECS: f64 = 600.0;
+
+// Stretches a decoding pass chunk by chunk into a 16-bit stereo WAV at MIX_SAMPLE_RATE
+struct PreviewSink {
+    channels: usize,
+    resamplers: Option<[StreamResampler; 2]>, // None when the file is at MIX_SAMPLE_RATE already
+    stretcher: Stretcher,
+    writer: hound::WavWriter<std::io::BufWriter<std::fs::File>>,
+    error: Option<hound::Error>, // First write error; AudioSink cannot fail, so it is checked after the pass
+}
+
+impl PreviewSink {
+    fn write(&mut self, left: Vec<f32>, right: Vec<f32>) {
+        let interleaved: Vec<f32> = left.iter().zip(&right).flat_map(|(&l, &r)| [l, r]).collect();
+        let stretched = self.stretcher.push(&interleaved);
+        self.write_samples(&stretched);
+    }
+
+    fn write_samples(&mut self, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+        for &sample in samples {
+            if let Err(e) = self.writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16) {
+                self.error = Some(e);
+                return;
+            }
+        }
+    }
+
+    fn finish(mut self) -> anyhow::Result<()> {
+        if let Some([left, right]) = self.resamplers.as_mut() {
+            let (left, right) = (left.finish(), right.finish());
+            self.write(left, right);
+        }
+        let tail = self.stretcher.finish();
+        self.write_samples(&tail);
+        if let Some(e) = self.error {
+            return Err(e.into());
+        }
+        self.writer.finalize()?;
+        Ok(())
+    }
+}
+
+impl AudioSink for PreviewSink {
+    fn push_native(&mut self, samples: &[f32]) {
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        for frame in samples.chunks_exact(self.channels) {
+            left.push(frame[0]);
+            right.push(frame[frame.len().min(2) - 1]);
+        }
+        if let Some([left_resampler, right_resampler]) = self.resamplers.as_mut() {
+            (left, right) = (left_resampler.process(&left), right_resampler.process(&right));
+        }
+        self.write(left, right);
+    }
+
+    fn wants_mono(&self) -> bool {
+        false
+    }
+}
+
+// Decode and stretch a track into a 16-bit WAV as it streams through, moved into place once complete
+fn render_preview(source: &std::path::Path, target: &std::path::Path, settings: &StretchSettings) -> anyhow::Result<()> {
+    let mut stream = AudioStream::open(&source.to_string_lossy())?;
+    let partial = target.with_extension(format!("{}.part", uuid::Uuid::new_v4()));
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: MIX_SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut sink = PreviewSink {
+        channels: stream.channels.max(1),
+        resamplers: (stream.sample_rate != MIX_SAMPLE_RATE).then(|| {
+            [StreamResampler::new(stream.sample_rate, MIX_SAMPLE_RATE), StreamResampler::new(stream.sample_rate, MIX_SAMPLE_RATE)]
+        }),
+        stretcher: Stretcher::new(settings),
+        writer: hound::WavWriter::create(&partial, spec)?,
+        error: None,
+    };
+
+    let rendered = stream
+        .run(AnalysisSpan::Truncate { max_secs: MAX_PREVIEW_SECS }, &mut [&mut sink])
+        .and_then(|_| sink.finish())
+        .and_then(|_| Ok(std::fs::rename(&partial, target)?));
+    if rendered.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    rendered
+}
+
 // Stream local cover images (both cached and direct files)
 pub async fn stream_local_cover(
     axum::extract::Path(file_path_param): axum::extract::Path<String>,
@@ -5,7 +5,7 @@ mod migrator;
 
 use anyhow::Result;
 use axum::{
-    http::Method,
+    http::{HeaderName, Method},
     middleware,
     routing::{get, post, delete, put},
     Router,
@@ -20,14 +20,17 @@ use tracing::{info, error};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use handlers::auth::{AppState, auth_middleware, register, login, logout, me};
-use handlers::streaming::{search_music, get_stream_url, get_backend_stream_url, connect_qobuz, connect_spotify, get_available_services, get_service_status, disconnect_service, get_spotify_auth_url, spotify_callback, transfer_spotify_playback, get_spotify_access_token, refresh_spotify_token, get_playlist_tracks, stream_local_file, stream_local_cover};
+use handlers::streaming::{search_music, get_stream_url, get_backend_stream_url, connect_qobuz, connect_spotify, get_available_services, get_service_status, disconnect_service, get_spotify_auth_url, spotify_callback, transfer_spotify_playback, get_spotify_access_token, refresh_spotify_token, get_playlist_tracks, stream_local_file, stream_local_cover, stream_local_preview, stream_cached_preview, find_track_on_other_services, resolve_spotify_playlist, get_search_preferences, update_search_preferences};
 use handlers::music::{get_user_playlists, create_playlist, get_playlist};
 use handlers::playlist::{get_playlists, create_playlist as create_new_playlist, get_playlist as get_new_playlist, update_playlist, delete_playlist, get_playlist_items, add_playlist_item, remove_playlist_item, reorder_playlist_item};
 use handlers::saved_tracks::{save_track, get_saved_tracks, remove_saved_track, is_track_saved};
 use handlers::saved_albums::{save_album, get_saved_albums, remove_saved_album, check_album_saved, get_album_tracks};
 use handlers::queue::{get_queue, add_to_queue, remove_from_queue, reorder_queue, clear_queue};
-use handlers::audio_analysis::{analyze_track_bpm, get_track_bpm, analyze_track_bpm_spectrogram, analyze_track_key};
-use services::{AuthService, streaming_service::StreamingService};
+use handlers::audio_analysis::{analyze_track_bpm, get_track_bpm, get_track_beat_grid, get_track_structure, get_track_key_timeline, get_track_chords, analyze_track_bpm_spectrogram, analyze_track_key, get_bpm_ranges, update_bpm_range, delete_bpm_range, analyze_track_loudness, get_album_loudness, get_track_waveform, get_analysis_artifact};
+use handlers::analysis_jobs::{StreamingUrlResolver, enqueue_analysis_jobs, enqueue_playlist_analysis, enqueue_saved_albums_analysis, list_analysis_jobs, get_analysis_job, get_analysis_batch, cancel_analysis_job, cancel_analysis_batch, get_library_ingest_status, scan_library, get_library_duplicates};
+use handlers::mixing::{get_next_track_suggestions, auto_dj_playlist, create_mix_export, list_mix_exports, get_mix_export, delete_mix_export, download_mix_export, get_mix_cue_sheet};
+use handlers::samples::{create_sample, list_samples, get_sample, delete_sample, download_sample};
+use services::{AuthService, AnalysisArtifacts, AnalysisJobQueue, LibraryIngestService, MixExportService, SampleLibraryService, WaveformService, streaming_service::StreamingService};
 use std::sync::Arc;
 use migrator::Migrator;
 
@@ -75,16 +78,54 @@ async fn main() -> Result<()> {
             e
         })?;
     
+    // Images rendered by analyses, removed again once old
+    let artifacts = AnalysisArtifacts::from_env();
+    artifacts.start();
+    
+    // Waveform peaks cached per file content, removed again once old
+    let waveforms = WaveformService::from_env();
+    waveforms.start();
+    
+    // Start the background analysis workers
+    let analysis_jobs = AnalysisJobQueue::new(db.clone(), artifacts.clone());
+    analysis_jobs.start(Arc::new(StreamingUrlResolver::new(db.clone()))).await
+        .map_err(|e| {
+            error!("Failed to start analysis workers: {}", e);
+            e
+        })?;
+    
+    // Queue analyses of new and changed files in the local library
+    let library_ingest = LibraryIngestService::new(std::env::current_dir()?.join("own_music"), analysis_jobs.clone());
+    library_ingest.start();
+    
+    // Mixes rendered from queues and playlists
+    let mix_exports = MixExportService::from_env(db.clone(), Arc::new(StreamingUrlResolver::new(db.clone())));
+    mix_exports.start();
+    
+    // Loops cut into users' sample collections
+    let samples = SampleLibraryService::from_env(db.clone(), Arc::new(StreamingUrlResolver::new(db.clone())), analysis_jobs.clone());
+    
     // Application state
     let app_state = AppState {
         auth_service,
         streaming_service: streaming_service.clone(),
+        analysis_jobs,
+        library_ingest,
+        artifacts,
+        waveforms,
+        mix_exports,
+        samples,
     };
 
     // CORS configuration
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_headers(Any)
+        .expose_headers([
+            HeaderName::from_static("x-preview-bpm"),
+            HeaderName::from_static("x-preview-pitch-shift"),
+            HeaderName::from_static("x-preview-camelot"),
+        ])
         .allow_origin(Any);
 
     // Build protected routes with authentication middleware
@@ -92,6 +133,8 @@ async fn main() -> Result<()> {
         .route("/api/auth/logout", post(logout))
         .route("/api/auth/me", get(me))
         .route("/api/streaming/search", get(search_music))
+        .route("/api/streaming/search/preferences", get(get_search_preferences))
+        .route("/api/streaming/search/preferences", put(update_search_preferences))
         .route("/api/streaming/stream-url", get(get_stream_url))
         .route("/api/streaming/backend-stream-url", get(get_backend_stream_url))
         .route("/api/streaming/services", get(get_available_services))
@@ -104,6 +147,8 @@ async fn main() -> Result<()> {
         .route("/api/streaming/spotify/refresh", post(refresh_spotify_token))
         .route("/api/streaming/disconnect", post(disconnect_service))
         .route("/api/streaming/playlist/{playlist_id}/tracks", get(get_playlist_tracks))
+        .route("/api/streaming/track/matches", get(find_track_on_other_services))
+        .route("/api/streaming/spotify/playlists/{playlist_id}/resolve", post(resolve_spotify_playlist))
         .route("/api/playlists", get(get_user_playlists))
         .route("/api/playlists", post(create_playlist))
         .route("/api/playlists/{id}", get(get_playlist))
@@ -117,6 +162,7 @@ async fn main() -> Result<()> {
         .route("/api/v2/playlists/{id}/items", post(add_playlist_item))
         .route("/api/v2/playlists/{playlist_id}/items/{item_id}", delete(remove_playlist_item))
         .route("/api/v2/playlists/{playlist_id}/items/{item_id}/reorder", put(reorder_playlist_item))
+        .route("/api/v2/playlists/{id}/auto-dj", post(auto_dj_playlist))
         .route("/api/saved-tracks", get(get_saved_tracks))
         .route("/api/saved-tracks", post(save_track))
         .route("/api/saved-tracks/{id}", delete(remove_saved_track))
@@ -134,7 +180,44 @@ async fn main() -> Result<()> {
         .route("/api/audio/analyze-bpm", post(analyze_track_bpm))
         .route("/api/audio/analyze-bpm-spectrogram", post(analyze_track_bpm_spectrogram))
         .route("/api/audio/analyze-key", post(analyze_track_key))
+        .route("/api/audio/analyze-loudness", post(analyze_track_loudness))
+        .route("/api/audio/album-loudness", get(get_album_loudness))
         .route("/api/audio/bpm", get(get_track_bpm))
+        .route("/api/audio/beat-grid", get(get_track_beat_grid))
+        .route("/api/audio/structure", get(get_track_structure))
+        .route("/api/audio/key-timeline", get(get_track_key_timeline))
+        .route("/api/audio/chords", get(get_track_chords))
+        .route("/api/audio/next-tracks", get(get_next_track_suggestions))
+        .route("/api/audio/waveform", get(get_track_waveform))
+        .route("/api/audio/artifacts/{kind}", get(get_analysis_artifact))
+        .route("/api/audio/bpm-ranges", get(get_bpm_ranges))
+        .route("/api/audio/bpm-ranges", put(update_bpm_range))
+        .route("/api/audio/bpm-ranges", delete(delete_bpm_range))
+        .route("/api/audio/jobs", get(list_analysis_jobs))
+        .route("/api/audio/jobs", post(enqueue_analysis_jobs))
+        .route("/api/audio/jobs/playlist/{playlist_id}", post(enqueue_playlist_analysis))
+        .route("/api/audio/jobs/saved-albums", post(enqueue_saved_albums_analysis))
+        .route("/api/audio/jobs/batches/{batch_id}", get(get_analysis_batch))
+        .route("/api/audio/jobs/batches/{batch_id}/cancel", post(cancel_analysis_batch))
+        .route("/api/audio/jobs/{id}", get(get_analysis_job))
+        .route("/api/audio/jobs/{id}/cancel", post(cancel_analysis_job))
+        .route("/api/library/ingest", get(get_library_ingest_status))
+        .route("/api/library/ingest/scan", post(scan_library))
+        .route("/api/library/duplicates", get(get_library_duplicates))
+        .route("/api/mixes", get(list_mix_exports))
+        .route("/api/mixes", post(create_mix_export))
+        .route("/api/mixes/{id}", get(get_mix_export))
+        .route("/api/mixes/{id}", delete(delete_mix_export))
+        .route("/api/mixes/{id}/file", get(download_mix_export))
+        .route("/api/mixes/{id}/cue", get(get_mix_cue_sheet))
+        .route("/api/samples", get(list_samples))
+        .route("/api/samples", post(create_sample))
+        .route("/api/samples/{id}", get(get_sample))
+        .route("/api/samples/{id}", delete(delete_sample))
+        .route("/api/samples/{id}/file", get(download_sample))
+        // Time-stretched and pitch-shifted previews, rendered on demand
+        .route("/api/stream/preview/local/{*file_path}", get(stream_local_preview))
+        .route("/api/stream/{track_id}/preview", get(stream_cached_preview))
         .layer(
             ServiceBuilder::new()
                 .layer(middleware::from_fn_with_state(
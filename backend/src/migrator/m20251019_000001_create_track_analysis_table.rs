@@ -0,0 +1,147 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TrackAnalysis::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TrackAnalysis::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TrackAnalysis::Source).string().not_null())
+                    .col(ColumnDef::new(TrackAnalysis::TrackId).string().not_null())
+                    .col(ColumnDef::new(TrackAnalysis::ContentHash).string().null())
+                    .col(ColumnDef::new(TrackAnalysis::Bpm).float().null())
+                    .col(ColumnDef::new(TrackAnalysis::KeyName).string().null())
+                    .col(ColumnDef::new(TrackAnalysis::Camelot).string().null())
+                    .col(ColumnDef::new(TrackAnalysis::KeyConfidence).float().null())
+                    .col(ColumnDef::new(TrackAnalysis::AlgorithmVersion).integer().not_null())
+                    .col(ColumnDef::new(TrackAnalysis::BpmAnalyzedAt).timestamp().null())
+                    .col(ColumnDef::new(TrackAnalysis::KeyAnalyzedAt).timestamp().null())
+                    .col(ColumnDef::new(TrackAnalysis::CreatedAt).timestamp().not_null())
+                    .col(ColumnDef::new(TrackAnalysis::UpdatedAt).timestamp().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_analysis_source_track_id")
+                    .table(TrackAnalysis::Table)
+                    .col(TrackAnalysis::Source)
+                    .col(TrackAnalysis::TrackId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_analysis_content_hash")
+                    .table(TrackAnalysis::Table)
+                    .col(TrackAnalysis::ContentHash)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Carry over results that were stored on users' saved tracks
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"INSERT INTO track_analysis
+                    (id, source, track_id, bpm, key_name, camelot, key_confidence, algorithm_version,
+                     bpm_analyzed_at, key_analyzed_at, created_at, updated_at)
+                SELECT DISTINCT ON (source, track_id)
+                    gen_random_uuid(), source, track_id, bpm, key_name, camelot, key_confidence, 1,
+                    CASE WHEN bpm IS NOT NULL THEN created_at END,
+                    CASE WHEN key_name IS NOT NULL THEN created_at END,
+                    created_at, created_at
+                FROM saved_track
+                WHERE bpm IS NOT NULL OR key_name IS NOT NULL
+                ORDER BY source, track_id, created_at DESC"#,
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SavedTrack::Table)
+                    .drop_column(SavedTrack::Bpm)
+                    .drop_column(SavedTrack::KeyName)
+                    .drop_column(SavedTrack::Camelot)
+                    .drop_column(SavedTrack::KeyConfidence)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SavedTrack::Table)
+                    .add_column(ColumnDef::new(SavedTrack::Bpm).float().null())
+                    .add_column(ColumnDef::new(SavedTrack::KeyName).string().null())
+                    .add_column(ColumnDef::new(SavedTrack::Camelot).string().null())
+                    .add_column(ColumnDef::new(SavedTrack::KeyConfidence).float().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"UPDATE saved_track SET
+                    bpm = track_analysis.bpm,
+                    key_name = track_analysis.key_name,
+                    camelot = track_analysis.camelot,
+                    key_confidence = track_analysis.key_confidence
+                FROM track_analysis
+                WHERE track_analysis.source = saved_track.source
+                    AND track_analysis.track_id = saved_track.track_id"#,
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(TrackAnalysis::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackAnalysis {
+    Table,
+    Id,
+    Source,
+    TrackId,
+    ContentHash,
+    Bpm,
+    KeyName,
+    Camelot,
+    KeyConfidence,
+    AlgorithmVersion,
+    BpmAnalyzedAt,
+    KeyAnalyzedAt,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum SavedTrack {
+    Table,
+    Bpm,
+    KeyName,
+    Camelot,
+    KeyConfidence,
+}
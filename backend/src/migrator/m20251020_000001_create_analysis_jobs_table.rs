@@ -0,0 +1,114 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AnalysisJobs::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AnalysisJobs::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(AnalysisJobs::UserId).uuid().not_null())
+                    .col(ColumnDef::new(AnalysisJobs::BatchId).uuid().null())
+                    .col(ColumnDef::new(AnalysisJobs::Source).string().not_null())
+                    .col(ColumnDef::new(AnalysisJobs::TrackId).string().not_null())
+                    .col(ColumnDef::new(AnalysisJobs::Title).string().null())
+                    .col(ColumnDef::new(AnalysisJobs::StreamUrl).string().null())
+                    .col(ColumnDef::new(AnalysisJobs::Kinds).string().not_null())
+                    .col(ColumnDef::new(AnalysisJobs::Status).string().not_null())
+                    .col(ColumnDef::new(AnalysisJobs::Progress).float().not_null())
+                    .col(ColumnDef::new(AnalysisJobs::Attempts).integer().not_null())
+                    .col(ColumnDef::new(AnalysisJobs::MaxAttempts).integer().not_null())
+                    .col(ColumnDef::new(AnalysisJobs::LastError).text().null())
+                    .col(ColumnDef::new(AnalysisJobs::RunAfter).timestamp().not_null())
+                    .col(ColumnDef::new(AnalysisJobs::CreatedAt).timestamp().not_null())
+                    .col(ColumnDef::new(AnalysisJobs::StartedAt).timestamp().null())
+                    .col(ColumnDef::new(AnalysisJobs::FinishedAt).timestamp().null())
+                    .col(ColumnDef::new(AnalysisJobs::UpdatedAt).timestamp().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_analysis_jobs_user_id")
+                            .from(AnalysisJobs::Table, AnalysisJobs::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_analysis_jobs_status_run_after")
+                    .table(AnalysisJobs::Table)
+                    .col(AnalysisJobs::Status)
+                    .col(AnalysisJobs::RunAfter)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_analysis_jobs_user_id")
+                    .table(AnalysisJobs::Table)
+                    .col(AnalysisJobs::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_analysis_jobs_batch_id")
+                    .table(AnalysisJobs::Table)
+                    .col(AnalysisJobs::BatchId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AnalysisJobs::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AnalysisJobs {
+    Table,
+    Id,
+    UserId,
+    BatchId,
+    Source,
+    TrackId,
+    Title,
+    StreamUrl,
+    Kinds,
+    Status,
+    Progress,
+    Attempts,
+    MaxAttempts,
+    LastError,
+    RunAfter,
+    CreatedAt,
+    StartedAt,
+    FinishedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
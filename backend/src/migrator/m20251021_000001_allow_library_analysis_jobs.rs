@@ -0,0 +1,45 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Jobs queued by the library scanner belong to no user
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AnalysisJobs::Table)
+                    .modify_column(ColumnDef::new(AnalysisJobs::UserId).uuid().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .exec_stmt(
+                Query::delete()
+                    .from_table(AnalysisJobs::Table)
+                    .and_where(Expr::col(AnalysisJobs::UserId).is_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AnalysisJobs::Table)
+                    .modify_column(ColumnDef::new(AnalysisJobs::UserId).uuid().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AnalysisJobs {
+    Table,
+    UserId,
+}
@@ -0,0 +1,38 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .add_column(float_null(TrackAnalysis::BpmConfidence))
+                    .add_column(text_null(TrackAnalysis::BpmCandidates))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .drop_column(TrackAnalysis::BpmConfidence)
+                    .drop_column(TrackAnalysis::BpmCandidates)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackAnalysis {
+    Table,
+    BpmConfidence,
+    BpmCandidates,
+}
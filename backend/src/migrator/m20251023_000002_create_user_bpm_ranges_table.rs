@@ -0,0 +1,71 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserBpmRanges::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserBpmRanges::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserBpmRanges::UserId).uuid().not_null())
+                    .col(ColumnDef::new(UserBpmRanges::Genre).string().not_null())
+                    .col(ColumnDef::new(UserBpmRanges::MinBpm).float().not_null())
+                    .col(ColumnDef::new(UserBpmRanges::MaxBpm).float().not_null())
+                    .col(ColumnDef::new(UserBpmRanges::UpdatedAt).timestamp().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_user_bpm_ranges_user_id")
+                            .from(UserBpmRanges::Table, UserBpmRanges::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_user_bpm_ranges_user_genre")
+                    .table(UserBpmRanges::Table)
+                    .col(UserBpmRanges::UserId)
+                    .col(UserBpmRanges::Genre)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserBpmRanges::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserBpmRanges {
+    Table,
+    Id,
+    UserId,
+    Genre,
+    MinBpm,
+    MaxBpm,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
@@ -0,0 +1,41 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .add_column(string_null(TrackAnalysis::KeyProfile))
+                    .add_column(text_null(TrackAnalysis::KeyRunnerUp))
+                    .add_column(text_null(TrackAnalysis::KeyTimeline))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .drop_column(TrackAnalysis::KeyProfile)
+                    .drop_column(TrackAnalysis::KeyRunnerUp)
+                    .drop_column(TrackAnalysis::KeyTimeline)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackAnalysis {
+    Table,
+    KeyProfile,
+    KeyRunnerUp,
+    KeyTimeline,
+}
@@ -0,0 +1,53 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .add_column(float_null(TrackAnalysis::IntegratedLoudness))
+                    .add_column(float_null(TrackAnalysis::LoudnessRange))
+                    .add_column(float_null(TrackAnalysis::TruePeak))
+                    .add_column(text_null(TrackAnalysis::LoudnessHistogram))
+                    .add_column(float_null(TrackAnalysis::AlbumLoudness))
+                    .add_column(float_null(TrackAnalysis::AlbumPeak))
+                    .add_column(ColumnDef::new(TrackAnalysis::LoudnessAnalyzedAt).timestamp().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .drop_column(TrackAnalysis::IntegratedLoudness)
+                    .drop_column(TrackAnalysis::LoudnessRange)
+                    .drop_column(TrackAnalysis::TruePeak)
+                    .drop_column(TrackAnalysis::LoudnessHistogram)
+                    .drop_column(TrackAnalysis::AlbumLoudness)
+                    .drop_column(TrackAnalysis::AlbumPeak)
+                    .drop_column(TrackAnalysis::LoudnessAnalyzedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackAnalysis {
+    Table,
+    IntegratedLoudness,
+    LoudnessRange,
+    TruePeak,
+    LoudnessHistogram,
+    AlbumLoudness,
+    AlbumPeak,
+    LoudnessAnalyzedAt,
+}
@@ -0,0 +1,50 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .add_column(float_null(TrackAnalysis::Energy))
+                    .add_column(float_null(TrackAnalysis::Danceability))
+                    .add_column(float_null(TrackAnalysis::PulseClarity))
+                    .add_column(float_null(TrackAnalysis::Brightness))
+                    .add_column(float_null(TrackAnalysis::DynamicRange))
+                    .add_column(float_null(TrackAnalysis::Acousticness))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .drop_column(TrackAnalysis::Energy)
+                    .drop_column(TrackAnalysis::Danceability)
+                    .drop_column(TrackAnalysis::PulseClarity)
+                    .drop_column(TrackAnalysis::Brightness)
+                    .drop_column(TrackAnalysis::DynamicRange)
+                    .drop_column(TrackAnalysis::Acousticness)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackAnalysis {
+    Table,
+    Energy,
+    Danceability,
+    PulseClarity,
+    Brightness,
+    DynamicRange,
+    Acousticness,
+}
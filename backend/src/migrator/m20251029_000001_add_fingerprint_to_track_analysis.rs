@@ -0,0 +1,50 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .add_column(text_null(TrackAnalysis::Fingerprint))
+                    .add_column(float_null(TrackAnalysis::Duration))
+                    .add_column(string_null(TrackAnalysis::Codec))
+                    .add_column(integer_null(TrackAnalysis::SampleRate))
+                    .add_column(integer_null(TrackAnalysis::BitDepth))
+                    .add_column(integer_null(TrackAnalysis::Bitrate))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .drop_column(TrackAnalysis::Fingerprint)
+                    .drop_column(TrackAnalysis::Duration)
+                    .drop_column(TrackAnalysis::Codec)
+                    .drop_column(TrackAnalysis::SampleRate)
+                    .drop_column(TrackAnalysis::BitDepth)
+                    .drop_column(TrackAnalysis::Bitrate)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackAnalysis {
+    Table,
+    Fingerprint,
+    Duration,
+    Codec,
+    SampleRate,
+    BitDepth,
+    Bitrate,
+}
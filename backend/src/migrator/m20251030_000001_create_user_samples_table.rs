@@ -0,0 +1,96 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserSamples::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserSamples::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserSamples::UserId).uuid().not_null())
+                    .col(ColumnDef::new(UserSamples::Name).string().not_null())
+                    .col(ColumnDef::new(UserSamples::Source).string().not_null())
+                    .col(ColumnDef::new(UserSamples::TrackId).string().not_null())
+                    .col(ColumnDef::new(UserSamples::Title).string().null())
+                    .col(ColumnDef::new(UserSamples::Artist).string().null())
+                    .col(ColumnDef::new(UserSamples::StartBeat).integer().not_null())
+                    .col(ColumnDef::new(UserSamples::Bars).integer().not_null())
+                    .col(ColumnDef::new(UserSamples::BeatsPerBar).integer().not_null())
+                    .col(ColumnDef::new(UserSamples::Bpm).float().not_null())
+                    .col(ColumnDef::new(UserSamples::StartTime).double().not_null())
+                    .col(ColumnDef::new(UserSamples::Duration).double().not_null())
+                    .col(ColumnDef::new(UserSamples::FadeMs).integer().not_null())
+                    .col(ColumnDef::new(UserSamples::SampleRate).integer().not_null())
+                    .col(ColumnDef::new(UserSamples::Channels).integer().not_null())
+                    .col(ColumnDef::new(UserSamples::Format).string().not_null())
+                    .col(ColumnDef::new(UserSamples::FileSize).big_integer().not_null())
+                    .col(ColumnDef::new(UserSamples::CreatedAt).timestamp().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_user_samples_user_id")
+                            .from(UserSamples::Table, UserSamples::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_user_samples_user_created")
+                    .table(UserSamples::Table)
+                    .col(UserSamples::UserId)
+                    .col(UserSamples::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserSamples::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserSamples {
+    Table,
+    Id,
+    UserId,
+    Name,
+    Source,
+    TrackId,
+    Title,
+    Artist,
+    StartBeat,
+    Bars,
+    BeatsPerBar,
+    Bpm,
+    StartTime,
+    Duration,
+    FadeMs,
+    SampleRate,
+    Channels,
+    Format,
+    FileSize,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
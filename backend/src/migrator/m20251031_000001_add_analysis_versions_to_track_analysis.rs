@@ -0,0 +1,77 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Each kind of analysis keeps the version of the algorithm it was computed with
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .add_column(integer_null(TrackAnalysis::BpmVersion))
+                    .add_column(integer_null(TrackAnalysis::KeyVersion))
+                    .add_column(integer_null(TrackAnalysis::LoudnessVersion))
+                    .add_column(integer_null(TrackAnalysis::FingerprintVersion))
+                    .to_owned(),
+            )
+            .await?;
+
+        // The shared version cannot tell which kind was current, so only the kinds whose algorithms
+        // are still at their first version are kept; keys are detected again
+        for (version, analyzed) in [
+            (TrackAnalysis::BpmVersion, TrackAnalysis::BpmAnalyzedAt),
+            (TrackAnalysis::LoudnessVersion, TrackAnalysis::LoudnessAnalyzedAt),
+            (TrackAnalysis::FingerprintVersion, TrackAnalysis::Fingerprint),
+        ] {
+            manager
+                .exec_stmt(
+                    Query::update()
+                        .table(TrackAnalysis::Table)
+                        .value(version, 1)
+                        .and_where(Expr::col(analyzed).is_not_null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .drop_column(TrackAnalysis::AlgorithmVersion)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TrackAnalysis::Table)
+                    .add_column(integer(TrackAnalysis::AlgorithmVersion).default(1))
+                    .drop_column(TrackAnalysis::BpmVersion)
+                    .drop_column(TrackAnalysis::KeyVersion)
+                    .drop_column(TrackAnalysis::LoudnessVersion)
+                    .drop_column(TrackAnalysis::FingerprintVersion)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackAnalysis {
+    Table,
+    AlgorithmVersion,
+    BpmVersion,
+    KeyVersion,
+    LoudnessVersion,
+    FingerprintVersion,
+    BpmAnalyzedAt,
+    LoudnessAnalyzedAt,
+    Fingerprint,
+}
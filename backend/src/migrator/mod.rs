@@ -16,6 +16,21 @@ mod m20250920_222456_add_track_details_to_playlist_items;
 mod m20250127_000001_create_saved_albums_table;
 mod m20250927_000001_add_bpm_to_saved_tracks;
 mod m20250928_000001_add_key_fields_to_saved_tracks;
+mod m20251018_000001_create_user_search_preferences_table;
+mod m20251019_000001_create_track_analysis_table;
+mod m20251020_000001_create_analysis_jobs_table;
+mod m20251021_000001_allow_library_analysis_jobs;
+mod m20251022_000001_add_beat_grid_to_track_analysis;
+mod m20251023_000001_add_bpm_confidence_to_track_analysis;
+mod m20251023_000002_create_user_bpm_ranges_table;
+mod m20251024_000001_add_key_timeline_to_track_analysis;
+mod m20251025_000001_add_loudness_to_track_analysis;
+mod m20251026_000001_add_structure_to_track_analysis;
+mod m20251027_000001_add_descriptors_to_track_analysis;
+mod m20251028_000001_add_chord_timeline_to_track_analysis;
+mod m20251029_000001_add_fingerprint_to_track_analysis;
+mod m20251030_000001_create_user_samples_table;
+mod m20251031_000001_add_analysis_versions_to_track_analysis;
 
 pub struct Migrator;
 
@@ -39,6 +54,21 @@ impl MigratorTrait for Migrator {
             Box::new(m20250127_000001_create_saved_albums_table::Migration),
             Box::new(m20250927_000001_add_bpm_to_saved_tracks::Migration),
             Box::new(m20250928_000001_add_key_fields_to_saved_tracks::Migration),
+            Box::new(m20251018_000001_create_user_search_preferences_table::Migration),
+            Box::new(m20251019_000001_create_track_analysis_table::Migration),
+            Box::new(m20251020_000001_create_analysis_jobs_table::Migration),
+            Box::new(m20251021_000001_allow_library_analysis_jobs::Migration),
+            Box::new(m20251022_000001_add_beat_grid_to_track_analysis::Migration),
+            Box::new(m20251023_000001_add_bpm_confidence_to_track_analysis::Migration),
+            Box::new(m20251023_000002_create_user_bpm_ranges_table::Migration),
+            Box::new(m20251024_000001_add_key_timeline_to_track_analysis::Migration),
+            Box::new(m20251025_000001_add_loudness_to_track_analysis::Migration),
+            Box::new(m20251026_000001_add_structure_to_track_analysis::Migration),
+            Box::new(m20251027_000001_add_descriptors_to_track_analysis::Migration),
+            Box::new(m20251028_000001_add_chord_timeline_to_track_analysis::Migration),
+            Box::new(m20251029_000001_add_fingerprint_to_track_analysis::Migration),
+            Box::new(m20251030_000001_create_user_samples_table::Migration),
+            Box::new(m20251031_000001_add_analysis_versions_to_track_analysis::Migration),
         ]
     }
 }
@@ -0,0 +1,152 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::{Set, ActiveModelBehavior};
+use serde::{Deserialize, Serialize};
+use uuid::{Uuid, Timestamp};
+use chrono::NaiveDateTime;
+
+// Job states
+pub const JOB_STATUS_QUEUED: &str = "queued";
+pub const JOB_STATUS_RUNNING: &str = "running";
+pub const JOB_STATUS_COMPLETED: &str = "completed";
+pub const JOB_STATUS_FAILED: &str = "failed";
+pub const JOB_STATUS_CANCELLED: &str = "cancelled";
+
+// Analyses a job can run
+pub const JOB_KIND_BPM: &str = "bpm";
+pub const JOB_KIND_KEY: &str = "key";
+pub const JOB_KIND_LOUDNESS: &str = "loudness";
+pub const JOB_KIND_FINGERPRINT: &str = "fingerprint";
+pub const JOB_KIND_SPECTROGRAM: &str = "spectrogram"; // BPM analysis that also renders the spectrogram images
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "analysis_jobs")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: Uuid,
+    pub user_id: Option<Uuid>, // User who queued the job, None for library scans
+    pub batch_id: Option<Uuid>, // Shared by jobs queued together (e.g. a whole playlist)
+    pub source: String,
+    pub track_id: String,
+    pub title: Option<String>,
+    pub stream_url: Option<String>, // Resolved by the worker when missing
+    pub kinds: String, // Comma-separated analyses, e.g. "bpm,key,loudness"
+    pub status: String, // "queued", "running", "completed", "failed" or "cancelled"
+    pub progress: f32, // 0.0 to 1.0
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub run_after: NaiveDateTime, // Not picked up before this time (retry backoff)
+    pub created_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::models::user::Entity",
+        from = "Column::UserId",
+        to = "crate::models::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<crate::models::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        let now = chrono::Utc::now().naive_utc();
+        Self {
+            id: Set(Uuid::new_v7(Timestamp::now(uuid::NoContext))),
+            status: Set(JOB_STATUS_QUEUED.to_string()),
+            progress: Set(0.0),
+            attempts: Set(0),
+            run_after: Set(now),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..ActiveModelTrait::default()
+        }
+    }
+}
+
+impl Model {
+    pub fn kind_list(&self) -> Vec<String> {
+        self.kinds
+            .split(',')
+            .map(|kind| kind.trim().to_string())
+            .filter(|kind| !kind.is_empty())
+            .collect()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status.as_str(), JOB_STATUS_COMPLETED | JOB_STATUS_FAILED | JOB_STATUS_CANCELLED)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnalysisJobResponseDto {
+    pub id: Uuid,
+    pub batch_id: Option<Uuid>,
+    pub source: String,
+    pub track_id: String,
+    pub title: Option<String>,
+    pub kinds: Vec<String>,
+    pub status: String,
+    pub progress: f32,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub run_after: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+impl From<Model> for AnalysisJobResponseDto {
+    fn from(job: Model) -> Self {
+        Self {
+            kinds: job.kind_list(),
+            id: job.id,
+            batch_id: job.batch_id,
+            source: job.source,
+            track_id: job.track_id,
+            title: job.title,
+            status: job.status,
+            progress: job.progress,
+            attempts: job.attempts,
+            max_attempts: job.max_attempts,
+            last_error: job.last_error,
+            run_after: job.run_after,
+            created_at: job.created_at,
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnalysisTrackDto {
+    pub track_id: String,
+    pub source: String,
+    pub title: Option<String>,
+    pub stream_url: Option<String>,
+    pub content_hash: Option<String>, // Current hash of the audio, so changed files are analyzed again
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnqueueAnalysisDto {
+    pub tracks: Vec<AnalysisTrackDto>,
+    pub kinds: Option<Vec<String>>, // Defaults to all analyses
+    pub force: Option<bool>, // Also queue tracks that already have current results
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnqueueCollectionDto {
+    pub kinds: Option<Vec<String>>,
+    pub force: Option<bool>,
+}
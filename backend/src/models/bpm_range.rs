@@ -0,0 +1,65 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use chrono::NaiveDateTime;
+
+use crate::services::bpm_estimate::BpmRange;
+
+/// Genre of a user's default range, used for tracks without a genre-specific range
+pub const DEFAULT_GENRE: &str = "";
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "user_bpm_ranges")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub genre: String, // Lowercase genre name, empty for the user's default range
+    pub min_bpm: f32,
+    pub max_bpm: f32,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::models::user::Entity",
+        from = "Column::UserId",
+        to = "crate::models::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<crate::models::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Model {
+    pub fn range(&self) -> BpmRange {
+        BpmRange {
+            min: self.min_bpm,
+            max: self.max_bpm,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BpmRangeDto {
+    pub genre: Option<String>, // None for the default range
+    pub min_bpm: f32,
+    pub max_bpm: f32,
+}
+
+impl From<Model> for BpmRangeDto {
+    fn from(range: Model) -> Self {
+        Self {
+            genre: (range.genre != DEFAULT_GENRE).then_some(range.genre),
+            min_bpm: range.min_bpm,
+            max_bpm: range.max_bpm,
+        }
+    }
+}
@@ -9,6 +9,11 @@ pub mod user_session;
 pub mod saved_track;
 pub mod saved_album;
 pub mod queue_item;
+pub mod search_preference;
+pub mod track_analysis;
+pub mod analysis_job;
+pub mod bpm_range;
+pub mod sample;
 
 // Re-export specific entities to avoid namespace conflicts
 pub use user::{Entity as UserEntity, Model as UserModel, ActiveModel as UserActiveModel, Column as UserColumn};
@@ -22,6 +27,11 @@ pub use user_session::{Entity as UserSessionEntity, Model as UserSessionModel, A
 pub use saved_track::{Entity as SavedTrackEntity, Model as SavedTrackModel, ActiveModel as SavedTrackActiveModel, Column as SavedTrackColumn};
 pub use saved_album::{Entity as SavedAlbumEntity, Model as SavedAlbumModel, ActiveModel as SavedAlbumActiveModel, Column as SavedAlbumColumn};
 pub use queue_item::{Entity as QueueItemEntity, Model as QueueItemModel, ActiveModel as QueueItemActiveModel, Column as QueueItemColumn};
+pub use search_preference::{Entity as SearchPreferenceEntity, Model as SearchPreferenceModel, ActiveModel as SearchPreferenceActiveModel, Column as SearchPreferenceColumn};
+pub use track_analysis::{Entity as TrackAnalysisEntity, Model as TrackAnalysisModel, ActiveModel as TrackAnalysisActiveModel, Column as TrackAnalysisColumn};
+pub use analysis_job::{Entity as AnalysisJobEntity, Model as AnalysisJobModel, ActiveModel as AnalysisJobActiveModel, Column as AnalysisJobColumn};
+pub use bpm_range::{Entity as BpmRangeEntity, Model as BpmRangeModel, ActiveModel as BpmRangeActiveModel, Column as BpmRangeColumn};
+pub use sample::{Entity as SampleEntity, Model as SampleModel, ActiveModel as SampleActiveModel, Column as SampleColumn};
 
 // Re-export DTOs without prefix
 pub use user::{CreateUserDto, LoginDto, UserResponseDto};
@@ -32,3 +42,8 @@ pub use playlist_item::{PlaylistItemResponseDto, AddPlaylistItemDto, ReorderPlay
 pub use playlist_song::{PlaylistSongResponseDto, AddSongToPlaylistDto};
 pub use streaming_service::{StreamingServiceResponseDto, ConnectServiceDto};
 pub use queue_item::{QueueItemResponseDto, AddToQueueDto, ReorderQueueDto};
+pub use search_preference::SearchPreferencesDto;
+pub use track_analysis::{TrackAnalysisDto, LoudnessDto};
+pub use analysis_job::{AnalysisJobResponseDto, AnalysisTrackDto, EnqueueAnalysisDto, EnqueueCollectionDto};
+pub use bpm_range::BpmRangeDto;
+pub use sample::{CreateSampleDto, SampleResponseDto};
@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use chrono::NaiveDateTime;
 use uuid::Uuid;
 
+use super::track_analysis::TrackAnalysisDto;
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "playlist_items")]
 pub struct Model {
@@ -79,6 +81,8 @@ pub struct PlaylistItemResponseDto {
     pub cover_url: Option<String>,
     pub is_playlist: bool,
     pub playlist_name: Option<String>,
+    #[serde(flatten)]
+    pub analysis: TrackAnalysisDto, // Filled in from track_analysis by the handlers
 }
 
 impl From<Model> for PlaylistItemResponseDto {
@@ -97,6 +101,7 @@ impl From<Model> for PlaylistItemResponseDto {
             cover_url: model.cover_url,
             is_playlist: model.item_type == "playlist",
             playlist_name: model.playlist_name,
+            analysis: TrackAnalysisDto::default(),
         }
     }
 }
@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 use uuid::{Uuid, Timestamp};
 use chrono::NaiveDateTime;
 
+use super::track_analysis::TrackAnalysisDto;
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "queue_items")]
 pub struct Model {
@@ -59,6 +61,8 @@ pub struct QueueItemResponseDto {
     pub cover_url: Option<String>,
     pub position: i32,
     pub added_at: NaiveDateTime,
+    #[serde(flatten)]
+    pub analysis: TrackAnalysisDto, // Filled in from track_analysis by the handlers
 }
 
 impl From<Model> for QueueItemResponseDto {
@@ -74,6 +78,7 @@ impl From<Model> for QueueItemResponseDto {
             cover_url: item.cover_url,
             position: item.position,
             added_at: item.added_at,
+            analysis: TrackAnalysisDto::default(),
         }
     }
 }
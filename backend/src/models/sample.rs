@@ -0,0 +1,109 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use chrono::NaiveDateTime;
+
+/// A loop cut from a track into a user's samples collection
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "user_samples")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub source: String,
+    pub track_id: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub start_beat: i32, // Index into the track's beat grid
+    pub bars: i32,
+    pub beats_per_bar: i32,
+    pub bpm: f32, // Tempo over the loop
+    pub start_time: f64, // Seconds into the track
+    pub duration: f64, // Seconds
+    pub fade_ms: i32, // Fade at each edge, 0 for a hard cut
+    pub sample_rate: i32,
+    pub channels: i32,
+    pub format: String, // "wav" or "flac"
+    pub file_size: i64, // In bytes
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::models::user::Entity",
+        from = "Column::UserId",
+        to = "crate::models::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<crate::models::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSampleDto {
+    pub source: String,
+    pub track_id: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub name: Option<String>, // Defaults to the track title with the loop position
+    pub start_beat: usize, // Index into the track's beat grid
+    pub bars: Option<u32>, // 4 by default
+    pub snap_to_bar: Option<bool>, // Move the start to the nearest downbeat
+    pub fade_ms: Option<u32>, // Fade at each edge, none by default
+    pub format: Option<String>, // "wav" (default) or "flac"
+}
+
+#[derive(Debug, Serialize)]
+pub struct SampleResponseDto {
+    pub id: Uuid,
+    pub name: String,
+    pub source: String,
+    pub track_id: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub start_beat: i32,
+    pub bars: i32,
+    pub beats_per_bar: i32,
+    pub bpm: f32,
+    pub start_time: f64,
+    pub duration: f64,
+    pub fade_ms: i32,
+    pub sample_rate: i32,
+    pub channels: i32,
+    pub format: String,
+    pub file_size: i64,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<Model> for SampleResponseDto {
+    fn from(sample: Model) -> Self {
+        Self {
+            id: sample.id,
+            name: sample.name,
+            source: sample.source,
+            track_id: sample.track_id,
+            title: sample.title,
+            artist: sample.artist,
+            start_beat: sample.start_beat,
+            bars: sample.bars,
+            beats_per_bar: sample.beats_per_bar,
+            bpm: sample.bpm,
+            start_time: sample.start_time,
+            duration: sample.duration,
+            fade_ms: sample.fade_ms,
+            sample_rate: sample.sample_rate,
+            channels: sample.channels,
+            format: sample.format,
+            file_size: sample.file_size,
+            created_at: sample.created_at,
+        }
+    }
+}
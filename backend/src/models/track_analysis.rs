@@ -0,0 +1,247 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::{Set, ActiveModelBehavior};
+use serde::{Deserialize, Serialize};
+use uuid::{Uuid, Timestamp};
+use chrono::NaiveDateTime;
+
+use crate::services::beat_grid::BeatGrid;
+use crate::services::bpm_estimate::BpmCandidate;
+use crate::services::chord_analysis::ChordSegment;
+use crate::services::fingerprint::{AudioQuality, TrackFingerprint};
+use crate::services::key_analysis::{KeyCandidate, KeyProfile, KeySegment};
+use crate::services::loudness_analysis::{LoudnessHistogram, peak_to_db, replaygain_gain};
+use crate::services::track_descriptors::TrackDescriptors;
+use crate::services::track_structure::TrackStructure;
+
+// Versions of the analysis algorithms, one per kind; results of older versions are recomputed on request
+pub const BPM_ALGORITHM_VERSION: i32 = 1;
+/// 2: keys are detected with the Temperley profile by default
+pub const KEY_ALGORITHM_VERSION: i32 = 2;
+pub const LOUDNESS_ALGORITHM_VERSION: i32 = 1;
+pub const FINGERPRINT_ALGORITHM_VERSION: i32 = 1;
+
+/// Analysis results of a track, shared by all users
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "track_analysis")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: Uuid,
+    pub source: String, // "server", "qobuz", "spotify", etc.
+    pub track_id: String,
+    pub content_hash: Option<String>, // SHA-256 of the analyzed audio file, when known
+    pub bpm: Option<f32>, // None after an analysis that could not determine the tempo
+    pub bpm_confidence: Option<f32>, // 0.0 to 1.0
+    pub bpm_candidates: Option<String>, // JSON list of alternative tempos with their scores
+    pub key_name: Option<String>, // Musical key in standard notation (e.g., "C#", "Am")
+    pub camelot: Option<String>, // Camelot notation (e.g., "8A", "9B")
+    pub key_confidence: Option<f32>,
+    pub key_profile: Option<String>, // Key templates used, see KeyProfile::name
+    pub key_runner_up: Option<String>, // JSON KeyCandidate
+    pub key_timeline: Option<String>, // JSON list of KeySegment
+    pub chord_timeline: Option<String>, // JSON list of ChordSegment, analyzed with the key
+    pub beat_grid: Option<String>, // Compact JSON, see BeatGrid::to_compact_json
+    pub structure: Option<String>, // JSON TrackStructure, analyzed with the BPM
+    pub energy: Option<f32>, // Descriptors analyzed with the BPM, see TrackDescriptors
+    pub danceability: Option<f32>,
+    pub pulse_clarity: Option<f32>,
+    pub brightness: Option<f32>, // Hz
+    pub dynamic_range: Option<f32>, // dB
+    pub acousticness: Option<f32>,
+    pub integrated_loudness: Option<f32>, // LUFS, None for silent tracks
+    pub loudness_range: Option<f32>, // LU
+    pub true_peak: Option<f32>, // Linear, 1.0 is 0 dBTP
+    pub loudness_histogram: Option<String>, // Compact JSON, see LoudnessHistogram::to_compact_json
+    pub album_loudness: Option<f32>, // LUFS of the album the track was last measured with
+    pub album_peak: Option<f32>, // Linear
+    pub fingerprint: Option<String>, // Compressed Chromaprint fingerprint, see Fingerprint::encode
+    pub duration: Option<f32>, // Seconds, with the fingerprint
+    pub codec: Option<String>, // Audio quality of the fingerprinted file, see AudioQuality
+    pub sample_rate: Option<i32>,
+    pub bit_depth: Option<i32>,
+    pub bitrate: Option<i32>, // kbps
+    pub bpm_version: Option<i32>, // Algorithm versions the results of each kind were computed with
+    pub key_version: Option<i32>,
+    pub loudness_version: Option<i32>,
+    pub fingerprint_version: Option<i32>,
+    pub bpm_analyzed_at: Option<NaiveDateTime>,
+    pub key_analyzed_at: Option<NaiveDateTime>,
+    pub loudness_analyzed_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {
+    fn new() -> Self {
+        let now = chrono::Utc::now().naive_utc();
+        Self {
+            id: Set(Uuid::new_v7(Timestamp::now(uuid::NoContext))),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..ActiveModelTrait::default()
+        }
+    }
+}
+
+impl Model {
+    /// Whether the BPM, its confidence, beat grid, structure and descriptors were computed by the current algorithms.
+    /// An undetermined tempo is current too, so it is not analyzed again on every request.
+    pub fn has_current_bpm(&self) -> bool {
+        self.bpm_analyzed_at.is_some()
+            && self.bpm_confidence.is_some()
+            && (self.bpm.is_none() || self.beat_grid.is_some())
+            && self.structure.is_some()
+            && self.energy.is_some()
+            && self.bpm_version.is_some_and(|version| version >= BPM_ALGORITHM_VERSION)
+    }
+
+    pub fn bpm_candidates(&self) -> Vec<BpmCandidate> {
+        self.bpm_candidates
+            .as_deref()
+            .and_then(|candidates| serde_json::from_str(candidates).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn beat_grid(&self) -> Option<BeatGrid> {
+        self.beat_grid.as_deref().and_then(BeatGrid::from_compact_json)
+    }
+
+    pub fn structure(&self) -> Option<TrackStructure> {
+        self.structure.as_deref().and_then(|structure| serde_json::from_str(structure).ok())
+    }
+
+    pub fn descriptors(&self) -> Option<TrackDescriptors> {
+        Some(TrackDescriptors {
+            energy: self.energy?,
+            danceability: self.danceability?,
+            pulse_clarity: self.pulse_clarity?,
+            brightness: self.brightness?,
+            dynamic_range: self.dynamic_range?,
+            acousticness: self.acousticness?,
+        })
+    }
+
+    /// Whether the key, its timeline and the chords were computed by the current algorithms with a profile.
+    /// Only the default profile is stored.
+    pub fn has_current_key(&self, profile: KeyProfile) -> bool {
+        self.key_name.is_some()
+            && self.camelot.is_some()
+            && self.key_timeline.is_some()
+            && self.chord_timeline.is_some()
+            && self.key_profile.as_deref() == Some(profile.name())
+            && self.key_version.is_some_and(|version| version >= KEY_ALGORITHM_VERSION)
+    }
+
+    pub fn key_runner_up(&self) -> Option<KeyCandidate> {
+        self.key_runner_up.as_deref().and_then(|runner_up| serde_json::from_str(runner_up).ok())
+    }
+
+    pub fn key_timeline(&self) -> Vec<KeySegment> {
+        self.key_timeline
+            .as_deref()
+            .and_then(|timeline| serde_json::from_str(timeline).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn chord_timeline(&self) -> Vec<ChordSegment> {
+        self.chord_timeline
+            .as_deref()
+            .and_then(|timeline| serde_json::from_str(timeline).ok())
+            .unwrap_or_default()
+    }
+
+    /// Whether loudness and its block histogram were computed by the current algorithms
+    pub fn has_current_loudness(&self) -> bool {
+        self.loudness_analyzed_at.is_some()
+            && self.loudness_histogram.is_some()
+            && self.loudness_version.is_some_and(|version| version >= LOUDNESS_ALGORITHM_VERSION)
+    }
+
+    /// Whether the fingerprint and audio quality were computed by the current algorithms
+    pub fn has_current_fingerprint(&self) -> bool {
+        self.fingerprint.is_some()
+            && self.codec.is_some()
+            && self.fingerprint_version.is_some_and(|version| version >= FINGERPRINT_ALGORITHM_VERSION)
+    }
+
+    pub fn track_fingerprint(&self) -> Option<TrackFingerprint> {
+        Some(TrackFingerprint {
+            fingerprint: self.fingerprint.clone()?,
+            duration: self.duration.unwrap_or(0.0),
+            quality: AudioQuality::new(
+                self.codec.clone()?,
+                self.sample_rate.unwrap_or(0) as u32,
+                self.bit_depth.map(|bits| bits as u32),
+                self.bitrate.map(|kbps| kbps as u32),
+            ),
+        })
+    }
+
+    pub fn loudness_histogram(&self) -> Option<LoudnessHistogram> {
+        self.loudness_histogram.as_deref().and_then(LoudnessHistogram::from_compact_json)
+    }
+
+    pub fn loudness(&self) -> Option<LoudnessDto> {
+        if !self.has_current_loudness() {
+            return None;
+        }
+        let true_peak = self.true_peak.unwrap_or(0.0);
+        Some(LoudnessDto {
+            integrated_lufs: self.integrated_loudness,
+            loudness_range: self.loudness_range.unwrap_or(0.0),
+            true_peak,
+            true_peak_dbtp: peak_to_db(true_peak),
+            track_gain: self.integrated_loudness.map(replaygain_gain),
+            album_loudness: self.album_loudness,
+            album_gain: self.album_loudness.map(replaygain_gain),
+            album_peak: self.album_peak,
+        })
+    }
+}
+
+/// Loudness of a track with ReplayGain 2.0 gains, for clients to normalize playback volume
+#[derive(Debug, Clone, Serialize)]
+pub struct LoudnessDto {
+    pub integrated_lufs: Option<f32>,
+    pub loudness_range: f32,
+    pub true_peak: f32,
+    pub true_peak_dbtp: f32,
+    pub track_gain: Option<f32>, // dB to reach the -18 LUFS reference
+    pub album_loudness: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+/// Analysis fields attached to saved tracks, queue items and playlist items
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TrackAnalysisDto {
+    pub bpm: Option<f32>,
+    pub bpm_confidence: Option<f32>,
+    pub key_name: Option<String>,
+    pub camelot: Option<String>,
+    pub key_confidence: Option<f32>,
+    pub energy: Option<f32>,
+    pub danceability: Option<f32>,
+    pub brightness: Option<f32>,
+    pub dynamic_range: Option<f32>,
+    pub acousticness: Option<f32>,
+}
+
+impl From<&Model> for TrackAnalysisDto {
+    fn from(analysis: &Model) -> Self {
+        Self {
+            bpm: analysis.bpm,
+            bpm_confidence: analysis.bpm_confidence,
+            key_name: analysis.key_name.clone(),
+            camelot: analysis.camelot.clone(),
+            key_confidence: analysis.key_confidence,
+            energy: analysis.energy,
+            danceability: analysis.danceability,
+            brightness: analysis.brightness,
+            dynamic_range: analysis.dynamic_range,
+            acousticness: analysis.acousticness,
+        }
+    }
+}
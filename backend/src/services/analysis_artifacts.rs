@@ -0,0 +1,184 @@
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// Artifact configuration
+const GC_INTERVAL: Duration = Duration::from_secs(6 * 3600);
+const DEFAULT_MAX_AGE_DAYS: u64 = 30;
+const DEFAULT_MAX_TOTAL_MB: u64 = 1024;
+
+/// Image generated by an analysis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Spectrogram,
+    Visualization, // Spectrogram with energy, thresholds and detected beats
+}
+
+impl ArtifactKind {
+    pub const ALL: [ArtifactKind; 2] = [ArtifactKind::Spectrogram, ArtifactKind::Visualization];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArtifactKind::Spectrogram => "spectrogram",
+            ArtifactKind::Visualization => "visualization",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ArtifactKind> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    fn file_name(&self) -> String {
+        format!("{}.png", self.name())
+    }
+}
+
+/// Managed directory of analysis images, one folder per track. Artifacts are removed once
+/// older than ANALYSIS_ARTIFACT_MAX_AGE_DAYS, oldest first when exceeding ANALYSIS_ARTIFACT_MAX_MB.
+#[derive(Debug, Clone)]
+pub struct AnalysisArtifacts {
+    dir: PathBuf,
+    max_age: Duration,
+    max_total_bytes: u64,
+}
+
+impl AnalysisArtifacts {
+    pub fn new(dir: PathBuf, max_age: Duration, max_total_bytes: u64) -> Self {
+        Self { dir, max_age, max_total_bytes }
+    }
+
+    pub fn from_env() -> Self {
+        let number = |name: &str, default: u64| {
+            std::env::var(name).ok().and_then(|value| value.parse::<u64>().ok()).unwrap_or(default)
+        };
+        let dir = std::env::var("ANALYSIS_ARTIFACT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")).join("cache").join("artifacts"));
+        Self::new(
+            dir,
+            Duration::from_secs(number("ANALYSIS_ARTIFACT_MAX_AGE_DAYS", DEFAULT_MAX_AGE_DAYS) * 24 * 3600),
+            number("ANALYSIS_ARTIFACT_MAX_MB", DEFAULT_MAX_TOTAL_MB) * 1024 * 1024,
+        )
+    }
+
+    /// Where an artifact of a track is stored; track ids may be paths, so they are hashed
+    pub fn path(&self, source: &str, track_id: &str, kind: ArtifactKind) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(source.as_bytes());
+        hasher.update([0]);
+        hasher.update(track_id.as_bytes());
+        let hash = format!("{:x}", hasher.finalize());
+        let source: String = source.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+        self.dir.join(format!("{}_{}", source, &hash[..32])).join(kind.file_name())
+    }
+
+    /// API path serving an artifact of a track
+    pub fn url(source: &str, track_id: &str, kind: ArtifactKind) -> String {
+        format!(
+            "/api/audio/artifacts/{}?source={}&track_id={}",
+            kind.name(),
+            urlencoding::encode(source),
+            urlencoding::encode(track_id)
+        )
+    }
+
+    /// Collect garbage now and then in the background
+    pub fn start(&self) {
+        let artifacts = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let gc = artifacts.clone();
+                match tokio::task::spawn_blocking(move || gc.collect_garbage()).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(removed)) => tracing::info!("Removed {} old analysis artifacts", removed),
+                    Ok(Err(e)) => tracing::warn!("Analysis artifact cleanup failed: {}", e),
+                    Err(e) => tracing::warn!("Analysis artifact cleanup panicked: {}", e),
+                }
+                tokio::time::sleep(GC_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Remove expired artifacts, then the oldest ones while over the size limit. Returns the number removed.
+    pub fn collect_garbage(&self) -> std::io::Result<usize> {
+        let now = SystemTime::now();
+        let mut files: Vec<(PathBuf, SystemTime, u64)> = Vec::new();
+        let mut removed = 0;
+
+        let track_dirs = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        for track_dir in track_dirs.filter_map(Result::ok).filter(|entry| entry.path().is_dir()) {
+            for file in std::fs::read_dir(track_dir.path())?.filter_map(Result::ok) {
+                let metadata = file.metadata()?;
+                let modified = metadata.modified().unwrap_or(now);
+                if now.duration_since(modified).unwrap_or_default() > self.max_age {
+                    std::fs::remove_file(file.path())?;
+                    removed += 1;
+                } else {
+                    files.push((file.path(), modified, metadata.len()));
+                }
+            }
+        }
+
+        files.sort_by_key(|(_, modified, _)| *modified);
+        let mut total: u64 = files.iter().map(|(_, _, size)| size).sum();
+        for (path, _, size) in files {
+            if total <= self.max_total_bytes {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            total -= size;
+            removed += 1;
+        }
+
+        // Folders of tracks without artifacts left
+        for track_dir in std::fs::read_dir(&self.dir)?.filter_map(Result::ok) {
+            if is_empty_dir(&track_dir.path()) {
+                std::fs::remove_dir(track_dir.path())?;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    std::fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_and_garbage_collection() {
+        let dir = std::env::temp_dir().join(format!("musestruct_artifacts_test_{}", uuid::Uuid::new_v4()));
+        let artifacts = AnalysisArtifacts::new(dir.clone(), Duration::from_secs(3600), 150);
+
+        let path = artifacts.path("server", "Artist/Album/01 Track.flac", ArtifactKind::Spectrogram);
+        assert!(path.starts_with(&dir));
+        assert!(path.ends_with("spectrogram.png"));
+        assert_ne!(path, artifacts.path("qobuz", "Artist/Album/01 Track.flac", ArtifactKind::Spectrogram));
+
+        // Over the size limit, the oldest artifact goes first
+        let tracks = ["1", "2", "3"];
+        for track_id in tracks {
+            let path = artifacts.path("server", track_id, ArtifactKind::Visualization);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, [0u8; 60]).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(artifacts.collect_garbage().unwrap(), 1);
+        let exists = |track_id| artifacts.path("server", track_id, ArtifactKind::Visualization).exists();
+        assert!(!exists("1") && exists("2") && exists("3"));
+        assert!(!artifacts.path("server", "1", ArtifactKind::Visualization).parent().unwrap().exists());
+
+        // Expired artifacts are removed regardless of size
+        let expired = AnalysisArtifacts::new(dir.clone(), Duration::ZERO, u64::MAX);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(expired.collect_garbage().unwrap(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,603 @@
+use async_trait::async_trait;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelBehavior, ActiveModelTrait, ColumnTrait, DatabaseConnection, DbBackend, DbErr, EntityTrait, PaginatorTrait,
+    QueryFilter, QueryOrder, Set, Statement,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::models::analysis_job::{
+    ActiveModel, JOB_KIND_BPM, JOB_KIND_FINGERPRINT, JOB_KIND_KEY, JOB_KIND_LOUDNESS, JOB_KIND_SPECTROGRAM, JOB_STATUS_CANCELLED,
+    JOB_STATUS_COMPLETED, JOB_STATUS_FAILED, JOB_STATUS_QUEUED, JOB_STATUS_RUNNING,
+};
+use crate::models::{AnalysisJobColumn, AnalysisJobEntity, AnalysisJobModel, AnalysisTrackDto, TrackAnalysisModel};
+use crate::services::analysis_artifacts::{AnalysisArtifacts, ArtifactKind};
+use crate::services::track_analysis_store::{AnalysisUpdate, TrackAnalysisStore};
+use crate::services::streaming::{confine_to_music_dir, default_music_dir};
+use crate::services::{
+    is_remote, AnalysisSpan, AudioQuality, AudioSink, AudioStream, DownloadError, Fingerprinter, KeyAnalysisService,
+    KeyProfile, LoudnessMeter, SpectrogramBpmAnalyzer, SpectrogramImages, TempAudioFile, TrackFingerprint, TrackLoudness,
+};
+
+// Queue configuration
+const POLL_INTERVAL: Duration = Duration::from_secs(5); // Fallback when no wake-up arrives
+const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+const RETRY_BASE_DELAY_SECS: i64 = 30; // Doubled on every further attempt
+const RETRY_MAX_DELAY_SECS: i64 = 3600;
+
+pub const ALL_JOB_KINDS: [&str; 4] = [JOB_KIND_BPM, JOB_KIND_KEY, JOB_KIND_LOUDNESS, JOB_KIND_FINGERPRINT];
+
+/// Failure of a job run; only retryable failures are attempted again
+#[derive(Debug, Error)]
+pub enum JobError {
+    #[error("{0}")]
+    Retryable(String),
+    #[error("{0}")]
+    Fatal(String),
+}
+
+/// Finds a playable URL or local path for a queued track
+#[async_trait]
+pub trait StreamUrlResolver: Send + Sync {
+    async fn resolve(&self, user_id: Option<Uuid>, source: &str, track_id: &str) -> Result<String, JobError>;
+}
+
+/// Result of queueing a set of tracks
+#[derive(Debug)]
+pub struct EnqueueOutcome {
+    pub batch_id: Uuid,
+    pub jobs: Vec<AnalysisJobModel>,
+    pub already_analyzed: usize, // Tracks skipped because their results are current
+}
+
+/// Progress summary of a batch of jobs
+#[derive(Debug, Default, serde::Serialize)]
+pub struct BatchProgress {
+    pub total: usize,
+    pub queued: usize,
+    pub running: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub progress: f32, // 0.0 to 1.0 over all jobs
+}
+
+/// Persistent queue of analysis jobs, worked off by a pool of background workers.
+/// Jobs live in the database, so queued work survives restarts.
+#[derive(Clone)]
+pub struct AnalysisJobQueue {
+    db: DatabaseConnection,
+    artifacts: AnalysisArtifacts, // Where spectrogram jobs render their images
+    wake: Arc<Notify>,
+}
+
+impl AnalysisJobQueue {
+    pub fn new(db: DatabaseConnection, artifacts: AnalysisArtifacts) -> Self {
+        Self {
+            db,
+            artifacts,
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    /// One worker per available CPU
+    pub fn worker_count() -> usize {
+        std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    }
+
+    /// Requeue jobs interrupted by a restart and spawn the worker pool
+    pub async fn start(&self, resolver: Arc<dyn StreamUrlResolver>) -> Result<(), DbErr> {
+        let interrupted = AnalysisJobEntity::update_many()
+            .col_expr(AnalysisJobColumn::Status, Expr::value(JOB_STATUS_QUEUED))
+            .col_expr(AnalysisJobColumn::Progress, Expr::value(0.0f32))
+            .filter(AnalysisJobColumn::Status.eq(JOB_STATUS_RUNNING))
+            .exec(&self.db)
+            .await?;
+        if interrupted.rows_affected > 0 {
+            tracing::info!("Requeued {} interrupted analysis jobs", interrupted.rows_affected);
+        }
+
+        let workers = Self::worker_count();
+        tracing::info!("Starting {} analysis workers", workers);
+        for worker in 0..workers {
+            let queue = self.clone();
+            let resolver = resolver.clone();
+            tokio::spawn(async move { queue.run_worker(worker, resolver).await });
+        }
+        Ok(())
+    }
+
+    /// Queue analyses of tracks as one batch; analyses with current results are skipped unless forced.
+    /// Jobs without a user are queued by the library scanner.
+    pub async fn enqueue(
+        &self,
+        user_id: Option<Uuid>,
+        tracks: Vec<AnalysisTrackDto>,
+        kinds: &[String],
+        force: bool,
+    ) -> Result<EnqueueOutcome, DbErr> {
+        let batch_id = Uuid::new_v4();
+        let mut jobs = Vec::new();
+        let mut already_analyzed = 0;
+
+        for track in tracks {
+            // Only the analyses without current results are run
+            let kinds = if force { kinds.to_vec() } else { self.missing_kinds(&track, kinds).await? };
+            if kinds.is_empty() {
+                already_analyzed += 1;
+                continue;
+            }
+
+            // Reuse the user's pending job for the same track instead of analyzing twice
+            let pending = AnalysisJobEntity::find()
+                .filter(user_filter(user_id))
+                .filter(AnalysisJobColumn::Source.eq(&track.source))
+                .filter(AnalysisJobColumn::TrackId.eq(&track.track_id))
+                .filter(AnalysisJobColumn::Kinds.eq(kinds.join(",")))
+                .filter(AnalysisJobColumn::Status.is_in([JOB_STATUS_QUEUED, JOB_STATUS_RUNNING]))
+                .one(&self.db)
+                .await?;
+            if let Some(job) = pending {
+                jobs.push(job);
+                continue;
+            }
+
+            let mut job = <ActiveModel as ActiveModelBehavior>::new();
+            job.user_id = Set(user_id);
+            job.batch_id = Set(Some(batch_id));
+            // Stream URLs from clients are not kept for remote sources, the worker resolves them
+            job.stream_url = Set(track.stream_url.filter(|_| track.source == "server"));
+            job.source = Set(track.source);
+            job.track_id = Set(track.track_id);
+            job.title = Set(track.title);
+            job.kinds = Set(kinds.join(","));
+            job.max_attempts = Set(DEFAULT_MAX_ATTEMPTS);
+            jobs.push(job.insert(&self.db).await?);
+        }
+
+        self.wake.notify_waiters();
+        Ok(EnqueueOutcome { batch_id, jobs, already_analyzed })
+    }
+
+    /// Whether an analysis of this kind is queued or running for the track, for any user
+    pub async fn has_pending(&self, source: &str, track_id: &str, kind: &str) -> Result<bool, DbErr> {
+        let pending = AnalysisJobEntity::find()
+            .filter(AnalysisJobColumn::Source.eq(source))
+            .filter(AnalysisJobColumn::TrackId.eq(track_id))
+            .filter(AnalysisJobColumn::Kinds.contains(kind))
+            .filter(AnalysisJobColumn::Status.is_in([JOB_STATUS_QUEUED, JOB_STATUS_RUNNING]))
+            .count(&self.db)
+            .await?;
+        Ok(pending > 0)
+    }
+
+    async fn missing_kinds(&self, track: &AnalysisTrackDto, kinds: &[String]) -> Result<Vec<String>, DbErr> {
+        let Some(analysis) = TrackAnalysisStore::find(&self.db, &track.source, &track.track_id).await? else {
+            return Ok(kinds.to_vec());
+        };
+        // Results of an earlier version of the file don't count
+        if track.content_hash.is_some() && analysis.content_hash != track.content_hash {
+            return Ok(kinds.to_vec());
+        }
+        Ok(kinds.iter().filter(|kind| !is_current(&analysis, kind)).cloned().collect())
+    }
+
+    pub async fn get(&self, user_id: Uuid, job_id: Uuid) -> Result<Option<AnalysisJobModel>, DbErr> {
+        AnalysisJobEntity::find_by_id(job_id)
+            .filter(AnalysisJobColumn::UserId.eq(user_id))
+            .one(&self.db)
+            .await
+    }
+
+    pub async fn list(
+        &self,
+        user_id: Option<Uuid>,
+        status: Option<&str>,
+        batch_id: Option<Uuid>,
+    ) -> Result<Vec<AnalysisJobModel>, DbErr> {
+        let mut select = AnalysisJobEntity::find().filter(user_filter(user_id));
+        if let Some(status) = status {
+            select = select.filter(AnalysisJobColumn::Status.eq(status));
+        }
+        if let Some(batch_id) = batch_id {
+            select = select.filter(AnalysisJobColumn::BatchId.eq(batch_id));
+        }
+        select.order_by_desc(AnalysisJobColumn::CreatedAt).all(&self.db).await
+    }
+
+    pub async fn batch_progress(&self, user_id: Option<Uuid>, batch_id: Uuid) -> Result<BatchProgress, DbErr> {
+        let jobs = self.list(user_id, None, Some(batch_id)).await?;
+        let mut summary = BatchProgress { total: jobs.len(), ..Default::default() };
+        let mut progress_sum = 0.0;
+
+        for job in &jobs {
+            match job.status.as_str() {
+                JOB_STATUS_QUEUED => summary.queued += 1,
+                JOB_STATUS_RUNNING => summary.running += 1,
+                JOB_STATUS_COMPLETED => summary.completed += 1,
+                JOB_STATUS_FAILED => summary.failed += 1,
+                _ => summary.cancelled += 1,
+            }
+            progress_sum += if job.is_finished() { 1.0 } else { job.progress };
+        }
+        if !jobs.is_empty() {
+            summary.progress = progress_sum / jobs.len() as f32;
+        }
+        Ok(summary)
+    }
+
+    /// Cancel queued or running jobs; running jobs stop at their next step. Returns the number cancelled.
+    pub async fn cancel(&self, user_id: Uuid, job_id: Option<Uuid>, batch_id: Option<Uuid>) -> Result<u64, DbErr> {
+        let now = chrono::Utc::now().naive_utc();
+        let mut update = AnalysisJobEntity::update_many()
+            .col_expr(AnalysisJobColumn::Status, Expr::value(JOB_STATUS_CANCELLED))
+            .col_expr(AnalysisJobColumn::FinishedAt, Expr::value(now))
+            .col_expr(AnalysisJobColumn::UpdatedAt, Expr::value(now))
+            .filter(AnalysisJobColumn::UserId.eq(user_id))
+            .filter(AnalysisJobColumn::Status.is_in([JOB_STATUS_QUEUED, JOB_STATUS_RUNNING]));
+        if let Some(job_id) = job_id {
+            update = update.filter(AnalysisJobColumn::Id.eq(job_id));
+        }
+        if let Some(batch_id) = batch_id {
+            update = update.filter(AnalysisJobColumn::BatchId.eq(batch_id));
+        }
+        Ok(update.exec(&self.db).await?.rows_affected)
+    }
+
+    async fn run_worker(&self, worker: usize, resolver: Arc<dyn StreamUrlResolver>) {
+        loop {
+            match self.claim_next().await {
+                Ok(Some(job)) => {
+                    tracing::info!("Worker {} running analysis job {} for {} ({})", worker, job.id, job.track_id, job.source);
+                    let result = self.run_job(&job, resolver.as_ref()).await;
+                    if let Err(e) = self.finish_job(&job, result).await {
+                        tracing::error!("Failed to record result of analysis job {}: {}", job.id, e);
+                    }
+                }
+                Ok(None) => {
+                    tokio::select! {
+                        _ = self.wake.notified() => {}
+                        _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Worker {} failed to claim an analysis job: {}", worker, e);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    // Atomically take the oldest due job; SKIP LOCKED keeps workers from taking the same one
+    async fn claim_next(&self) -> Result<Option<AnalysisJobModel>, DbErr> {
+        let now = chrono::Utc::now().naive_utc();
+        AnalysisJobEntity::find()
+            .from_raw_sql(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                r#"UPDATE analysis_jobs
+                SET status = $1, attempts = attempts + 1, progress = 0, started_at = $2, updated_at = $2
+                WHERE id = (
+                    SELECT id FROM analysis_jobs
+                    WHERE status = $3 AND run_after <= $2
+                    ORDER BY created_at
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING *"#,
+                [JOB_STATUS_RUNNING.into(), now.into(), JOB_STATUS_QUEUED.into()],
+            ))
+            .one(&self.db)
+            .await
+    }
+
+    async fn is_cancelled(&self, job_id: Uuid) -> bool {
+        match AnalysisJobEntity::find_by_id(job_id).one(&self.db).await {
+            Ok(Some(job)) => job.status == JOB_STATUS_CANCELLED,
+            Ok(None) => true,
+            Err(_) => false,
+        }
+    }
+
+    async fn set_progress(&self, job_id: Uuid, progress: f32) {
+        let result = AnalysisJobEntity::update_many()
+            .col_expr(AnalysisJobColumn::Progress, Expr::value(progress))
+            .col_expr(AnalysisJobColumn::UpdatedAt, Expr::value(chrono::Utc::now().naive_utc()))
+            .filter(AnalysisJobColumn::Id.eq(job_id))
+            .filter(AnalysisJobColumn::Status.eq(JOB_STATUS_RUNNING))
+            .exec(&self.db)
+            .await;
+        if let Err(e) = result {
+            tracing::warn!("Failed to update progress of analysis job {}: {}", job_id, e);
+        }
+    }
+
+    async fn run_job(&self, job: &AnalysisJobModel, resolver: &dyn StreamUrlResolver) -> Result<(), JobError> {
+        // A stored path is only trusted inside the music library; everything else is resolved
+        // through the track's provider
+        let stream_url = match job.stream_url.as_deref().filter(|_| job.source == "server") {
+            Some(path) => {
+                let path = Path::new(path.strip_prefix("file://").unwrap_or(path));
+                confine_to_music_dir(&default_music_dir(), path)
+                    .ok_or_else(|| JobError::Fatal(format!("{:?} is not in the music library", path)))?
+                    .to_string_lossy()
+                    .to_string()
+            }
+            None => resolver.resolve(job.user_id, &job.source, &job.track_id).await?,
+        };
+        self.set_progress(job.id, 0.05).await;
+
+        // Remote audio is downloaded once for all analyses of the job, and removed afterwards
+        if is_remote(&stream_url) {
+            let download = TempAudioFile::download(&stream_url).await.map_err(|e| match e {
+                DownloadError::Transient(message) => JobError::Retryable(message),
+                DownloadError::Rejected(message) => JobError::Fatal(message),
+            })?;
+            self.set_progress(job.id, 0.2).await;
+            self.analyze(job, download.path()).await
+        } else {
+            self.set_progress(job.id, 0.2).await;
+            self.analyze(job, &PathBuf::from(stream_url.strip_prefix("file://").unwrap_or(&stream_url))).await
+        }
+    }
+
+    async fn analyze(&self, job: &AnalysisJobModel, path: &Path) -> Result<(), JobError> {
+        let hash_path = path.to_path_buf();
+        let content_hash = tokio::task::spawn_blocking(move || TrackAnalysisStore::content_hash_of_file(&hash_path))
+            .await
+            .map_err(|e| JobError::Fatal(e.to_string()))?
+            .map_err(|e| JobError::Fatal(format!("Cannot read audio file {:?}: {}", path, e)))?;
+
+        // The same audio may already be analyzed under another track id, e.g. a moved file
+        let same_content = TrackAnalysisStore::find_by_content_hash(&self.db, &content_hash)
+            .await
+            .map_err(|e| JobError::Retryable(format!("Failed to look up analysis: {}", e)))?
+            .filter(|analysis| analysis.source != job.source || analysis.track_id != job.track_id);
+
+        // Reuse what is already known and collect the analyses that need the audio
+        let kinds = job.kind_list();
+        let mut updates = Vec::new();
+        let mut compute = Vec::new();
+        for kind in &kinds {
+            let update = match (kind.as_str(), &same_content) {
+                // A spectrogram job computes the BPM anyway while rendering its images
+                (JOB_KIND_BPM, Some(analysis)) if analysis.has_current_bpm() && !kinds.iter().any(|k| k == JOB_KIND_SPECTROGRAM) => {
+                    AnalysisUpdate::Bpm {
+                        bpm: analysis.bpm,
+                        confidence: analysis.bpm_confidence.unwrap_or_default(),
+                        candidates: analysis.bpm_candidates(),
+                        beat_grid: analysis.beat_grid(),
+                        structure: analysis.structure().unwrap_or_default(),
+                        descriptors: analysis.descriptors().unwrap_or_default(),
+                    }
+                }
+                (JOB_KIND_KEY, Some(analysis)) if analysis.has_current_key(KeyProfile::default()) => AnalysisUpdate::Key {
+                    key_name: analysis.key_name.clone().unwrap_or_default(),
+                    camelot: analysis.camelot.clone().unwrap_or_default(),
+                    confidence: analysis.key_confidence.unwrap_or_default(),
+                    profile: KeyProfile::default(),
+                    runner_up: analysis.key_runner_up(),
+                    timeline: analysis.key_timeline(),
+                    chords: analysis.chord_timeline(),
+                },
+                (JOB_KIND_LOUDNESS, Some(analysis)) if analysis.has_current_loudness() => {
+                    AnalysisUpdate::Loudness(TrackLoudness {
+                        integrated_lufs: analysis.integrated_loudness,
+                        loudness_range: analysis.loudness_range.unwrap_or_default(),
+                        true_peak: analysis.true_peak.unwrap_or_default(),
+                        histogram: analysis.loudness_histogram().unwrap_or_default(),
+                    })
+                }
+                (JOB_KIND_FINGERPRINT, Some(analysis)) if analysis.has_current_fingerprint() => {
+                    match analysis.track_fingerprint() {
+                        Some(fingerprint) => AnalysisUpdate::Fingerprint(fingerprint),
+                        None => {
+                            compute.push(kind.clone());
+                            continue;
+                        }
+                    }
+                }
+                (JOB_KIND_BPM | JOB_KIND_KEY | JOB_KIND_LOUDNESS | JOB_KIND_FINGERPRINT | JOB_KIND_SPECTROGRAM, _) => {
+                    compute.push(kind.clone());
+                    continue;
+                }
+                (other, _) => return Err(JobError::Fatal(format!("Unknown analysis kind: {}", other))),
+            };
+            updates.push(update);
+        }
+
+        // All remaining analyses share one streaming pass over the audio
+        if !compute.is_empty() {
+            if self.is_cancelled(job.id).await {
+                return Ok(());
+            }
+            // Chords follow the beats of a reused beat grid unless the BPM is analyzed in the same pass
+            let beats = updates
+                .iter()
+                .find_map(|update| match update {
+                    AnalysisUpdate::Bpm { beat_grid: Some(grid), .. } => Some(grid.beats.clone()),
+                    _ => None,
+                })
+                .unwrap_or_default();
+            let images = if compute.iter().any(|kind| kind == JOB_KIND_SPECTROGRAM) {
+                SpectrogramImages {
+                    spectrogram: Some(self.artifacts.path(&job.source, &job.track_id, ArtifactKind::Spectrogram)),
+                    visualization: Some(self.artifacts.path(&job.source, &job.track_id, ArtifactKind::Visualization)),
+                }
+            } else {
+                SpectrogramImages::default()
+            };
+            let path_str = path.to_string_lossy().to_string();
+            let computed = tokio::task::spawn_blocking(move || analyze_in_one_pass(&path_str, &compute, beats, images))
+                .await
+                .map_err(|e| JobError::Fatal(e.to_string()))??;
+            updates.extend(computed);
+            self.set_progress(job.id, 0.9).await;
+        }
+
+        let count = updates.len();
+        for (step, update) in updates.into_iter().enumerate() {
+            if self.is_cancelled(job.id).await {
+                return Ok(());
+            }
+            TrackAnalysisStore::save(&self.db, &job.source, &job.track_id, Some(content_hash.clone()), update)
+                .await
+                .map_err(|e| JobError::Retryable(format!("Failed to store analysis: {}", e)))?;
+            self.set_progress(job.id, 0.9 + 0.1 * (step + 1) as f32 / count as f32).await;
+        }
+        Ok(())
+    }
+
+    async fn finish_job(&self, job: &AnalysisJobModel, result: Result<(), JobError>) -> Result<(), DbErr> {
+        let now = chrono::Utc::now().naive_utc();
+        let mut update = AnalysisJobEntity::update_many()
+            .col_expr(AnalysisJobColumn::UpdatedAt, Expr::value(now))
+            .filter(AnalysisJobColumn::Id.eq(job.id))
+            // A job cancelled while running keeps its cancelled state
+            .filter(AnalysisJobColumn::Status.eq(JOB_STATUS_RUNNING));
+
+        update = match result {
+            Ok(()) => update
+                .col_expr(AnalysisJobColumn::Status, Expr::value(JOB_STATUS_COMPLETED))
+                .col_expr(AnalysisJobColumn::Progress, Expr::value(1.0f32))
+                .col_expr(AnalysisJobColumn::FinishedAt, Expr::value(now)),
+            Err(JobError::Retryable(message)) if job.attempts < job.max_attempts => {
+                let delay = retry_delay_secs(job.attempts);
+                tracing::warn!("Analysis job {} failed (attempt {}), retrying in {}s: {}", job.id, job.attempts, delay, message);
+                update
+                    .col_expr(AnalysisJobColumn::Status, Expr::value(JOB_STATUS_QUEUED))
+                    .col_expr(AnalysisJobColumn::Progress, Expr::value(0.0f32))
+                    .col_expr(AnalysisJobColumn::LastError, Expr::value(message))
+                    .col_expr(AnalysisJobColumn::RunAfter, Expr::value(now + chrono::Duration::seconds(delay)))
+            }
+            Err(e) => {
+                tracing::error!("Analysis job {} failed: {}", job.id, e);
+                update
+                    .col_expr(AnalysisJobColumn::Status, Expr::value(JOB_STATUS_FAILED))
+                    .col_expr(AnalysisJobColumn::LastError, Expr::value(e.to_string()))
+                    .col_expr(AnalysisJobColumn::FinishedAt, Expr::value(now))
+            }
+        };
+
+        update.exec(&self.db).await.map(|_| ())
+    }
+}
+
+// Jobs of a user, or the library scanner's jobs
+fn user_filter(user_id: Option<Uuid>) -> sea_orm::sea_query::SimpleExpr {
+    match user_id {
+        Some(user_id) => AnalysisJobColumn::UserId.eq(user_id),
+        None => AnalysisJobColumn::UserId.is_null(),
+    }
+}
+
+// Exponential backoff after the given number of attempts
+fn retry_delay_secs(attempts: i32) -> i64 {
+    let exponent = (attempts - 1).clamp(0, 16) as u32;
+    (RETRY_BASE_DELAY_SECS * 2i64.pow(exponent)).min(RETRY_MAX_DELAY_SECS)
+}
+
+// Whether the stored results of an analysis kind are up to date
+fn is_current(analysis: &TrackAnalysisModel, kind: &str) -> bool {
+    match kind {
+        JOB_KIND_BPM => analysis.has_current_bpm(),
+        JOB_KIND_KEY => analysis.has_current_key(KeyProfile::default()),
+        JOB_KIND_LOUDNESS => analysis.has_current_loudness(),
+        JOB_KIND_FINGERPRINT => analysis.has_current_fingerprint(),
+        _ => false,
+    }
+}
+
+// Run the analyses of the given kinds while decoding the file once; decoding errors are not worth retrying
+fn analyze_in_one_pass(
+    path: &str,
+    kinds: &[String],
+    mut beats: Vec<f32>,
+    images: SpectrogramImages,
+) -> Result<Vec<AnalysisUpdate>, JobError> {
+    let mut stream =
+        AudioStream::open(path).map_err(|e| JobError::Fatal(format!("Cannot decode audio file {}: {}", path, e)))?;
+    let wants = |kind: &str| kinds.iter().any(|k| k == kind);
+    let mut bpm = (wants(JOB_KIND_BPM) || wants(JOB_KIND_SPECTROGRAM)).then(|| SpectrogramBpmAnalyzer::with_images(images));
+    let mut key = wants(JOB_KIND_KEY).then(|| KeyAnalysisService::new().analyzer());
+    let mut loudness = wants(JOB_KIND_LOUDNESS).then(|| LoudnessMeter::new(stream.sample_rate, stream.channels));
+    let mut fingerprinter = wants(JOB_KIND_FINGERPRINT).then(Fingerprinter::new);
+
+    let mut sinks: Vec<&mut dyn AudioSink> = Vec::new();
+    if let Some(analyzer) = &mut bpm {
+        sinks.push(analyzer);
+    }
+    if let Some(analyzer) = &mut key {
+        sinks.push(analyzer);
+    }
+    if let Some(meter) = &mut loudness {
+        sinks.push(meter);
+    }
+    if let Some(fingerprinter) = &mut fingerprinter {
+        sinks.push(fingerprinter);
+    }
+    let analyzed = stream
+        .run(AnalysisSpan::from_env(), &mut sinks)
+        .map_err(|e| JobError::Fatal(format!("Cannot decode audio file {}: {}", path, e)))?;
+
+    let mut updates = Vec::new();
+    if let Some(analyzer) = bpm {
+        let result = analyzer
+            .finish()
+            .map_err(|e| JobError::Fatal(format!("BPM analysis failed: {}", e)))?;
+        if let Some(grid) = &result.beat_grid {
+            beats = grid.beats.clone();
+        }
+        updates.push(AnalysisUpdate::Bpm {
+            bpm: result.bpm,
+            confidence: result.confidence,
+            candidates: result.candidates,
+            beat_grid: result.beat_grid,
+            structure: result.structure,
+            descriptors: result.descriptors,
+        });
+    }
+    if let Some(analyzer) = key {
+        let key = analyzer
+            .finish_with_beats(&beats)
+            .map_err(|e| JobError::Fatal(format!("Key analysis failed: {}", e)))?;
+        updates.push(AnalysisUpdate::Key {
+            key_name: key.key_name,
+            camelot: key.camelot,
+            confidence: key.confidence,
+            profile: key.profile,
+            runner_up: key.runner_up,
+            timeline: key.timeline,
+            chords: key.chords,
+        });
+    }
+    if let Some(meter) = loudness {
+        updates.push(AnalysisUpdate::Loudness(meter.finish()));
+    }
+    if let Some(fingerprinter) = fingerprinter {
+        let duration = stream.duration_secs.unwrap_or(analyzed);
+        updates.push(AnalysisUpdate::Fingerprint(TrackFingerprint {
+            fingerprint: fingerprinter.finish().encode(),
+            duration: duration as f32,
+            quality: AudioQuality::of(&stream, Path::new(path), duration),
+        }));
+    }
+    Ok(updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_delay_secs(1), 30);
+        assert_eq!(retry_delay_secs(2), 60);
+        assert_eq!(retry_delay_secs(4), 240);
+        assert_eq!(retry_delay_secs(20), RETRY_MAX_DELAY_SECS);
+    }
+}
@@ -0,0 +1,734 @@
+use anyhow::{Result, anyhow};
+use futures_util::StreamExt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+
+/// Sample rate of the mono signal tempo and key analyses work on
+pub const ANALYSIS_SAMPLE_RATE: u32 = 44100;
+
+// Resampling filter configuration
+const RESAMPLE_ZERO_CROSSINGS: f64 = 12.0; // Sinc lobes on each side of the interpolated sample
+const RESAMPLE_PASSBAND: f64 = 0.95; // Low-pass cutoff as a fraction of the lower Nyquist frequency
+
+// Long file configuration
+const DEFAULT_SEGMENT_COUNT: usize = 4;
+const WAV_CHUNK_FRAMES: usize = 4096;
+
+const DEFAULT_MAX_DOWNLOAD_MB: u64 = 1024; // Remote audio larger than this is not downloaded
+
+/// Part of a track the analyzers see. Very long files (e.g. DJ mixes) can be limited
+/// with ANALYSIS_MAX_DURATION_SECS and ANALYSIS_LONG_FILE_MODE ("truncate" or "segments").
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnalysisSpan {
+    Full,
+    Truncate { max_secs: f64 }, // Only the beginning of the track
+    Segments { max_secs: f64, count: usize }, // Evenly spaced excerpts adding up to max_secs
+}
+
+impl AnalysisSpan {
+    pub fn from_env() -> AnalysisSpan {
+        let Some(max_secs) = std::env::var("ANALYSIS_MAX_DURATION_SECS")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|max_secs| *max_secs > 0.0)
+        else {
+            return AnalysisSpan::Full;
+        };
+
+        match std::env::var("ANALYSIS_LONG_FILE_MODE").as_deref() {
+            Ok("segments") => {
+                let count = std::env::var("ANALYSIS_SEGMENT_COUNT")
+                    .ok()
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .filter(|count| *count > 0)
+                    .unwrap_or(DEFAULT_SEGMENT_COUNT);
+                AnalysisSpan::Segments { max_secs, count }
+            }
+            _ => AnalysisSpan::Truncate { max_secs },
+        }
+    }
+
+    /// (start, end) time ranges to analyze; an unknown duration falls back to the beginning of the track
+    pub fn ranges(&self, duration_secs: Option<f64>) -> Vec<(f64, Option<f64>)> {
+        match *self {
+            AnalysisSpan::Full => vec![(0.0, None)],
+            AnalysisSpan::Truncate { max_secs } => vec![(0.0, Some(max_secs))],
+            AnalysisSpan::Segments { max_secs, count } => match duration_secs {
+                Some(duration) if duration > max_secs && count > 1 => {
+                    let length = max_secs / count as f64;
+                    let spacing = (duration - length) / (count - 1) as f64;
+                    (0..count)
+                        .map(|i| (i as f64 * spacing, Some(i as f64 * spacing + length)))
+                        .collect()
+                }
+                _ => vec![(0.0, Some(max_secs))],
+            },
+        }
+    }
+}
+
+/// Incremental consumer of a decoding pass; several sinks share one pass over the file
+pub trait AudioSink {
+    /// Called before the first samples of every analyzed range, with its start time in seconds
+    fn start_span(&mut self, _time: f64) {}
+
+    /// Interleaved samples at the native sample rate and channel layout
+    fn push_native(&mut self, _samples: &[f32]) {}
+
+    /// Mono samples at ANALYSIS_SAMPLE_RATE
+    fn push_mono(&mut self, _samples: &[f32]) {}
+
+    /// Whether push_mono is used; the resampling is skipped when no sink needs it
+    fn wants_mono(&self) -> bool {
+        true
+    }
+}
+
+enum PacketSource {
+    Symphonia {
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn Decoder>,
+        track_id: u32,
+        time_base: Option<TimeBase>,
+        buffer: Option<SampleBuffer<f32>>,
+    },
+    Wav {
+        reader: hound::WavReader<BufReader<File>>,
+        scale: Option<f32>, // Integer sample scale, None for float samples
+        position: u64, // Next frame
+    },
+}
+
+/// Decoder yielding a file packet by packet, so memory use does not grow with the track length
+pub struct AudioStream {
+    source: PacketSource,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub duration_secs: Option<f64>, // From the container, when known
+    pub codec: String, // Decoder name, e.g. "flac", "mp3"
+    pub bit_depth: Option<u32>, // Of the encoded samples, None for lossy codecs
+}
+
+impl AudioStream {
+    /// Open a file with symphonia, falling back to hound for WAV files symphonia rejects
+    pub fn open(file_path: &str) -> Result<AudioStream> {
+        let clean_path = file_path.strip_prefix("file://").unwrap_or(file_path);
+        if !Path::new(clean_path).exists() {
+            return Err(anyhow!("Audio file not found: {}", clean_path));
+        }
+
+        let extension = Path::new(clean_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("unknown")
+            .to_lowercase();
+        tracing::debug!("Opening audio file: {} (format: {})", clean_path, extension);
+
+        match Self::open_with_symphonia(clean_path) {
+            Ok(stream) => Ok(stream),
+            Err(symphonia_err) if extension == "wav" => {
+                tracing::debug!("Symphonia failed: {}, falling back to hound for WAV file", symphonia_err);
+                Self::open_wav_with_hound(clean_path)
+            }
+            Err(symphonia_err) => Err(anyhow!("Failed to read audio file '{}': {}", clean_path, symphonia_err)),
+        }
+    }
+
+    fn open_with_symphonia(file_path: &str) -> Result<AudioStream> {
+        let file = File::open(file_path).map_err(|e| anyhow!("Failed to open file '{}': {}", file_path, e))?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+        let mut hint = Hint::new();
+        if let Some(extension) = Path::new(file_path).extension().and_then(|ext| ext.to_str()) {
+            hint.with_extension(extension);
+        }
+
+        let probed = symphonia::default::get_probe()
+            .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+            .map_err(|e| anyhow!("Failed to probe audio format: {}", e))?;
+        let format = probed.format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow!("No supported audio tracks found"))?;
+        let params = &track.codec_params;
+        let sample_rate = params.sample_rate.ok_or_else(|| anyhow!("Sample rate not found in track"))?;
+        let channels = params.channels.map(|channels| channels.count()).unwrap_or(0);
+        let duration_secs = params.n_frames.map(|frames| match params.time_base {
+            Some(time_base) => seconds(time_base.calc_time(frames)),
+            None => frames as f64 / sample_rate as f64,
+        });
+        let codec = symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map_or_else(|| "unknown".to_string(), |descriptor| descriptor.short_name.to_string());
+        let decoder = symphonia::default::get_codecs()
+            .make(params, &DecoderOptions::default())
+            .map_err(|e| anyhow!("Failed to create decoder: {}", e))?;
+
+        Ok(AudioStream {
+            sample_rate,
+            channels,
+            duration_secs,
+            codec,
+            bit_depth: params.bits_per_sample,
+            source: PacketSource::Symphonia {
+                track_id: track.id,
+                time_base: params.time_base,
+                format,
+                decoder,
+                buffer: None,
+            },
+        })
+    }
+
+    fn open_wav_with_hound(file_path: &str) -> Result<AudioStream> {
+        let reader = hound::WavReader::open(file_path)
+            .map_err(|e| anyhow!("Failed to open WAV file '{}': {}", file_path, e))?;
+        let spec = reader.spec();
+        let scale = match (spec.sample_format, spec.bits_per_sample) {
+            (hound::SampleFormat::Float, _) => None,
+            (hound::SampleFormat::Int, bits @ (8 | 16 | 24 | 32)) => Some((1u64 << (bits - 1)) as f32),
+            (_, bits) => return Err(anyhow!("Unsupported bit depth: {} bits", bits)),
+        };
+
+        Ok(AudioStream {
+            sample_rate: spec.sample_rate,
+            channels: spec.channels.max(1) as usize,
+            duration_secs: Some(reader.duration() as f64 / spec.sample_rate as f64),
+            codec: "pcm".to_string(),
+            bit_depth: Some(spec.bits_per_sample as u32),
+            source: PacketSource::Wav { reader, scale, position: 0 },
+        })
+    }
+
+    /// Next decoded chunk as (start time in seconds, interleaved samples), None at the end of the file
+    fn next_chunk(&mut self) -> Result<Option<(f64, Vec<f32>)>> {
+        match &mut self.source {
+            PacketSource::Symphonia { format, decoder, track_id, time_base, buffer } => loop {
+                let packet = match format.next_packet() {
+                    Ok(packet) => packet,
+                    Err(SymphoniaError::ResetRequired) => return Err(anyhow!("Track list changed during decoding")),
+                    Err(SymphoniaError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(SymphoniaError::IoError(err)) => return Err(anyhow!("IO error during decoding: {}", err)),
+                    Err(err) => return Err(anyhow!("Decoding error: {}", err)),
+                };
+
+                while !format.metadata().is_latest() {
+                    format.metadata().pop();
+                }
+                if packet.track_id() != *track_id {
+                    continue;
+                }
+
+                let time = match time_base {
+                    Some(time_base) => seconds(time_base.calc_time(packet.ts())),
+                    None => packet.ts() as f64 / self.sample_rate as f64,
+                };
+                match decoder.decode(&packet) {
+                    Ok(decoded) => {
+                        let spec = *decoded.spec();
+                        if self.channels == 0 {
+                            self.channels = spec.channels.count();
+                        } else if spec.channels.count() != self.channels {
+                            return Err(anyhow!("Channel layout changed during decoding"));
+                        }
+                        // Planar buffers are interleaved here, so frames stay intact across channels
+                        let buffer = match buffer {
+                            Some(buffer) if buffer.capacity() >= decoded.capacity() * self.channels => buffer,
+                            _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+                        };
+                        buffer.copy_interleaved_ref(decoded);
+                        return Ok(Some((time, buffer.samples().to_vec())));
+                    }
+                    Err(SymphoniaError::IoError(_)) | Err(SymphoniaError::DecodeError(_)) => continue,
+                    Err(err) => return Err(anyhow!("Decode error: {}", err)),
+                }
+            },
+            PacketSource::Wav { reader, scale, position } => {
+                let time = *position as f64 / self.sample_rate as f64;
+                let count = WAV_CHUNK_FRAMES * self.channels;
+                let samples: Vec<f32> = match scale {
+                    None => reader.samples::<f32>().take(count).collect::<Result<_, _>>(),
+                    Some(scale) => reader
+                        .samples::<i32>()
+                        .take(count)
+                        .map(|s| s.map(|sample| sample as f32 / *scale))
+                        .collect::<Result<_, _>>(),
+                }
+                .map_err(|e| anyhow!("Failed to read WAV samples: {}", e))?;
+
+                *position += (samples.len() / self.channels) as u64;
+                Ok((!samples.is_empty()).then_some((time, samples)))
+            }
+        }
+    }
+
+    /// Jump close to a time; formats that cannot seek are decoded up to it instead
+    fn seek(&mut self, time: f64) {
+        let result = match &mut self.source {
+            PacketSource::Symphonia { format, decoder, track_id, .. } => format
+                .seek(SeekMode::Coarse, SeekTo::Time { time: Time::from(time), track_id: Some(*track_id) })
+                .map(|_| decoder.reset())
+                .map_err(|e| e.to_string()),
+            PacketSource::Wav { reader, position, .. } => {
+                let frame = (time * self.sample_rate as f64) as u32;
+                reader.seek(frame).map(|_| *position = frame as u64).map_err(|e| e.to_string())
+            }
+        };
+        if let Err(e) = result {
+            tracing::debug!("Seeking to {:.1}s failed, decoding up to it: {}", time, e);
+        }
+    }
+
+    /// Decode the configured ranges of the file once, feeding every sink. Returns the seconds analyzed.
+    pub fn run(&mut self, span: AnalysisSpan, sinks: &mut [&mut dyn AudioSink]) -> Result<f64> {
+        let ranges = span.ranges(self.duration_secs);
+        let mut analyzed = 0.0;
+        let mut decoded_any = false;
+        let wants_mono = sinks.iter().any(|sink| sink.wants_mono());
+
+        for (start, end) in ranges {
+            if start > 0.0 {
+                self.seek(start);
+            }
+            for sink in sinks.iter_mut() {
+                sink.start_span(start);
+            }
+            let mut resampler = StreamResampler::new(self.sample_rate, ANALYSIS_SAMPLE_RATE);
+
+            while let Some((time, mut samples)) = self.next_chunk()? {
+                let channels = self.channels.max(1);
+                let rate = self.sample_rate as f64;
+                let frames = samples.len() / channels;
+                if time + frames as f64 / rate <= start {
+                    continue;
+                }
+
+                // Trim the chunk to the range
+                let skip = (((start - time) * rate).round().max(0.0) as usize).min(frames);
+                let take = match end {
+                    Some(end) => (((end - time) * rate).round().max(0.0) as usize).min(frames),
+                    None => frames,
+                };
+                if take > skip {
+                    samples.truncate(take * channels);
+                    samples.drain(..skip * channels);
+                    decoded_any = true;
+                    analyzed += (take - skip) as f64 / rate;
+
+                    let resampled = if wants_mono { resampler.process(&downmix(&samples, channels)) } else { Vec::new() };
+                    for sink in sinks.iter_mut() {
+                        sink.push_native(&samples);
+                        sink.push_mono(&resampled);
+                    }
+                }
+                if take < frames {
+                    break;
+                }
+            }
+
+            let tail = resampler.finish();
+            for sink in sinks.iter_mut() {
+                sink.push_mono(&tail);
+            }
+        }
+
+        if !decoded_any {
+            return Err(anyhow!("No audio samples decoded"));
+        }
+        tracing::info!("Audio streamed - Sample rate: {} Hz, Channels: {}, Analyzed: {:.2}s of {:?}s",
+                       self.sample_rate, self.channels, analyzed, self.duration_secs);
+        Ok(analyzed)
+    }
+}
+
+fn seconds(time: Time) -> f64 {
+    time.seconds as f64 + time.frac
+}
+
+/// Average of all channels of interleaved samples
+pub fn downmix(samples: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// Band-limited resampling of a mono stream with a windowed-sinc interpolator.
+/// When downsampling, the filter cutoff follows the lower Nyquist frequency so nothing aliases.
+pub struct StreamResampler {
+    ratio: f64,
+    cutoff: f64, // In cycles per input sample, times two
+    half_width: f64, // In input samples
+    buffer: Vec<f32>, // Input samples still needed, starting at `offset`
+    offset: usize,
+    received: usize,
+    produced: usize,
+}
+
+impl StreamResampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        let ratio = to_rate as f64 / from_rate as f64;
+        let cutoff = ratio.min(1.0) * RESAMPLE_PASSBAND;
+        Self {
+            ratio,
+            cutoff,
+            half_width: RESAMPLE_ZERO_CROSSINGS / cutoff,
+            buffer: Vec::new(),
+            offset: 0,
+            received: 0,
+            produced: 0,
+        }
+    }
+
+    /// Output samples whose filter window is complete
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        if self.ratio == 1.0 {
+            return input.to_vec();
+        }
+        self.buffer.extend_from_slice(input);
+        self.received += input.len();
+
+        let mut output = Vec::with_capacity((input.len() as f64 * self.ratio) as usize + 1);
+        loop {
+            let position = self.produced as f64 / self.ratio;
+            if (position + self.half_width).floor() as usize >= self.received {
+                break;
+            }
+            output.push(self.interpolate(position));
+            self.produced += 1;
+        }
+
+        // Drop input no later output reaches
+        let keep_from = ((self.produced as f64 / self.ratio - self.half_width).ceil().max(0.0) as usize).max(self.offset);
+        self.buffer.drain(..keep_from - self.offset);
+        self.offset = keep_from;
+        output
+    }
+
+    /// Remaining output at the end of the stream, treating later input as silence
+    pub fn finish(&mut self) -> Vec<f32> {
+        if self.ratio == 1.0 {
+            return Vec::new();
+        }
+        let total = (self.received as f64 * self.ratio).floor() as usize;
+        let output = (self.produced..total).map(|i| self.interpolate(i as f64 / self.ratio)).collect();
+        self.produced = total;
+        output
+    }
+
+    fn interpolate(&self, position: f64) -> f32 {
+        let first = ((position - self.half_width).ceil().max(0.0) as usize).max(self.offset);
+        let end = ((position + self.half_width).floor() as usize).min(self.received.saturating_sub(1));
+        (first..=end)
+            .map(|j| {
+                let x = position - j as f64;
+                let t = std::f64::consts::PI * x * self.cutoff;
+                let sinc = if t.abs() < 1e-9 { 1.0 } else { t.sin() / t };
+                let window = 0.5 + 0.5 * (std::f64::consts::PI * x / self.half_width).cos();
+                self.buffer[j - self.offset] as f64 * self.cutoff * sinc * window
+            })
+            .sum::<f64>() as f32
+    }
+}
+
+/// Overlapping analysis frames over a mono stream. At the end of every range, the remaining
+/// frames with at least half a window of audio are zero-padded.
+pub struct FrameWindow {
+    size: usize,
+    hop: usize,
+    sample_rate: u32,
+    buffer: Vec<f32>,
+    read: usize, // Start of the next frame in the buffer
+    span_start: f64,
+    frames_in_span: usize,
+}
+
+impl FrameWindow {
+    pub fn new(size: usize, hop: usize, sample_rate: u32) -> Self {
+        Self {
+            size,
+            hop,
+            sample_rate,
+            buffer: Vec::with_capacity(size * 2),
+            read: 0,
+            span_start: 0.0,
+            frames_in_span: 0,
+        }
+    }
+
+    /// Start time of the next frame
+    fn frame_time(&self) -> f64 {
+        self.span_start + (self.frames_in_span * self.hop) as f64 / self.sample_rate as f64
+    }
+
+    /// Flush the current range and continue at another time
+    pub fn start_span(&mut self, time: f64, on_frame: impl FnMut(&[f32], f64)) {
+        self.finish(on_frame);
+        self.span_start = time;
+        self.frames_in_span = 0;
+    }
+
+    pub fn push(&mut self, samples: &[f32], mut on_frame: impl FnMut(&[f32], f64)) {
+        self.buffer.extend_from_slice(samples);
+        while self.buffer.len() - self.read >= self.size {
+            on_frame(&self.buffer[self.read..self.read + self.size], self.frame_time());
+            self.read += self.hop;
+            self.frames_in_span += 1;
+        }
+        if self.read >= self.size {
+            self.buffer.drain(..self.read);
+            self.read = 0;
+        }
+    }
+
+    pub fn finish(&mut self, mut on_frame: impl FnMut(&[f32], f64)) {
+        let mut frame = vec![0.0; self.size];
+        while self.read < self.buffer.len() && self.buffer.len() - self.read >= self.size / 2 {
+            let available = self.buffer.len() - self.read;
+            frame[..available].copy_from_slice(&self.buffer[self.read..]);
+            frame[available..].fill(0.0);
+            on_frame(&frame, self.frame_time());
+            self.read += self.hop;
+            self.frames_in_span += 1;
+        }
+        self.buffer.clear();
+        self.read = 0;
+    }
+
+    /// End time of the audio pushed so far in the current range
+    pub fn end_time(&self) -> f64 {
+        let pending = self.buffer.len().saturating_sub(self.read);
+        self.frame_time() + pending as f64 / self.sample_rate as f64
+    }
+}
+
+pub fn is_remote(stream_url: &str) -> bool {
+    stream_url.starts_with("http://") || stream_url.starts_with("https://")
+}
+
+/// Failure to download remote audio
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("{0}")]
+    Transient(String), // Network errors, rate limiting and server errors
+    #[error("{0}")]
+    Rejected(String), // Other HTTP errors
+}
+
+/// Downloaded audio in the temporary directory, removed when dropped
+#[derive(Debug)]
+pub struct TempAudioFile {
+    path: PathBuf,
+}
+
+impl TempAudioFile {
+    pub async fn download(url: &str) -> Result<TempAudioFile, DownloadError> {
+        tracing::debug!("Downloading audio from URL: {}", url);
+        let response = reqwest::get(url)
+            .await
+            .map_err(|e| DownloadError::Transient(format!("Failed to download audio from '{}': {}", url, e)))?;
+
+        let status = response.status();
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(DownloadError::Transient(format!("Audio download failed with HTTP {}", status)));
+        }
+        if !status.is_success() {
+            return Err(DownloadError::Rejected(format!("Audio download failed with HTTP {}", status)));
+        }
+
+        let max_bytes = Self::max_download_bytes();
+        if response.content_length().is_some_and(|length| length > max_bytes) {
+            return Err(DownloadError::Rejected(format!("Audio is larger than {} MB", max_bytes / (1024 * 1024))));
+        }
+
+        // Written as it arrives, so memory stays bounded; the file is removed on error when dropped
+        let file = TempAudioFile {
+            path: std::env::temp_dir().join(format!("musestruct_audio_{}.tmp", uuid::Uuid::new_v4())),
+        };
+        let write_error = |e: std::io::Error| DownloadError::Transient(format!("Failed to write temporary file: {}", e));
+        let mut output = tokio::fs::File::create(&file.path).await.map_err(write_error)?;
+        let mut stream = response.bytes_stream();
+        let mut size = 0u64;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| DownloadError::Transient(format!("Audio download was interrupted: {}", e)))?;
+            size += chunk.len() as u64;
+            if size > max_bytes {
+                return Err(DownloadError::Rejected(format!("Audio is larger than {} MB", max_bytes / (1024 * 1024))));
+            }
+            output.write_all(&chunk).await.map_err(write_error)?;
+        }
+        output.flush().await.map_err(write_error)?;
+        tracing::info!("Audio downloaded - Size: {} bytes", size);
+        Ok(file)
+    }
+
+    // ANALYSIS_MAX_DOWNLOAD_MB, 1 GB by default
+    fn max_download_bytes() -> u64 {
+        std::env::var("ANALYSIS_MAX_DOWNLOAD_MB")
+            .ok()
+            .and_then(|mb| mb.trim().parse::<u64>().ok())
+            .filter(|&mb| mb > 0)
+            .unwrap_or(DEFAULT_MAX_DOWNLOAD_MB)
+            * 1024
+            * 1024
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn path_str(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TempAudioFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Failed to clean up temporary file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f32, sample_rate: u32, seconds: f32) -> Vec<f32> {
+        (0..(seconds * sample_rate as f32) as usize)
+            .map(|i| (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+        let mut resampler = StreamResampler::new(from_rate, to_rate);
+        let mut output = resampler.process(samples);
+        output.extend(resampler.finish());
+        output
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        spans: Vec<f64>,
+        native: Vec<f32>,
+        mono: Vec<f32>,
+    }
+
+    impl AudioSink for Recorder {
+        fn start_span(&mut self, time: f64) {
+            self.spans.push(time);
+        }
+
+        fn push_native(&mut self, samples: &[f32]) {
+            self.native.extend_from_slice(samples);
+        }
+
+        fn push_mono(&mut self, samples: &[f32]) {
+            self.mono.extend_from_slice(samples);
+        }
+    }
+
+    // Stereo WAV with a constant left channel and a silent right channel
+    fn write_test_wav(seconds: usize) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("musestruct_decode_test_{}.wav", uuid::Uuid::new_v4()));
+        let spec = hound::WavSpec { channels: 2, sample_rate: 22050, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..22050 * seconds {
+            writer.write_sample(i16::MAX / 2).unwrap();
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_resample_keeps_passband_and_removes_aliases() {
+        let input = sine(1000.0, 48000, 1.0);
+        let resampled = resample(&input, 48000, ANALYSIS_SAMPLE_RATE);
+        assert_eq!(resampled.len(), ANALYSIS_SAMPLE_RATE as usize);
+        let expected = sine(1000.0, ANALYSIS_SAMPLE_RATE, 1.0);
+        let middle = 1000..resampled.len() - 1000;
+        let error = resampled[middle.clone()]
+            .iter()
+            .zip(&expected[middle])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 0.01, "max error {}", error);
+
+        // Chunked input gives the same output as a single block
+        let mut resampler = StreamResampler::new(48000, ANALYSIS_SAMPLE_RATE);
+        let mut chunked: Vec<f32> = input.chunks(1000).flat_map(|chunk| resampler.process(chunk)).collect();
+        chunked.extend(resampler.finish());
+        assert_eq!(chunked, resampled);
+
+        // 15 kHz is above the Nyquist frequency of 22050 Hz and must not fold back
+        let downsampled = resample(&sine(15000.0, ANALYSIS_SAMPLE_RATE, 1.0), ANALYSIS_SAMPLE_RATE, 22050);
+        assert!(rms(&downsampled[500..downsampled.len() - 500]) < 0.02);
+    }
+
+    #[test]
+    fn test_decode_wav_keeps_channels_interleaved() {
+        let path = write_test_wav(1);
+        let mut stream = AudioStream::open(&path.to_string_lossy()).unwrap();
+        let mut recorder = Recorder::default();
+        let analyzed = stream.run(AnalysisSpan::Full, &mut [&mut recorder]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(stream.channels, 2);
+        assert!((analyzed - 1.0).abs() < 1e-6);
+        assert_eq!(recorder.native.len(), 2 * 22050);
+        assert!(recorder.native.chunks_exact(2).all(|frame| (frame[0] - 0.5).abs() < 0.01 && frame[1] == 0.0));
+        assert_eq!(recorder.mono.len(), ANALYSIS_SAMPLE_RATE as usize);
+        assert!((recorder.mono[ANALYSIS_SAMPLE_RATE as usize / 2] - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_long_file_spans() {
+        let path = write_test_wav(10);
+        let mut stream = AudioStream::open(&path.to_string_lossy()).unwrap();
+        let mut recorder = Recorder::default();
+        let analyzed = stream.run(AnalysisSpan::Truncate { max_secs: 2.5 }, &mut [&mut recorder]).unwrap();
+        assert!((analyzed - 2.5).abs() < 1e-3);
+        assert_eq!(recorder.native.len(), 2 * 22050 * 5 / 2);
+
+        let mut stream = AudioStream::open(&path.to_string_lossy()).unwrap();
+        let mut recorder = Recorder::default();
+        let span = AnalysisSpan::Segments { max_secs: 4.0, count: 4 };
+        let analyzed = stream.run(span, &mut [&mut recorder]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!((analyzed - 4.0).abs() < 1e-3);
+        assert_eq!(recorder.spans, vec![0.0, 3.0, 6.0, 9.0]);
+        assert!((recorder.mono.len() as f64 - 4.0 * ANALYSIS_SAMPLE_RATE as f64).abs() < 8.0);
+
+        // Frames restart their clock at every range
+        let mut window = FrameWindow::new(4, 2, 10);
+        let mut times = Vec::new();
+        window.start_span(0.0, |_, time| times.push(time));
+        window.push(&[1.0; 6], |_, time| times.push(time));
+        window.start_span(5.0, |_, time| times.push(time));
+        window.push(&[1.0; 4], |_, time| times.push(time));
+        window.finish(|_, time| times.push(time));
+        assert_eq!(times, vec![0.0, 0.2, 0.4, 5.0, 5.2]);
+    }
+}
@@ -0,0 +1,365 @@
+use serde::Serialize;
+
+use crate::services::harmonic_mixing::{suggest, tempo_match, Camelot, KeyRelation, MixOptions, MixTrack};
+
+// Set ordering costs; a perfect transition costs 0
+const KEY_CLASH_COST: f32 = 1.0;
+const TEMPO_JUMP_COST: f32 = 1.0; // Added when no pitch change within the tolerance matches the tempos
+const HALF_DOUBLE_TEMPO_COST: f32 = 0.15;
+const UNKNOWN_COST: f32 = 0.5; // Key or tempo transition with a track that is not analyzed
+const ENERGY_WEIGHT: f32 = 1.0; // Per unit of distance from the energy curve
+const UNKNOWN_ENERGY_COST: f32 = 0.25;
+const MAX_GREEDY_STARTS: usize = 64; // Opening tracks tried, those closest to the curve's start first
+const MAX_IMPROVEMENT_PASSES: usize = 50;
+pub const MAX_SET_TRACKS: usize = 500; // Ordering is quadratic in the number of tracks
+
+/// Energy over the course of a set, from 0.0 (calmest track of the set) to 1.0 (most energetic)
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnergyCurve {
+    WarmUpPeakCoolDown,
+    Rising,
+    Flat,
+    Custom(Vec<f32>), // Evenly spaced over the set, interpolated in between
+}
+
+impl EnergyCurve {
+    pub fn from_name(name: &str) -> Option<EnergyCurve> {
+        match name {
+            "warm_up_peak_cool_down" => Some(EnergyCurve::WarmUpPeakCoolDown),
+            "rising" => Some(EnergyCurve::Rising),
+            "flat" => Some(EnergyCurve::Flat),
+            _ => None,
+        }
+    }
+
+    /// Target energy at a position in the set, 0.0 being the first track and 1.0 the last
+    pub fn target(&self, position: f32) -> f32 {
+        let points: &[f32] = match self {
+            EnergyCurve::WarmUpPeakCoolDown => return interpolate(&[(0.0, 0.2), (0.7, 1.0), (1.0, 0.5)], position),
+            EnergyCurve::Rising => return interpolate(&[(0.0, 0.1), (1.0, 1.0)], position),
+            EnergyCurve::Flat => return 0.5,
+            EnergyCurve::Custom(points) => points,
+        };
+        match points.len() {
+            0 => 0.5,
+            1 => points[0],
+            len => {
+                let step = 1.0 / (len - 1) as f32;
+                let points: Vec<(f32, f32)> = points.iter().enumerate().map(|(i, &energy)| (i as f32 * step, energy)).collect();
+                interpolate(&points, position)
+            }
+        }
+    }
+}
+
+// Piecewise linear curve through (position, value) points sorted by position
+fn interpolate(points: &[(f32, f32)], position: f32) -> f32 {
+    let position = position.clamp(0.0, 1.0);
+    for pair in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        if position <= x1 {
+            return if x1 > x0 { y0 + (y1 - y0) * (position - x0) / (x1 - x0) } else { y1 };
+        }
+    }
+    points.last().map_or(0.5, |&(_, y)| y)
+}
+
+/// How one track of a set leads into the next
+#[derive(Debug, Clone, Serialize)]
+pub struct SetTransition {
+    pub key_relation: Option<KeyRelation>, // None for a clash or an unknown key
+    pub pitch_shift: Option<f32>, // Percent the next track is sped up to match, None when out of range or unknown
+    pub cost: f32,
+    pub reason: String,
+}
+
+/// A proposed play order of a set
+#[derive(Debug, Clone, Serialize)]
+pub struct SetOrder {
+    pub order: Vec<usize>, // Indexes into the given tracks
+    pub target_energies: Vec<f32>, // Of each position, on the set's energy scale
+    pub transitions: Vec<SetTransition>, // Into each track but the first
+    pub cost: f32,
+    pub original_cost: f32, // Of the given order
+}
+
+// A track of the set with its key parsed once
+#[derive(Clone, Copy)]
+struct SetTrack<'a> {
+    track: &'a MixTrack,
+    key: Option<Camelot>,
+}
+
+impl<'a> SetTrack<'a> {
+    fn new(track: &'a MixTrack) -> Self {
+        Self { track, key: track.camelot.as_deref().and_then(Camelot::parse) }
+    }
+}
+
+// The current key and the key the next track is played in, None unless both are known
+fn played_keys(current: SetTrack, next: SetTrack, options: MixOptions) -> Option<(Camelot, Camelot)> {
+    let (current_key, next_key) = (current.key?, next.key?);
+    let tempo = current.track.bpm.zip(next.track.bpm).and_then(|(a, b)| tempo_match(a, b, options.pitch_tolerance));
+    Some((current_key, tempo.map_or(next_key, |tempo| options.played_key(next_key, &tempo))))
+}
+
+fn key_cost(current: SetTrack, next: SetTrack, options: MixOptions) -> f32 {
+    match played_keys(current, next, options) {
+        Some((current_key, played_key)) => {
+            current_key.relation_to(&played_key).map_or(KEY_CLASH_COST, |relation| 1.0 - relation.score())
+        }
+        None => UNKNOWN_COST,
+    }
+}
+
+fn tempo_cost(current: &MixTrack, next: &MixTrack, options: MixOptions) -> f32 {
+    let (Some(current_bpm), Some(next_bpm)) = (current.bpm, next.bpm) else {
+        return UNKNOWN_COST;
+    };
+    if current_bpm <= 0.0 || next_bpm <= 0.0 {
+        return UNKNOWN_COST;
+    }
+    // The smallest speed change, in units of the tolerance, with jumps beyond it costing extra
+    let tolerance = options.pitch_tolerance.max(1.0);
+    [1.0f32, 2.0, 0.5]
+        .into_iter()
+        .map(|ratio| {
+            let shift = (current_bpm / (next_bpm * ratio) - 1.0).abs() * 100.0;
+            let mut cost = shift / tolerance;
+            if shift > options.pitch_tolerance + 1e-3 {
+                cost += TEMPO_JUMP_COST;
+            }
+            if ratio != 1.0 {
+                cost += HALF_DOUBLE_TEMPO_COST;
+            }
+            cost
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+fn transition_cost(current: SetTrack, next: SetTrack, options: MixOptions) -> f32 {
+    key_cost(current, next, options) + tempo_cost(current.track, next.track, options)
+}
+
+fn describe_transition(current: SetTrack, next: SetTrack, options: MixOptions, cost: f32) -> SetTransition {
+    if let Some(suggestion) = suggest(current.track, next.track, options) {
+        return SetTransition {
+            key_relation: Some(suggestion.key_relation),
+            pitch_shift: Some(suggestion.tempo.pitch_shift),
+            cost,
+            reason: suggestion.reason,
+        };
+    }
+
+    let mut problems = Vec::new();
+    let keys = played_keys(current, next, options);
+    let key_relation = keys.and_then(|(current_key, played_key)| current_key.relation_to(&played_key));
+    match keys {
+        Some((current_key, played_key)) if key_relation.is_none() => {
+            problems.push(format!("key clash ({} → {})", current_key, played_key))
+        }
+        Some(_) => {}
+        None => problems.push("unknown key".to_string()),
+    }
+    let tempo = current.track.bpm.zip(next.track.bpm).and_then(|(a, b)| tempo_match(a, b, options.pitch_tolerance));
+    match (current.track.bpm, next.track.bpm) {
+        (Some(from), Some(to)) if tempo.is_none() => problems.push(format!("tempo jump ({:.0} → {:.0} BPM)", from, to)),
+        (Some(_), Some(_)) => {}
+        _ => problems.push("unknown tempo".to_string()),
+    }
+    SetTransition { key_relation, pitch_shift: tempo.map(|tempo| tempo.pitch_shift), cost, reason: problems.join("; ") }
+}
+
+struct SetCosts {
+    transitions: Vec<Vec<f32>>, // [from][to]
+    energies: Vec<Vec<f32>>, // [track][position]
+}
+
+impl SetCosts {
+    fn total(&self, order: &[usize]) -> f32 {
+        let energy: f32 = order.iter().enumerate().map(|(position, &track)| self.energies[track][position]).sum();
+        energy + order.windows(2).map(|pair| self.transitions[pair[0]][pair[1]]).sum::<f32>()
+    }
+
+    // Cost of positions `i` < `j` and of the transitions into and out of them
+    fn around(&self, order: &[usize], i: usize, j: usize) -> f32 {
+        let transition = |from: usize| match order.get(from + 1) {
+            Some(&next) => self.transitions[order[from]][next],
+            None => 0.0,
+        };
+        let mut cost = self.energies[order[i]][i] + self.energies[order[j]][j] + transition(i) + transition(j);
+        if i > 0 {
+            cost += transition(i - 1);
+        }
+        // Adjacent positions share the transition between them
+        if j > i + 1 {
+            cost += transition(j - 1);
+        }
+        cost
+    }
+
+    // Nearest neighbour order from an opening track, following the energy curve
+    fn greedy(&self, first: usize) -> Vec<usize> {
+        let count = self.transitions.len();
+        let mut used = vec![false; count];
+        let mut order = Vec::with_capacity(count);
+        used[first] = true;
+        order.push(first);
+        for position in 1..count {
+            let last = order[position - 1];
+            let next = (0..count)
+                .filter(|&track| !used[track])
+                .min_by(|&a, &b| {
+                    let cost = |track: usize| self.transitions[last][track] + self.energies[track][position];
+                    cost(a).total_cmp(&cost(b))
+                })
+                .expect("an unused track is left");
+            used[next] = true;
+            order.push(next);
+        }
+        order
+    }
+
+    // Swap pairs of tracks while that lowers the cost
+    fn improve(&self, order: &mut [usize]) {
+        for _ in 0..MAX_IMPROVEMENT_PASSES {
+            let mut improved = false;
+            for i in 0..order.len() {
+                for j in i + 1..order.len() {
+                    let before = self.around(order, i, j);
+                    order.swap(i, j);
+                    if self.around(order, i, j) < before - 1e-6 {
+                        improved = true;
+                    } else {
+                        order.swap(i, j);
+                    }
+                }
+            }
+            if !improved {
+                break;
+            }
+        }
+    }
+}
+
+/// Order tracks for smooth transitions: few key clashes and BPM jumps, with energies following
+/// the curve. Track energies are rescaled to the set's own range before comparing with the curve.
+/// Callers keep sets within MAX_SET_TRACKS.
+pub fn order_set(tracks: &[MixTrack], curve: &EnergyCurve, options: MixOptions) -> SetOrder {
+    let count = tracks.len();
+    let set: Vec<SetTrack> = tracks.iter().map(SetTrack::new).collect();
+    let known: Vec<f32> = tracks.iter().filter_map(|track| track.energy).collect();
+    let (low, high) = known.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), &e| (low.min(e), high.max(e)));
+    let scaled = |energy: f32| if high - low > 1e-3 { (energy - low) / (high - low) } else { 0.5 };
+
+    let target_energies: Vec<f32> = (0..count)
+        .map(|position| curve.target(if count > 1 { position as f32 / (count - 1) as f32 } else { 0.0 }))
+        .collect();
+    let costs = SetCosts {
+        transitions: set
+            .iter()
+            .map(|&current| set.iter().map(|&next| transition_cost(current, next, options)).collect())
+            .collect(),
+        energies: tracks
+            .iter()
+            .map(|track| {
+                target_energies
+                    .iter()
+                    .map(|target| track.energy.map_or(UNKNOWN_ENERGY_COST, |energy| ENERGY_WEIGHT * (scaled(energy) - target).abs()))
+                    .collect()
+            })
+            .collect(),
+    };
+
+    let original: Vec<usize> = (0..count).collect();
+    let original_cost = costs.total(&original);
+    let mut best = (original.clone(), original_cost);
+    if count > 2 {
+        let mut openings = original.clone();
+        openings.sort_by(|&a, &b| costs.energies[a][0].total_cmp(&costs.energies[b][0]));
+        for &first in openings.iter().take(MAX_GREEDY_STARTS) {
+            let order = costs.greedy(first);
+            let cost = costs.total(&order);
+            if cost < best.1 {
+                best = (order, cost);
+            }
+        }
+        costs.improve(&mut best.0);
+        best.1 = costs.total(&best.0);
+    }
+
+    let (order, cost) = best;
+    let transitions = order
+        .windows(2)
+        .map(|pair| describe_transition(set[pair[0]], set[pair[1]], options, costs.transitions[pair[0]][pair[1]]))
+        .collect();
+    SetOrder { order, target_energies, transitions, cost, original_cost }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: &str, bpm: f32, camelot: &str, energy: f32) -> MixTrack {
+        MixTrack {
+            source: "server".to_string(),
+            track_id: id.to_string(),
+            bpm: Some(bpm),
+            camelot: Some(camelot.to_string()),
+            energy: Some(energy),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_energy_curves() {
+        let curve = EnergyCurve::WarmUpPeakCoolDown;
+        assert!(curve.target(0.0) < curve.target(0.5));
+        assert_eq!(curve.target(0.7), 1.0);
+        assert!(curve.target(1.0) < curve.target(0.7));
+        assert_eq!(EnergyCurve::Custom(vec![0.0, 1.0, 0.0]).target(0.25), 0.5);
+        assert_eq!(EnergyCurve::from_name("rising"), Some(EnergyCurve::Rising));
+    }
+
+    #[test]
+    fn test_order_set() {
+        // A rising walk around the wheel, shuffled
+        let tracks = vec![
+            track("d", 126.0, "10A", 0.7),
+            track("a", 120.0, "7A", 0.1),
+            track("c", 124.0, "9A", 0.5),
+            track("e", 128.0, "11A", 0.9),
+            track("b", 122.0, "8A", 0.3),
+        ];
+        let set = order_set(&tracks, &EnergyCurve::Rising, MixOptions::default());
+        let ids: Vec<&str> = set.order.iter().map(|&i| tracks[i].track_id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c", "d", "e"]);
+        assert!(set.cost < set.original_cost);
+        assert_eq!(set.transitions.len(), 4);
+        assert!(set.transitions.iter().all(|t| t.key_relation == Some(KeyRelation::StepUp)), "{:?}", set.transitions);
+
+        let clash = order_set(&[track("x", 120.0, "1A", 0.5), track("y", 150.0, "7B", 0.5)], &EnergyCurve::Flat, MixOptions::default());
+        assert_eq!(clash.transitions[0].reason, "key clash (1A → 7B); tempo jump (120 → 150 BPM)");
+    }
+
+    #[test]
+    fn test_swap_cost_is_local() {
+        let tracks: Vec<MixTrack> = (0..6)
+            .map(|i| track(&i.to_string(), 118.0 + 3.0 * i as f32, &format!("{}A", 1 + (i * 5) % 12), 0.15 * i as f32))
+            .collect();
+        let set: Vec<SetTrack> = tracks.iter().map(SetTrack::new).collect();
+        let costs = SetCosts {
+            transitions: set.iter().map(|&a| set.iter().map(|&b| transition_cost(a, b, MixOptions::default())).collect()).collect(),
+            energies: (0..6).map(|track| (0..6).map(|position| (track as f32 - position as f32).abs()).collect()).collect(),
+        };
+
+        // Swapping changes the total by exactly the change around the swapped positions
+        let mut order: Vec<usize> = vec![3, 0, 5, 1, 4, 2];
+        for (i, j) in [(0, 1), (0, 5), (2, 3), (1, 4)] {
+            let (total, local) = (costs.total(&order), costs.around(&order, i, j));
+            order.swap(i, j);
+            let delta = costs.total(&order) - total;
+            assert!((delta - (costs.around(&order, i, j) - local)).abs() < 1e-4, "swap {} {}", i, j);
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use serde::{Deserialize, Serialize};
+
+// Grid fitting configuration
+const BEATS_PER_BAR: u8 = 4;
+const MATCH_TOLERANCE: f32 = 0.2; // Fraction of a beat period an onset may be off the grid
+const PHASE_STEPS: usize = 100; // Phase candidates tried per beat period
+const PHASE_WINDOW_BEATS: f32 = 32.0; // Beats at the start of the track used to find the phase
+const PHASE_CORRECTION: f32 = 0.5; // Share of an onset's deviation applied to the beat position
+const TEMPO_CORRECTION: f32 = 0.1; // Share of an onset's deviation applied to the beat period
+const MAX_TEMPO_DRIFT: f32 = 0.15; // Local period stays within 15% of the analyzed tempo
+const TEMPO_CURVE_RESOLUTION: f32 = 0.5; // BPM change needed for a new tempo curve point
+
+/// Onset found by an analyzer, used as evidence for the beat grid
+#[derive(Debug, Clone, Copy)]
+pub struct BeatOnset {
+    pub time: f32, // Seconds from the start of the track
+    pub strength: f32,
+}
+
+/// Local tempo at a point in time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TempoPoint {
+    pub time: f32,
+    pub bpm: f32,
+}
+
+/// Beat positions of a track with its bar structure and tempo drift
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BeatGrid {
+    pub bpm: f32,
+    pub beats_per_bar: u8,
+    pub beats: Vec<f32>, // Seconds from the start of the track
+    pub first_downbeat: f32,
+    pub bar_phase: u8, // Beats before the first downbeat
+    pub tempo_curve: Vec<TempoPoint>, // Only points where the tempo changes
+}
+
+/// Storage form: beat positions as millisecond deltas, tempo in tenths of a BPM
+#[derive(Serialize, Deserialize)]
+struct CompactBeatGrid {
+    bpm: f32,
+    beats_per_bar: u8,
+    bar_phase: u8,
+    start_ms: u32,
+    deltas_ms: Vec<u32>,
+    tempo: Vec<(u32, u32)>, // (time in ms, BPM * 10)
+}
+
+impl BeatGrid {
+    /// Fit a beat grid to detected onsets, following tempo drift.
+    /// The phase is taken from the start of the track; later beats are tracked onset by onset.
+    pub fn fit(onsets: &[BeatOnset], bpm: f32, duration: f32) -> Option<BeatGrid> {
+        if !bpm.is_finite() || bpm <= 0.0 || onsets.len() < 2 || duration <= 0.0 {
+            return None;
+        }
+
+        let mut onsets = onsets.to_vec();
+        onsets.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let period = 60.0 / bpm;
+        let tolerance = period * MATCH_TOLERANCE;
+        let first_beat = Self::find_phase(&onsets, period);
+
+        // Track beats forward, nudging position and period towards matching onsets
+        let mut beats = Vec::new();
+        let mut matched_strength = Vec::new();
+        let mut local_period = period;
+        let mut time = first_beat;
+        let mut strength = Self::nearest_onset(&onsets, time, tolerance).map_or(0.0, |onset| onset.strength);
+        while time <= duration {
+            beats.push(time);
+            matched_strength.push(strength);
+
+            let predicted = time + local_period;
+            match Self::nearest_onset(&onsets, predicted, tolerance) {
+                Some(onset) => {
+                    let deviation = onset.time - predicted;
+                    time = predicted + deviation * PHASE_CORRECTION;
+                    local_period = (local_period + deviation * TEMPO_CORRECTION)
+                        .clamp(period * (1.0 - MAX_TEMPO_DRIFT), period * (1.0 + MAX_TEMPO_DRIFT));
+                    strength = onset.strength;
+                }
+                None => {
+                    time = predicted;
+                    strength = 0.0;
+                }
+            }
+        }
+        if beats.len() < 2 {
+            return None;
+        }
+
+        // The downbeat is the bar position with the strongest onsets
+        let bar_phase = (0..BEATS_PER_BAR as usize)
+            .map(|position| {
+                let total: f32 = matched_strength.iter().skip(position).step_by(BEATS_PER_BAR as usize).sum();
+                (position, total)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0, |(position, _)| position)
+            .min(beats.len() - 1);
+
+        let tempo_curve = Self::tempo_curve(&beats, bar_phase);
+        Some(BeatGrid {
+            bpm,
+            beats_per_bar: BEATS_PER_BAR,
+            first_downbeat: beats[bar_phase],
+            bar_phase: bar_phase as u8,
+            beats,
+            tempo_curve,
+        })
+    }
+
+    // Phase within the first beat period that lines up best with the onsets at the start of the track
+    fn find_phase(onsets: &[BeatOnset], period: f32) -> f32 {
+        let window_end = onsets[0].time + period * PHASE_WINDOW_BEATS;
+        let window: Vec<&BeatOnset> = onsets.iter().take_while(|onset| onset.time <= window_end).collect();
+
+        (0..PHASE_STEPS)
+            .map(|step| {
+                let phase = period * step as f32 / PHASE_STEPS as f32;
+                let score: f32 = window
+                    .iter()
+                    .map(|onset| {
+                        let offset = (onset.time - phase).rem_euclid(period);
+                        let distance = offset.min(period - offset) / (period * MATCH_TOLERANCE);
+                        onset.strength * (1.0 - distance).max(0.0)
+                    })
+                    .sum();
+                (phase, score)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0.0, |(phase, _)| phase)
+    }
+
+    // Strongest onset near a time, weighted by its distance
+    fn nearest_onset(onsets: &[BeatOnset], time: f32, tolerance: f32) -> Option<BeatOnset> {
+        let start = onsets.partition_point(|onset| onset.time < time - tolerance);
+        onsets[start..]
+            .iter()
+            .take_while(|onset| onset.time <= time + tolerance)
+            .max_by(|a, b| {
+                let score = |onset: &BeatOnset| onset.strength * (1.0 - (onset.time - time).abs() / tolerance);
+                score(a).total_cmp(&score(b))
+            })
+            .copied()
+    }
+
+    // Tempo over two bars around every bar start, keeping only points where it changes
+    fn tempo_curve(beats: &[f32], bar_phase: usize) -> Vec<TempoPoint> {
+        let span = BEATS_PER_BAR as usize;
+        let mut bar_starts: Vec<usize> = (bar_phase..beats.len()).step_by(span).collect();
+        if bar_starts.first() != Some(&0) {
+            bar_starts.insert(0, 0);
+        }
+
+        let points: Vec<TempoPoint> = bar_starts
+            .into_iter()
+            .filter_map(|start| {
+                let from = start.saturating_sub(span);
+                let to = (from + 2 * span).min(beats.len() - 1);
+                let elapsed = beats[to] - beats[from];
+                (to > from && elapsed > 0.0).then(|| TempoPoint {
+                    time: beats[start],
+                    bpm: 60.0 * (to - from) as f32 / elapsed,
+                })
+            })
+            .collect();
+
+        let mut curve: Vec<TempoPoint> = Vec::new();
+        for point in &points {
+            if curve.last().is_none_or(|last| (last.bpm - point.bpm).abs() >= TEMPO_CURVE_RESOLUTION) {
+                curve.push(*point);
+            }
+        }
+        if let (Some(last), Some(kept)) = (points.last(), curve.last())
+            && last != kept
+        {
+            curve.push(*last);
+        }
+        curve
+    }
+
+    /// Serialize for storage
+    pub fn to_compact_json(&self) -> String {
+        let millis = |seconds: f32| (seconds.max(0.0) * 1000.0).round() as u32;
+        let beat_ms: Vec<u32> = self.beats.iter().map(|&beat| millis(beat)).collect();
+        let compact = CompactBeatGrid {
+            bpm: self.bpm,
+            beats_per_bar: self.beats_per_bar,
+            bar_phase: self.bar_phase,
+            start_ms: beat_ms.first().copied().unwrap_or(0),
+            deltas_ms: beat_ms.windows(2).map(|pair| pair[1] - pair[0]).collect(),
+            tempo: self
+                .tempo_curve
+                .iter()
+                .map(|point| (millis(point.time), (point.bpm * 10.0).round() as u32))
+                .collect(),
+        };
+        serde_json::to_string(&compact).unwrap_or_default()
+    }
+
+    pub fn from_compact_json(data: &str) -> Option<BeatGrid> {
+        let compact: CompactBeatGrid = serde_json::from_str(data).ok()?;
+        let mut beats = Vec::with_capacity(compact.deltas_ms.len() + 1);
+        let mut position = compact.start_ms;
+        beats.push(position as f32 / 1000.0);
+        for delta in &compact.deltas_ms {
+            position += delta;
+            beats.push(position as f32 / 1000.0);
+        }
+
+        Some(BeatGrid {
+            bpm: compact.bpm,
+            beats_per_bar: compact.beats_per_bar,
+            first_downbeat: beats.get(compact.bar_phase as usize).copied().unwrap_or(beats[0]),
+            bar_phase: compact.bar_phase,
+            beats,
+            tempo_curve: compact
+                .tempo
+                .into_iter()
+                .map(|(time, bpm)| TempoPoint {
+                    time: time as f32 / 1000.0,
+                    bpm: bpm as f32 / 10.0,
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Onsets on every beat with an accent every bar, plus off-beat noise
+    fn onsets_at(times: &[f32], accent_from: usize) -> Vec<BeatOnset> {
+        let mut onsets: Vec<BeatOnset> = times
+            .iter()
+            .enumerate()
+            .map(|(i, &time)| BeatOnset {
+                time: time + if i % 3 == 0 { 0.008 } else { -0.005 },
+                strength: if i >= accent_from && (i - accent_from) % 4 == 0 { 2.0 } else { 1.0 },
+            })
+            .collect();
+        onsets.extend(times.windows(2).step_by(5).map(|pair| BeatOnset {
+            time: (pair[0] + pair[1]) / 2.0,
+            strength: 0.4,
+        }));
+        onsets
+    }
+
+    #[test]
+    fn test_steady_grid_and_downbeat() {
+        let times: Vec<f32> = (0..120).map(|i| 0.31 + i as f32 * 0.5).collect();
+        let grid = BeatGrid::fit(&onsets_at(&times, 1), 120.0, 60.5).unwrap();
+
+        assert!((grid.beats[0] - 0.31).abs() < 0.02, "first beat {}", grid.beats[0]);
+        assert_eq!(grid.bar_phase, 1);
+        assert!((grid.first_downbeat - 0.81).abs() < 0.02);
+        for (beat, expected) in grid.beats.iter().zip(&times) {
+            assert!((beat - expected).abs() < 0.02, "beat {} expected {}", beat, expected);
+        }
+        assert!(grid.tempo_curve.len() <= 2);
+        assert!(grid.tempo_curve.iter().all(|point| (point.bpm - 120.0).abs() < 0.5));
+    }
+
+    #[test]
+    fn test_tempo_drift() {
+        // Accelerating from 120 to 130 BPM over the track
+        let mut times = vec![0.0f32];
+        for i in 1..200 {
+            let bpm = 120.0 + 10.0 * i as f32 / 200.0;
+            times.push(times[i - 1] + 60.0 / bpm);
+        }
+        let duration = times[times.len() - 1] + 0.2;
+        let grid = BeatGrid::fit(&onsets_at(&times, 0), 120.0, duration).unwrap();
+
+        let first = grid.tempo_curve.first().unwrap().bpm;
+        let last = grid.tempo_curve.last().unwrap().bpm;
+        assert!((first - 120.5).abs() < 1.5, "start tempo {}", first);
+        assert!((last - 129.5).abs() < 1.5, "end tempo {}", last);
+        assert!(grid.tempo_curve.len() > 2);
+    }
+
+    #[test]
+    fn test_compact_round_trip() {
+        let times: Vec<f32> = (0..16).map(|i| 0.25 + i as f32 * 0.5).collect();
+        let grid = BeatGrid::fit(&onsets_at(&times, 2), 120.0, 8.2).unwrap();
+        let restored = BeatGrid::from_compact_json(&grid.to_compact_json()).unwrap();
+
+        assert_eq!(restored.bar_phase, grid.bar_phase);
+        assert_eq!(restored.beats.len(), grid.beats.len());
+        assert!(restored.beats.iter().zip(&grid.beats).all(|(a, b)| (a - b).abs() < 0.001));
+        assert!((restored.first_downbeat - grid.first_downbeat).abs() < 0.001);
+    }
+}
@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+
+/// Range used to pick between half and double tempo when the user has no preference
+pub const DEFAULT_BPM_RANGE: BpmRange = BpmRange { min: 80.0, max: 160.0 };
+
+// Estimation configuration
+const MIN_PLAUSIBLE_BPM: f32 = 50.0; // Tempos outside this range are reported as undetermined
+const MAX_PLAUSIBLE_BPM: f32 = 250.0;
+const CANDIDATE_RATIOS: [f32; 5] = [0.5, 2.0 / 3.0, 1.0, 1.5, 2.0]; // Octave and triplet alternatives
+const INTERVAL_TOLERANCE: f32 = 0.06; // Relative deviation of an interval still counted as a match
+const SAME_TEMPO_TOLERANCE: f32 = 0.01;
+// (multiple of the beat period, weight) an interval may span; off-beats and skipped beats count less
+const INTERVAL_MULTIPLES: [(f32, f32); 5] = [(0.5, 0.5), (1.0, 1.0), (2.0, 0.5), (3.0, 0.33), (4.0, 0.25)];
+
+/// Alternative tempo with its support in the detected beat intervals, 0.0 to 1.0
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BpmCandidate {
+    pub bpm: f32,
+    pub score: f32,
+}
+
+/// Preferred tempo range used to resolve octave errors
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BpmRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl BpmRange {
+    pub fn contains(&self, bpm: f32) -> bool {
+        bpm >= self.min && bpm <= self.max
+    }
+
+    fn distance(&self, bpm: f32) -> f32 {
+        if bpm < self.min {
+            self.min - bpm
+        } else if bpm > self.max {
+            bpm - self.max
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Tempo of a track with how sure the analyzer is; `bpm` is None when no tempo could be determined
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BpmEstimate {
+    pub bpm: Option<f32>,
+    pub confidence: f32,
+    pub candidates: Vec<BpmCandidate>,
+}
+
+impl BpmEstimate {
+    pub fn undetermined() -> Self {
+        Self {
+            bpm: None,
+            confidence: 0.0,
+            candidates: Vec::new(),
+        }
+    }
+
+    /// Score the octave alternatives of a tempo against the beat intervals and pick the one in the range
+    pub fn from_intervals(intervals: &[f32], tempo: f32, range: BpmRange) -> Self {
+        if intervals.is_empty() || !tempo.is_finite() || tempo <= 0.0 {
+            return Self::undetermined();
+        }
+
+        let mut candidates: Vec<BpmCandidate> = CANDIDATE_RATIOS
+            .iter()
+            .map(|ratio| tempo * ratio)
+            .filter(|&bpm| (MIN_PLAUSIBLE_BPM..=MAX_PLAUSIBLE_BPM).contains(&bpm))
+            .map(|bpm| BpmCandidate {
+                bpm,
+                score: interval_support(intervals, 60.0 / bpm),
+            })
+            .collect();
+        let best_score = candidates.iter().map(|candidate| candidate.score).fold(0.0f32, f32::max);
+        if best_score > 0.0 {
+            for candidate in &mut candidates {
+                candidate.score /= best_score;
+            }
+        }
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        let bpm = resolve_octave(tempo, &candidates, range);
+        if !(MIN_PLAUSIBLE_BPM..=MAX_PLAUSIBLE_BPM).contains(&bpm) {
+            return Self {
+                bpm: None,
+                confidence: 0.0,
+                candidates,
+            };
+        }
+
+        Self {
+            bpm: Some(bpm),
+            confidence: interval_support(intervals, 60.0 / bpm).min(1.0),
+            candidates,
+        }
+    }
+}
+
+/// Pick the half, double or same tempo inside the preferred range; triplet alternatives
+/// are only used when no octave of the tempo fits
+pub fn resolve_octave(bpm: f32, candidates: &[BpmCandidate], range: BpmRange) -> f32 {
+    let score_of = |tempo: f32| {
+        candidates
+            .iter()
+            .find(|candidate| (candidate.bpm - tempo).abs() <= tempo * SAME_TEMPO_TOLERANCE)
+            .map_or(0.0, |candidate| candidate.score)
+    };
+    let octaves: Vec<f32> = [0.25, 0.5, 1.0, 2.0, 4.0].iter().map(|factor| bpm * factor).collect();
+
+    let in_range = octaves
+        .iter()
+        .copied()
+        .filter(|&tempo| range.contains(tempo))
+        .max_by(|&a, &b| score_of(a).total_cmp(&score_of(b)));
+    if let Some(tempo) = in_range {
+        return tempo;
+    }
+
+    let alternative = candidates
+        .iter()
+        .filter(|candidate| range.contains(candidate.bpm))
+        .max_by(|a, b| a.score.total_cmp(&b.score));
+    if let Some(candidate) = alternative {
+        return candidate.bpm;
+    }
+
+    octaves
+        .into_iter()
+        .min_by(|&a, &b| range.distance(a).total_cmp(&range.distance(b)))
+        .unwrap_or(bpm)
+}
+
+// Share of intervals explained by a beat period, allowing off-beats and skipped beats.
+// Small timing deviations cost little, deviations near the tolerance almost all of a match.
+fn interval_support(intervals: &[f32], period: f32) -> f32 {
+    let total: f32 = intervals
+        .iter()
+        .map(|&interval| {
+            INTERVAL_MULTIPLES
+                .iter()
+                .map(|&(multiple, weight)| {
+                    let expected = period * multiple;
+                    let deviation = (interval - expected).abs() / expected;
+                    weight * (1.0 - (deviation / INTERVAL_TOLERANCE).powi(2)).max(0.0)
+                })
+                .fold(0.0f32, f32::max)
+        })
+        .sum();
+    total / intervals.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Beat intervals of a tempo with some skipped beats and timing noise
+    fn intervals(bpm: f32) -> Vec<f32> {
+        let period = 60.0 / bpm;
+        (0..64)
+            .map(|i| {
+                let jitter = if i % 2 == 0 { 0.005 } else { -0.005 };
+                if i % 5 == 0 { 2.0 * period + jitter } else { period + jitter }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_octave_resolution_with_preferred_range() {
+        // Drum and bass detected at half tempo
+        let estimate = BpmEstimate::from_intervals(&intervals(170.0), 85.0, DEFAULT_BPM_RANGE);
+        assert_eq!(estimate.bpm, Some(85.0));
+        assert!(estimate.candidates.iter().any(|candidate| (candidate.bpm - 170.0).abs() < 0.1));
+
+        let drum_and_bass = BpmRange { min: 160.0, max: 185.0 };
+        let resolved = resolve_octave(85.0, &estimate.candidates, drum_and_bass);
+        assert!((resolved - 170.0).abs() < 0.1);
+
+        // Hip-hop detected at double tempo
+        let hip_hop = BpmRange { min: 60.0, max: 100.0 };
+        let estimate = BpmEstimate::from_intervals(&intervals(140.0), 140.0, hip_hop);
+        assert_eq!(estimate.bpm, Some(70.0));
+    }
+
+    #[test]
+    fn test_confidence_and_undetermined() {
+        let steady = BpmEstimate::from_intervals(&intervals(124.0), 124.0, DEFAULT_BPM_RANGE);
+        assert!(steady.confidence > 0.8, "confidence {}", steady.confidence);
+
+        let erratic: Vec<f32> = (0..64).map(|i| 0.3 + ((i * 37) % 100) as f32 * 0.013).collect();
+        let noisy = BpmEstimate::from_intervals(&erratic, 124.0, DEFAULT_BPM_RANGE);
+        assert!(noisy.confidence < 0.5, "confidence {}", noisy.confidence);
+
+        assert_eq!(BpmEstimate::from_intervals(&[], 120.0, DEFAULT_BPM_RANGE).bpm, None);
+        assert_eq!(BpmEstimate::from_intervals(&intervals(30.0), 30.0, BpmRange { min: 20.0, max: 40.0 }).bpm, None);
+    }
+}
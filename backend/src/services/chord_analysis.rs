@@ -0,0 +1,250 @@
+use serde::{Deserialize, Serialize};
+
+// Chord recognition configuration
+const CHORD_CHANGE_PENALTY: f32 = 0.15; // Similarity given up to change chords, smooths out passing notes
+const NO_CHORD_LEVEL: f32 = 0.1; // Spans with less chroma energy than this share of the median have no chord
+const FALLBACK_SPAN_SECS: f32 = 0.5; // Length of a chord decision without beats
+const SUMMARY_CHORDS: usize = 8;
+const PROGRESSION_LENGTH: usize = 4;
+const NO_CHORD: &str = "N";
+
+const PITCH_NAMES: [&str; 12] = ["C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChordQuality {
+    Major,
+    Minor,
+    Dominant7,
+    Major7,
+    Minor7,
+}
+
+impl ChordQuality {
+    pub const ALL: [ChordQuality; 5] = [
+        ChordQuality::Major,
+        ChordQuality::Minor,
+        ChordQuality::Dominant7,
+        ChordQuality::Major7,
+        ChordQuality::Minor7,
+    ];
+
+    // Semitones above the root
+    fn intervals(&self) -> &'static [usize] {
+        match self {
+            ChordQuality::Major => &[0, 4, 7],
+            ChordQuality::Minor => &[0, 3, 7],
+            ChordQuality::Dominant7 => &[0, 4, 7, 10],
+            ChordQuality::Major7 => &[0, 4, 7, 11],
+            ChordQuality::Minor7 => &[0, 3, 7, 10],
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            ChordQuality::Major => "",
+            ChordQuality::Minor => "m",
+            ChordQuality::Dominant7 => "7",
+            ChordQuality::Major7 => "maj7",
+            ChordQuality::Minor7 => "m7",
+        }
+    }
+}
+
+/// Part of a track over which one chord sounds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChordSegment {
+    pub start: f32, // Seconds from the start of the track
+    pub end: f32,
+    pub chord: String, // e.g. "Am7", "N" where no chord sounds
+    pub root: Option<String>, // Pitch class of the root, None without a chord
+    pub quality: Option<ChordQuality>,
+    pub confidence: f32, // Mean similarity of the chroma to the chord template (0.0 to 1.0)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChordShare {
+    pub chord: String,
+    pub share: f32, // Of the time with a chord
+}
+
+/// The chords a track is mostly made of, for learning it
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ChordSummary {
+    pub chords: Vec<ChordShare>, // Most used first
+    pub progression: Vec<String>, // Most repeated sequence of chords, empty if nothing repeats
+}
+
+// Chord templates as (root, quality, unit vector), followed by the "no chord" state
+fn templates() -> Vec<(usize, ChordQuality, [f32; 12])> {
+    ChordQuality::ALL
+        .into_iter()
+        .flat_map(|quality| {
+            (0..12).map(move |root| {
+                let mut template = [0.0; 12];
+                let weight = 1.0 / (quality.intervals().len() as f32).sqrt();
+                for interval in quality.intervals() {
+                    template[(root + interval) % 12] = weight;
+                }
+                (root, quality, template)
+            })
+        })
+        .collect()
+}
+
+/// Recognize chords over beat-synchronous chroma. Frames are (centre time, chroma);
+/// without beats, chords are decided over fixed spans.
+pub fn chord_timeline(frames: &[(f32, [f32; 12])], beats: &[f32], duration: f32) -> Vec<ChordSegment> {
+    if frames.is_empty() || duration <= 0.0 {
+        return Vec::new();
+    }
+
+    // Span boundaries: the beats, or fixed steps, with the start and end of the track
+    let mut boundaries: Vec<f32> = if beats.is_empty() {
+        (1..).map(|i| i as f32 * FALLBACK_SPAN_SECS).take_while(|&time| time < duration).collect()
+    } else {
+        beats.iter().copied().filter(|&beat| beat > 0.0 && beat < duration).collect()
+    };
+    boundaries.sort_by(f32::total_cmp);
+    boundaries.insert(0, 0.0);
+    boundaries.push(duration);
+
+    // Sum the chroma of the frames in each span
+    let mut spans = vec![([0.0f32; 12], 0usize); boundaries.len() - 1];
+    for (time, chroma) in frames {
+        let span = boundaries.partition_point(|&boundary| boundary <= *time).clamp(1, spans.len()) - 1;
+        for (bin, value) in spans[span].0.iter_mut().zip(chroma) {
+            *bin += value;
+        }
+        spans[span].1 += 1;
+    }
+    let energies: Vec<f32> = spans
+        .iter()
+        .map(|(chroma, count)| if *count > 0 { chroma.iter().sum::<f32>() / *count as f32 } else { 0.0 })
+        .collect();
+    let mut sorted: Vec<f32> = energies.iter().copied().filter(|&energy| energy > 0.0).collect();
+    sorted.sort_by(f32::total_cmp);
+    let quiet = sorted.get(sorted.len() / 2).map_or(f32::INFINITY, |median| median * NO_CHORD_LEVEL);
+
+    // Similarity of every span to every chord, the last state being "no chord"
+    let templates = templates();
+    let scores: Vec<Vec<f32>> = spans
+        .iter()
+        .zip(&energies)
+        .map(|((chroma, _), &energy)| {
+            let norm = chroma.iter().map(|value| value * value).sum::<f32>().sqrt();
+            if energy < quiet || norm <= 0.0 {
+                let mut scores = vec![0.0; templates.len() + 1];
+                scores[templates.len()] = 1.0;
+                return scores;
+            }
+            templates
+                .iter()
+                .map(|(_, _, template)| chroma.iter().zip(template).map(|(c, t)| c * t).sum::<f32>() / norm)
+                .chain([0.0])
+                .collect()
+        })
+        .collect();
+
+    // Best chord sequence when every change costs CHORD_CHANGE_PENALTY (Viterbi)
+    let states = templates.len() + 1;
+    let mut totals = scores[0].clone();
+    let mut backtrack: Vec<Vec<usize>> = Vec::with_capacity(scores.len());
+    for span_scores in &scores[1..] {
+        let (best_state, best_total) = totals
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((0, 0.0));
+        let mut from = vec![0; states];
+        for state in 0..states {
+            let (previous, total) = if totals[state] >= best_total - CHORD_CHANGE_PENALTY {
+                (state, totals[state])
+            } else {
+                (best_state, best_total - CHORD_CHANGE_PENALTY)
+            };
+            from[state] = previous;
+            totals[state] = total + span_scores[state];
+        }
+        backtrack.push(from);
+    }
+    let mut state = totals
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map_or(states - 1, |(state, _)| state);
+    let mut path = vec![state; scores.len()];
+    for (i, from) in backtrack.iter().enumerate().rev() {
+        state = from[state];
+        path[i] = state;
+    }
+
+    // Merge spans of the same chord into segments
+    let mut segments: Vec<(usize, usize, usize)> = Vec::new(); // (state, first span, last span)
+    for (span, &state) in path.iter().enumerate() {
+        match segments.last_mut() {
+            Some(last) if last.0 == state => last.2 = span,
+            _ => segments.push((state, span, span)),
+        }
+    }
+    segments
+        .into_iter()
+        .map(|(state, first, last)| {
+            let confidence = (first..=last).map(|span| scores[span][state]).sum::<f32>() / (last - first + 1) as f32;
+            let (chord, root, quality) = match templates.get(state) {
+                Some(&(root, quality, _)) => (
+                    format!("{}{}", PITCH_NAMES[root], quality.suffix()),
+                    Some(PITCH_NAMES[root].to_string()),
+                    Some(quality),
+                ),
+                None => (NO_CHORD.to_string(), None, None),
+            };
+            ChordSegment {
+                start: boundaries[first],
+                end: boundaries[last + 1],
+                chord,
+                root,
+                quality,
+                confidence: confidence.clamp(0.0, 1.0),
+            }
+        })
+        .collect()
+}
+
+impl ChordSummary {
+    pub fn of(timeline: &[ChordSegment]) -> ChordSummary {
+        let chords: Vec<&ChordSegment> = timeline.iter().filter(|segment| segment.quality.is_some()).collect();
+        let total: f32 = chords.iter().map(|segment| segment.end - segment.start).sum();
+        if total <= 0.0 {
+            return ChordSummary::default();
+        }
+
+        let mut shares: Vec<ChordShare> = Vec::new();
+        for segment in &chords {
+            let share = (segment.end - segment.start) / total;
+            match shares.iter_mut().find(|entry| entry.chord == segment.chord) {
+                Some(entry) => entry.share += share,
+                None => shares.push(ChordShare { chord: segment.chord.clone(), share }),
+            }
+        }
+        shares.sort_by(|a, b| b.share.total_cmp(&a.share));
+        shares.truncate(SUMMARY_CHORDS);
+
+        // Chord changes with gaps without a chord left out; the earliest of equally repeated sequences wins
+        let mut sequence: Vec<&str> = chords.iter().map(|segment| segment.chord.as_str()).collect();
+        sequence.dedup();
+        let mut best: Option<(&[&str], usize)> = None;
+        for window in sequence.windows(PROGRESSION_LENGTH) {
+            let count = sequence.windows(PROGRESSION_LENGTH).filter(|other| other == &window).count();
+            if count > 1 && best.is_none_or(|(_, best_count)| count > best_count) {
+                best = Some((window, count));
+            }
+        }
+
+        ChordSummary {
+            chords: shares,
+            progression: best.map(|(window, _)| window.iter().map(|chord| chord.to_string()).collect()).unwrap_or_default(),
+        }
+    }
+}
@@ -0,0 +1,602 @@
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::services::audio_decode::{AudioSink, AudioStream, FrameWindow, StreamResampler, ANALYSIS_SAMPLE_RATE};
+
+// Chromaprint configuration (algorithm 2, the default of fpcalc and AcoustID)
+const FINGERPRINT_SAMPLE_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 4096;
+const FRAME_HOP: usize = FRAME_SIZE / 3;
+const MIN_FREQ: f64 = 28.0;
+const MAX_FREQ: f64 = 3520.0;
+const CHROMA_FILTER: [f64; 5] = [0.25, 0.75, 1.0, 0.75, 0.25]; // Smoothing over consecutive frames
+const NORM_THRESHOLD: f64 = 0.01; // Quieter chroma vectors are zeroed
+const MAX_FINGERPRINT_SECS: f64 = 120.0; // Like fpcalc, only the beginning of a track is fingerprinted
+const ALGORITHM_ID: u8 = 1; // Chromaprint's id of algorithm 2
+const MAX_FILTER_WIDTH: usize = 16;
+
+// Compression
+const MAX_NORMAL_VALUE: u32 = 7; // Bit gaps from here on continue in the exceptional 5-bit stream
+
+// Duplicate detection
+const MAX_ALIGN_OFFSET: usize = 80; // Subfingerprints (about 10 s) two copies may be shifted by
+const MIN_OVERLAP: usize = 40; // Subfingerprints (about 5 s) compared at least
+const DUPLICATE_SIMILARITY: f32 = 0.85; // Share of equal bits; unrelated audio is around 0.5
+const DURATION_TOLERANCE_SECS: f32 = 5.0;
+
+// (filter type, first chroma bin, chroma bins, frames)
+type Filter = (u8, usize, usize, usize);
+
+// Filters and quantizer thresholds of the 16 classifiers
+const CLASSIFIERS: [(Filter, [f64; 3]); 16] = [
+    ((0, 4, 3, 15), [1.98215, 2.35817, 2.63523]),
+    ((4, 4, 6, 15), [-1.03809, -0.651211, -0.282167]),
+    ((1, 0, 4, 16), [-0.298702, 0.119262, 0.558497]),
+    ((3, 8, 2, 12), [-0.105439, 0.0153946, 0.135898]),
+    ((3, 4, 4, 8), [-0.142891, 0.0258736, 0.200632]),
+    ((4, 0, 3, 5), [-0.826319, -0.590612, -0.368214]),
+    ((1, 2, 2, 9), [-0.557409, -0.233035, 0.0534525]),
+    ((2, 7, 3, 4), [-0.0646826, 0.00620476, 0.0784847]),
+    ((2, 6, 2, 16), [-0.192387, -0.029699, 0.215855]),
+    ((2, 1, 3, 2), [-0.0397818, -0.00568076, 0.0292026]),
+    ((5, 10, 1, 15), [-0.53823, -0.369934, -0.190235]),
+    ((3, 6, 2, 10), [-0.124877, 0.0296483, 0.139239]),
+    ((2, 1, 1, 14), [-0.101475, 0.0225617, 0.231971]),
+    ((3, 5, 6, 4), [-0.0799915, -0.00729616, 0.063262]),
+    ((1, 9, 2, 12), [-0.272556, 0.019424, 0.302559]),
+    ((3, 4, 2, 14), [-0.164292, -0.0321188, 0.0846339]),
+];
+const GRAY_CODE: [u32; 4] = [0, 1, 3, 2];
+
+/// Codec and resolution of an audio file, to pick the best of several copies
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AudioQuality {
+    pub codec: String, // Decoder name, e.g. "flac", "mp3", "pcm_s24le"
+    pub lossless: bool,
+    pub sample_rate: u32,
+    pub bit_depth: Option<u32>, // None for lossy codecs
+    pub bitrate: Option<u32>, // kbps, averaged over the file
+}
+
+impl AudioQuality {
+    pub fn new(codec: String, sample_rate: u32, bit_depth: Option<u32>, bitrate: Option<u32>) -> Self {
+        let lossless = matches!(codec.as_str(), "flac" | "alac" | "wavpack") || codec.starts_with("pcm");
+        Self { codec, lossless, sample_rate, bit_depth, bitrate }
+    }
+
+    pub fn of(stream: &AudioStream, path: &Path, duration: f64) -> Self {
+        let bitrate = std::fs::metadata(path)
+            .ok()
+            .filter(|_| duration > 0.0)
+            .map(|metadata| (metadata.len() as f64 * 8.0 / duration / 1000.0).round() as u32);
+        Self::new(stream.codec.clone(), stream.sample_rate, stream.bit_depth, bitrate)
+    }
+
+    // Lossless first, then the highest resolution
+    fn rank(&self) -> (bool, u32, u32, u32) {
+        (self.lossless, self.bit_depth.unwrap_or(0), self.sample_rate, self.bitrate.unwrap_or(0))
+    }
+}
+
+/// Fingerprint of a track as stored, with what is needed to compare copies
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackFingerprint {
+    pub fingerprint: String, // Compressed and base64 encoded like fpcalc, usable with AcoustID
+    pub duration: f32, // Seconds of the whole file
+    pub quality: AudioQuality,
+}
+
+/// Chromaprint subfingerprints, one per FRAME_HOP samples at 11025 Hz
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fingerprint(pub Vec<u32>);
+
+impl Fingerprint {
+    /// Compress like chromaprint_encode_fingerprint: bit positions set in the XOR of consecutive
+    /// subfingerprints, as 3-bit gaps with larger gaps continued in 5-bit values
+    pub fn encode(&self) -> String {
+        let size = self.0.len();
+        let mut normal = BitWriter::default();
+        let mut exceptional = BitWriter::default();
+        let mut previous = 0;
+        for &subfingerprint in &self.0 {
+            let mut bits = subfingerprint ^ previous;
+            previous = subfingerprint;
+            let (mut bit, mut last_bit) = (1, 0);
+            while bits != 0 {
+                if bits & 1 != 0 {
+                    let gap = bit - last_bit;
+                    if gap >= MAX_NORMAL_VALUE {
+                        normal.write(MAX_NORMAL_VALUE, 3);
+                        exceptional.write(gap - MAX_NORMAL_VALUE, 5);
+                    } else {
+                        normal.write(gap, 3);
+                    }
+                    last_bit = bit;
+                }
+                bits >>= 1;
+                bit += 1;
+            }
+            normal.write(0, 3);
+        }
+
+        let mut data = vec![ALGORITHM_ID, (size >> 16) as u8, (size >> 8) as u8, size as u8];
+        data.extend(normal.bytes);
+        data.extend(exceptional.bytes);
+        URL_SAFE_NO_PAD.encode(data)
+    }
+
+    pub fn decode(encoded: &str) -> Result<Fingerprint> {
+        let data = URL_SAFE_NO_PAD.decode(encoded.trim_end_matches('='))?;
+        if data.len() < 4 {
+            return Err(anyhow!("Fingerprint too short"));
+        }
+        let size = ((data[1] as usize) << 16) | ((data[2] as usize) << 8) | data[3] as usize;
+
+        let mut normal = BitReader::new(&data[4..]);
+        let mut gaps = Vec::new();
+        let mut ended = 0;
+        while ended < size {
+            let gap = normal.read(3).ok_or_else(|| anyhow!("Fingerprint data ends early"))?;
+            ended += (gap == 0) as usize;
+            gaps.push(gap);
+        }
+        let mut exceptional = BitReader::new(&data[4 + (gaps.len() * 3).div_ceil(8)..]);
+
+        let mut subfingerprints = Vec::with_capacity(size);
+        let (mut bits, mut last_bit, mut previous) = (0u32, 0u32, 0u32);
+        for mut gap in gaps {
+            if gap == 0 {
+                previous ^= bits;
+                subfingerprints.push(previous);
+                (bits, last_bit) = (0, 0);
+                continue;
+            }
+            if gap == MAX_NORMAL_VALUE {
+                gap += exceptional.read(5).ok_or_else(|| anyhow!("Fingerprint data ends early"))?;
+            }
+            last_bit += gap;
+            if last_bit > 32 {
+                return Err(anyhow!("Invalid fingerprint data"));
+            }
+            bits |= 1 << (last_bit - 1);
+        }
+        Ok(Fingerprint(subfingerprints))
+    }
+
+    /// Share of equal bits at the best alignment of two fingerprints, 0.0 if they barely overlap
+    pub fn similarity(&self, other: &Fingerprint) -> f32 {
+        let compare = |a: &[u32], b: &[u32]| {
+            let overlap = a.len().min(b.len());
+            if overlap < MIN_OVERLAP {
+                return 0.0;
+            }
+            let differing: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+            1.0 - differing as f32 / (overlap * 32) as f32
+        };
+        (0..=MAX_ALIGN_OFFSET)
+            .flat_map(|offset| {
+                [
+                    compare(self.0.get(offset..).unwrap_or_default(), &other.0),
+                    compare(&self.0, other.0.get(offset..).unwrap_or_default()),
+                ]
+            })
+            .fold(0.0, f32::max)
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    used: u32, // Bits used in the last byte
+}
+
+impl BitWriter {
+    // Least significant bit first
+    fn write(&mut self, value: u32, bits: u32) {
+        for i in 0..bits {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= (((value >> i) & 1) as u8) << self.used;
+            self.used = (self.used + 1) % 8;
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize, // In bits
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn read(&mut self, bits: u32) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..bits {
+            let byte = self.bytes.get(self.position / 8)?;
+            value |= (((byte >> (self.position % 8)) & 1) as u32) << i;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+/// Streaming Chromaprint computation over the first two minutes of a track
+pub struct Fingerprinter {
+    resampler: StreamResampler,
+    window: FrameWindow,
+    fft: Arc<dyn Fft<f64>>,
+    hamming: Vec<f64>,
+    notes: Vec<usize>, // Chroma bin of every FFT bin from min_bin
+    min_bin: usize,
+    recent: Vec<[f64; 12]>, // Last chroma vectors for the smoothing filter
+    frames: usize,
+    integral: Vec<[f64; 12]>, // Summed-area table of the normalized chroma image
+    subfingerprints: Vec<u32>,
+    samples: usize, // Pushed at FINGERPRINT_SAMPLE_RATE
+    active: bool, // Only the range starting at the beginning of the track counts
+}
+
+impl Default for Fingerprinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fingerprinter {
+    pub fn new() -> Self {
+        let bin = |freq: f64| (FRAME_SIZE as f64 * freq / FINGERPRINT_SAMPLE_RATE as f64).round() as usize;
+        let (min_bin, max_bin) = (bin(MIN_FREQ), bin(MAX_FREQ));
+        let notes = (min_bin..max_bin)
+            .map(|i| {
+                let freq = i as f64 * FINGERPRINT_SAMPLE_RATE as f64 / FRAME_SIZE as f64;
+                let octave = (freq / (440.0 / 16.0)).log2();
+                (12.0 * (octave - octave.floor())) as usize
+            })
+            .collect();
+        let hamming = (0..FRAME_SIZE)
+            .map(|i| 0.54 - 0.46 * (2.0 * std::f64::consts::PI * i as f64 / (FRAME_SIZE - 1) as f64).cos())
+            .collect();
+
+        Self {
+            resampler: StreamResampler::new(ANALYSIS_SAMPLE_RATE, FINGERPRINT_SAMPLE_RATE),
+            window: FrameWindow::new(FRAME_SIZE, FRAME_HOP, FINGERPRINT_SAMPLE_RATE),
+            fft: FftPlanner::new().plan_fft_forward(FRAME_SIZE),
+            hamming,
+            notes,
+            min_bin,
+            recent: Vec::with_capacity(CHROMA_FILTER.len()),
+            frames: 0,
+            integral: Vec::new(),
+            subfingerprints: Vec::new(),
+            samples: 0,
+            active: true,
+        }
+    }
+
+    pub fn finish(mut self) -> Fingerprint {
+        if self.active {
+            let tail = self.resampler.finish();
+            self.push_resampled(&tail);
+        }
+        Fingerprint(self.subfingerprints)
+    }
+
+    fn push_resampled(&mut self, samples: &[f32]) {
+        let max_samples = (MAX_FINGERPRINT_SECS * FINGERPRINT_SAMPLE_RATE as f64) as usize;
+        let samples = &samples[..samples.len().min(max_samples.saturating_sub(self.samples))];
+        self.samples += samples.len();
+
+        let mut chroma_frames = Vec::new();
+        let (fft, hamming, notes, min_bin) = (&self.fft, &self.hamming, &self.notes, self.min_bin);
+        self.window.push(samples, |frame, _| {
+            let mut spectrum: Vec<Complex<f64>> =
+                frame.iter().zip(hamming).map(|(&sample, &w)| Complex::new(sample as f64 * w, 0.0)).collect();
+            fft.process(&mut spectrum);
+            let mut chroma = [0.0; 12];
+            for (bin, &note) in notes.iter().enumerate() {
+                chroma[note] += spectrum[min_bin + bin].norm_sqr();
+            }
+            chroma_frames.push(chroma);
+        });
+        for chroma in chroma_frames {
+            self.push_chroma(chroma);
+        }
+    }
+
+    fn push_chroma(&mut self, chroma: [f64; 12]) {
+        // Smooth over the last frames; as in Chromaprint, the first frame only fills the filter
+        if self.recent.len() == CHROMA_FILTER.len() {
+            self.recent.remove(0);
+        }
+        self.recent.push(chroma);
+        self.frames += 1;
+        if self.frames <= CHROMA_FILTER.len() {
+            return;
+        }
+        let mut filtered = [0.0; 12];
+        for (frame, coefficient) in self.recent.iter().zip(CHROMA_FILTER) {
+            for (bin, value) in filtered.iter_mut().zip(frame) {
+                *bin += value * coefficient;
+            }
+        }
+
+        let norm = filtered.iter().map(|value| value * value).sum::<f64>().sqrt();
+        for value in filtered.iter_mut() {
+            *value = if norm < NORM_THRESHOLD { 0.0 } else { *value / norm };
+        }
+
+        let mut row = [0.0; 12];
+        let mut running = 0.0;
+        for (bin, value) in filtered.iter().enumerate() {
+            running += value;
+            row[bin] = running + self.integral.last().map_or(0.0, |previous| previous[bin]);
+        }
+        self.integral.push(row);
+
+        if self.integral.len() >= MAX_FILTER_WIDTH {
+            let offset = self.integral.len() - MAX_FILTER_WIDTH;
+            let subfingerprint = CLASSIFIERS.iter().fold(0, |bits, &(filter, thresholds)| {
+                (bits << 2) | GRAY_CODE[quantize(self.apply_filter(filter, offset), thresholds)]
+            });
+            self.subfingerprints.push(subfingerprint);
+        }
+    }
+
+    // Sum of the chroma image over frames x1..x2 and chroma bins y1..y2
+    fn area(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> f64 {
+        if x1 == x2 || y1 == y2 {
+            return 0.0;
+        }
+        let at = |x: usize, y: usize| if x == 0 || y == 0 { 0.0 } else { self.integral[x - 1][y - 1] };
+        at(x2, y2) - at(x1, y2) - at(x2, y1) + at(x1, y1)
+    }
+
+    // Haar-like filter comparing areas of the chroma image at frame x
+    fn apply_filter(&self, (kind, y, height, width): Filter, x: usize) -> f64 {
+        let (a, b) = match kind {
+            0 => (self.area(x, y, x + width, y + height), 0.0),
+            1 => {
+                let h = height / 2;
+                (self.area(x, y + h, x + width, y + height), self.area(x, y, x + width, y + h))
+            }
+            2 => {
+                let w = width / 2;
+                (self.area(x + w, y, x + width, y + height), self.area(x, y, x + w, y + height))
+            }
+            3 => {
+                let (w, h) = (width / 2, height / 2);
+                (
+                    self.area(x, y + h, x + w, y + height) + self.area(x + w, y, x + width, y + h),
+                    self.area(x, y, x + w, y + h) + self.area(x + w, y + h, x + width, y + height),
+                )
+            }
+            4 => {
+                let h = height / 3;
+                (
+                    self.area(x, y + h, x + width, y + 2 * h),
+                    self.area(x, y, x + width, y + h) + self.area(x, y + 2 * h, x + width, y + height),
+                )
+            }
+            _ => {
+                let w = width / 3;
+                (
+                    self.area(x + w, y, x + 2 * w, y + height),
+                    self.area(x, y, x + w, y + height) + self.area(x + 2 * w, y, x + width, y + height),
+                )
+            }
+        };
+        (1.0 + a).ln() - (1.0 + b).ln()
+    }
+}
+
+fn quantize(value: f64, [t0, t1, t2]: [f64; 3]) -> usize {
+    if value < t1 {
+        if value < t0 { 0 } else { 1 }
+    } else if value < t2 {
+        2
+    } else {
+        3
+    }
+}
+
+impl AudioSink for Fingerprinter {
+    fn start_span(&mut self, time: f64) {
+        self.active = time <= 0.0;
+    }
+
+    fn push_mono(&mut self, samples: &[f32]) {
+        if self.active && (self.samples as f64) < MAX_FINGERPRINT_SECS * FINGERPRINT_SAMPLE_RATE as f64 {
+            let resampled = self.resampler.process(samples);
+            self.push_resampled(&resampled);
+        }
+    }
+}
+
+/// A fingerprinted track among which duplicates are searched
+#[derive(Debug, Clone)]
+pub struct FingerprintedTrack {
+    pub source: String,
+    pub track_id: String,
+    pub fingerprint: Fingerprint,
+    pub duration: f32,
+    pub quality: AudioQuality,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateCopy {
+    pub source: String,
+    pub track_id: String,
+    pub duration: f32,
+    #[serde(flatten)]
+    pub quality: AudioQuality,
+    pub best: bool, // The copy worth keeping
+}
+
+/// Copies of the same recording, best copy first
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub similarity: f32, // Lowest similarity that joined a copy to the group
+    pub copies: Vec<DuplicateCopy>,
+}
+
+/// Group tracks whose fingerprints match; tracks without copies are left out
+pub fn find_duplicates(tracks: &[FingerprintedTrack]) -> Vec<DuplicateGroup> {
+    // Only tracks of about the same length are compared
+    let mut order: Vec<usize> = (0..tracks.len()).collect();
+    order.sort_by(|&a, &b| tracks[a].duration.total_cmp(&tracks[b].duration));
+
+    let mut parent: Vec<usize> = (0..tracks.len()).collect();
+    let mut similarity = vec![1.0f32; tracks.len()];
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for (position, &a) in order.iter().enumerate() {
+        for &b in &order[position + 1..] {
+            if tracks[b].duration - tracks[a].duration > DURATION_TOLERANCE_SECS {
+                break;
+            }
+            let (root_a, root_b) = (root(&mut parent, a), root(&mut parent, b));
+            if root_a == root_b {
+                continue;
+            }
+            let score = tracks[a].fingerprint.similarity(&tracks[b].fingerprint);
+            if score >= DUPLICATE_SIMILARITY {
+                parent[root_b] = root_a;
+                similarity[root_a] = similarity[root_a].min(similarity[root_b]).min(score);
+            }
+        }
+    }
+
+    let mut groups: Vec<(usize, Vec<usize>)> = Vec::new();
+    for &i in &order {
+        let group_root = root(&mut parent, i);
+        match groups.iter_mut().find(|(r, _)| *r == group_root) {
+            Some((_, members)) => members.push(i),
+            None => groups.push((group_root, vec![i])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(group_root, mut members)| {
+            members.sort_by(|&a, &b| tracks[b].quality.rank().cmp(&tracks[a].quality.rank()));
+            DuplicateGroup {
+                similarity: similarity[group_root],
+                copies: members
+                    .iter()
+                    .enumerate()
+                    .map(|(rank, &i)| DuplicateCopy {
+                        source: tracks[i].source.clone(),
+                        track_id: tracks[i].track_id.clone(),
+                        duration: tracks[i].duration,
+                        quality: tracks[i].quality.clone(),
+                        best: rank == 0,
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pseudo-random chord sequence with a different pitch set every half second
+    fn recording(seed: u32, seconds: usize) -> Vec<f32> {
+        let rate = ANALYSIS_SAMPLE_RATE as usize;
+        (0..seconds * rate)
+            .map(|i| {
+                let step = (i / (rate / 2)) as u32;
+                let t = i as f32 / rate as f32;
+                (0..3u32)
+                    .map(|voice| {
+                        let mut hash = seed.wrapping_mul(0x9e37_79b9) ^ step.wrapping_mul(0x85eb_ca6b) ^ voice.wrapping_mul(0xc2b2_ae35);
+                        hash ^= hash >> 15;
+                        hash = hash.wrapping_mul(0x2c1b_3c6d);
+                        hash ^= hash >> 12;
+                        let note = 48 + hash % 24;
+                        let frequency = 440.0 * 2f32.powf((note as f32 - 69.0) / 12.0);
+                        (2.0 * std::f32::consts::PI * frequency * t).sin() * 0.2
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn fingerprint(samples: &[f32]) -> Fingerprint {
+        let mut fingerprinter = Fingerprinter::new();
+        fingerprinter.start_span(0.0);
+        for chunk in samples.chunks(4096) {
+            fingerprinter.push_mono(chunk);
+        }
+        fingerprinter.finish()
+    }
+
+    #[test]
+    fn test_compression_matches_chromaprint() {
+        let header = |size: u8| vec![ALGORITHM_ID, 0, 0, size];
+        let bytes = |fingerprint: Vec<u32>| URL_SAFE_NO_PAD.decode(Fingerprint(fingerprint).encode()).unwrap();
+        assert_eq!(bytes(vec![1]), [header(1), vec![1]].concat());
+        assert_eq!(bytes(vec![7]), [header(1), vec![73, 0]].concat());
+        assert_eq!(bytes(vec![1 << 6]), [header(1), vec![7, 0]].concat());
+
+        let fingerprint = Fingerprint(vec![0, 1 << 31, 0xdead_beef, 0xdead_beef, 42]);
+        assert_eq!(Fingerprint::decode(&fingerprint.encode()).unwrap(), fingerprint);
+    }
+
+    #[test]
+    fn test_duplicates_of_the_same_recording() {
+        let original = fingerprint(&recording(1, 30));
+        let expected = 30 * FINGERPRINT_SAMPLE_RATE as usize / FRAME_HOP - CHROMA_FILTER.len() - MAX_FILTER_WIDTH;
+        assert!(original.0.len().abs_diff(expected) <= 3, "{} subfingerprints", original.0.len());
+
+        // A lossy copy: quieter, with noise and a little delay
+        let mut noise = 1u32;
+        let mut lossy: Vec<f32> = vec![0.0; 2000];
+        lossy.extend(recording(1, 30).into_iter().map(|sample| {
+            noise = noise.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            sample * 0.7 + (noise >> 16) as f32 / 65536.0 * 0.02 - 0.01
+        }));
+        let copy = fingerprint(&lossy);
+        let other = fingerprint(&recording(2, 30));
+
+        let copy_score = original.similarity(&copy);
+        let other_score = original.similarity(&other);
+        assert!(copy_score > 0.9, "copy similarity {}", copy_score);
+        assert!(other_score < 0.7, "unrelated similarity {}", other_score);
+
+        let track = |track_id: &str, fingerprint: &Fingerprint, quality: AudioQuality| FingerprintedTrack {
+            source: "server".to_string(),
+            track_id: track_id.to_string(),
+            fingerprint: Fingerprint::decode(&fingerprint.encode()).unwrap(),
+            duration: 30.0,
+            quality,
+        };
+        let groups = find_duplicates(&[
+            track("song.mp3", &copy, AudioQuality::new("mp3".to_string(), 44100, None, Some(320))),
+            track("other.flac", &other, AudioQuality::new("flac".to_string(), 44100, Some(16), Some(900))),
+            track("song.flac", &original, AudioQuality::new("flac".to_string(), 96000, Some(24), Some(2800))),
+        ]);
+        assert_eq!(groups.len(), 1);
+        let copies: Vec<(&str, bool)> = groups[0].copies.iter().map(|copy| (copy.track_id.as_str(), copy.best)).collect();
+        assert_eq!(copies, [("song.flac", true), ("song.mp3", false)]);
+    }
+}
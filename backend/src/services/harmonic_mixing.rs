@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Suggestion scoring
+const KEY_WEIGHT: f32 = 0.6; // Of the score, the rest goes to the tempo match
+const HALF_DOUBLE_TEMPO_FACTOR: f32 = 0.85; // Tempo score kept when mixing at half or double tempo
+pub const DEFAULT_PITCH_TOLERANCE: f32 = 6.0; // Percent, the usual range of a pitch fader
+
+/// Position of a key on the Camelot wheel, e.g. "8A" (A minor)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Camelot {
+    pub number: u8, // 1 to 12
+    pub minor: bool, // "A" keys are minor, "B" keys major
+}
+
+impl Camelot {
+    pub fn parse(code: &str) -> Option<Camelot> {
+        let code = code.trim().to_uppercase();
+        let letter = code.chars().last()?;
+        let number: u8 = code[..code.len() - letter.len_utf8()].parse().ok()?;
+        if !(1..=12).contains(&number) {
+            return None;
+        }
+        match letter {
+            'A' => Some(Camelot { number, minor: true }),
+            'B' => Some(Camelot { number, minor: false }),
+            _ => None,
+        }
+    }
+
+    // Steps around the wheel, wrapping from 12 to 1
+    fn step(&self, steps: i32) -> Camelot {
+        Camelot { number: ((self.number as i32 - 1 + steps).rem_euclid(12) + 1) as u8, minor: self.minor }
+    }
+
+    /// The key after pitching by whole semitones; one semitone up is seven steps around the wheel
+    pub fn transpose(&self, semitones: i32) -> Camelot {
+        self.step(semitones * 7)
+    }
+
+    /// How the key of a next track relates to this one, None for a clash
+    pub fn relation_to(&self, next: &Camelot) -> Option<KeyRelation> {
+        let steps = (next.number as i32 - self.number as i32).rem_euclid(12);
+        match (self.minor == next.minor, steps) {
+            (true, 0) => Some(KeyRelation::Same),
+            (false, 0) => Some(KeyRelation::Relative),
+            (true, 1) => Some(KeyRelation::StepUp),
+            (true, 11) => Some(KeyRelation::StepDown),
+            (true, 2) => Some(KeyRelation::EnergyBoost),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Camelot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.number, if self.minor { 'A' } else { 'B' })
+    }
+}
+
+/// Harmonic compatibility of two keys on the Camelot wheel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyRelation {
+    Same,
+    Relative, // Relative major or minor, same number
+    StepUp, // +1, up a fifth
+    StepDown, // -1, down a fifth
+    EnergyBoost, // +2, lifts the mood
+}
+
+impl KeyRelation {
+    /// How smoothly the keys blend, 1.0 for the same key
+    pub fn score(&self) -> f32 {
+        match self {
+            KeyRelation::Same => 1.0,
+            KeyRelation::Relative => 0.9,
+            KeyRelation::StepUp | KeyRelation::StepDown => 0.85,
+            KeyRelation::EnergyBoost => 0.7,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            KeyRelation::Same => "same key",
+            KeyRelation::Relative => "relative major/minor",
+            KeyRelation::StepUp => "one step up the wheel",
+            KeyRelation::StepDown => "one step down the wheel",
+            KeyRelation::EnergyBoost => "energy boost",
+        }
+    }
+}
+
+/// How a next track's tempo is matched to the current one
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TempoMatch {
+    pub ratio: f32, // Beats of the next track per beat of the current one: 1.0, or 2.0/0.5 for half/double tempo
+    pub pitch_shift: f32, // Percent the next track is sped up (negative: slowed down) to match
+}
+
+/// Match the tempo of a next track within a pitch tolerance in percent, preferring the
+/// smallest speed change and, for equal changes, the same tempo over half or double
+pub fn tempo_match(current_bpm: f32, next_bpm: f32, pitch_tolerance: f32) -> Option<TempoMatch> {
+    if current_bpm <= 0.0 || next_bpm <= 0.0 {
+        return None;
+    }
+    [1.0f32, 2.0, 0.5]
+        .into_iter()
+        .map(|ratio| TempoMatch { ratio, pitch_shift: (current_bpm / (next_bpm * ratio) - 1.0) * 100.0 })
+        .filter(|tempo| tempo.pitch_shift.abs() <= pitch_tolerance + 1e-3)
+        .min_by(|a, b| a.pitch_shift.abs().total_cmp(&b.pitch_shift.abs()))
+}
+
+/// What is known about a track when suggesting what to play after another
+#[derive(Debug, Clone, Default)]
+pub struct MixTrack {
+    pub source: String,
+    pub track_id: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub bpm: Option<f32>,
+    pub camelot: Option<String>,
+    pub energy: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MixOptions {
+    pub pitch_tolerance: f32, // Percent the next track may be sped up or slowed down
+    pub key_lock: bool, // Whether the player keeps the key when changing speed; without it the key moves with the pitch
+}
+
+impl MixOptions {
+    /// The key a track sounds in when sped up to match a tempo; without key lock it moves with the pitch
+    pub fn played_key(&self, key: Camelot, tempo: &TempoMatch) -> Camelot {
+        if self.key_lock {
+            key
+        } else {
+            key.transpose((12.0 * (1.0 + tempo.pitch_shift / 100.0).log2()).round() as i32)
+        }
+    }
+}
+
+impl Default for MixOptions {
+    fn default() -> Self {
+        Self { pitch_tolerance: DEFAULT_PITCH_TOLERANCE, key_lock: true }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NextTrackSuggestion {
+    pub source: String,
+    pub track_id: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub bpm: f32,
+    pub camelot: String,
+    pub energy: Option<f32>,
+    pub key_relation: KeyRelation,
+    pub tempo: TempoMatch,
+    pub score: f32, // 0.0 to 1.0, higher mixes more smoothly
+    pub reason: String,
+}
+
+/// Score how smoothly `next` follows `current`; None when the keys clash, the tempos are out of
+/// pitch range or either track lacks a BPM or Camelot key
+pub fn suggest(current: &MixTrack, next: &MixTrack, options: MixOptions) -> Option<NextTrackSuggestion> {
+    let current_key = Camelot::parse(current.camelot.as_deref()?)?;
+    let next_key = Camelot::parse(next.camelot.as_deref()?)?;
+    let (current_bpm, next_bpm) = (current.bpm?, next.bpm?);
+    let tempo = tempo_match(current_bpm, next_bpm, options.pitch_tolerance)?;
+
+    let played_key = options.played_key(next_key, &tempo);
+    let key_relation = current_key.relation_to(&played_key)?;
+
+    let mut tempo_score = if options.pitch_tolerance > 0.0 {
+        1.0 - tempo.pitch_shift.abs() / options.pitch_tolerance
+    } else {
+        1.0
+    };
+    if tempo.ratio != 1.0 {
+        tempo_score *= HALF_DOUBLE_TEMPO_FACTOR;
+    }
+    let score = KEY_WEIGHT * key_relation.score() + (1.0 - KEY_WEIGHT) * tempo_score.clamp(0.0, 1.0);
+
+    // e.g. "energy boost (8A → 10A); 64 BPM at double tempo, pitched +1.6%; energy 0.55 → 0.71"
+    let mut reason = format!("{} ({} → {}", key_relation.describe(), current_key, played_key);
+    if played_key != next_key {
+        reason.push_str(&format!(", from {} by the pitch change", next_key));
+    }
+    reason.push_str(&format!("); {:.0} BPM", next_bpm));
+    if tempo.ratio == 2.0 {
+        reason.push_str(" at double tempo");
+    } else if tempo.ratio == 0.5 {
+        reason.push_str(" at half tempo");
+    }
+    if tempo.pitch_shift.abs() >= 0.05 {
+        reason.push_str(&format!(", pitched {:+.1}%", tempo.pitch_shift));
+    }
+    if let (Some(from), Some(to)) = (current.energy, next.energy) {
+        reason.push_str(&format!("; energy {:.2} → {:.2}", from, to));
+    }
+
+    Some(NextTrackSuggestion {
+        source: next.source.clone(),
+        track_id: next.track_id.clone(),
+        title: next.title.clone(),
+        artist: next.artist.clone(),
+        bpm: next_bpm,
+        camelot: next_key.to_string(),
+        energy: next.energy,
+        key_relation,
+        tempo,
+        score,
+        reason,
+    })
+}
+
+/// Rank candidates to play after `current`, best first; the current track itself is left out
+pub fn suggest_next(current: &MixTrack, candidates: &[MixTrack], options: MixOptions, limit: usize) -> Vec<NextTrackSuggestion> {
+    let mut suggestions: Vec<NextTrackSuggestion> = candidates
+        .iter()
+        .filter(|next| next.source != current.source || next.track_id != current.track_id)
+        .filter_map(|next| suggest(current, next, options))
+        .collect();
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    suggestions.truncate(limit);
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: &str, bpm: f32, camelot: &str) -> MixTrack {
+        MixTrack {
+            source: "server".to_string(),
+            track_id: id.to_string(),
+            bpm: Some(bpm),
+            camelot: Some(camelot.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_camelot_relations() {
+        let key = Camelot::parse("12a").unwrap();
+        assert_eq!(key.to_string(), "12A");
+        assert_eq!(key.relation_to(&Camelot::parse("12A").unwrap()), Some(KeyRelation::Same));
+        assert_eq!(key.relation_to(&Camelot::parse("12B").unwrap()), Some(KeyRelation::Relative));
+        assert_eq!(key.relation_to(&Camelot::parse("1A").unwrap()), Some(KeyRelation::StepUp));
+        assert_eq!(key.relation_to(&Camelot::parse("11A").unwrap()), Some(KeyRelation::StepDown));
+        assert_eq!(key.relation_to(&Camelot::parse("2A").unwrap()), Some(KeyRelation::EnergyBoost));
+        assert_eq!(key.relation_to(&Camelot::parse("10A").unwrap()), None);
+        assert_eq!(key.relation_to(&Camelot::parse("1B").unwrap()), None);
+        assert_eq!(Camelot::parse("13A"), None);
+
+        // C major (8B) a semitone up is C# major (3B)
+        assert_eq!(Camelot::parse("8B").unwrap().transpose(1).to_string(), "3B");
+    }
+
+    #[test]
+    fn test_suggest_next() {
+        let current = track("current", 128.0, "8A");
+        let candidates = vec![
+            current.clone(),
+            track("same", 127.0, "8A"),
+            track("half-time", 64.5, "9A"),
+            track("boost", 128.0, "10A"),
+            track("clash", 128.0, "3A"),
+            track("too-fast", 140.0, "8A"),
+            MixTrack { bpm: None, ..track("unanalyzed", 0.0, "8A") },
+        ];
+
+        let suggestions = suggest_next(&current, &candidates, MixOptions::default(), 10);
+        let ids: Vec<&str> = suggestions.iter().map(|s| s.track_id.as_str()).collect();
+        assert_eq!(ids, ["same", "boost", "half-time"]);
+        assert_eq!(suggestions[2].tempo.ratio, 2.0);
+        assert!(suggestions[2].reason.contains("double tempo"), "{}", suggestions[2].reason);
+
+        // 1A pitched up 6.7% without key lock sounds a semitone higher, in 8A
+        let options = MixOptions { pitch_tolerance: 8.0, key_lock: false };
+        let shifted = suggest(&current, &track("shifted", 120.0, "1A"), options).unwrap();
+        assert_eq!(shifted.key_relation, KeyRelation::Same);
+        assert!(suggest(&current, &track("locked", 120.0, "8A"), options).is_none());
+    }
+}
@@ -1,29 +1,21 @@
 use anyhow::{Result, anyhow};
-use std::path::Path;
-use tokio::task;
-use hound::Error as HoundError;
-use symphonia::core::audio::AudioBufferRef;
-use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
-use symphonia::core::errors::Error as SymphoniaError;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
-use std::fs::File;
 use spectrum_analyzer::{samples_fft_to_spectrum, FrequencyLimit};
 use spectrum_analyzer::windows::hann_window;
 use spectrum_analyzer::scaling::divide_by_N_sqrt;
 
-// Additional imports for remote file support
-use uuid;
-use reqwest;
+use crate::services::chord_analysis::{chord_timeline, ChordSegment};
+use crate::services::audio_decode::{AudioSink, FrameWindow, ANALYSIS_SAMPLE_RATE};
+use serde::{Deserialize, Serialize};
 
 // Key analysis configuration
 const KEY_WINDOW_SIZE: usize = 8192; // Larger window for better frequency resolution
 const KEY_HOP_SIZE: usize = 1024; // Hop size for analysis
-const SAMPLE_RATE: u32 = 44100;
 const MIN_FREQ: f32 = 80.0; // A1 (55 Hz) to cover bass notes
 const MAX_FREQ: f32 = 2000.0; // Up to about C7 for harmonic analysis
+const CONFIDENT_KEY_MARGIN: f32 = 0.1; // Correlation lead over the runner-up needed for full confidence
+const KEY_TIMELINE_WINDOW_SECS: f32 = 12.0; // Audio per key decision in the timeline
+const KEY_TIMELINE_HOP_SECS: f32 = 4.0;
+const KEY_TIMELINE_MIN_WINDOWS: usize = 3; // Shorter key changes are treated as passing chords
 
 // Chromatic scale frequencies (A4 = 440 Hz)
 const CHROMATIC_FREQUENCIES: [f32; 12] = [
@@ -59,6 +51,69 @@ const CIRCLE_OF_FIFTHS_MAJOR: [usize; 12] = [0, 7, 2, 9, 4, 11, 6, 1, 8, 3, 10,
 // Circle of fifths for minor keys (starting from Am)
 const CIRCLE_OF_FIFTHS_MINOR: [usize; 12] = [9, 4, 11, 6, 1, 8, 3, 10, 5, 0, 7, 2];
 
+/// Key templates correlated against the chroma profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyProfile {
+    Krumhansl, // Krumhansl-Kessler probe tone ratings
+    #[default]
+    Temperley, // Temperley-Kostka-Payne, derived from classical scores
+    Shaath, // Tuned on electronic dance music (KeyFinder)
+}
+
+impl KeyProfile {
+    pub const ALL: [KeyProfile; 3] = [KeyProfile::Krumhansl, KeyProfile::Temperley, KeyProfile::Shaath];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyProfile::Krumhansl => "krumhansl",
+            KeyProfile::Temperley => "temperley",
+            KeyProfile::Shaath => "shaath",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<KeyProfile> {
+        let name = name.trim().to_lowercase();
+        Self::ALL.into_iter().find(|profile| profile.name() == name || (name == "edm" && *profile == KeyProfile::Shaath))
+    }
+
+    // (major, minor) templates starting at the tonic
+    fn templates(&self) -> ([f32; 12], [f32; 12]) {
+        match self {
+            KeyProfile::Krumhansl => (
+                [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88],
+                [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17],
+            ),
+            KeyProfile::Temperley => (
+                [0.748, 0.060, 0.488, 0.082, 0.670, 0.460, 0.096, 0.715, 0.104, 0.366, 0.057, 0.400],
+                [0.712, 0.084, 0.474, 0.618, 0.049, 0.460, 0.105, 0.747, 0.404, 0.067, 0.133, 0.330],
+            ),
+            KeyProfile::Shaath => (
+                [7.239, 3.504, 3.585, 2.850, 5.819, 4.559, 2.448, 6.996, 3.391, 4.557, 4.072, 4.447],
+                [7.003, 3.144, 4.359, 5.404, 3.672, 4.090, 3.909, 6.200, 3.634, 2.873, 5.355, 3.835],
+            ),
+        }
+    }
+}
+
+/// A key with its correlation against the chroma profile (-1.0 to 1.0)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyCandidate {
+    pub key_name: String,
+    pub camelot: String,
+    pub is_major: bool,
+    pub correlation: f32,
+}
+
+/// Part of a track in one key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeySegment {
+    pub start: f32, // Seconds from the start of the track
+    pub end: f32,
+    pub key_name: String,
+    pub camelot: String,
+    pub confidence: f32,
+}
+
 /// Represents a detected musical key
 #[derive(Debug, Clone)]
 pub struct MusicalKey {
@@ -67,191 +122,79 @@ pub struct MusicalKey {
     pub confidence: f32,       // Confidence score (0.0 to 1.0)
     pub is_major: bool,        // True for major, false for minor
     pub key_index: usize,      // Index in the key arrays
+    pub profile: KeyProfile,
+    pub runner_up: Option<KeyCandidate>, // Second best key, often the relative or a fifth away
+    pub timeline: Vec<KeySegment>, // Key changes over the track; a single segment without modulation
+    pub chords: Vec<ChordSegment>, // Chord changes over the track
 }
 
 /// Chromatic profile for key detection
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default)]
 struct ChromaProfile {
     profile: [f32; 12], // Energy for each chromatic note
 }
 
-/// Audio track structure (reused from BPM analysis)
-struct AudioTrack {
-    pub samples: Vec<f32>,
-    pub sample_rate: u32,
+impl ChromaProfile {
+    fn add(&mut self, other: &ChromaProfile) {
+        for (bin, value) in self.profile.iter_mut().zip(other.profile) {
+            *bin += value;
+        }
+    }
 }
 
-pub struct KeyAnalysisService;
+#[derive(Default)]
+pub struct KeyAnalysisService {
+    profile: KeyProfile,
+}
 
 impl KeyAnalysisService {
     pub fn new() -> Self {
-        Self
+        Self::with_profile(KeyProfile::default())
     }
 
-    /// Analyze the musical key of a track
-    pub async fn analyze_key(&self, file_path: &str) -> Result<MusicalKey> {
-        tracing::info!("Starting key analysis for file: {}", file_path);
-        let start_time = std::time::Instant::now();
-        let file_path_owned = file_path.to_string();
-        let file_path_for_logging = file_path_owned.clone();
-        
-        // Run the analysis in a blocking task
-        let key = task::spawn_blocking(move || {
-            Self::analyze_key_blocking(&file_path_owned)
-        }).await??;
-
-        let analysis_duration = start_time.elapsed();
-        tracing::info!("Key analysis completed for file: {} - Result: {} ({}) - Duration: {:?}", 
-                       file_path_for_logging, key.key_name, key.camelot, analysis_duration);
-
-        Ok(key)
+    pub fn with_profile(profile: KeyProfile) -> Self {
+        Self { profile }
     }
 
-    /// Analyze key of a remote file
-    pub async fn analyze_remote_file_key(&self, url: &str) -> Result<MusicalKey> {
-        tracing::info!("Starting remote key analysis for URL: {}", url);
-        
-        // Create a temporary file
-        let temp_dir = std::env::temp_dir();
-        let temp_file = temp_dir.join(format!("musestruct_key_{}.tmp", uuid::Uuid::new_v4()));
-        let temp_path = temp_file.to_string_lossy().to_string();
-        
-        tracing::debug!("Created temporary file for key analysis: {}", temp_path);
-
-        tracing::debug!("Downloading file from URL: {}", url);
-        // Download the file
-        let response = reqwest::get(url).await
-            .map_err(|e| {
-                let error_msg = format!("Failed to download file from URL '{}': {}", url, e);
-                tracing::error!("{}", error_msg);
-                anyhow!(error_msg)
-            })?;
-        
-        let content_length = response.content_length();
-        tracing::debug!("Download response received - Content length: {:?}", content_length);
-        
-        if !response.status().is_success() {
-            let error_msg = format!("HTTP error when downloading file from '{}': {}", url, response.status());
-            tracing::error!("{}", error_msg);
-            return Err(anyhow!(error_msg));
-        }
-        
-        let bytes = response.bytes().await
-            .map_err(|e| {
-                let error_msg = format!("Failed to read response bytes from URL '{}': {}", url, e);
-                tracing::error!("{}", error_msg);
-                anyhow!(error_msg)
-            })?;
-        tracing::info!("File downloaded successfully - Size: {} bytes", bytes.len());
-        
-        // Write to temporary file
-        tokio::fs::write(&temp_file, bytes).await?;
-        tracing::debug!("File written to temporary location: {}", temp_path);
-
-        // Analyze the temporary file
-        let result = self.analyze_key(&temp_path).await;
-
-        // Clean up temporary file
-        match tokio::fs::remove_file(&temp_file).await {
-            Ok(_) => tracing::debug!("Temporary file cleaned up: {}", temp_path),
-            Err(e) => tracing::warn!("Failed to clean up temporary file {}: {}", temp_path, e),
-        }
-
-        result
+    /// Streaming analyzer with the service's key profile
+    pub fn analyzer(&self) -> KeyAnalyzer {
+        KeyAnalyzer::new(self.profile)
     }
 
-    /// Blocking key analysis
-    fn analyze_key_blocking(file_path: &str) -> Result<MusicalKey> {
-        tracing::debug!("Reading audio file for key analysis: {}", file_path);
-        
-        // Check if file exists
-        if !Path::new(file_path).exists() {
-            tracing::error!("Audio file not found: {}", file_path);
-            return Err(anyhow!("Audio file not found: {}", file_path));
-        }
-
-        // Step 1: Read audio file
-        let track = Self::read_audio_file(file_path)?;
-        tracing::info!("Audio loaded - Sample rate: {} Hz, Samples: {}, Duration: {:.2}s", 
-                       track.sample_rate, track.samples.len(), 
-                       track.samples.len() as f32 / track.sample_rate as f32);
-        
-        // Step 2: Generate chromatic profile
-        tracing::debug!("Generating chromatic profile...");
-        let chroma_profile = Self::generate_chroma_profile(&track.samples, track.sample_rate)?;
-        tracing::debug!("Chromatic profile generated: {:?}", chroma_profile.profile);
-        
-        // Step 3: Detect key using template matching
-        tracing::debug!("Detecting key using template matching...");
-        let key = Self::detect_key_from_chroma(&chroma_profile)?;
-        tracing::info!("Key detection completed: {} ({}), confidence: {:.3}", 
-                       key.key_name, key.camelot, key.confidence);
-
-        Ok(key)
+    /// Detect the overall key and the key timeline of mono samples
+    #[cfg(test)]
+    fn analyze_samples(samples: &[f32], sample_rate: u32, profile: KeyProfile) -> Result<MusicalKey> {
+        let mut analyzer = KeyAnalyzer::with_sample_rate(profile, sample_rate);
+        analyzer.push_mono(samples);
+        analyzer.finish()
     }
 
-    /// Generate chromatic profile from audio samples
-    fn generate_chroma_profile(samples: &[f32], sample_rate: u32) -> Result<ChromaProfile> {
-        let mut chroma_bins = [0.0f32; 12];
-        let mut total_energy = 0.0f32;
-        let mut window_count = 0;
-
-        // Process audio in overlapping windows
-        for window_start in (0..samples.len()).step_by(KEY_HOP_SIZE) {
-            let window_end = (window_start + KEY_WINDOW_SIZE).min(samples.len());
-            if window_end - window_start < KEY_WINDOW_SIZE / 2 {
-                break; // Skip incomplete windows at the end
-            }
-            
-            // Extract and pad window
-            let mut window_samples = samples[window_start..window_end].to_vec();
-            window_samples.resize(KEY_WINDOW_SIZE, 0.0);
-            
-            // Apply Hann window
-            let windowed_samples = hann_window(&window_samples);
-            
-            // Calculate spectrum
-            let spectrum_result = samples_fft_to_spectrum(
-                &windowed_samples,
-                sample_rate,
-                FrequencyLimit::Range(MIN_FREQ, MAX_FREQ),
-                Some(&divide_by_N_sqrt),
-            );
-            
-            match spectrum_result {
-                Ok(spectrum) => {
-                    // Map frequencies to chromatic bins
-                    for (frequency, magnitude) in spectrum.data() {
-                        let magnitude_val = magnitude.val();
-                        total_energy += magnitude_val;
-                        
-                        // Convert frequency to chromatic bin
-                        let chroma_bin = Self::frequency_to_chroma_bin(frequency.val());
-                        chroma_bins[chroma_bin] += magnitude_val;
-                    }
-                    window_count += 1;
-                }
-                Err(e) => {
-                    tracing::debug!("FFT failed for window starting at {}: {}", window_start, e);
-                    continue;
-                }
-            }
-        }
-        
-        if window_count == 0 {
-            return Err(anyhow!("Failed to generate chromatic profile"));
-        }
+    /// Chromatic profile of one analysis frame
+    fn chroma_frame(samples: &[f32], sample_rate: u32) -> ChromaProfile {
+        // Apply Hann window
+        let windowed_samples = hann_window(samples);
 
-        // Normalize the chromatic profile
-        if total_energy > 0.0 {
-            for bin in &mut chroma_bins {
-                *bin /= total_energy;
+        // Calculate spectrum
+        let spectrum_result = samples_fft_to_spectrum(
+            &windowed_samples,
+            sample_rate,
+            FrequencyLimit::Range(MIN_FREQ, MAX_FREQ),
+            Some(&divide_by_N_sqrt),
+        );
+
+        // Map frequencies to chromatic bins
+        let mut frame = ChromaProfile::default();
+        match spectrum_result {
+            Ok(spectrum) => {
+                for (frequency, magnitude) in spectrum.data() {
+                    let chroma_bin = Self::frequency_to_chroma_bin(frequency.val());
+                    frame.profile[chroma_bin] += magnitude.val();
+                }
             }
+            // An empty frame keeps frame times aligned with the audio
+            Err(e) => tracing::debug!("FFT failed for key analysis window: {}", e),
         }
-
-        Ok(ChromaProfile {
-            profile: chroma_bins,
-        })
+        frame
     }
 
     /// Convert frequency to chromatic bin (0-11, where 0 = C)
@@ -266,335 +209,260 @@ impl KeyAnalysisService {
         ((chroma_class + 12) % 12) as usize
     }
 
-    /// Detect key from chromatic profile using template matching
-    fn detect_key_from_chroma(chroma_profile: &ChromaProfile) -> Result<MusicalKey> {
-        let mut best_score = 0.0f32;
-        let mut best_key_index = 0;
-        let mut best_is_major = true;
-
-        // Major key templates (Krumhansl-Schmuckler profiles)
-        let major_template = [
-            6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88
-        ];
-
-        // Minor key templates
-        let minor_template = [
-            6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17
-        ];
-
-        // Test all 12 major keys
-        for root in 0..12 {
-            let score = Self::calculate_template_correlation(&chroma_profile.profile, &major_template, root);
-            if score > best_score {
-                best_score = score;
-                best_key_index = root;
-                best_is_major = true;
-            }
+    /// Detect key from chromatic profile by correlating it with all 24 rotated key templates
+    fn detect_key_from_chroma(chroma_profile: &ChromaProfile, profile: KeyProfile) -> Result<MusicalKey> {
+        if chroma_profile.profile.iter().all(|&bin| bin <= 0.0) {
+            return Err(anyhow!("No tonal content found for key detection"));
         }
 
-        // Test all 12 minor keys
-        for root in 0..12 {
-            let score = Self::calculate_template_correlation(&chroma_profile.profile, &minor_template, root);
-            if score > best_score {
-                best_score = score;
-                best_key_index = root;
-                best_is_major = false;
-            }
-        }
+        let mut ranked = Self::rank_keys(&chroma_profile.profile, profile);
+        let (best_root, best_is_major, best) = ranked.remove(0);
+        let runner_up = ranked.first().map(|&(root, is_major, correlation)| {
+            let (key_name, camelot, _) = Self::key_notation(root, is_major);
+            KeyCandidate { key_name, camelot, is_major, correlation }
+        });
 
-        // Convert to key information
-        let (key_name, camelot, final_key_index) = if best_is_major {
-            let circle_index = CIRCLE_OF_FIFTHS_MAJOR.iter().position(|&x| x == best_key_index).unwrap_or(0);
-            (KEY_NAMES[circle_index].to_string(), CAMELOT_NOTATION[circle_index].to_string(), circle_index)
-        } else {
-            let circle_index = CIRCLE_OF_FIFTHS_MINOR.iter().position(|&x| x == best_key_index).unwrap_or(0);
-            let minor_index = circle_index + 12; // Minor keys start at index 12
-            (KEY_NAMES[minor_index].to_string(), CAMELOT_NOTATION[minor_index].to_string(), minor_index)
-        };
-
-        // Normalize confidence score (0.0 to 1.0)
-        let confidence = (best_score / 10.0).min(1.0).max(0.0);
+        let (key_name, camelot, key_index) = Self::key_notation(best_root, best_is_major);
+        let confidence = Self::key_confidence(best, runner_up.as_ref().map_or(-1.0, |key| key.correlation));
 
         Ok(MusicalKey {
             key_name,
             camelot,
             confidence,
             is_major: best_is_major,
-            key_index: final_key_index,
+            key_index,
+            profile,
+            runner_up,
+            timeline: Vec::new(),
+            chords: Vec::new(),
         })
     }
 
-    /// Calculate correlation between chroma profile and key template
-    fn calculate_template_correlation(chroma: &[f32; 12], template: &[f32; 12], root: usize) -> f32 {
-        let mut correlation = 0.0f32;
-        
-        for i in 0..12 {
-            let template_index = (i + root) % 12;
-            correlation += chroma[i] * template[template_index];
-        }
-        
-        correlation
+    /// All keys as (root pitch class, is major, correlation), best first
+    fn rank_keys(chroma: &[f32; 12], profile: KeyProfile) -> Vec<(usize, bool, f32)> {
+        let (major_template, minor_template) = profile.templates();
+        let mut ranked: Vec<(usize, bool, f32)> = (0..12)
+            .flat_map(|root| {
+                [
+                    (root, true, Self::calculate_template_correlation(chroma, &major_template, root)),
+                    (root, false, Self::calculate_template_correlation(chroma, &minor_template, root)),
+                ]
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.2.total_cmp(&a.2));
+        ranked
+    }
+
+    /// Confidence from how well the best key fits and how clearly it beats the runner-up
+    fn key_confidence(best: f32, runner_up: f32) -> f32 {
+        let fit = best.clamp(0.0, 1.0);
+        let separation = ((best - runner_up) / CONFIDENT_KEY_MARGIN).clamp(0.0, 1.0);
+        fit * (0.5 + 0.5 * separation)
     }
 
-    /// Read audio file (reused from BPM analysis with minor modifications)
-    fn read_audio_file(file_path: &str) -> Result<AudioTrack> {
-        // Remove file:// prefix if present
-        let clean_path = if file_path.starts_with("file://") {
-            &file_path[7..]
+    /// Key name, Camelot notation and index into the key arrays of a root pitch class
+    fn key_notation(root: usize, is_major: bool) -> (String, String, usize) {
+        let index = if is_major {
+            CIRCLE_OF_FIFTHS_MAJOR.iter().position(|&x| x == root).unwrap_or(0)
         } else {
-            file_path
+            CIRCLE_OF_FIFTHS_MINOR.iter().position(|&x| x == root).unwrap_or(0) + 12 // Minor keys start at index 12
         };
+        (KEY_NAMES[index].to_string(), CAMELOT_NOTATION[index].to_string(), index)
+    }
 
-        let path = Path::new(clean_path);
-        let extension = path.extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("unknown")
-            .to_lowercase();
-
-        tracing::debug!("Reading audio file for key analysis: {} (format: {})", clean_path, extension);
-
-        // Try symphonia first, fallback to hound for WAV
-        match Self::read_with_symphonia(clean_path) {
-            Ok(track) => {
-                tracing::info!("Successfully read audio file with symphonia: {}", clean_path);
-                Ok(track)
-            },
-            Err(symphonia_err) => {
-                tracing::debug!("Symphonia failed: {}", symphonia_err);
-                
-                if extension == "wav" {
-                    tracing::debug!("Falling back to hound for WAV file");
-                    Self::read_wav_with_hound(clean_path)
-                } else {
-                    Err(anyhow!(
-                        "Failed to read audio file '{}': {}",
-                        clean_path, symphonia_err
-                    ))
-                }
-            }
+    /// Pearson correlation between the chroma profile and a key template rotated to a root
+    fn calculate_template_correlation(chroma: &[f32; 12], template: &[f32; 12], root: usize) -> f32 {
+        let chroma_mean = chroma.iter().sum::<f32>() / 12.0;
+        let template_mean = template.iter().sum::<f32>() / 12.0;
+
+        let mut covariance = 0.0f32;
+        let mut chroma_variance = 0.0f32;
+        let mut template_variance = 0.0f32;
+        for (interval, &weight) in template.iter().enumerate() {
+            let c = chroma[(root + interval) % 12] - chroma_mean;
+            let t = weight - template_mean;
+            covariance += c * t;
+            chroma_variance += c * c;
+            template_variance += t * t;
         }
+
+        let denominator = (chroma_variance * template_variance).sqrt();
+        if denominator > 0.0 { covariance / denominator } else { 0.0 }
     }
 
-    /// Read with symphonia (simplified version)
-    fn read_with_symphonia(file_path: &str) -> Result<AudioTrack> {
-        let file = File::open(file_path)
-            .map_err(|e| anyhow!("Failed to open file '{}': {}", file_path, e))?;
-        
-        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    /// Key per sliding window, merged into segments; short excursions are absorbed by their neighbours
+    fn key_timeline(frames: &[(f32, ChromaProfile)], frame_secs: f32, duration: f32, profile: KeyProfile) -> Vec<KeySegment> {
+        let window = ((KEY_TIMELINE_WINDOW_SECS / frame_secs).round() as usize).max(1);
+        let hop = ((KEY_TIMELINE_HOP_SECS / frame_secs).round() as usize).max(1);
 
-        let mut hint = Hint::new();
-        if let Some(extension) = Path::new(file_path).extension() {
-            if let Some(ext_str) = extension.to_str() {
-                hint.with_extension(ext_str);
+        // (start frame, key index, confidence) of every window; the last window ends with the track
+        let mut windows: Vec<(usize, usize, f32)> = Vec::new();
+        let mut start = 0;
+        loop {
+            let end = (start + window).min(frames.len());
+            let mut chroma = ChromaProfile::default();
+            for (_, frame) in &frames[start..end] {
+                chroma.add(frame);
+            }
+            if let Ok(key) = Self::detect_key_from_chroma(&chroma, profile) {
+                windows.push((start, key.key_index, key.confidence));
             }
+            if end == frames.len() {
+                break;
+            }
+            start += hop;
         }
 
-        let meta_opts: MetadataOptions = Default::default();
-        let fmt_opts: FormatOptions = Default::default();
+        // Each window decides the key around its centre
+        let mut segments: Vec<(f32, usize, Vec<f32>)> = Vec::new(); // (start, key index, window confidences)
+        for (i, &(start, key_index, confidence)) in windows.iter().enumerate() {
+            let boundary = if i == 0 {
+                frames[0].0
+            } else {
+                frames[(start + window / 2).saturating_sub(hop / 2).min(frames.len() - 1)].0
+            };
+            match segments.last_mut() {
+                Some(last) if last.1 == key_index => last.2.push(confidence),
+                _ => segments.push((boundary, key_index, vec![confidence])),
+            }
+        }
 
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &fmt_opts, &meta_opts)
-            .map_err(|e| anyhow!("Failed to probe audio format: {}", e))?;
+        // Merge segments too short to be a modulation into the previous one
+        let mut merged: Vec<(f32, usize, Vec<f32>)> = Vec::new();
+        for segment in segments {
+            let short = segment.2.len() < KEY_TIMELINE_MIN_WINDOWS;
+            match merged.last_mut() {
+                Some(last) if last.1 == segment.1 || short => last.2.extend(segment.2),
+                _ => merged.push(segment),
+            }
+        }
+        // A short first segment belongs to the next one
+        if merged.len() > 1 && merged[0].2.len() < KEY_TIMELINE_MIN_WINDOWS {
+            let first = merged.remove(0);
+            merged[0].0 = first.0;
+            merged[0].2.extend(first.2);
+        }
 
-        let mut format = probed.format;
+        let ends: Vec<f32> = merged.iter().skip(1).map(|segment| segment.0).chain([duration]).collect();
+        merged
+            .into_iter()
+            .zip(ends)
+            .map(|((start, key_index, confidences), end)| KeySegment {
+                start,
+                end,
+                key_name: KEY_NAMES[key_index].to_string(),
+                camelot: CAMELOT_NOTATION[key_index].to_string(),
+                confidence: confidences.iter().sum::<f32>() / confidences.len() as f32,
+            })
+            .collect()
+    }
+}
 
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .ok_or_else(|| anyhow!("No supported audio tracks found"))?;
+/// Streaming key detection: chromatic profiles are computed per frame as audio arrives
+pub struct KeyAnalyzer {
+    profile: KeyProfile,
+    sample_rate: u32,
+    window: FrameWindow,
+    frames: Vec<(f32, ChromaProfile)>, // (start time, chroma) of every analysis frame
+    end_time: f64,
+}
 
-        let dec_opts: DecoderOptions = Default::default();
-        let mut decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &dec_opts)
-            .map_err(|e| anyhow!("Failed to create decoder: {}", e))?;
+impl KeyAnalyzer {
+    pub fn new(profile: KeyProfile) -> Self {
+        Self::with_sample_rate(profile, ANALYSIS_SAMPLE_RATE)
+    }
 
-        let track_id = track.id;
-        let sample_rate = track.codec_params.sample_rate
-            .ok_or_else(|| anyhow!("Sample rate not found in track"))?;
+    fn with_sample_rate(profile: KeyProfile, sample_rate: u32) -> Self {
+        Self {
+            profile,
+            sample_rate,
+            window: FrameWindow::new(KEY_WINDOW_SIZE, KEY_HOP_SIZE, sample_rate),
+            frames: Vec::new(),
+            end_time: 0.0,
+        }
+    }
 
-        let mut samples = Vec::new();
-        let mut channel_count = None;
+    /// Overall key, key timeline and chords over fixed spans of all audio pushed
+    pub fn finish(self) -> Result<MusicalKey> {
+        self.finish_with_beats(&[])
+    }
 
-        loop {
-            let packet = match format.next_packet() {
-                Ok(packet) => packet,
-                Err(SymphoniaError::ResetRequired) => {
-                    return Err(anyhow!("Track list changed during decoding"));
-                }
-                Err(SymphoniaError::IoError(err)) => {
-                    if err.kind() == std::io::ErrorKind::UnexpectedEof {
-                        break;
-                    } else {
-                        return Err(anyhow!("IO error during decoding: {}", err));
-                    }
-                }
-                Err(err) => {
-                    return Err(anyhow!("Decoding error: {}", err));
-                }
-            };
+    /// Overall key and key timeline of all audio pushed, with chords recognized per beat
+    pub fn finish_with_beats(mut self, beats: &[f32]) -> Result<MusicalKey> {
+        self.window.finish(collect_chroma(&mut self.frames, self.sample_rate));
+        if self.frames.is_empty() {
+            return Err(anyhow!("Failed to generate chromatic profile"));
+        }
 
-            while !format.metadata().is_latest() {
-                format.metadata().pop();
-            }
+        // Step 2: Sum the chromatic profiles of all frames
+        let mut chroma_profile = ChromaProfile::default();
+        for (_, frame) in &self.frames {
+            chroma_profile.add(frame);
+        }
+        tracing::debug!("Chromatic profile generated: {:?}", chroma_profile.profile);
 
-            if packet.track_id() != track_id {
-                continue;
-            }
+        // Step 3: Detect key using template correlation
+        tracing::debug!("Detecting key using template correlation...");
+        let mut key = KeyAnalysisService::detect_key_from_chroma(&chroma_profile, self.profile)?;
 
-            match decoder.decode(&packet) {
-                Ok(decoded) => {
-                    if channel_count.is_none() {
-                        channel_count = Some(decoded.spec().channels.count());
-                    }
-
-                    match decoded {
-                        AudioBufferRef::F32(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend_from_slice(plane);
-                            }
-                        }
-                        AudioBufferRef::U8(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| (s as f32 - 128.0) / 128.0));
-                            }
-                        }
-                        AudioBufferRef::U16(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| (s as f32 - 32768.0) / 32768.0));
-                            }
-                        }
-                        AudioBufferRef::U24(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| (s.into_u32() as f32 - 8388608.0) / 8388608.0));
-                            }
-                        }
-                        AudioBufferRef::U32(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| (s as f32 - 2147483648.0) / 2147483648.0));
-                            }
-                        }
-                        AudioBufferRef::S8(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| s as f32 / 128.0));
-                            }
-                        }
-                        AudioBufferRef::S16(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| s as f32 / 32768.0));
-                            }
-                        }
-                        AudioBufferRef::S24(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| s.inner() as f32 / 8388608.0));
-                            }
-                        }
-                        AudioBufferRef::S32(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| s as f32 / 2147483648.0));
-                            }
-                        }
-                        AudioBufferRef::F64(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| s as f32));
-                            }
-                        }
-                    }
-                }
-                Err(SymphoniaError::IoError(_)) => continue,
-                Err(SymphoniaError::DecodeError(_)) => continue,
-                Err(err) => {
-                    return Err(anyhow!("Decode error: {}", err));
-                }
-            }
-        }
+        // Step 4: Follow modulations over sliding windows
+        let frame_secs = KEY_HOP_SIZE as f32 / self.sample_rate as f32;
+        key.timeline = KeyAnalysisService::key_timeline(&self.frames, frame_secs, self.end_time as f32, self.profile);
 
-        if samples.is_empty() {
-            return Err(anyhow!("No audio samples decoded"));
-        }
+        // Step 5: Recognize chords over beat-synchronous chroma, placing frames at their centre
+        let centre = KEY_WINDOW_SIZE as f32 / 2.0 / self.sample_rate as f32;
+        let chroma: Vec<(f32, [f32; 12])> = self.frames.iter().map(|(time, frame)| (time + centre, frame.profile)).collect();
+        key.chords = chord_timeline(&chroma, beats, self.end_time as f32);
+        Ok(key)
+    }
+}
 
-        // Convert to mono
-        let mono_samples = if let Some(channels) = channel_count {
-            if channels == 1 {
-                samples
-            } else {
-                samples
-                    .chunks(channels)
-                    .map(|chunk| chunk.iter().sum::<f32>() / chunk.len() as f32)
-                    .collect()
-            }
-        } else {
-            samples
-        };
+fn collect_chroma(frames: &mut Vec<(f32, ChromaProfile)>, sample_rate: u32) -> impl FnMut(&[f32], f64) + '_ {
+    move |samples, time| frames.push((time as f32, KeyAnalysisService::chroma_frame(samples, sample_rate)))
+}
 
-        Ok(AudioTrack {
-            samples: mono_samples,
-            sample_rate,
-        })
+impl AudioSink for KeyAnalyzer {
+    fn start_span(&mut self, time: f64) {
+        self.window.start_span(time, collect_chroma(&mut self.frames, self.sample_rate));
     }
 
-    /// Read WAV with hound (simplified version)
-    fn read_wav_with_hound(file_path: &str) -> Result<AudioTrack> {
-        let mut reader = hound::WavReader::open(file_path)
-            .map_err(|e| anyhow!("Failed to open WAV file '{}': {}", file_path, e))?;
-        
-        let spec = reader.spec();
-        
-        let samples: Vec<f32> = match spec.sample_format {
-            hound::SampleFormat::Int => {
-                match spec.bits_per_sample {
-                    16 => {
-                        reader
-                            .samples::<i16>()
-                            .map(|s| s.map(|sample| sample as f32 / i16::MAX as f32))
-                            .collect::<Result<Vec<f32>, HoundError>>()
-                            .map_err(|e| anyhow!("Failed to read 16-bit samples: {}", e))?
-                    }
-                    24 => {
-                        reader
-                            .samples::<i32>()
-                            .map(|s| s.map(|sample| sample as f32 / (1 << 23) as f32))
-                            .collect::<Result<Vec<f32>, HoundError>>()
-                            .map_err(|e| anyhow!("Failed to read 24-bit samples: {}", e))?
-                    }
-                    32 => {
-                        reader
-                            .samples::<i32>()
-                            .map(|s| s.map(|sample| sample as f32 / i32::MAX as f32))
-                            .collect::<Result<Vec<f32>, HoundError>>()
-                            .map_err(|e| anyhow!("Failed to read 32-bit samples: {}", e))?
-                    }
-                    _ => return Err(anyhow!("Unsupported bit depth: {} bits", spec.bits_per_sample)),
-                }
-            }
-            hound::SampleFormat::Float => {
-                reader
-                    .samples::<f32>()
-                    .collect::<Result<Vec<f32>, HoundError>>()
-                    .map_err(|e| anyhow!("Failed to read float samples: {}", e))?
-            }
-        };
-        
-        // Convert to mono
-        let mono_samples = if spec.channels == 1 {
-            samples
-        } else {
-            samples
-                .chunks(spec.channels as usize)
-                .map(|chunk| chunk.iter().sum::<f32>() / chunk.len() as f32)
-                .collect()
-        };
-        
-        Ok(AudioTrack {
-            samples: mono_samples,
-            sample_rate: spec.sample_rate,
-        })
+    fn push_mono(&mut self, samples: &[f32]) {
+        self.window.push(samples, collect_chroma(&mut self.frames, self.sample_rate));
+        self.end_time = self.window.end_time();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::services::chord_analysis::{ChordQuality, ChordSummary};
+
+    const TEST_SAMPLE_RATE: u32 = 22050;
+
+    // MIDI note numbers of a chord progression, one chord per second
+    fn progression(samples: &mut Vec<f32>, chords: &[[u8; 4]], seconds: usize) {
+        for second in 0..seconds {
+            let chord = chords[second % chords.len()];
+            for i in 0..TEST_SAMPLE_RATE as usize {
+                let t = i as f32 / TEST_SAMPLE_RATE as f32;
+                let sample: f32 = chord
+                    .iter()
+                    .map(|&note| {
+                        let frequency = 440.0 * 2f32.powf((note as f32 - 69.0) / 12.0);
+                        (2.0 * std::f32::consts::PI * frequency * t).sin() * 0.2
+                    })
+                    .sum();
+                samples.push(sample);
+            }
+        }
+    }
+
+    // I - IV - V - I in C major with the root in the bass
+    const C_MAJOR: [[u8; 4]; 4] = [[48, 60, 64, 67], [53, 65, 69, 72], [55, 67, 71, 74], [48, 60, 64, 67]];
+    // i - iv - V - i in A minor, the dominant with its leading tone G#
+    const A_MINOR: [[u8; 4]; 4] = [[45, 57, 60, 64], [50, 62, 65, 69], [52, 64, 68, 71], [45, 57, 60, 64]];
+    // I - IV - V - I in E major
+    const E_MAJOR: [[u8; 4]; 4] = [[52, 64, 68, 71], [57, 69, 73, 76], [59, 71, 75, 78], [52, 64, 68, 71]];
 
     #[tokio::test]
     async fn test_key_analysis_service_creation() {
@@ -614,4 +482,63 @@ mod tests {
         // Test C5 = 523.25Hz should also map to chroma bin 0 (C)
         assert_eq!(KeyAnalysisService::frequency_to_chroma_bin(523.25), 0);
     }
+
+    #[test]
+    fn test_synthetic_keys_with_all_profiles() {
+        let mut c_major = Vec::new();
+        progression(&mut c_major, &C_MAJOR, 8);
+        let mut a_minor = Vec::new();
+        progression(&mut a_minor, &A_MINOR, 8);
+
+        for profile in KeyProfile::ALL {
+            let key = KeyAnalysisService::analyze_samples(&c_major, TEST_SAMPLE_RATE, profile).unwrap();
+            assert_eq!((key.key_name.as_str(), key.camelot.as_str()), ("C", "8B"), "{:?}", profile);
+            assert!(key.runner_up.is_some_and(|runner_up| runner_up.correlation < 1.0));
+            assert!(key.confidence > 0.3 && key.confidence <= 1.0, "{:?} confidence {}", profile, key.confidence);
+
+            let key = KeyAnalysisService::analyze_samples(&a_minor, TEST_SAMPLE_RATE, profile).unwrap();
+            assert_eq!((key.key_name.as_str(), key.is_major), ("Am", false), "{:?}", profile);
+        }
+    }
+
+    #[test]
+    fn test_modulation_timeline() {
+        let mut samples = Vec::new();
+        progression(&mut samples, &C_MAJOR, 32);
+        progression(&mut samples, &E_MAJOR, 32);
+
+        let key = KeyAnalysisService::analyze_samples(&samples, TEST_SAMPLE_RATE, KeyProfile::default()).unwrap();
+        let keys: Vec<&str> = key.timeline.iter().map(|segment| segment.key_name.as_str()).collect();
+        assert_eq!(keys, ["C", "E"]);
+        assert!((key.timeline[1].start - 32.0).abs() <= KEY_TIMELINE_HOP_SECS, "modulation at {}", key.timeline[1].start);
+        assert!((key.timeline[1].end - 64.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_chord_timeline_per_beat() {
+        // I - vi - IV - V7 in C major, one chord per second at 120 BPM
+        let chords = [[48, 60, 64, 67], [45, 57, 60, 64], [41, 53, 57, 60], [43, 59, 62, 65]];
+        let mut samples = Vec::new();
+        progression(&mut samples, &chords, 16);
+        let beats: Vec<f32> = (1..32).map(|beat| beat as f32 * 0.5).collect();
+
+        let mut analyzer = KeyAnalyzer::with_sample_rate(KeyProfile::default(), TEST_SAMPLE_RATE);
+        analyzer.push_mono(&samples);
+        let key = analyzer.finish_with_beats(&beats).unwrap();
+        let names: Vec<&str> = key.chords.iter().map(|segment| segment.chord.as_str()).collect();
+        assert_eq!(names, ["C", "Am", "F", "G7"].repeat(4), "{:?}", key.chords);
+        assert!(key.chords.iter().all(|segment| beats.contains(&segment.end) || segment.end > 15.9));
+        assert_eq!(key.chords[3].quality, Some(ChordQuality::Dominant7));
+
+        let summary = ChordSummary::of(&key.chords);
+        assert_eq!(summary.progression, ["C", "Am", "F", "G7"]);
+        assert!(summary.chords.iter().all(|share| (share.share - 0.25).abs() < 0.05), "{:?}", summary.chords);
+    }
+
+    #[test]
+    fn test_profile_names() {
+        assert_eq!(KeyProfile::from_name("Temperley"), Some(KeyProfile::Temperley));
+        assert_eq!(KeyProfile::from_name("edm"), Some(KeyProfile::Shaath));
+        assert_eq!(KeyProfile::from_name("unknown"), None);
+    }
 }
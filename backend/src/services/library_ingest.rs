@@ -0,0 +1,329 @@
+use chrono::NaiveDateTime;
+use sea_orm::DbErr;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::AnalysisTrackDto;
+use crate::services::analysis_jobs::{AnalysisJobQueue, BatchProgress, ALL_JOB_KINDS};
+use crate::services::streaming::local_index::{LibraryFile, LocalLibraryIndex};
+use crate::services::track_analysis_store::TrackAnalysisStore;
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(300);
+const MIN_TRIGGER_INTERVAL: Duration = Duration::from_secs(60); // Between the start of any scan and a requested one
+
+/// File as seen by the last ingest scan
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IngestedFile {
+    modified_secs: u64,
+    size: u64,
+    content_hash: String,
+    #[serde(default)]
+    kinds: Vec<String>, // Analyses queued for this content; kinds added later are queued on the next scan
+}
+
+/// Scan state of one library root
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RootScanProgress {
+    pub root: String,
+    pub scanning: bool,
+    pub files: usize,
+    pub checked: usize, // Files looked at so far in the current scan
+    pub changed: usize, // New files and files whose content changed
+    pub queued: usize, // Analysis jobs queued by the last scan
+    pub last_scan_at: Option<NaiveDateTime>,
+    pub batch_id: Option<Uuid>, // Jobs queued by the last scan that found changes
+    pub last_error: Option<String>,
+}
+
+/// Scan state of a library root together with the progress of its queued analyses
+#[derive(Debug, Serialize)]
+pub struct RootIngestStatus {
+    #[serde(flatten)]
+    pub scan: RootScanProgress,
+    pub analysis: Option<BatchProgress>,
+}
+
+/// Outcome of a request to scan now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanTrigger {
+    Started,
+    AlreadyRunning,
+    TooSoon(Duration), // Time left until a scan may be requested again
+}
+
+#[derive(Default)]
+struct IngestState {
+    files: HashMap<PathBuf, IngestedFile>,
+    roots: BTreeMap<String, RootScanProgress>,
+    last_scan_started: Option<Instant>,
+}
+
+/// Queues analyses of new and changed files of the local library.
+/// Library roots are the top-level folders of the music directory; files directly inside it form a root of their own.
+/// A file is only re-analyzed when its content hash changed, so touched or moved files cost a hash but no analysis.
+#[derive(Clone)]
+pub struct LibraryIngestService {
+    music_dir: PathBuf,
+    queue: AnalysisJobQueue,
+    state: Arc<Mutex<IngestState>>,
+    scan_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl LibraryIngestService {
+    pub fn new(music_dir: PathBuf, queue: AnalysisJobQueue) -> Self {
+        let service = Self {
+            music_dir,
+            queue,
+            state: Arc::new(Mutex::new(IngestState::default())),
+            scan_lock: Arc::new(tokio::sync::Mutex::new(())),
+        };
+        service.load_snapshot();
+        service
+    }
+
+    /// Scan now and then periodically in the background
+    pub fn start(&self) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                service.scan().await;
+                tokio::time::sleep(SCAN_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Start a scan unless one is running or the last one started less than a minute ago.
+    /// The library is shared by all users, so requests of any user count towards the limit.
+    pub fn trigger_scan(&self) -> ScanTrigger {
+        if self.scan_lock.try_lock().is_err() {
+            return ScanTrigger::AlreadyRunning;
+        }
+        if let Some(elapsed) = self.lock_state().last_scan_started.map(|at| at.elapsed())
+            && elapsed < MIN_TRIGGER_INTERVAL
+        {
+            return ScanTrigger::TooSoon(MIN_TRIGGER_INTERVAL - elapsed);
+        }
+        let service = self.clone();
+        tokio::spawn(async move { service.scan().await });
+        ScanTrigger::Started
+    }
+
+    pub async fn status(&self) -> Result<Vec<RootIngestStatus>, DbErr> {
+        let roots: Vec<RootScanProgress> = self.lock_state().roots.values().cloned().collect();
+        let mut statuses = Vec::with_capacity(roots.len());
+        for scan in roots {
+            let analysis = match scan.batch_id {
+                Some(batch_id) => Some(self.queue.batch_progress(None, batch_id).await?),
+                None => None,
+            };
+            statuses.push(RootIngestStatus { scan, analysis });
+        }
+        Ok(statuses)
+    }
+
+    async fn scan(&self) {
+        let Ok(_guard) = self.scan_lock.try_lock() else { return };
+        self.lock_state().last_scan_started = Some(Instant::now());
+
+        let music_dir = self.music_dir.clone();
+        let files = match tokio::task::spawn_blocking(move || LocalLibraryIndex::list_audio_files(&music_dir)).await {
+            Ok(files) => files,
+            Err(e) => {
+                tracing::error!("Failed to scan music directory {:?}: {}", self.music_dir, e);
+                return;
+            }
+        };
+
+        let mut by_root: BTreeMap<String, Vec<LibraryFile>> = BTreeMap::new();
+        for file in files {
+            by_root.entry(root_of(&self.music_dir, &file.path)).or_default().push(file);
+        }
+        {
+            let mut state = self.lock_state();
+            let current: HashSet<PathBuf> = by_root.values().flatten().map(|file| file.path.clone()).collect();
+            state.files.retain(|path, _| current.contains(path));
+            state.roots.retain(|root, _| by_root.contains_key(root));
+        }
+
+        for (root, files) in &by_root {
+            self.scan_root(root, files).await;
+        }
+        self.save_snapshot();
+    }
+
+    async fn scan_root(&self, root: &str, files: &[LibraryFile]) {
+        self.update_root(root, |progress| {
+            progress.scanning = true;
+            progress.files = files.len();
+            progress.checked = 0;
+            progress.changed = 0;
+            progress.last_error = None;
+        });
+
+        let mut queued_tracks = Vec::new();
+        let mut changed = 0;
+        let mut hashes = Vec::new();
+        for file in files {
+            let known = self.lock_state().files.get(&file.path).cloned();
+            let unchanged_meta = known
+                .as_ref()
+                .is_some_and(|known| known.modified_secs == file.modified_secs && known.size == file.size);
+
+            if let Some(known) = known.as_ref().filter(|_| unchanged_meta) {
+                // Analysis kinds added since the file was queued
+                if ALL_JOB_KINDS.iter().any(|kind| !known.kinds.iter().any(|known_kind| known_kind == kind)) {
+                    queued_tracks.push(library_track(file, &known.content_hash));
+                    hashes.push((file.clone(), known.content_hash.clone()));
+                }
+            } else {
+                let path = file.path.clone();
+                let content_hash = tokio::task::spawn_blocking(move || TrackAnalysisStore::content_hash_of_file(&path)).await;
+                match content_hash {
+                    Ok(Ok(content_hash)) => {
+                        if known.as_ref().is_none_or(|known| known.content_hash != content_hash) {
+                            queued_tracks.push(library_track(file, &content_hash));
+                            changed += 1;
+                        }
+                        hashes.push((file.clone(), content_hash));
+                    }
+                    Ok(Err(e)) => tracing::warn!("Cannot read {:?} for analysis: {}", file.path, e),
+                    Err(e) => tracing::warn!("Hashing {:?} failed: {}", file.path, e),
+                }
+            }
+            self.update_root(root, |progress| progress.checked += 1);
+        }
+
+        // The queue only runs the kinds without current results, e.g. just the newly added ones
+        let kinds: Vec<String> = ALL_JOB_KINDS.iter().map(|kind| kind.to_string()).collect();
+        let outcome = if queued_tracks.is_empty() {
+            None
+        } else {
+            match self.queue.enqueue(None, queued_tracks, &kinds, false).await {
+                Ok(outcome) => Some(outcome),
+                Err(e) => {
+                    // Hashes are not recorded, so the next scan queues these files again
+                    tracing::error!("Failed to queue analyses for library root {}: {}", root, e);
+                    self.update_root(root, |progress| {
+                        progress.scanning = false;
+                        progress.last_error = Some(e.to_string());
+                    });
+                    return;
+                }
+            }
+        };
+
+        {
+            let mut state = self.lock_state();
+            for (file, content_hash) in hashes {
+                state.files.insert(
+                    file.path.clone(),
+                    IngestedFile {
+                        modified_secs: file.modified_secs,
+                        size: file.size,
+                        content_hash,
+                        kinds: kinds.clone(),
+                    },
+                );
+            }
+        }
+
+        if changed > 0 {
+            tracing::info!("Library root {}: {} new or changed files", root, changed);
+        }
+        self.update_root(root, |progress| {
+            progress.scanning = false;
+            progress.changed = changed;
+            progress.last_scan_at = Some(chrono::Utc::now().naive_utc());
+            if let Some(outcome) = &outcome {
+                progress.queued = outcome.jobs.len();
+                progress.batch_id = Some(outcome.batch_id);
+            }
+        });
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, IngestState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update_root(&self, root: &str, update: impl FnOnce(&mut RootScanProgress)) {
+        let mut state = self.lock_state();
+        let progress = state.roots.entry(root.to_string()).or_insert_with(|| RootScanProgress {
+            root: root.to_string(),
+            ..Default::default()
+        });
+        update(progress);
+    }
+
+    fn snapshot_path(&self) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(self.music_dir.to_string_lossy().as_bytes());
+        let hash = format!("{:x}", hasher.finalize());
+        std::env::current_dir()
+            .unwrap_or_else(|_| PathBuf::from("."))
+            .join("cache")
+            .join(format!("library_ingest_{}.json", &hash[..16]))
+    }
+
+    fn load_snapshot(&self) {
+        let Ok(data) = std::fs::read(self.snapshot_path()) else { return };
+        match serde_json::from_slice::<HashMap<PathBuf, IngestedFile>>(&data) {
+            Ok(files) => self.lock_state().files = files,
+            Err(e) => tracing::warn!("Ignoring unreadable library ingest snapshot: {}", e),
+        }
+    }
+
+    fn save_snapshot(&self) {
+        let path = self.snapshot_path();
+        let data = serde_json::to_vec(&self.lock_state().files);
+        let result = data.map_err(|e| e.to_string()).and_then(|data| {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            std::fs::write(&path, data).map_err(|e| e.to_string())
+        });
+        if let Err(e) = result {
+            tracing::warn!("Failed to save library ingest snapshot: {}", e);
+        }
+    }
+}
+
+fn library_track(file: &LibraryFile, content_hash: &str) -> AnalysisTrackDto {
+    AnalysisTrackDto {
+        track_id: format!("server_{}", file.path.to_string_lossy()),
+        source: "server".to_string(),
+        title: file.path.file_stem().map(|stem| stem.to_string_lossy().to_string()),
+        stream_url: Some(file.path.to_string_lossy().to_string()),
+        content_hash: Some(content_hash.to_string()),
+    }
+}
+
+// Top-level folder of the music directory a file belongs to
+fn root_of(music_dir: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(music_dir).unwrap_or(path);
+    let mut components = relative.components();
+    match (components.next(), components.next()) {
+        (Some(first), Some(_)) => first.as_os_str().to_string_lossy().to_string(),
+        _ => music_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| ".".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root_of() {
+        let music_dir = Path::new("/srv/own_music");
+        assert_eq!(root_of(music_dir, Path::new("/srv/own_music/Musik/Artist/track.flac")), "Musik");
+        assert_eq!(root_of(music_dir, Path::new("/srv/own_music/music_sl/track.mp3")), "music_sl");
+        assert_eq!(root_of(music_dir, Path::new("/srv/own_music/loose.wav")), "own_music");
+    }
+}
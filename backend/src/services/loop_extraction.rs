@@ -0,0 +1,217 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::path::Path;
+
+use crate::services::audio_decode::{AnalysisSpan, AudioSink, AudioStream};
+use crate::services::beat_grid::BeatGrid;
+
+pub const MAX_LOOP_BARS: u32 = 64;
+const LOOP_BITS_PER_SAMPLE: u16 = 24;
+
+/// Span of a loop on a track's beat grid
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LoopRegion {
+    pub start_beat: usize, // Index into the beat grid
+    pub beats: usize,
+    pub start: f64, // Seconds
+    pub end: f64, // Seconds, the beat after the last one of the loop
+    pub bpm: f32, // Tempo over the loop, from its beat positions
+    pub starts_on_downbeat: bool,
+}
+
+impl LoopRegion {
+    /// The loop of `bars` bars starting at beat `start_beat`; at the end of the grid the beat
+    /// closing the loop is one beat period after the last detected one
+    pub fn on_grid(grid: &BeatGrid, start_beat: usize, bars: u32) -> Result<LoopRegion, String> {
+        if bars == 0 || bars > MAX_LOOP_BARS {
+            return Err(format!("Loops are 1 to {} bars long", MAX_LOOP_BARS));
+        }
+        let count = grid.beats.len();
+        if start_beat >= count {
+            return Err(format!("Beat {} is past the end of the beat grid ({} beats)", start_beat, count));
+        }
+
+        let beats = bars as usize * grid.beats_per_bar.max(1) as usize;
+        let end_beat = start_beat + beats;
+        let end = match end_beat {
+            end_beat if end_beat < count => grid.beats[end_beat],
+            end_beat if end_beat == count && count >= 2 => 2.0 * grid.beats[count - 1] - grid.beats[count - 2],
+            _ => return Err(format!("A {}-bar loop from beat {} runs past the last beat", bars, start_beat)),
+        };
+        let (start, end) = (grid.beats[start_beat] as f64, end as f64);
+        Ok(LoopRegion {
+            start_beat,
+            beats,
+            start,
+            end,
+            bpm: (beats as f64 * 60.0 / (end - start)) as f32,
+            starts_on_downbeat: is_downbeat(grid, start_beat),
+        })
+    }
+}
+
+/// Whether a beat of the grid starts a bar
+pub fn is_downbeat(grid: &BeatGrid, beat: usize) -> bool {
+    let phase = grid.bar_phase as usize;
+    beat >= phase && (beat - phase).is_multiple_of(grid.beats_per_bar.max(1) as usize)
+}
+
+/// The downbeat closest to a beat, for loops that should start on a bar
+pub fn nearest_downbeat(grid: &BeatGrid, beat: usize) -> usize {
+    let per_bar = grid.beats_per_bar.max(1) as usize;
+    let phase = grid.bar_phase as usize;
+    if beat <= phase {
+        return phase;
+    }
+    let offset = (beat - phase) % per_bar;
+    let earlier = beat - offset;
+    let later = earlier + per_bar;
+    if offset * 2 < per_bar || later >= grid.beats.len() { earlier } else { later }
+}
+
+// Keeps the native frames in [start, end)
+struct RangeCollector {
+    channels: usize,
+    start: u64,
+    end: u64,
+    position: u64, // Frames decoded so far
+    samples: Vec<f32>,
+}
+
+impl AudioSink for RangeCollector {
+    fn push_native(&mut self, samples: &[f32]) {
+        let frames = (samples.len() / self.channels) as u64;
+        let from = self.start.clamp(self.position, self.position + frames) - self.position;
+        let to = self.end.clamp(self.position, self.position + frames) - self.position;
+        self.samples.extend_from_slice(&samples[from as usize * self.channels..to as usize * self.channels]);
+        self.position += frames;
+    }
+
+    fn wants_mono(&self) -> bool {
+        false
+    }
+}
+
+/// Audio of a loop at the track's own sample rate and channel layout
+#[derive(Debug, Clone)]
+pub struct ExtractedLoop {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub samples: Vec<f32>, // Interleaved
+}
+
+impl ExtractedLoop {
+    /// Decode a loop, cut at the sample nearest to each of its beats
+    pub fn extract(path: &str, region: &LoopRegion) -> Result<ExtractedLoop> {
+        let mut stream = AudioStream::open(path)?;
+        let channels = stream.channels.max(1);
+        let rate = stream.sample_rate as f64;
+        let (start, end) = ((region.start * rate).round() as u64, (region.end * rate).round() as u64);
+        let mut collector = RangeCollector { channels, start, end, position: 0, samples: Vec::new() };
+        stream.run(AnalysisSpan::Full, &mut [&mut collector])?;
+        if collector.position < end {
+            return Err(anyhow!("The track ends before the loop does"));
+        }
+        Ok(ExtractedLoop { sample_rate: stream.sample_rate, channels, samples: collector.samples })
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frames() as f64 / self.sample_rate as f64
+    }
+
+    /// Fade in and out with a raised cosine over `fade_secs` at each edge, at most a quarter of the loop
+    pub fn fade_edges(&mut self, fade_secs: f32) {
+        let frames = self.frames();
+        let fade = ((fade_secs.max(0.0) as f64 * self.sample_rate as f64) as usize).min(frames / 4);
+        for i in 0..fade {
+            let gain = 0.5 - 0.5 * (std::f32::consts::PI * (i as f32 + 0.5) / fade as f32).cos();
+            let (head, tail) = (i * self.channels, (frames - 1 - i) * self.channels);
+            for channel in 0..self.channels {
+                self.samples[head + channel] *= gain;
+                self.samples[tail + channel] *= gain;
+            }
+        }
+    }
+
+    /// Write as 24-bit WAV
+    pub fn write_wav(&self, path: &Path) -> Result<()> {
+        let spec = hound::WavSpec {
+            channels: self.channels as u16,
+            sample_rate: self.sample_rate,
+            bits_per_sample: LOOP_BITS_PER_SAMPLE,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let scale = ((1i32 << (LOOP_BITS_PER_SAMPLE - 1)) - 1) as f32;
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for &sample in &self.samples {
+            writer.write_sample((sample.clamp(-1.0, 1.0) * scale).round() as i32)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(bpm: f32, beats: usize, bar_phase: u8) -> BeatGrid {
+        let period = 60.0 / bpm;
+        BeatGrid {
+            bpm,
+            beats_per_bar: 4,
+            beats: (0..beats).map(|i| 0.5 + i as f32 * period).collect(),
+            first_downbeat: 0.5 + bar_phase as f32 * period,
+            bar_phase,
+            tempo_curve: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_loop_region() {
+        let grid = grid(120.0, 64, 1);
+        let region = LoopRegion::on_grid(&grid, 1, 4).unwrap();
+        assert_eq!(region.beats, 16);
+        assert!((region.start - 1.0).abs() < 1e-6 && (region.end - 9.0).abs() < 1e-6, "{:?}", region);
+        assert!((region.bpm - 120.0).abs() < 0.01);
+        assert!(region.starts_on_downbeat);
+
+        // The last bar closes one beat after the grid ends
+        assert!((LoopRegion::on_grid(&grid, 48, 4).unwrap().end - 32.5).abs() < 1e-5);
+        assert!(LoopRegion::on_grid(&grid, 49, 4).is_err());
+        assert!(LoopRegion::on_grid(&grid, 0, 0).is_err());
+
+        assert_eq!(nearest_downbeat(&grid, 0), 1);
+        assert_eq!(nearest_downbeat(&grid, 6), 5);
+        assert_eq!(nearest_downbeat(&grid, 8), 9);
+    }
+
+    #[test]
+    fn test_extract_sample_accurate_loop() {
+        // Every sample holds its frame number, so the cut shows exactly where it was made
+        let path = std::env::temp_dir().join(format!("musestruct_loop_test_{}.wav", uuid::Uuid::new_v4()));
+        let spec = hound::WavSpec { channels: 1, sample_rate: 8000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for frame in 0..8000 * 12 {
+            writer.write_sample((frame % 30000) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let region = LoopRegion::on_grid(&grid(120.0, 20, 0), 2, 1).unwrap();
+        let mut extracted = ExtractedLoop::extract(&path.to_string_lossy(), &region).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(extracted.frames(), 16000);
+        let frame_number = |sample: f32| (sample * 32768.0).round() as i32;
+        assert_eq!(frame_number(extracted.samples[0]), 12000);
+        assert_eq!(frame_number(extracted.samples[15999]), 27999);
+
+        extracted.fade_edges(0.5);
+        assert!(extracted.samples[0].abs() < 1e-3 && extracted.samples[15999].abs() < 1e-2);
+        assert_eq!(frame_number(extracted.samples[8000]), 20000);
+    }
+}
@@ -0,0 +1,427 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::services::audio_decode::AudioSink;
+
+// Loudness analysis configuration (EBU R128, ITU-R BS.1770-4)
+const HOPS_PER_BLOCK: usize = 4; // 400 ms momentary blocks overlapping by 75%
+const HOPS_PER_SHORT_TERM: usize = 30; // 3 s short-term blocks for the loudness range
+const SHORT_TERM_EVERY_HOPS: usize = 10; // Short-term loudness sampled every second
+const HOP_SECS: f64 = 0.1;
+const ABSOLUTE_GATE_LUFS: f32 = -70.0;
+const RELATIVE_GATE_LU: f32 = -10.0;
+const RANGE_RELATIVE_GATE_LU: f32 = -20.0;
+const RANGE_LOW_PERCENTILE: f32 = 0.10;
+const RANGE_HIGH_PERCENTILE: f32 = 0.95;
+const HISTOGRAM_STEP_LU: f32 = 0.1; // Resolution of stored block loudness, used for album loudness
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_TAPS: usize = 12; // Interpolation filter taps per oversampling phase
+
+/// ReplayGain 2.0 reference loudness
+pub const REPLAYGAIN_REFERENCE_LUFS: f32 = -18.0;
+
+/// Gated block loudness of one or more tracks, in HISTOGRAM_STEP_LU bins.
+/// Album loudness is the integrated loudness of the merged histograms of its tracks.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LoudnessHistogram {
+    bins: BTreeMap<i32, u32>, // Bin index (loudness / step) to block count
+}
+
+impl LoudnessHistogram {
+    fn add(&mut self, loudness: f32) {
+        if loudness >= ABSOLUTE_GATE_LUFS {
+            *self.bins.entry((loudness / HISTOGRAM_STEP_LU).round() as i32).or_insert(0) += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &LoudnessHistogram) {
+        for (&bin, &count) in &other.bins {
+            *self.bins.entry(bin).or_insert(0) += count;
+        }
+    }
+
+    /// Integrated loudness with the relative gate; None for silence
+    pub fn integrated(&self) -> Option<f32> {
+        let absolute = self.mean_loudness(ABSOLUTE_GATE_LUFS)?;
+        self.mean_loudness(absolute + RELATIVE_GATE_LU)
+    }
+
+    // Loudness of the mean energy of all blocks at or above a gate
+    fn mean_loudness(&self, gate: f32) -> Option<f32> {
+        let (energy, count) = self
+            .bins
+            .iter()
+            .map(|(&bin, &count)| (bin as f32 * HISTOGRAM_STEP_LU, count))
+            .filter(|&(loudness, _)| loudness >= gate)
+            .fold((0.0f64, 0u64), |(energy, total), (loudness, count)| {
+                (energy + count as f64 * energy_of(loudness), total + count as u64)
+            });
+        (count > 0).then(|| loudness_of(energy / count as f64))
+    }
+
+    pub fn to_compact_json(&self) -> String {
+        let pairs: Vec<(i32, u32)> = self.bins.iter().map(|(&bin, &count)| (bin, count)).collect();
+        serde_json::to_string(&pairs).unwrap_or_default()
+    }
+
+    pub fn from_compact_json(data: &str) -> Option<LoudnessHistogram> {
+        let pairs: Vec<(i32, u32)> = serde_json::from_str(data).ok()?;
+        Some(LoudnessHistogram { bins: pairs.into_iter().collect() })
+    }
+}
+
+/// Loudness of a track per EBU R128
+#[derive(Debug, Clone)]
+pub struct TrackLoudness {
+    pub integrated_lufs: Option<f32>, // None for silence
+    pub loudness_range: f32, // LU
+    pub true_peak: f32, // Linear, 1.0 is 0 dBTP
+    pub histogram: LoudnessHistogram,
+}
+
+impl TrackLoudness {
+    #[cfg(test)]
+    pub fn true_peak_dbtp(&self) -> f32 {
+        peak_to_db(self.true_peak)
+    }
+}
+
+/// Gain in dB bringing a loudness to the ReplayGain reference
+pub fn replaygain_gain(loudness_lufs: f32) -> f32 {
+    REPLAYGAIN_REFERENCE_LUFS - loudness_lufs
+}
+
+pub fn peak_to_db(peak: f32) -> f32 {
+    20.0 * peak.max(1e-6).log10()
+}
+
+fn energy_of(loudness: f32) -> f64 {
+    10f64.powf((loudness as f64 + 0.691) / 10.0)
+}
+
+fn loudness_of(energy: f64) -> f32 {
+    if energy <= 0.0 { f32::NEG_INFINITY } else { (-0.691 + 10.0 * energy.log10()) as f32 }
+}
+
+/// Second-order IIR section, transposed direct form II
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2], // a1, a2 with a0 normalized to 1
+    state: [f64; 2],
+}
+
+impl Biquad {
+    pub(crate) fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+
+    /// Butterworth low-pass; two in series form a Linkwitz-Riley crossover
+    pub(crate) fn low_pass(sample_rate: u32, cutoff: f64) -> Biquad {
+        let k = (std::f64::consts::PI * cutoff / sample_rate as f64).tan();
+        let q = std::f64::consts::FRAC_1_SQRT_2;
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [k * k / a0, 2.0 * k * k / a0, k * k / a0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            state: [0.0; 2],
+        }
+    }
+
+    /// Butterworth high-pass
+    pub(crate) fn high_pass(sample_rate: u32, cutoff: f64) -> Biquad {
+        let k = (std::f64::consts::PI * cutoff / sample_rate as f64).tan();
+        let q = std::f64::consts::FRAC_1_SQRT_2;
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [1.0 / a0, -2.0 / a0, 1.0 / a0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            state: [0.0; 2],
+        }
+    }
+
+    /// K-weighting of BS.1770: a high shelf for the head followed by the RLB high-pass,
+    /// designed for the sample rate
+    fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+        let fs = sample_rate as f64;
+
+        let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (std::f64::consts::PI * f0 / fs).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad {
+            b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            state: [0.0; 2],
+        };
+
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (std::f64::consts::PI * f0 / fs).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            state: [0.0; 2],
+        };
+
+        [shelf, high_pass]
+    }
+}
+
+/// Inter-sample peak of one channel through polyphase windowed-sinc oversampling
+struct TruePeakMeter {
+    history: VecDeque<f32>,
+    peak: f32,
+}
+
+impl TruePeakMeter {
+    fn new() -> Self {
+        Self {
+            history: VecDeque::from(vec![0.0; TRUE_PEAK_TAPS]),
+            peak: 0.0,
+        }
+    }
+
+    fn coefficients() -> Vec<[f32; TRUE_PEAK_TAPS]> {
+        let length = TRUE_PEAK_OVERSAMPLING * TRUE_PEAK_TAPS;
+        let centre = (length - 1) as f64 / 2.0;
+        let filter: Vec<f64> = (0..length)
+            .map(|n| {
+                let x = (n as f64 - centre) / TRUE_PEAK_OVERSAMPLING as f64;
+                let sinc = if x.abs() < 1e-9 { 1.0 } else { (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x) };
+                let window = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * (n as f64 + 0.5) / length as f64).cos();
+                sinc * window
+            })
+            .collect();
+
+        (0..TRUE_PEAK_OVERSAMPLING)
+            .map(|phase| {
+                let mut taps = [0.0f32; TRUE_PEAK_TAPS];
+                let gain: f64 = (0..TRUE_PEAK_TAPS).map(|tap| filter[phase + tap * TRUE_PEAK_OVERSAMPLING]).sum();
+                for (tap, coefficient) in taps.iter_mut().enumerate() {
+                    *coefficient = (filter[phase + tap * TRUE_PEAK_OVERSAMPLING] / gain) as f32;
+                }
+                taps
+            })
+            .collect()
+    }
+
+    fn process(&mut self, sample: f32, coefficients: &[[f32; TRUE_PEAK_TAPS]]) {
+        self.history.pop_back();
+        self.history.push_front(sample);
+        self.peak = self.peak.max(sample.abs());
+        for taps in coefficients {
+            let value: f32 = taps.iter().zip(&self.history).map(|(c, x)| c * x).sum();
+            self.peak = self.peak.max(value.abs());
+        }
+    }
+}
+
+/// Streaming loudness meter; samples are pushed interleaved as they are decoded
+pub struct LoudnessMeter {
+    sample_rate: u32,
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    peaks: Vec<TruePeakMeter>,
+    peak_coefficients: Vec<[f32; TRUE_PEAK_TAPS]>,
+    hop_frames: usize,
+    hop_position: usize,
+    hop_energy: f64,
+    recent_hops: VecDeque<f64>, // Energy sums of the last short-term block
+    hop_count: usize,
+    blocks: LoudnessHistogram,
+    short_term: Vec<f32>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        // Channel weights of BS.1770 for 5.1 (L, R, C, LFE, Ls, Rs); other layouts weigh all channels equally
+        let weights = if channels == 6 {
+            vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41]
+        } else {
+            vec![1.0; channels]
+        };
+
+        Self {
+            sample_rate,
+            channels,
+            filters: (0..channels).map(|_| Biquad::k_weighting(sample_rate)).collect(),
+            weights,
+            peaks: (0..channels).map(|_| TruePeakMeter::new()).collect(),
+            peak_coefficients: TruePeakMeter::coefficients(),
+            hop_frames: ((sample_rate as f64 * HOP_SECS).round() as usize).max(1),
+            hop_position: 0,
+            hop_energy: 0.0,
+            recent_hops: VecDeque::with_capacity(HOPS_PER_SHORT_TERM),
+            hop_count: 0,
+            blocks: LoudnessHistogram::default(),
+            short_term: Vec::new(),
+        }
+    }
+
+    pub fn push_interleaved(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            let mut energy = 0.0f64;
+            for (channel, &sample) in frame.iter().enumerate() {
+                self.peaks[channel].process(sample, &self.peak_coefficients);
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let filtered = high_pass.process(shelf.process(sample as f64));
+                energy += self.weights[channel] * filtered * filtered;
+            }
+            self.hop_energy += energy;
+            self.hop_position += 1;
+            if self.hop_position == self.hop_frames {
+                self.finish_hop();
+            }
+        }
+    }
+
+    fn finish_hop(&mut self) {
+        if self.recent_hops.len() == HOPS_PER_SHORT_TERM {
+            self.recent_hops.pop_front();
+        }
+        self.recent_hops.push_back(self.hop_energy);
+        self.hop_energy = 0.0;
+        self.hop_position = 0;
+        self.hop_count += 1;
+
+        let mean_over = |hops: usize| {
+            let energy: f64 = self.recent_hops.iter().rev().take(hops).sum();
+            loudness_of(energy / (hops * self.hop_frames) as f64)
+        };
+        if self.recent_hops.len() >= HOPS_PER_BLOCK {
+            self.blocks.add(mean_over(HOPS_PER_BLOCK));
+        }
+        if self.recent_hops.len() == HOPS_PER_SHORT_TERM && self.hop_count.is_multiple_of(SHORT_TERM_EVERY_HOPS) {
+            self.short_term.push(mean_over(HOPS_PER_SHORT_TERM));
+        }
+    }
+
+    /// Continue at another part of the track without carrying filter and block state over the gap
+    fn restart(&mut self) {
+        self.filters = (0..self.channels).map(|_| Biquad::k_weighting(self.sample_rate)).collect();
+        self.hop_position = 0;
+        self.hop_energy = 0.0;
+        self.recent_hops.clear();
+        self.hop_count = 0;
+    }
+
+    pub fn finish(self) -> TrackLoudness {
+        TrackLoudness {
+            integrated_lufs: self.blocks.integrated(),
+            loudness_range: Self::loudness_range(&self.short_term),
+            true_peak: self.peaks.iter().map(|meter| meter.peak).fold(0.0, f32::max),
+            histogram: self.blocks,
+        }
+    }
+
+    // Spread between the 10th and 95th percentile of gated short-term loudness (EBU Tech 3342)
+    fn loudness_range(short_term: &[f32]) -> f32 {
+        let above_absolute: Vec<f32> = short_term.iter().copied().filter(|&l| l >= ABSOLUTE_GATE_LUFS).collect();
+        if above_absolute.is_empty() {
+            return 0.0;
+        }
+        let mean = above_absolute.iter().map(|&l| energy_of(l)).sum::<f64>() / above_absolute.len() as f64;
+        let gate = loudness_of(mean) + RANGE_RELATIVE_GATE_LU;
+
+        let mut gated: Vec<f32> = above_absolute.into_iter().filter(|&l| l >= gate).collect();
+        if gated.len() < 2 {
+            return 0.0;
+        }
+        gated.sort_by(f32::total_cmp);
+        let at = |percentile: f32| gated[((gated.len() - 1) as f32 * percentile).round() as usize];
+        at(RANGE_HIGH_PERCENTILE) - at(RANGE_LOW_PERCENTILE)
+    }
+}
+
+impl AudioSink for LoudnessMeter {
+    fn start_span(&mut self, _time: f64) {
+        self.restart();
+    }
+
+    fn push_native(&mut self, samples: &[f32]) {
+        self.push_interleaved(samples);
+    }
+
+    fn wants_mono(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_SAMPLE_RATE: u32 = 48000;
+
+    // Stereo sine with the same amplitude in both channels
+    fn stereo_sine(meter: &mut LoudnessMeter, frequency: f32, amplitude_dbfs: f32, seconds: f32, phase: f32) {
+        let amplitude = 10f32.powf(amplitude_dbfs / 20.0);
+        let frames = (seconds * TEST_SAMPLE_RATE as f32) as usize;
+        let samples: Vec<f32> = (0..frames)
+            .flat_map(|i| {
+                let t = i as f32 / TEST_SAMPLE_RATE as f32;
+                let value = amplitude * (2.0 * std::f32::consts::PI * frequency * t + phase).sin();
+                [value, value]
+            })
+            .collect();
+        meter.push_interleaved(&samples);
+    }
+
+    #[test]
+    fn test_reference_sine_and_gating() {
+        // EBU Tech 3341 case 1: 1 kHz at -23 dBFS measures -23 LUFS
+        let mut meter = LoudnessMeter::new(TEST_SAMPLE_RATE, 2);
+        stereo_sine(&mut meter, 1000.0, -23.0, 10.0, 0.0);
+        let loudness = meter.finish();
+        let integrated = loudness.integrated_lufs.unwrap();
+        assert!((integrated + 23.0).abs() < 0.1, "integrated {}", integrated);
+        assert!(loudness.loudness_range < 0.5);
+        assert!((loudness.true_peak_dbtp() + 23.0).abs() < 0.2);
+
+        // Quiet passages fall below the relative gate
+        let mut meter = LoudnessMeter::new(TEST_SAMPLE_RATE, 2);
+        stereo_sine(&mut meter, 1000.0, -36.0, 5.0, 0.0);
+        stereo_sine(&mut meter, 1000.0, -23.0, 20.0, 0.0);
+        stereo_sine(&mut meter, 1000.0, -36.0, 5.0, 0.0);
+        let loudness = meter.finish();
+        let integrated = loudness.integrated_lufs.unwrap();
+        assert!((integrated + 23.0).abs() < 0.2, "gated integrated {}", integrated);
+        assert!(loudness.loudness_range > 10.0, "range {}", loudness.loudness_range);
+
+        let silence = LoudnessMeter::new(TEST_SAMPLE_RATE, 2).finish();
+        assert_eq!(silence.integrated_lufs, None);
+    }
+
+    #[test]
+    fn test_inter_sample_true_peak() {
+        // A quarter sample rate sine sampled 45 degrees off its peaks
+        let mut meter = LoudnessMeter::new(TEST_SAMPLE_RATE, 2);
+        stereo_sine(&mut meter, TEST_SAMPLE_RATE as f32 / 4.0, -6.0, 1.0, std::f32::consts::FRAC_PI_4);
+        let loudness = meter.finish();
+        assert!((loudness.true_peak_dbtp() + 6.0).abs() < 0.5, "true peak {}", loudness.true_peak_dbtp());
+    }
+
+    #[test]
+    fn test_album_loudness_from_histograms() {
+        let mut album = LoudnessHistogram::default();
+        for level in [-20.0, -26.0] {
+            let mut meter = LoudnessMeter::new(TEST_SAMPLE_RATE, 2);
+            stereo_sine(&mut meter, 1000.0, level, 5.0, 0.0);
+            let track = meter.finish();
+            let restored = LoudnessHistogram::from_compact_json(&track.histogram.to_compact_json()).unwrap();
+            album.merge(&restored);
+        }
+
+        // Energy mean of equally long tracks at -20 and -26 LUFS
+        let album_loudness = album.integrated().unwrap();
+        assert!((album_loudness + 22.0).abs() < 0.2, "album {}", album_loudness);
+        assert!((replaygain_gain(album_loudness) - 4.0).abs() < 0.2);
+    }
+}
@@ -0,0 +1,439 @@
+use chrono::NaiveDateTime;
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::services::analysis_jobs::StreamUrlResolver;
+use crate::services::audio_decode::{is_remote, TempAudioFile};
+use crate::services::mix_render::{cue_sheet, render_mix, DecodedTrack, MixCue, MixSettings, MIX_SAMPLE_RATE};
+use crate::services::track_analysis_store::TrackAnalysisStore;
+
+/// Most tracks in one mix
+pub const MAX_MIX_TRACKS: usize = 200;
+const MAX_MIX_SECS: usize = 6 * 3600; // Longest mix rendered, about 3.8 GB of WAV
+const MAX_RENDERING_PER_USER: usize = 1; // Mixes a user may have rendering or encoding at once
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+const DEFAULT_MAX_AGE_HOURS: u64 = 24;
+
+/// File format of a rendered mix; everything but WAV is encoded with ffmpeg
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MixFormat {
+    Wav,
+    Flac,
+    Mp3,
+    Opus,
+}
+
+impl MixFormat {
+    pub fn from_name(name: &str) -> Option<MixFormat> {
+        match name {
+            "wav" => Some(MixFormat::Wav),
+            "flac" => Some(MixFormat::Flac),
+            "mp3" => Some(MixFormat::Mp3),
+            "opus" => Some(MixFormat::Opus),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MixFormat::Wav => "wav",
+            MixFormat::Flac => "flac",
+            MixFormat::Mp3 => "mp3",
+            MixFormat::Opus => "opus",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            MixFormat::Wav => "audio/wav",
+            MixFormat::Flac => "audio/flac",
+            MixFormat::Mp3 => "audio/mpeg",
+            MixFormat::Opus => "audio/ogg",
+        }
+    }
+
+    // File type of the CUE sheet FILE command
+    fn cue_file_type(&self) -> &'static str {
+        match self {
+            MixFormat::Mp3 => "MP3",
+            _ => "WAVE",
+        }
+    }
+
+    fn encoder_args(&self) -> &'static [&'static str] {
+        match self {
+            MixFormat::Wav => &[],
+            MixFormat::Flac => &["-c:a", "flac", "-compression_level", "8"],
+            MixFormat::Mp3 => &["-c:a", "libmp3lame", "-b:a", "320k"],
+            MixFormat::Opus => &["-c:a", "libopus", "-b:a", "192k"],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MixExportStatus {
+    Rendering,
+    Encoding,
+    Completed,
+    Failed,
+}
+
+/// A track to render into a mix
+#[derive(Debug, Clone)]
+pub struct MixSourceTrack {
+    pub source: String,
+    pub track_id: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+/// State of a mix export; kept in memory, so exports do not survive a restart
+#[derive(Debug, Clone, Serialize)]
+pub struct MixExport {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: Uuid,
+    pub name: String,
+    pub format: MixFormat,
+    pub status: MixExportStatus,
+    pub tracks: usize,
+    pub rendered: usize, // Tracks mixed in so far
+    pub skipped: Vec<String>, // Tracks that could not be loaded, with the reason
+    pub cues: Vec<MixCue>,
+    pub duration: Option<f64>, // Seconds, once rendered
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl MixExport {
+    fn is_running(&self) -> bool {
+        matches!(self.status, MixExportStatus::Rendering | MixExportStatus::Encoding)
+    }
+}
+
+/// Renders queues and playlists into continuous mix files in MIX_EXPORT_DIR, in the background.
+/// FLAC, MP3 and Opus are encoded by the ffmpeg binary at FFMPEG_PATH.
+#[derive(Clone)]
+pub struct MixExportService {
+    dir: PathBuf,
+    ffmpeg: String,
+    db: DatabaseConnection,
+    resolver: Arc<dyn StreamUrlResolver>,
+    max_age: Duration, // Finished exports are removed after this long
+    exports: Arc<Mutex<HashMap<Uuid, MixExport>>>,
+}
+
+impl MixExportService {
+    pub fn new(
+        dir: PathBuf,
+        ffmpeg: String,
+        db: DatabaseConnection,
+        resolver: Arc<dyn StreamUrlResolver>,
+        max_age: Duration,
+    ) -> Self {
+        Self { dir, ffmpeg, db, resolver, max_age, exports: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    pub fn from_env(db: DatabaseConnection, resolver: Arc<dyn StreamUrlResolver>) -> Self {
+        let dir = std::env::var("MIX_EXPORT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")).join("cache").join("mixes"));
+        let ffmpeg = std::env::var("FFMPEG_PATH").unwrap_or_else(|_| "ffmpeg".to_string());
+        let max_age_hours = std::env::var("MIX_EXPORT_MAX_AGE_HOURS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_MAX_AGE_HOURS);
+        Self::new(dir, ffmpeg, db, resolver, Duration::from_secs(max_age_hours * 3600))
+    }
+
+    /// Remove mix files of a previous run, whose exports are gone with the process, then
+    /// expire finished exports now and then in the background
+    pub fn start(&self) {
+        if let Ok(entries) = std::fs::read_dir(&self.dir) {
+            let removed = entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().is_file())
+                .filter(|entry| std::fs::remove_file(entry.path()).is_ok())
+                .count();
+            if removed > 0 {
+                tracing::info!("Removed {} mix files of a previous run", removed);
+            }
+        }
+
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(CLEANUP_INTERVAL).await;
+                let removed = service.remove_expired(chrono::Utc::now().naive_utc());
+                if removed > 0 {
+                    tracing::info!("Removed {} expired mix exports", removed);
+                }
+            }
+        });
+    }
+
+    /// Forget finished exports older than the maximum age and remove their files. Returns the number removed.
+    pub fn remove_expired(&self, now: NaiveDateTime) -> usize {
+        let max_age = chrono::Duration::from_std(self.max_age).unwrap_or(chrono::Duration::MAX);
+        let mut exports = self.lock_exports();
+        let expired: Vec<(Uuid, MixFormat)> = exports
+            .values()
+            .filter(|export| !export.is_running() && now - export.created_at > max_age)
+            .map(|export| (export.id, export.format))
+            .collect();
+        for (id, format) in &expired {
+            let _ = std::fs::remove_file(self.file_path(*id, *format));
+            exports.remove(id);
+        }
+        expired.len()
+    }
+
+    fn lock_exports(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, MixExport>> {
+        self.exports.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, id: Uuid, update: impl FnOnce(&mut MixExport)) {
+        if let Some(export) = self.lock_exports().get_mut(&id) {
+            update(export);
+        }
+    }
+
+    /// Start rendering tracks into a mix; None while the user already has as many mixes rendering as allowed
+    pub fn export(
+        &self,
+        user_id: Uuid,
+        name: String,
+        tracks: Vec<MixSourceTrack>,
+        settings: MixSettings,
+        format: MixFormat,
+    ) -> Option<MixExport> {
+        let mut exports = self.lock_exports();
+        let rendering = exports.values().filter(|export| export.user_id == user_id && export.is_running()).count();
+        if rendering >= MAX_RENDERING_PER_USER {
+            return None;
+        }
+        let export = MixExport {
+            id: Uuid::new_v4(),
+            user_id,
+            name,
+            format,
+            status: MixExportStatus::Rendering,
+            tracks: tracks.len(),
+            rendered: 0,
+            skipped: Vec::new(),
+            cues: Vec::new(),
+            duration: None,
+            error: None,
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        exports.insert(export.id, export.clone());
+        drop(exports);
+
+        let service = self.clone();
+        let id = export.id;
+        tokio::spawn(async move {
+            if let Err(e) = service.run(id, user_id, tracks, settings, format).await {
+                tracing::warn!("Mix export {} failed: {}", id, e);
+                service.update(id, |export| {
+                    export.status = MixExportStatus::Failed;
+                    export.error = Some(e);
+                });
+            }
+            // A mix removed while rendering leaves no file behind
+            if service.lock_exports().get(&id).is_none() {
+                let _ = std::fs::remove_file(service.file_path(id, format));
+            }
+        });
+        Some(export)
+    }
+
+    pub fn get(&self, user_id: Uuid, id: Uuid) -> Option<MixExport> {
+        self.lock_exports().get(&id).filter(|export| export.user_id == user_id).cloned()
+    }
+
+    /// The user's mix exports, newest first
+    pub fn list(&self, user_id: Uuid) -> Vec<MixExport> {
+        let mut exports: Vec<MixExport> =
+            self.lock_exports().values().filter(|export| export.user_id == user_id).cloned().collect();
+        exports.sort_by_key(|export| std::cmp::Reverse(export.created_at));
+        exports
+    }
+
+    /// Forget an export and remove its file; returns false for unknown exports
+    pub fn remove(&self, user_id: Uuid, id: Uuid) -> bool {
+        let mut exports = self.lock_exports();
+        let Some(export) = exports.get(&id).filter(|export| export.user_id == user_id) else {
+            return false;
+        };
+        let _ = std::fs::remove_file(self.file_path(id, export.format));
+        exports.remove(&id);
+        true
+    }
+
+    pub fn file_path(&self, id: Uuid, format: MixFormat) -> PathBuf {
+        self.dir.join(format!("{}.{}", id, format.extension()))
+    }
+
+    /// File name offered for download, from the mix name
+    pub fn file_name(export: &MixExport) -> String {
+        download_file_name(&export.name, "mix", export.format)
+    }
+
+    pub fn cue_sheet(export: &MixExport) -> String {
+        cue_sheet(&export.name, &Self::file_name(export), export.format.cue_file_type(), &export.cues)
+    }
+
+    async fn run(
+        &self,
+        id: Uuid,
+        user_id: Uuid,
+        tracks: Vec<MixSourceTrack>,
+        settings: MixSettings,
+        format: MixFormat,
+    ) -> Result<(), String> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| format!("Cannot create mix directory {:?}: {}", self.dir, e))?;
+        let wav_path = match format {
+            MixFormat::Wav => self.file_path(id, format),
+            _ => self.dir.join(format!("{}.render.wav", id)),
+        };
+
+        // Tracks are loaded one ahead of the renderer, which mixes them as they arrive
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<DecodedTrack>(1);
+        let loader = self.clone();
+        tokio::spawn(async move {
+            for track in tracks {
+                match loader.load(user_id, &track).await {
+                    Ok(decoded) => {
+                        if sender.send(decoded).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        let title = track.title.clone().unwrap_or_else(|| track.track_id.clone());
+                        tracing::debug!("Skipping {} in mix {}: {}", title, id, e);
+                        loader.update(id, |export| export.skipped.push(format!("{}: {}", title, e)));
+                    }
+                }
+            }
+        });
+
+        let service = self.clone();
+        let render_path = wav_path.clone();
+        let (cues, frames) = tokio::task::spawn_blocking(move || -> anyhow::Result<(Vec<MixCue>, usize)> {
+            let spec = hound::WavSpec {
+                channels: 2,
+                sample_rate: MIX_SAMPLE_RATE,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            };
+            let mut writer = hound::WavWriter::create(&render_path, spec)?;
+            let mut samples_written = 0;
+            let cues = render_mix(
+                std::iter::from_fn(|| receiver.blocking_recv()),
+                &settings,
+                |samples| {
+                    if (samples_written + samples.len()) / 2 > MAX_MIX_SECS * MIX_SAMPLE_RATE as usize {
+                        anyhow::bail!("Mixes are limited to {} hours", MAX_MIX_SECS / 3600);
+                    }
+                    for &sample in samples {
+                        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16)?;
+                    }
+                    samples_written += samples.len();
+                    Ok(())
+                },
+                |index| service.update(id, |export| export.rendered = index + 1),
+            )?;
+            writer.finalize()?;
+            Ok((cues, samples_written / 2))
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Rendering failed: {}", e))?;
+
+        if cues.is_empty() {
+            let _ = tokio::fs::remove_file(&wav_path).await;
+            return Err("None of the tracks could be loaded".to_string());
+        }
+        self.update(id, |export| {
+            export.cues = cues;
+            export.duration = Some(frames as f64 / MIX_SAMPLE_RATE as f64);
+        });
+
+        if format != MixFormat::Wav {
+            self.update(id, |export| export.status = MixExportStatus::Encoding);
+            let encoded = self.encode(&wav_path, &self.file_path(id, format), format).await;
+            let _ = tokio::fs::remove_file(&wav_path).await;
+            encoded?;
+        }
+        self.update(id, |export| export.status = MixExportStatus::Completed);
+        tracing::info!("Mix export {} completed", id);
+        Ok(())
+    }
+
+    // Decode a track with its beat grid; remote audio is downloaded first
+    async fn load(&self, user_id: Uuid, track: &MixSourceTrack) -> Result<DecodedTrack, String> {
+        let stream_url = self
+            .resolver
+            .resolve(Some(user_id), &track.source, &track.track_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let beat_grid = TrackAnalysisStore::find(&self.db, &track.source, &track.track_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .and_then(|analysis| analysis.beat_grid());
+
+        let download = if is_remote(&stream_url) {
+            Some(TempAudioFile::download(&stream_url).await.map_err(|e| e.to_string())?)
+        } else {
+            None
+        };
+        let path = download.as_ref().map(|file| file.path_str()).unwrap_or(stream_url);
+        let mut decoded = tokio::task::spawn_blocking(move || DecodedTrack::decode(&path, beat_grid))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+        decoded.title = track.title.clone();
+        decoded.artist = track.artist.clone();
+        Ok(decoded)
+    }
+
+    async fn encode(&self, input: &Path, output: &Path, format: MixFormat) -> Result<(), String> {
+        encode_with_ffmpeg(&self.ffmpeg, input, output, format).await
+    }
+}
+
+/// File name offered for download: `name` with characters unsafe in file names replaced
+pub fn download_file_name(name: &str, fallback: &str, format: MixFormat) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == ' ' { c } else { '_' })
+        .collect();
+    let stem = stem.trim();
+    format!("{}.{}", if stem.is_empty() { fallback } else { stem }, format.extension())
+}
+
+/// Encode a WAV file into `format` with the ffmpeg binary at `ffmpeg`
+pub async fn encode_with_ffmpeg(ffmpeg: &str, input: &Path, output: &Path, format: MixFormat) -> Result<(), String> {
+    let result = tokio::process::Command::new(ffmpeg)
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(input)
+        .args(format.encoder_args())
+        .arg(output)
+        .output()
+        .await
+        .map_err(|e| format!("Cannot run ffmpeg ({}): {}", ffmpeg, e))?;
+    if !result.status.success() {
+        let _ = tokio::fs::remove_file(output).await;
+        return Err(format!("ffmpeg failed: {}", String::from_utf8_lossy(&result.stderr).trim()));
+    }
+    Ok(())
+}
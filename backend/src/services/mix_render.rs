@@ -0,0 +1,434 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::services::audio_decode::{AnalysisSpan, AudioSink, AudioStream, StreamResampler};
+use crate::services::beat_grid::BeatGrid;
+use crate::services::harmonic_mixing::tempo_match;
+use crate::services::loudness_analysis::{LoudnessMeter, peak_to_db};
+use crate::services::time_stretch::hermite;
+
+/// Sample rate of rendered mixes, always stereo
+pub const MIX_SAMPLE_RATE: u32 = 44100;
+/// Longest track that is decoded into memory for mixing, in seconds
+pub const MAX_MIX_TRACK_SECS: f64 = 30.0 * 60.0;
+
+// Mix rendering configuration
+const MIX_CHANNELS: usize = 2;
+const TEMPO_RAMP_BEATS: f64 = 16.0; // Beats over which a beat-matched track returns to its own tempo
+const MIN_GRID_BEATS: usize = 16; // Beats a track needs for beat-matched transitions
+const PEAK_CEILING_DB: f32 = -1.0; // Normalization gain is limited to keep true peaks below this
+const SOFT_CLIP_KNEE: f32 = 0.9; // Overlapping tracks are compressed smoothly above this level
+const WRITE_CHUNK_FRAMES: usize = 4096;
+
+/// How tracks are joined into a mix
+#[derive(Debug, Clone, Copy)]
+pub struct MixSettings {
+    pub crossfade_secs: f32, // 0 for gapless playback
+    pub beat_match: bool, // Align beats and tempos during crossfades when both tracks have a beat grid
+    pub max_tempo_change: f32, // Percent a track may be sped up or slowed down to match the previous one
+    pub normalize: bool,
+    pub target_lufs: f32,
+}
+
+impl Default for MixSettings {
+    fn default() -> Self {
+        Self { crossfade_secs: 8.0, beat_match: true, max_tempo_change: 8.0, normalize: true, target_lufs: -14.0 }
+    }
+}
+
+/// A track decoded for mixing: stereo at MIX_SAMPLE_RATE with its measured loudness
+#[derive(Debug, Clone, Default)]
+pub struct DecodedTrack {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub samples: Vec<f32>, // Interleaved stereo
+    pub integrated_lufs: Option<f32>,
+    pub true_peak: f32, // Linear
+    pub beat_grid: Option<BeatGrid>,
+}
+
+// Collects a decoding pass as stereo at the native sample rate
+struct StereoCollector {
+    channels: usize,
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+impl AudioSink for StereoCollector {
+    fn push_native(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            self.left.push(frame[0]);
+            self.right.push(frame[frame.len().min(2) - 1]);
+        }
+    }
+
+    fn wants_mono(&self) -> bool {
+        false
+    }
+}
+
+impl DecodedTrack {
+    /// Decode a whole file to stereo at MIX_SAMPLE_RATE, measuring its loudness on the way
+    pub fn decode(path: &str, beat_grid: Option<BeatGrid>) -> Result<DecodedTrack> {
+        let mut stream = AudioStream::open(path)?;
+        let too_long = || anyhow::anyhow!("Tracks longer than {} minutes cannot be mixed", MAX_MIX_TRACK_SECS / 60.0);
+        if stream.duration_secs.is_some_and(|duration| duration > MAX_MIX_TRACK_SECS) {
+            return Err(too_long());
+        }
+        let channels = stream.channels.max(1);
+        let mut collector = StereoCollector { channels, left: Vec::new(), right: Vec::new() };
+        let mut meter = LoudnessMeter::new(stream.sample_rate, channels);
+        // Decoding stops at the limit for files that do not tell their length up front
+        let decoded = stream.run(AnalysisSpan::Truncate { max_secs: MAX_MIX_TRACK_SECS }, &mut [&mut collector, &mut meter])?;
+        if decoded >= MAX_MIX_TRACK_SECS {
+            return Err(too_long());
+        }
+        let loudness = meter.finish();
+
+        let resample = |channel: Vec<f32>| {
+            if stream.sample_rate == MIX_SAMPLE_RATE {
+                return channel;
+            }
+            let mut resampler = StreamResampler::new(stream.sample_rate, MIX_SAMPLE_RATE);
+            let mut output = resampler.process(&channel);
+            output.extend(resampler.finish());
+            output
+        };
+        let (left, right) = (resample(collector.left), resample(collector.right));
+        Ok(DecodedTrack {
+            samples: left.iter().zip(&right).flat_map(|(&l, &r)| [l, r]).collect(),
+            integrated_lufs: loudness.integrated_lufs,
+            true_peak: loudness.true_peak,
+            beat_grid,
+            ..Default::default()
+        })
+    }
+
+    fn frames(&self) -> usize {
+        self.samples.len() / MIX_CHANNELS
+    }
+
+    // Linear gain reaching the target loudness without pushing true peaks over the ceiling
+    fn gain(&self, settings: &MixSettings) -> f32 {
+        let Some(lufs) = self.integrated_lufs.filter(|_| settings.normalize) else {
+            return 1.0;
+        };
+        let headroom = PEAK_CEILING_DB - peak_to_db(self.true_peak);
+        10f32.powf((settings.target_lufs - lufs).min(headroom) / 20.0)
+    }
+
+    // The beat grid if it is good enough for beat matching
+    fn grid(&self) -> Option<&BeatGrid> {
+        self.beat_grid.as_ref().filter(|grid| grid.bpm > 0.0 && grid.beats.len() >= MIN_GRID_BEATS)
+    }
+}
+
+/// Where a track starts in the mix, for the cue sheet
+#[derive(Debug, Clone, Serialize)]
+pub struct MixCue {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub start: f64, // Seconds into the mix; the middle of the transition into the track
+    pub tempo_change: Option<f32>, // Percent the track starts sped up by when beat-matched
+}
+
+// A track being played into the mix at a variable rate
+struct Playback {
+    track: DecodedTrack,
+    gain: f32,
+    position: f64, // In source frames
+    start_rate: f64,
+    hold_frames: usize, // Output frames played at the start rate
+    ramp_frames: usize, // Output frames over which the rate returns to 1
+    played: usize,
+}
+
+impl Playback {
+    fn new(track: DecodedTrack, settings: &MixSettings, position: f64, start_rate: f64, hold_frames: usize) -> Self {
+        let ramp_frames = match track.grid() {
+            Some(grid) if start_rate != 1.0 => (TEMPO_RAMP_BEATS * 60.0 / grid.bpm as f64 * MIX_SAMPLE_RATE as f64) as usize,
+            _ => 0,
+        };
+        let gain = track.gain(settings);
+        Self { track, gain, position, start_rate, hold_frames, ramp_frames, played: 0 }
+    }
+
+    fn rate(&self) -> f64 {
+        if self.played < self.hold_frames {
+            self.start_rate
+        } else if self.played < self.hold_frames + self.ramp_frames {
+            let progress = (self.played - self.hold_frames) as f64 / self.ramp_frames as f64;
+            self.start_rate + (1.0 - self.start_rate) * progress
+        } else {
+            1.0
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.position >= self.track.frames() as f64
+    }
+
+    // Next output frame, interpolated with a cubic Hermite spline between source frames
+    fn next_frame(&mut self) -> Option<[f32; 2]> {
+        if self.is_finished() {
+            return None;
+        }
+        let index = self.position.floor() as usize;
+        let fraction = (self.position - index as f64) as f32;
+        let frames = self.track.frames();
+        let sample = |frame: isize, channel: usize| {
+            let frame = frame.clamp(0, frames as isize - 1) as usize;
+            self.track.samples[frame * MIX_CHANNELS + channel]
+        };
+        let mut output = [0.0; 2];
+        for (channel, value) in output.iter_mut().enumerate() {
+            let i = index as isize;
+            let y = [sample(i - 1, channel), sample(i, channel), sample(i + 1, channel), sample(i + 2, channel)];
+            *value = self.gain * hermite(y, fraction);
+        }
+        self.position += self.rate();
+        self.played += 1;
+        Some(output)
+    }
+}
+
+// How the next track is brought in
+struct Transition {
+    fade_start: f64, // Source frame of the outgoing track where the crossfade starts
+    overlap: usize, // Output frames of the crossfade
+    next_start: f64, // Source frame the incoming track starts at
+    next_rate: f64,
+}
+
+fn plan_transition(current: &Playback, next: &DecodedTrack, settings: &MixSettings) -> Transition {
+    let rate = MIX_SAMPLE_RATE as f64;
+    let current_frames = current.track.frames() as f64;
+    let crossfade = (settings.crossfade_secs.max(0.0) as f64 * rate)
+        .min((current_frames - current.position).max(0.0) / 2.0)
+        .min(next.frames() as f64 / 2.0);
+
+    // Beat-matched: the incoming track's first downbeat lands on a downbeat of the outgoing track,
+    // with whole bars overlapping and the incoming track at the outgoing track's tempo
+    if settings.beat_match && crossfade > 0.0
+        && let (Some(from), Some(to)) = (current.track.grid(), next.grid())
+        && let Some(tempo) = tempo_match(from.bpm, to.bpm, settings.max_tempo_change)
+    {
+        let bar = from.beats_per_bar.max(1) as usize;
+        let bars = ((crossfade / rate * from.bpm as f64 / 60.0 / bar as f64).round() as usize).max(1);
+        let beats = bars * bar;
+        let downbeat = (0..from.beats.len().saturating_sub(beats))
+            .rev()
+            .find(|&k| (k + bar - from.bar_phase as usize % bar).is_multiple_of(bar) && from.beats[k] as f64 * rate >= current.position);
+        if let Some(k) = downbeat {
+            let next_rate = (1.0 + tempo.pitch_shift as f64 / 100.0) * current.rate();
+            let fade_start = from.beats[k] as f64 * rate;
+            let fade_end = from.beats[k + beats] as f64 * rate;
+            return Transition {
+                fade_start,
+                overlap: ((fade_end - fade_start) / current.rate()) as usize,
+                next_start: to.first_downbeat as f64 * rate,
+                next_rate,
+            };
+        }
+    }
+
+    Transition {
+        fade_start: (current_frames - crossfade).max(current.position),
+        overlap: crossfade as usize,
+        next_start: 0.0,
+        next_rate: 1.0,
+    }
+}
+
+// Smooth limiting of overlapping peaks
+fn soft_clip(sample: f32) -> f32 {
+    let level = sample.abs();
+    if level <= SOFT_CLIP_KNEE {
+        return sample;
+    }
+    let range = 1.0 - SOFT_CLIP_KNEE;
+    sample.signum() * (SOFT_CLIP_KNEE + range * ((level - SOFT_CLIP_KNEE) / range).tanh())
+}
+
+struct MixWriter<W: FnMut(&[f32]) -> Result<()>> {
+    write: W,
+    buffer: Vec<f32>,
+    frames: usize,
+}
+
+impl<W: FnMut(&[f32]) -> Result<()>> MixWriter<W> {
+    fn push(&mut self, frame: [f32; 2]) -> Result<()> {
+        self.buffer.extend(frame.map(soft_clip));
+        self.frames += 1;
+        if self.buffer.len() >= WRITE_CHUNK_FRAMES * MIX_CHANNELS {
+            (self.write)(&self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            (self.write)(&self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Render tracks into one continuous mix, writing interleaved stereo samples at MIX_SAMPLE_RATE
+/// as they are produced. Tracks are taken one at a time, so only two are decoded at once.
+/// `on_track` is called with the index of every track as it starts. Returns the cues of the tracks.
+pub fn render_mix(
+    tracks: impl IntoIterator<Item = DecodedTrack>,
+    settings: &MixSettings,
+    write: impl FnMut(&[f32]) -> Result<()>,
+    mut on_track: impl FnMut(usize),
+) -> Result<Vec<MixCue>> {
+    let mut writer = MixWriter { write, buffer: Vec::new(), frames: 0 };
+    let mut tracks = tracks.into_iter();
+    let mut cues = Vec::new();
+    let Some(first) = tracks.next() else {
+        return Ok(cues);
+    };
+    on_track(0);
+    cues.push(MixCue { title: first.title.clone(), artist: first.artist.clone(), start: 0.0, tempo_change: None });
+    let mut current = Playback::new(first, settings, 0.0, 1.0, 0);
+
+    for (index, next) in tracks.enumerate() {
+        on_track(index + 1);
+        let transition = plan_transition(&current, &next, settings);
+        while current.position < transition.fade_start {
+            match current.next_frame() {
+                Some(frame) => writer.push(frame)?,
+                None => break,
+            }
+        }
+
+        cues.push(MixCue {
+            title: next.title.clone(),
+            artist: next.artist.clone(),
+            start: (writer.frames + transition.overlap / 2) as f64 / MIX_SAMPLE_RATE as f64,
+            tempo_change: (transition.next_rate != 1.0).then_some(((transition.next_rate - 1.0) * 100.0) as f32),
+        });
+        let mut incoming = Playback::new(next, settings, transition.next_start, transition.next_rate, transition.overlap);
+        // Equal-power crossfade
+        for i in 0..transition.overlap {
+            let progress = (i as f32 + 0.5) / transition.overlap as f32 * std::f32::consts::FRAC_PI_2;
+            let [a_left, a_right] = current.next_frame().unwrap_or_default();
+            let [b_left, b_right] = incoming.next_frame().unwrap_or_default();
+            let (out, fade_in) = (progress.cos(), progress.sin());
+            writer.push([a_left * out + b_left * fade_in, a_right * out + b_right * fade_in])?;
+        }
+        current = incoming;
+    }
+
+    while let Some(frame) = current.next_frame() {
+        writer.push(frame)?;
+    }
+    writer.flush()?;
+    Ok(cues)
+}
+
+/// CUE sheet of a rendered mix, with times in CD frames (1/75 s)
+pub fn cue_sheet(title: &str, file_name: &str, file_type: &str, cues: &[MixCue]) -> String {
+    let quote = |text: &str| text.replace('"', "'");
+    let mut sheet = format!("TITLE \"{}\"\nFILE \"{}\" {}\n", quote(title), quote(file_name), file_type);
+    for (number, cue) in cues.iter().enumerate() {
+        let frames = (cue.start * 75.0).round() as u64;
+        sheet.push_str(&format!("  TRACK {:02} AUDIO\n", number + 1));
+        if let Some(title) = &cue.title {
+            sheet.push_str(&format!("    TITLE \"{}\"\n", quote(title)));
+        }
+        if let Some(artist) = &cue.artist {
+            sheet.push_str(&format!("    PERFORMER \"{}\"\n", quote(artist)));
+        }
+        sheet.push_str(&format!("    INDEX 01 {:02}:{:02}:{:02}\n", frames / 75 / 60, frames / 75 % 60, frames % 75));
+    }
+    sheet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stereo clicks on every beat
+    fn click_track(bpm: f32, seconds: f32) -> DecodedTrack {
+        let frames = (seconds * MIX_SAMPLE_RATE as f32) as usize;
+        let period = 60.0 / bpm;
+        let beats: Vec<f32> = (0..).map(|i| i as f32 * period).take_while(|&t| t < seconds).collect();
+        let mut samples = vec![0.0; frames * 2];
+        for &beat in &beats {
+            let frame = (beat * MIX_SAMPLE_RATE as f32) as usize;
+            for i in frame..(frame + 200).min(frames) {
+                samples[i * 2] = 0.5;
+                samples[i * 2 + 1] = 0.5;
+            }
+        }
+        DecodedTrack {
+            samples,
+            true_peak: 0.5,
+            beat_grid: Some(BeatGrid {
+                bpm,
+                beats_per_bar: 4,
+                beats,
+                first_downbeat: 0.0,
+                bar_phase: 0,
+                tempo_curve: Vec::new(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn render(tracks: Vec<DecodedTrack>, settings: MixSettings) -> (Vec<f32>, Vec<MixCue>) {
+        let mut output = Vec::new();
+        let cues = render_mix(tracks, &settings, |samples| {
+            output.extend_from_slice(samples);
+            Ok(())
+        }, |_| {})
+        .unwrap();
+        (output, cues)
+    }
+
+    #[test]
+    fn test_gapless_mix() {
+        let tracks = vec![click_track(120.0, 10.0), click_track(120.0, 10.0)];
+        let settings = MixSettings { crossfade_secs: 0.0, normalize: false, ..Default::default() };
+        let (output, cues) = render(tracks.clone(), settings);
+        let expected: Vec<f32> = tracks.iter().flat_map(|track| track.samples.clone()).collect();
+        assert_eq!(output, expected);
+        assert_eq!(cues[1].start, 10.0);
+    }
+
+    #[test]
+    fn test_beat_matched_crossfade() {
+        let settings = MixSettings { crossfade_secs: 8.0, normalize: false, ..Default::default() };
+        let (output, cues) = render(vec![click_track(120.0, 30.0), click_track(125.0, 30.0)], settings);
+
+        // The incoming track is slowed to 120 BPM; 4 bars of 2 s overlap
+        let change = cues[1].tempo_change.unwrap();
+        assert!((change + 4.0).abs() < 0.1, "tempo change {}", change);
+        let seconds = output.len() as f32 / 2.0 / MIX_SAMPLE_RATE as f32;
+        assert!(seconds > 49.0 && seconds < 53.0, "mix length {}", seconds);
+
+        // During the crossfade, clicks of both tracks coincide: no click falls between beats
+        let fade_start = (cues[1].start - 4.0) as f32;
+        for beat in 0..16 {
+            let between = fade_start + beat as f32 * 0.5 + 0.25;
+            let frame = (between * MIX_SAMPLE_RATE as f32) as usize;
+            let window = &output[frame * 2 - 2000..frame * 2 + 2000];
+            assert!(window.iter().all(|&s| s.abs() < 1e-3), "click between beats at {:.2}s", between);
+        }
+    }
+
+    #[test]
+    fn test_cue_sheet() {
+        let cues = vec![
+            MixCue { title: Some("Intro".to_string()), artist: None, start: 0.0, tempo_change: None },
+            MixCue { title: Some("Say \"Hi\"".to_string()), artist: Some("Band".to_string()), start: 65.5, tempo_change: None },
+        ];
+        let sheet = cue_sheet("Mix", "mix.flac", "WAVE", &cues);
+        assert!(sheet.contains("FILE \"mix.flac\" WAVE"));
+        assert!(sheet.contains("TITLE \"Say 'Hi'\"\n    PERFORMER \"Band\"\n    INDEX 01 01:05:38"));
+    }
+}
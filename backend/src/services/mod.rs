@@ -1,11 +1,57 @@
 pub mod streaming;
 pub mod streaming_service;
 pub mod auth;
+pub mod audio_decode;
 pub mod spectrogram_bpm_analysis;
+pub mod analysis_artifacts;
+pub mod beat_grid;
+pub mod track_structure;
+pub mod track_descriptors;
+pub mod bpm_estimate;
 pub mod key_analysis;
+pub mod harmonic_mixing;
+pub mod auto_dj;
+pub mod chord_analysis;
+pub mod loudness_analysis;
+pub mod fingerprint;
+pub mod mix_render;
+pub mod mix_export;
+pub mod time_stretch;
+pub mod loop_extraction;
+pub mod sample_library;
+pub mod waveform;
+pub mod track_matching;
+pub mod search_ranking;
+pub mod search_query;
+pub mod track_analysis_store;
+pub mod analysis_jobs;
+pub mod library_ingest;
 
 pub use streaming::*;
 pub use streaming_service::*;
 pub use auth::*;
+pub use audio_decode::*;
 pub use spectrogram_bpm_analysis::*;
+pub use analysis_artifacts::*;
+pub use beat_grid::*;
+pub use track_structure::*;
+pub use track_descriptors::*;
+pub use bpm_estimate::*;
 pub use key_analysis::*;
+pub use harmonic_mixing::*;
+pub use auto_dj::*;
+pub use chord_analysis::*;
+pub use loudness_analysis::*;
+pub use fingerprint::*;
+pub use mix_render::*;
+pub use mix_export::*;
+pub use time_stretch::*;
+pub use loop_extraction::*;
+pub use sample_library::*;
+pub use waveform::*;
+pub use track_matching::*;
+pub use search_ranking::*;
+pub use search_query::*;
+pub use track_analysis_store::*;
+pub use analysis_jobs::*;
+pub use library_ingest::*;
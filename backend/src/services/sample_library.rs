@@ -0,0 +1,230 @@
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, ModelTrait, QueryFilter, QueryOrder, Set};
+use std::path::PathBuf;
+use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::models::analysis_job::JOB_KIND_BPM;
+use crate::models::{AnalysisTrackDto, SampleActiveModel, SampleColumn, SampleEntity, SampleModel};
+use crate::services::analysis_jobs::{AnalysisJobQueue, JobError, StreamUrlResolver};
+use crate::services::audio_decode::{is_remote, TempAudioFile};
+use crate::services::beat_grid::BeatGrid;
+use crate::services::loop_extraction::{nearest_downbeat, ExtractedLoop, LoopRegion};
+use crate::services::mix_export::{download_file_name, encode_with_ffmpeg, MixFormat};
+use crate::services::track_analysis_store::TrackAnalysisStore;
+
+/// Why a loop could not be added to the samples collection
+#[derive(Debug, Error)]
+pub enum SampleError {
+    #[error("{0}")]
+    Invalid(String), // The loop cannot be cut from the track as requested
+    #[error("The beats of this track are being detected, try again once its analysis is done")]
+    NotAnalyzed, // A beat detection has been queued
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl From<sea_orm::DbErr> for SampleError {
+    fn from(e: sea_orm::DbErr) -> Self {
+        SampleError::Failed(format!("Database error: {}", e))
+    }
+}
+
+/// A loop to cut from a track
+#[derive(Debug, Clone)]
+pub struct LoopRequest {
+    pub source: String,
+    pub track_id: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub name: Option<String>,
+    pub start_beat: usize,
+    pub bars: u32,
+    pub snap_to_bar: bool,
+    pub fade_ms: u32,
+    pub format: MixFormat, // WAV or FLAC
+}
+
+/// Users' collections of loops cut from tracks, kept as files in SAMPLE_DIR.
+/// FLAC loops are encoded by the ffmpeg binary at FFMPEG_PATH.
+#[derive(Clone)]
+pub struct SampleLibraryService {
+    dir: PathBuf,
+    ffmpeg: String,
+    db: DatabaseConnection,
+    resolver: Arc<dyn StreamUrlResolver>,
+    analysis_jobs: AnalysisJobQueue, // Detects the beats of tracks loops are cut from
+}
+
+impl SampleLibraryService {
+    pub fn new(
+        dir: PathBuf,
+        ffmpeg: String,
+        db: DatabaseConnection,
+        resolver: Arc<dyn StreamUrlResolver>,
+        analysis_jobs: AnalysisJobQueue,
+    ) -> Self {
+        Self { dir, ffmpeg, db, resolver, analysis_jobs }
+    }
+
+    pub fn from_env(db: DatabaseConnection, resolver: Arc<dyn StreamUrlResolver>, analysis_jobs: AnalysisJobQueue) -> Self {
+        let dir = std::env::var("SAMPLE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")).join("samples"));
+        let ffmpeg = std::env::var("FFMPEG_PATH").unwrap_or_else(|_| "ffmpeg".to_string());
+        Self::new(dir, ffmpeg, db, resolver, analysis_jobs)
+    }
+
+    pub fn file_path(&self, sample: &SampleModel) -> PathBuf {
+        self.dir.join(sample.user_id.to_string()).join(format!("{}.{}", sample.id, sample.format))
+    }
+
+    /// File name offered for download, from the sample name
+    pub fn file_name(sample: &SampleModel) -> String {
+        let format = MixFormat::from_name(&sample.format).unwrap_or(MixFormat::Wav);
+        download_file_name(&sample.name, "sample", format)
+    }
+
+    /// The user's samples, newest first
+    pub async fn list(&self, user_id: Uuid) -> Result<Vec<SampleModel>, sea_orm::DbErr> {
+        SampleEntity::find()
+            .filter(SampleColumn::UserId.eq(user_id))
+            .order_by_desc(SampleColumn::CreatedAt)
+            .all(&self.db)
+            .await
+    }
+
+    pub async fn get(&self, user_id: Uuid, id: Uuid) -> Result<Option<SampleModel>, sea_orm::DbErr> {
+        SampleEntity::find_by_id(id)
+            .filter(SampleColumn::UserId.eq(user_id))
+            .one(&self.db)
+            .await
+    }
+
+    /// Remove a sample and its file; returns false for unknown samples
+    pub async fn remove(&self, user_id: Uuid, id: Uuid) -> Result<bool, sea_orm::DbErr> {
+        let Some(sample) = self.get(user_id, id).await? else {
+            return Ok(false);
+        };
+        let path = self.file_path(&sample);
+        sample.delete(&self.db).await?;
+        let _ = tokio::fs::remove_file(path).await;
+        Ok(true)
+    }
+
+    /// Cut a loop snapped to the track's beat grid and add it to the user's samples
+    pub async fn extract(&self, user_id: Uuid, request: LoopRequest) -> Result<SampleModel, SampleError> {
+        let grid = self.beat_grid(user_id, &request).await?;
+        let start_beat = if request.snap_to_bar { nearest_downbeat(&grid, request.start_beat) } else { request.start_beat };
+        let region = LoopRegion::on_grid(&grid, start_beat, request.bars).map_err(SampleError::Invalid)?;
+
+        let stream_url = self
+            .resolver
+            .resolve(Some(user_id), &request.source, &request.track_id)
+            .await
+            .map_err(|e| match e {
+                JobError::Fatal(message) => SampleError::Invalid(message),
+                JobError::Retryable(message) => SampleError::Failed(message),
+            })?;
+        let download = if is_remote(&stream_url) {
+            Some(TempAudioFile::download(&stream_url).await.map_err(|e| SampleError::Failed(e.to_string()))?)
+        } else {
+            None
+        };
+        let path = download.as_ref().map(|file| file.path_str()).unwrap_or(stream_url);
+
+        let id = Uuid::new_v4();
+        let user_dir = self.dir.join(user_id.to_string());
+        tokio::fs::create_dir_all(&user_dir)
+            .await
+            .map_err(|e| SampleError::Failed(format!("Cannot create sample directory {:?}: {}", user_dir, e)))?;
+        let file_path = user_dir.join(format!("{}.{}", id, request.format.extension()));
+        let wav_path = match request.format {
+            MixFormat::Wav => file_path.clone(),
+            _ => user_dir.join(format!("{}.render.wav", id)),
+        };
+
+        let render_path = wav_path.clone();
+        let fade_secs = request.fade_ms as f32 / 1000.0;
+        let extracted = tokio::task::spawn_blocking(move || -> anyhow::Result<ExtractedLoop> {
+            let mut extracted = ExtractedLoop::extract(&path, &region)?;
+            extracted.fade_edges(fade_secs);
+            extracted.write_wav(&render_path)?;
+            Ok(extracted)
+        })
+        .await
+        .map_err(|e| SampleError::Failed(e.to_string()))
+        .and_then(|extracted| extracted.map_err(|e| SampleError::Failed(format!("Extracting the loop failed: {}", e))));
+        let extracted = match extracted {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&wav_path).await;
+                return Err(e);
+            }
+        };
+
+        if request.format != MixFormat::Wav {
+            let encoded = encode_with_ffmpeg(&self.ffmpeg, &wav_path, &file_path, request.format).await;
+            let _ = tokio::fs::remove_file(&wav_path).await;
+            encoded.map_err(SampleError::Failed)?;
+        }
+        let file_size = tokio::fs::metadata(&file_path).await.map(|metadata| metadata.len()).unwrap_or(0);
+
+        let label = request.title.clone().unwrap_or_else(|| request.track_id.clone());
+        let sample = SampleActiveModel {
+            id: Set(id),
+            user_id: Set(user_id),
+            name: Set(request
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("{} ({} bars, {:.0} BPM)", label, request.bars, region.bpm))),
+            source: Set(request.source.clone()),
+            track_id: Set(request.track_id.clone()),
+            title: Set(request.title.clone()),
+            artist: Set(request.artist.clone()),
+            start_beat: Set(region.start_beat as i32),
+            bars: Set(request.bars as i32),
+            beats_per_bar: Set((region.beats / request.bars as usize) as i32),
+            bpm: Set(region.bpm),
+            start_time: Set(region.start),
+            duration: Set(extracted.duration()),
+            fade_ms: Set(request.fade_ms as i32),
+            sample_rate: Set(extracted.sample_rate as i32),
+            channels: Set(extracted.channels as i32),
+            format: Set(request.format.extension().to_string()),
+            file_size: Set(file_size as i64),
+            created_at: Set(chrono::Utc::now().naive_utc()),
+        };
+        match sample.insert(&self.db).await {
+            Ok(sample) => {
+                tracing::info!("Added {}-bar loop {} of track {} ({}) to the samples of user {}", request.bars, id, request.track_id, request.source, user_id);
+                Ok(sample)
+            }
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&file_path).await;
+                Err(e.into())
+            }
+        }
+    }
+
+    // The stored beat grid of a track; tracks without a current beat detection get one queued
+    async fn beat_grid(&self, user_id: Uuid, request: &LoopRequest) -> Result<BeatGrid, SampleError> {
+        let stored = TrackAnalysisStore::find(&self.db, &request.source, &request.track_id).await?;
+        if let Some(grid) = stored.as_ref().and_then(|analysis| analysis.beat_grid()) {
+            return Ok(grid);
+        }
+        if stored.is_some_and(|analysis| analysis.has_current_bpm()) {
+            return Err(SampleError::Invalid("No beats could be detected in this track".to_string()));
+        }
+
+        let track = AnalysisTrackDto {
+            track_id: request.track_id.clone(),
+            source: request.source.clone(),
+            title: request.title.clone(),
+            stream_url: None,
+            content_hash: None,
+        };
+        self.analysis_jobs.enqueue(Some(user_id), vec![track], &[JOB_KIND_BPM.to_string()], false).await?;
+        Err(SampleError::NotAnalyzed)
+    }
+}
@@ -0,0 +1,371 @@
+use thiserror::Error;
+
+use crate::models::TrackAnalysisModel;
+use crate::services::track_descriptors::{Descriptor, TrackDescriptors};
+use crate::services::track_matching::TrackMatchingService;
+
+pub const SEARCH_SOURCES: [&str; 3] = ["server", "qobuz", "spotify"];
+
+/// Error for a search query that cannot be parsed
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Invalid search query: {message}")]
+pub struct QueryParseError {
+    pub message: String,
+}
+
+impl QueryParseError {
+    fn new(message: impl Into<String>) -> Self {
+        Self { message: message.into() }
+    }
+}
+
+/// Inclusive numeric range; a single value is a range with equal bounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NumericRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl NumericRange {
+    pub fn contains(&self, value: f32) -> bool {
+        value >= self.min && value <= self.max
+    }
+}
+
+/// A field predicate of a track query
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldFilter {
+    Title(String),
+    Artist(String),
+    Album(String),
+    Year(NumericRange),
+    Bpm(NumericRange),
+    Key(Vec<String>),     // Key names ("Am") or Camelot codes ("8A")
+    Camelot(Vec<String>), // Normalized Camelot codes ("8A")
+    Source(Vec<String>),  // "server", "qobuz", "spotify"
+    Descriptor(Descriptor, NumericRange), // e.g. `energy:0.7..`, `brightness:..2000`
+}
+
+/// Analysis results of a track, used by the `bpm:`, `key:`, `camelot:` and descriptor filters
+#[derive(Debug, Clone, Default)]
+pub struct TrackAnalysisInfo {
+    pub bpm: Option<f32>,
+    pub key_name: Option<String>,
+    pub camelot: Option<String>,
+    pub descriptors: Option<TrackDescriptors>,
+}
+
+impl From<&TrackAnalysisModel> for TrackAnalysisInfo {
+    fn from(analysis: &TrackAnalysisModel) -> Self {
+        Self {
+            bpm: analysis.bpm,
+            key_name: analysis.key_name.clone(),
+            camelot: analysis.camelot.clone(),
+            descriptors: analysis.descriptors(),
+        }
+    }
+}
+
+/// What is known about a search result when evaluating filters.
+/// Albums have no title of their own and tracks of remote providers carry no year,
+/// so filters on missing facts reject the result.
+#[derive(Debug, Clone, Copy)]
+pub struct FilterSubject<'a> {
+    pub title: Option<&'a str>,
+    pub artist: &'a str,
+    pub album: &'a str,
+    pub year: Option<u32>,
+    pub source: &'a str,
+    pub analysis: Option<&'a TrackAnalysisInfo>,
+}
+
+impl FieldFilter {
+    pub fn matches(&self, subject: &FilterSubject) -> bool {
+        let contains = |haystack: &str, needle: &str| {
+            TrackMatchingService::normalize_text(haystack).contains(&TrackMatchingService::normalize_text(needle))
+        };
+        let analysis = subject.analysis;
+
+        match self {
+            FieldFilter::Title(value) => subject.title.is_some_and(|title| contains(title, value)),
+            FieldFilter::Artist(value) => contains(subject.artist, value),
+            FieldFilter::Album(value) => contains(subject.album, value),
+            FieldFilter::Year(range) => subject.year.is_some_and(|year| range.contains(year as f32)),
+            FieldFilter::Bpm(range) => analysis.and_then(|a| a.bpm).is_some_and(|bpm| range.contains(bpm)),
+            FieldFilter::Key(values) => analysis.is_some_and(|a| {
+                values.iter().any(|value| {
+                    a.camelot.as_deref().is_some_and(|c| c.eq_ignore_ascii_case(value))
+                        || a.key_name.as_deref().is_some_and(|k| k.eq_ignore_ascii_case(value))
+                })
+            }),
+            FieldFilter::Camelot(values) => analysis
+                .and_then(|a| a.camelot.as_deref())
+                .is_some_and(|c| values.iter().any(|value| c.eq_ignore_ascii_case(value))),
+            FieldFilter::Source(sources) => sources.iter().any(|s| s == subject.source),
+            FieldFilter::Descriptor(descriptor, range) => analysis
+                .and_then(|a| a.descriptors.as_ref())
+                .is_some_and(|descriptors| range.contains(descriptor.value(descriptors))),
+        }
+    }
+
+    /// Whether the filter needs stored analysis results
+    pub fn needs_analysis(&self) -> bool {
+        matches!(self, FieldFilter::Bpm(_) | FieldFilter::Key(_) | FieldFilter::Camelot(_) | FieldFilter::Descriptor(..))
+    }
+}
+
+/// A search query split into free text and field filters,
+/// e.g. `artist:"Daft Punk" bpm:118..124 camelot:8A,9A energy:0.7.. source:qobuz around the world`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackQuery {
+    pub free_text: String,
+    pub filters: Vec<FieldFilter>,
+}
+
+impl TrackQuery {
+    pub fn parse(query: &str) -> Result<Self, QueryParseError> {
+        let mut free_text = Vec::new();
+        let mut filters = Vec::new();
+
+        for term in tokenize(query)? {
+            match term.split_once(':') {
+                Some((field, value)) if !field.is_empty() && is_known_field(field) => {
+                    if value.is_empty() {
+                        return Err(QueryParseError::new(format!("missing value for `{}:`", field)));
+                    }
+                    filters.push(parse_filter(&field.to_lowercase(), value)?);
+                }
+                _ => free_text.push(term),
+            }
+        }
+
+        Ok(Self {
+            free_text: free_text.join(" "),
+            filters,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.free_text.trim().is_empty() && self.filters.is_empty()
+    }
+
+    pub fn matches(&self, subject: &FilterSubject) -> bool {
+        self.filters.iter().all(|filter| filter.matches(subject))
+    }
+
+    /// Sources named by `source:` filters (intersected when given more than once)
+    pub fn sources(&self) -> Option<Vec<String>> {
+        self.filters.iter().fold(None, |acc: Option<Vec<String>>, filter| match filter {
+            FieldFilter::Source(sources) => Some(match acc {
+                Some(previous) => previous.into_iter().filter(|s| sources.contains(s)).collect(),
+                None => sources.clone(),
+            }),
+            _ => acc,
+        })
+    }
+
+    pub fn needs_analysis(&self) -> bool {
+        self.filters.iter().any(FieldFilter::needs_analysis)
+    }
+
+    /// The filters that can be evaluated without analysis results, without free text
+    pub fn without_analysis_filters(&self) -> TrackQuery {
+        TrackQuery {
+            free_text: String::new(),
+            filters: self.filters.iter().filter(|filter| !filter.needs_analysis()).cloned().collect(),
+        }
+    }
+
+    /// Whether any filter other than `source:` applies
+    pub fn has_result_filters(&self) -> bool {
+        self.filters.iter().any(|filter| !matches!(filter, FieldFilter::Source(_)))
+    }
+}
+
+fn is_known_field(field: &str) -> bool {
+    let field = field.to_lowercase();
+    matches!(
+        field.as_str(),
+        "title" | "artist" | "album" | "year" | "bpm" | "key" | "camelot" | "source"
+    ) || Descriptor::from_name(&field).is_some()
+}
+
+// Split on whitespace, keeping double-quoted phrases (also after `field:`) together
+fn tokenize(query: &str) -> Result<Vec<String>, QueryParseError> {
+    let mut terms = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in query.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    terms.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(QueryParseError::new("unterminated quote"));
+    }
+    if !current.is_empty() {
+        terms.push(current);
+    }
+    Ok(terms)
+}
+
+fn parse_filter(field: &str, value: &str) -> Result<FieldFilter, QueryParseError> {
+    match field {
+        "title" => Ok(FieldFilter::Title(value.to_string())),
+        "artist" => Ok(FieldFilter::Artist(value.to_string())),
+        "album" => Ok(FieldFilter::Album(value.to_string())),
+        "year" => parse_range(field, value).map(FieldFilter::Year),
+        "bpm" => parse_range(field, value).map(FieldFilter::Bpm),
+        "key" => parse_list(field, value).map(FieldFilter::Key),
+        "camelot" => parse_list(field, value)?
+            .into_iter()
+            .map(|code| normalize_camelot(&code).ok_or_else(|| {
+                QueryParseError::new(format!("`{}` is not a Camelot code (1A-12B) in `camelot:{}`", code, value))
+            }))
+            .collect::<Result<_, _>>()
+            .map(FieldFilter::Camelot),
+        "source" => parse_list(field, value)?
+            .into_iter()
+            .map(|source| {
+                let source = source.to_lowercase();
+                if SEARCH_SOURCES.contains(&source.as_str()) {
+                    Ok(source)
+                } else {
+                    Err(QueryParseError::new(format!(
+                        "unknown source `{}` in `source:{}`, expected one of {}",
+                        source, value, SEARCH_SOURCES.join(", ")
+                    )))
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(FieldFilter::Source),
+        _ => match Descriptor::from_name(field) {
+            Some(descriptor) => parse_range(field, value).map(|range| FieldFilter::Descriptor(descriptor, range)),
+            None => Err(QueryParseError::new(format!("unknown field `{}`", field))),
+        },
+    }
+}
+
+// "a,b,c"
+fn parse_list(field: &str, value: &str) -> Result<Vec<String>, QueryParseError> {
+    value
+        .split(',')
+        .map(|item| {
+            let item = item.trim();
+            if item.is_empty() {
+                Err(QueryParseError::new(format!("empty list entry in `{}:{}`", field, value)))
+            } else {
+                Ok(item.to_string())
+            }
+        })
+        .collect()
+}
+
+// "8a" -> "8A"; None unless 1-12 followed by A or B
+fn normalize_camelot(code: &str) -> Option<String> {
+    let code = code.to_uppercase();
+    let letter = code.chars().last()?;
+    let number: u8 = code[..code.len() - letter.len_utf8()].parse().ok()?;
+    ((1..=12).contains(&number) && (letter == 'A' || letter == 'B')).then(|| format!("{}{}", number, letter))
+}
+
+// "124", "118..124", "118..", "..124" or "120-128"
+fn parse_range(field: &str, value: &str) -> Result<NumericRange, QueryParseError> {
+    let parse_number = |s: &str| {
+        s.trim().parse::<f32>().ok().filter(|n| n.is_finite()).ok_or_else(|| {
+            QueryParseError::new(format!("`{}` is not a number in `{}:{}`", s, field, value))
+        })
+    };
+
+    let (min, max) = if let Some((min, max)) = value.split_once("..") {
+        if min.is_empty() && max.is_empty() {
+            return Err(QueryParseError::new(format!("range `{}:{}` needs at least one bound", field, value)));
+        }
+        let min = if min.is_empty() { f32::MIN } else { parse_number(min)? };
+        let max = if max.is_empty() { f32::MAX } else { parse_number(max)? };
+        (min, max)
+    } else if let Some((min, max)) = value.split_once('-') {
+        (parse_number(min)?, parse_number(max)?)
+    } else {
+        let exact = parse_number(value)?;
+        (exact, exact)
+    };
+
+    if min > max {
+        return Err(QueryParseError::new(format!("empty range `{}:{}`", field, value)));
+    }
+    Ok(NumericRange { min, max })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filters_and_free_text() {
+        let query = TrackQuery::parse(r#"artist:"Daft Punk" bpm:118..124 camelot:8a,9A source:qobuz around the world"#).unwrap();
+
+        assert_eq!(query.free_text, "around the world");
+        assert_eq!(query.filters, vec![
+            FieldFilter::Artist("Daft Punk".to_string()),
+            FieldFilter::Bpm(NumericRange { min: 118.0, max: 124.0 }),
+            FieldFilter::Camelot(vec!["8A".to_string(), "9A".to_string()]),
+            FieldFilter::Source(vec!["qobuz".to_string()]),
+        ]);
+        assert_eq!(query.sources(), Some(vec!["qobuz".to_string()]));
+
+        let open = TrackQuery::parse("year:..1999").unwrap();
+        assert!(matches!(open.filters[0], FieldFilter::Year(range) if range.contains(1985.0) && !range.contains(2001.0)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(TrackQuery::parse("bpm:fast").is_err());
+        assert!(TrackQuery::parse("bpm:..").is_err());
+        assert!(TrackQuery::parse("year:2000-1990").is_err());
+        assert!(TrackQuery::parse(r#"artist:"Daft Punk"#).is_err());
+        assert!(TrackQuery::parse("camelot:13A").is_err());
+        assert!(TrackQuery::parse("camelot:8A,").is_err());
+        let error = TrackQuery::parse("source:tidal").unwrap_err();
+        assert!(error.message.contains("unknown source `tidal`"));
+        // Unknown prefixes are ordinary words
+        assert_eq!(TrackQuery::parse("re:member").unwrap().free_text, "re:member");
+    }
+
+    #[test]
+    fn test_matches_analysis() {
+        let query = TrackQuery::parse("bpm:118..124 camelot:8A,9A energy:0.7.. dynamic_range:..8 source:qobuz,server").unwrap();
+        let descriptors = TrackDescriptors { energy: 0.82, dynamic_range: 6.5, ..Default::default() };
+        let analysis = TrackAnalysisInfo {
+            bpm: Some(122.0),
+            key_name: Some("Am".to_string()),
+            camelot: Some("8A".to_string()),
+            descriptors: Some(descriptors),
+        };
+        let mut subject = FilterSubject {
+            title: Some("Around the World"),
+            artist: "Daft Punk",
+            album: "Homework",
+            year: None,
+            source: "qobuz",
+            analysis: Some(&analysis),
+        };
+
+        assert!(query.matches(&subject));
+        let calm = TrackAnalysisInfo { descriptors: Some(TrackDescriptors { energy: 0.4, ..descriptors }), ..analysis.clone() };
+        assert!(!query.matches(&FilterSubject { analysis: Some(&calm), ..subject }));
+        subject.source = "spotify";
+        assert!(!query.matches(&subject));
+        subject.source = "server";
+        subject.analysis = None;
+        assert!(!query.matches(&subject));
+    }
+}
@@ -0,0 +1,363 @@
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::streaming::{StreamingAlbum, StreamingPlaylist, StreamingTrack};
+use super::track_matching::TrackMatchingService;
+
+// Ranking configuration
+const TITLE_WEIGHT: f32 = 1.0;
+const ARTIST_WEIGHT: f32 = 0.8;
+const ALBUM_WEIGHT: f32 = 0.5;
+const PHRASE_BONUS: f32 = 0.2; // The whole query appears verbatim in a field
+const FUZZY_TOKEN_THRESHOLD: f64 = 0.75; // Minimum similarity for a misspelled token to count
+const PREFERENCE_WEIGHT: f32 = 0.05; // Tie-breaker between equally relevant sources
+const DEDUPE_CONFIDENCE: f32 = 0.9; // Stricter than playback matching: only merge obvious duplicates
+
+/// Opaque pagination state for merged searches: the next offset of every provider
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchCursor {
+    pub fingerprint: String,
+    pub offsets: BTreeMap<String, u32>,
+    pub exhausted: BTreeSet<String>,
+}
+
+impl SearchCursor {
+    /// Identifies the search a cursor belongs to, so it cannot be replayed against another query
+    pub fn fingerprint_for(query: &str, kind: &str, services: &[String]) -> String {
+        let mut sorted_services = services.to_vec();
+        sorted_services.sort();
+
+        let mut hasher = Sha256::new();
+        hasher.update(query.trim().to_lowercase().as_bytes());
+        hasher.update([0]);
+        hasher.update(kind.as_bytes());
+        hasher.update([0]);
+        hasher.update(sorted_services.join(",").as_bytes());
+        format!("{:x}", hasher.finalize())[..16].to_string()
+    }
+
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(cursor.trim())
+            .map_err(|_| anyhow!("Invalid search cursor"))?;
+        serde_json::from_slice(&bytes).map_err(|_| anyhow!("Invalid search cursor"))
+    }
+
+    pub fn offset_for(&self, service: &str) -> u32 {
+        self.offsets.get(service).copied().unwrap_or(0)
+    }
+}
+
+/// A provider's results, in the order the provider ranked them
+pub struct ProviderResults<T> {
+    pub service: String,
+    pub items: Vec<T>,
+}
+
+/// Merged page plus how many items of every provider it used up (emitted or deduplicated)
+pub struct MergedPage<T> {
+    pub items: Vec<T>,
+    pub consumed: HashMap<String, usize>,
+}
+
+/// Items that can be ranked and deduplicated across providers
+pub trait Rankable {
+    fn source(&self) -> &str;
+    fn relevance(&self, query: &str) -> f32;
+    fn is_duplicate_of(&self, other: &Self, matcher: &TrackMatchingService) -> bool;
+}
+
+impl Rankable for StreamingTrack {
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn relevance(&self, query: &str) -> f32 {
+        SearchRankingService::relevance(query, &[
+            (&self.title, TITLE_WEIGHT),
+            (&self.artist, ARTIST_WEIGHT),
+            (&self.album, ALBUM_WEIGHT),
+        ])
+    }
+
+    fn is_duplicate_of(&self, other: &Self, matcher: &TrackMatchingService) -> bool {
+        // Different releases on the same provider are kept apart
+        self.source != other.source
+            && matcher
+                .match_track(self, other)
+                .is_some_and(|m| m.confidence >= DEDUPE_CONFIDENCE)
+    }
+}
+
+impl Rankable for StreamingAlbum {
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn relevance(&self, query: &str) -> f32 {
+        SearchRankingService::relevance(query, &[(&self.title, TITLE_WEIGHT), (&self.artist, ARTIST_WEIGHT)])
+    }
+
+    fn is_duplicate_of(&self, other: &Self, _matcher: &TrackMatchingService) -> bool {
+        if self.source == other.source {
+            return false;
+        }
+        if let (Some(a), Some(b)) = (&self.upc, &other.upc) {
+            return a.trim_start_matches('0') == b.trim_start_matches('0');
+        }
+        TrackMatchingService::normalize_title(&self.title) == TrackMatchingService::normalize_title(&other.title)
+            && TrackMatchingService::normalize_artist(&self.artist) == TrackMatchingService::normalize_artist(&other.artist)
+    }
+}
+
+impl Rankable for StreamingPlaylist {
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn relevance(&self, query: &str) -> f32 {
+        let description = self.description.clone().unwrap_or_default();
+        SearchRankingService::relevance(query, &[(&self.name, TITLE_WEIGHT), (&description, ALBUM_WEIGHT)])
+    }
+
+    fn is_duplicate_of(&self, other: &Self, _matcher: &TrackMatchingService) -> bool {
+        // Playlists are curated per service, the same name does not make them equal
+        self.source == other.source && self.id == other.id
+    }
+}
+
+/// Merges per-provider search results into one relevance-ranked, deduplicated list
+pub struct SearchRankingService {
+    matcher: TrackMatchingService,
+}
+
+impl SearchRankingService {
+    pub fn new() -> Self {
+        Self {
+            matcher: TrackMatchingService::new(),
+        }
+    }
+
+    /// Relevance of weighted text fields to a query; every query token counts with its best field
+    pub fn relevance(query: &str, fields: &[(&str, f32)]) -> f32 {
+        let query = TrackMatchingService::normalize_text(query);
+        if query.is_empty() {
+            return 0.0;
+        }
+
+        let normalized_fields: Vec<(String, f32)> = fields
+            .iter()
+            .map(|(text, weight)| (TrackMatchingService::normalize_text(text), *weight))
+            .collect();
+
+        let query_tokens: Vec<&str> = query.split(' ').collect();
+        let token_score: f32 = query_tokens
+            .iter()
+            .map(|token| {
+                normalized_fields
+                    .iter()
+                    .map(|(field, weight)| Self::token_similarity(token, field) * weight)
+                    .fold(0.0, f32::max)
+            })
+            .sum::<f32>()
+            / query_tokens.len() as f32;
+
+        let phrase_bonus = if normalized_fields.iter().any(|(field, _)| field.contains(&query)) {
+            PHRASE_BONUS
+        } else {
+            0.0
+        };
+
+        token_score + phrase_bonus
+    }
+
+    fn token_similarity(token: &str, field: &str) -> f32 {
+        field
+            .split(' ')
+            .map(|word| {
+                if word == token {
+                    1.0
+                } else if word.starts_with(token) {
+                    0.9
+                } else {
+                    let similarity = strsim::normalized_levenshtein(token, word);
+                    if similarity >= FUZZY_TOKEN_THRESHOLD { similarity as f32 * 0.8 } else { 0.0 }
+                }
+            })
+            .fold(0.0, f32::max)
+    }
+
+    fn preference_bonus(source: &str, preferred_sources: &[String]) -> f32 {
+        match preferred_sources.iter().position(|s| s == source) {
+            Some(rank) => PREFERENCE_WEIGHT * (preferred_sources.len() - rank) as f32 / preferred_sources.len() as f32,
+            None => 0.0,
+        }
+    }
+
+    fn preference_rank(source: &str, preferred_sources: &[String]) -> usize {
+        preferred_sources.iter().position(|s| s == source).unwrap_or(usize::MAX)
+    }
+
+    /// K-way merge of provider lists by relevance. Every provider is consumed strictly in its own
+    /// order, so `consumed` can advance the provider offsets and the next page continues seamlessly.
+    /// When two providers return the same item, the copy from the more preferred source is kept.
+    pub fn merge<T: Rankable>(
+        &self,
+        query: &str,
+        providers: Vec<ProviderResults<T>>,
+        preferred_sources: &[String],
+        limit: usize,
+    ) -> MergedPage<T> {
+        let mut queues: Vec<(String, std::collections::VecDeque<(f32, T)>)> = providers
+            .into_iter()
+            .map(|provider| {
+                let scored = provider
+                    .items
+                    .into_iter()
+                    .map(|item| {
+                        let score = item.relevance(query) + Self::preference_bonus(item.source(), preferred_sources);
+                        (score, item)
+                    })
+                    .collect();
+                (provider.service, scored)
+            })
+            .collect();
+
+        let mut consumed: HashMap<String, usize> = queues.iter().map(|(service, _)| (service.clone(), 0)).collect();
+        let mut items: Vec<T> = Vec::new();
+
+        while items.len() < limit {
+            // Provider whose next item is the most relevant; earlier providers win ties
+            let next = queues
+                .iter()
+                .enumerate()
+                .filter_map(|(index, (_, queue))| queue.front().map(|(score, _)| (index, *score)))
+                .fold(None, |best: Option<(usize, f32)>, (index, score)| match best {
+                    Some((_, best_score)) if best_score >= score => best,
+                    _ => Some((index, score)),
+                });
+
+            let Some((index, _)) = next else { break };
+            let (service, queue) = &mut queues[index];
+            let Some((_, item)) = queue.pop_front() else { break };
+            *consumed.entry(service.clone()).or_insert(0) += 1;
+
+            self.fold_duplicate(&mut items, item, preferred_sources);
+        }
+
+        // Use up duplicates of this page waiting at the head of a provider, so they do not
+        // reappear on the next page
+        for (service, queue) in queues.iter_mut() {
+            while let Some((_, head)) = queue.front() {
+                if !items.iter().any(|existing| head.is_duplicate_of(existing, &self.matcher)) {
+                    break;
+                }
+                if let Some((_, item)) = queue.pop_front() {
+                    *consumed.entry(service.clone()).or_insert(0) += 1;
+                    self.fold_duplicate(&mut items, item, preferred_sources);
+                }
+            }
+        }
+
+        MergedPage { items, consumed }
+    }
+
+    /// Add an item to the page unless it duplicates one already there, in which case the copy
+    /// from the more preferred source is kept
+    fn fold_duplicate<T: Rankable>(&self, items: &mut Vec<T>, item: T, preferred_sources: &[String]) {
+        match items.iter().position(|existing| item.is_duplicate_of(existing, &self.matcher)) {
+            Some(existing_index) => {
+                let existing_rank = Self::preference_rank(items[existing_index].source(), preferred_sources);
+                if Self::preference_rank(item.source(), preferred_sources) < existing_rank {
+                    items[existing_index] = item;
+                }
+            }
+            None => items.push(item),
+        }
+    }
+}
+
+impl Default for SearchRankingService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(source: &str, id: &str, title: &str, artist: &str) -> StreamingTrack {
+        StreamingTrack {
+            id: id.to_string(),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album: "Album".to_string(),
+            duration: Some(240),
+            stream_url: None,
+            cover_url: None,
+            quality: None,
+            source: source.to_string(),
+            bitrate: None,
+            sample_rate: None,
+            bit_depth: None,
+            isrc: None,
+        }
+    }
+
+    #[test]
+    fn test_relevance_prefers_exact_matches() {
+        let exact = SearchRankingService::relevance("around the world", &[("Around the World", 1.0), ("Daft Punk", 0.8)]);
+        let typo = SearchRankingService::relevance("arond the world", &[("Around the World", 1.0), ("Daft Punk", 0.8)]);
+        let unrelated = SearchRankingService::relevance("around the world", &[("One More Time", 1.0), ("Daft Punk", 0.8)]);
+
+        assert!(exact > typo);
+        assert!(typo > unrelated);
+    }
+
+    #[test]
+    fn test_merge_dedupes_and_tracks_consumption() {
+        let ranking = SearchRankingService::new();
+        let preferred = vec!["server".to_string(), "qobuz".to_string()];
+        let providers = vec![
+            ProviderResults {
+                service: "qobuz".to_string(),
+                items: vec![track("qobuz", "q1", "Around the World", "Daft Punk"), track("qobuz", "q2", "Aerodynamic", "Daft Punk")],
+            },
+            ProviderResults {
+                service: "server".to_string(),
+                items: vec![track("server", "s1", "Around the World", "Daft Punk")],
+            },
+        ];
+
+        let page = ranking.merge("around the world", providers, &preferred, 1);
+
+        // The local copy is preferred, and the duplicate qobuz hit was used up along with it
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].id, "s1");
+        assert_eq!(page.consumed["server"], 1);
+        assert_eq!(page.consumed["qobuz"], 1);
+    }
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let mut cursor = SearchCursor {
+            fingerprint: SearchCursor::fingerprint_for("daft punk", "track", &["qobuz".to_string()]),
+            ..Default::default()
+        };
+        cursor.offsets.insert("qobuz".to_string(), 40);
+
+        let decoded = SearchCursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded, cursor);
+        assert!(SearchCursor::decode("not a cursor").is_err());
+    }
+}
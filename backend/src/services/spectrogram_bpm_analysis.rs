@@ -1,28 +1,19 @@
 use anyhow::{Result, anyhow};
-use std::path::Path;
-use tokio::task;
-use hound::Error as HoundError;
-use symphonia::core::audio::AudioBufferRef;
-use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
-use symphonia::core::errors::Error as SymphoniaError;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
-use std::fs::File;
+use std::path::{Path, PathBuf};
 use spectrum_analyzer::{samples_fft_to_spectrum, FrequencyLimit};
 use spectrum_analyzer::windows::hann_window;
 use spectrum_analyzer::scaling::divide_by_N_sqrt;
-use image::{ImageBuffer, Rgb, RgbImage};
+use image::{ImageBuffer, ImageFormat, Rgb, RgbImage};
 
-// Additional imports for remote file support
-use uuid;
-use reqwest;
+use crate::services::beat_grid::{BeatGrid, BeatOnset};
+use crate::services::track_descriptors::{DescriptorFrame, TrackDescriptors};
+use crate::services::track_structure::{EnergySection, TrackStructure};
+use crate::services::audio_decode::{AudioSink, FrameWindow, ANALYSIS_SAMPLE_RATE};
+use crate::services::bpm_estimate::{BpmCandidate, BpmEstimate, DEFAULT_BPM_RANGE};
 
 // Analysis configuration for spectrogram approach
 const SPECTROGRAM_WINDOW_SIZE: usize = 4096; // Larger window for better frequency resolution
 const SPECTROGRAM_HOP_SIZE: usize = 256; // 4x more precise time resolution (was 1024)
-const SAMPLE_RATE: u32 = 44100;
 const LOW_FREQ_CUTOFF: f32 = 10.0; // Slightly higher for beat detection
 const HIGH_FREQ_CUTOFF: f32 = 2000.0; // Upper limit for beat-relevant frequencies
 const ADAPTIVE_THRESHOLD_PERCENTAGE: f32 = 0.8; // 80% of energy range: min + (max-min) * 80%
@@ -32,15 +23,29 @@ const SCORE_DEVIATION_PERCENTAGE: f32 = 0.1; // 10% deviation from max score for
 const USE_WEIGHTED_AVERAGING: bool = true; // If true, average weighted by score; if false, unweighted average
 
 // Image generation configuration
-const GENERATE_SPECTROGRAM_IMAGE: bool = true; // If true, generate spectrogram image files
-const GENERATE_ANALYSIS_VISUALIZATION: bool = true; // If true, generate analysis visualization image
-const OVERRIDE_EXISTING_IMAGES: bool = true; // If true, overwrite existing image files; if false, skip if exists
+const MAX_IMAGE_COLUMNS: usize = 16384; // Longer tracks are max-pooled over several frames per image column
+
+// Beat grid configuration
+const BASS_ONSET_FREQ: f32 = 200.0; // Beats dominated by lower frequencies (kicks) are more likely downbeats
+const BASS_ONSET_BOOST: f32 = 1.5;
+
+/// Per-frame summary of the spectrogram used for beat detection
+#[derive(Debug, Clone, Copy)]
+struct SpectrogramFrame {
+    time: f32,
+    energy: f32, // Average magnitude
+    dominant_freq: f32,
+    centroid: f32, // Spectral centroid over all frequencies
+    rms: f32, // Of the samples, for the track descriptors
+}
 
-/// Represents the full spectrogram of a song
+/// Represents the spectrogram of a song; full resolution is only kept for the frame summaries
 #[derive(Debug)]
 struct Spectrogram {
-    data: Vec<Vec<f32>>, // [time_frame][frequency_bin]
-    time_resolution: f32, // Time per frame in seconds
+    frames: Vec<SpectrogramFrame>,
+    columns: Vec<Vec<f32>>, // [image_column][frequency_bin], at most MAX_IMAGE_COLUMNS
+    frames_per_column: usize,
+    pending_column: Option<(Vec<f32>, usize)>, // Column being pooled and its frame count
     freq_resolution: f32, // Frequency per bin in Hz
     min_freq: f32,
     max_freq: f32,
@@ -50,6 +55,7 @@ struct Spectrogram {
 /// Detected beat with enhanced information
 #[derive(Debug, Clone)]
 struct SpectrogramBeat {
+    frame: usize, // Index into the spectrogram frames
     timestamp: f32,
     energy: f32,
     dominant_freq: f32, // Dominant frequency at this beat
@@ -65,12 +71,27 @@ struct AnalysisCache {
     max_energy: f32,
 }
 
-/// Audio track structure (reused from main service)
-struct AudioTrack {
-    pub samples: Vec<f32>,
-    pub sample_rate: u32,
+/// Result of a spectrogram BPM analysis including the fitted beat grid
+#[derive(Debug, Clone)]
+pub struct SpectrogramBpmResult {
+    pub bpm: Option<f32>, // None when no tempo could be determined
+    pub confidence: f32,
+    pub candidates: Vec<BpmCandidate>, // Half, double and triplet alternatives
+    pub spectrogram_path: Option<PathBuf>, // Only set when the image was requested
+    pub visualization_path: Option<PathBuf>,
+    pub beat_grid: Option<BeatGrid>,
+    pub structure: TrackStructure, // Silence, fades, intro/outro and suggested hot cues
+    pub descriptors: TrackDescriptors, // Energy, danceability, brightness, dynamic range and acousticness
+}
+
+/// Where to render the images of an analysis; nothing is rendered by default
+#[derive(Debug, Clone, Default)]
+pub struct SpectrogramImages {
+    pub spectrogram: Option<PathBuf>,
+    pub visualization: Option<PathBuf>, // Spectrogram with energy, thresholds and detected beats
 }
 
+#[derive(Default)]
 pub struct SpectrogramBpmAnalysisService;
 
 impl SpectrogramBpmAnalysisService {
@@ -78,232 +99,169 @@ impl SpectrogramBpmAnalysisService {
         Self
     }
 
-    /// Analyze BPM using full-song spectrogram approach
-    pub async fn analyze_bpm_with_spectrogram(&self, file_path: &str) -> Result<(f32, String, String)> {
-        tracing::info!("Starting spectrogram-based BPM analysis for file: {}", file_path);
-        let start_time = std::time::Instant::now();
-        let file_path_owned = file_path.to_string();
-        let file_path_for_logging = file_path_owned.clone();
-        
-        // Run the analysis in a blocking task
-        let (bpm, spectrogram_path, visualization_path) = task::spawn_blocking(move || {
-            Self::analyze_bpm_spectrogram_blocking(&file_path_owned)
-        }).await??;
-
-        let analysis_duration = start_time.elapsed();
-        tracing::info!("Spectrogram BPM analysis completed for file: {} - Result: {} BPM - Duration: {:?}", 
-                       file_path_for_logging, bpm, analysis_duration);
-
-        Ok((bpm, spectrogram_path, visualization_path))
-    }
-
-    /// Blocking spectrogram-based BPM analysis
-    fn analyze_bpm_spectrogram_blocking(file_path: &str) -> Result<(f32, String, String)> {
-        tracing::debug!("Reading audio file for spectrogram analysis: {}", file_path);
-        
-        // Check if file exists
-        if !Path::new(file_path).exists() {
-            tracing::error!("Audio file not found: {}", file_path);
-            return Err(anyhow!("Audio file not found: {}", file_path));
-        }
-
-        // Step 1: Read audio file
-        let track = Self::read_audio_file(file_path)?;
-        tracing::info!("Audio loaded - Sample rate: {} Hz, Samples: {}, Duration: {:.2}s", 
-                       track.sample_rate, track.samples.len(), 
-                       track.samples.len() as f32 / track.sample_rate as f32);
-        
-        // Step 2: Generate full-song spectrogram
-        tracing::debug!("Generating full-song spectrogram...");
-        let spectrogram = Self::generate_spectrogram(&track.samples, track.sample_rate)?;
-        tracing::info!("Spectrogram generated - Size: {}x{} (time x freq), Duration: {:.2}s", 
-                       spectrogram.data.len(), 
-                       spectrogram.data.first().map_or(0, |f| f.len()),
+    /// BPM, beat grid and images of a completed spectrogram
+    fn analyze_spectrogram(spectrogram: &Spectrogram, images: &SpectrogramImages) -> Result<SpectrogramBpmResult> {
+        tracing::info!("Spectrogram generated - Size: {}x{} (frames x freq, {} frames per image column), Duration: {:.2}s",
+                       spectrogram.frames.len(),
+                       spectrogram.columns.first().map_or(0, |f| f.len()),
+                       spectrogram.frames_per_column,
                        spectrogram.duration);
         
-        // Step 3: Save spectrogram as image
-        let spectrogram_path = Self::save_spectrogram_image(&spectrogram, file_path)?;
-        if GENERATE_SPECTROGRAM_IMAGE {
-            tracing::info!("Spectrogram image saved to: {}", spectrogram_path);
-        } else {
-            tracing::debug!("Spectrogram image generation disabled, placeholder path: {}", spectrogram_path);
+        // Step 2: Save spectrogram as image if requested
+        if let Some(path) = &images.spectrogram {
+            Self::save_spectrogram_image(spectrogram, path)?;
         }
         
-        // Step 4: Detect beats from spectrogram
+        // Step 3: Detect beats from spectrogram
         tracing::debug!("Detecting beats from spectrogram...");
-        let (beats, analysis_cache) = Self::detect_beats_from_spectrogram(&spectrogram)?;
+        let (beats, analysis_cache) = Self::detect_beats_from_spectrogram(spectrogram)?;
         tracing::info!("Beat detection completed - Found {} beats", beats.len());
         
-        if beats.len() < 3 {
-            tracing::warn!("Not enough beats detected for BPM calculation, using default");
-            // Still create visualization even with few beats
-            let visualization_path = Self::create_analysis_visualization(&beats, &spectrogram, &analysis_cache, file_path, 120.0)?;
-            return Ok((120.0, spectrogram_path, visualization_path));
-        }
-        
-        // Step 5: Calculate BPM using histogram-based interval analysis
+        // Step 4: Calculate BPM using histogram-based interval analysis, choosing between octaves
+        // with the default range; users' preferred ranges are applied to the stored candidates later
         tracing::debug!("Calculating BPM using histogram analysis...");
-        let bpm = Self::calculate_bpm_histogram(&beats)?;
-        tracing::debug!("Histogram BPM calculation result: {:.1}", bpm);
-        
-        // Step 6: Create analysis visualization
-        tracing::debug!("Creating analysis visualization...");
-        let visualization_path = Self::create_analysis_visualization(&beats, &spectrogram, &analysis_cache, file_path, bpm)?;
-        if GENERATE_ANALYSIS_VISUALIZATION {
-            tracing::info!("Analysis visualization saved to: {}", visualization_path);
-        } else {
-            tracing::debug!("Analysis visualization generation disabled, placeholder path: {}", visualization_path);
-        }
+        let estimate = match Self::calculate_bpm_histogram(&beats) {
+            Ok((tempo, intervals)) => {
+                tracing::debug!("Histogram BPM calculation result: {:.1}", tempo);
+                BpmEstimate::from_intervals(&intervals, tempo, DEFAULT_BPM_RANGE)
+            }
+            Err(e) => {
+                tracing::warn!("BPM could not be determined from {} beats: {}", beats.len(), e);
+                BpmEstimate::undetermined()
+            }
+        };
         
-        // Validate BPM range
-        let final_bpm = if bpm >= 50.0 && bpm <= 250.0 {
-            tracing::info!("Spectrogram BPM analysis successful: {:.1} BPM", bpm);
-            bpm
-        } else {
-            tracing::warn!("BPM analysis resulted in unrealistic value: {:.1}, using fallback (120 BPM)", bpm);
-            120.0
+        // Step 5: Create analysis visualization if requested
+        if let Some(path) = &images.visualization {
+            tracing::debug!("Creating analysis visualization...");
+            Self::create_analysis_visualization(&beats, spectrogram, &analysis_cache, path)?;
+        }
+        let spectrogram_path = images.spectrogram.clone();
+        let visualization_path = images.visualization.clone();
+        
+        let Some(bpm) = estimate.bpm else {
+            tracing::warn!("BPM analysis undetermined");
+            return Ok(SpectrogramBpmResult {
+                bpm: None,
+                confidence: 0.0,
+                candidates: estimate.candidates,
+                spectrogram_path,
+                visualization_path,
+                beat_grid: None,
+                structure: Self::detect_structure(spectrogram, &analysis_cache, None),
+                descriptors: Self::describe(spectrogram, &beats, None),
+            });
         };
+        tracing::info!("Spectrogram BPM analysis successful: {:.1} BPM (confidence {:.2})", bpm, estimate.confidence);
 
-        Ok((final_bpm, spectrogram_path, visualization_path))
+        // Step 6: Fit the beat grid to the detected beats
+        let onsets: Vec<BeatOnset> = beats
+            .iter()
+            .map(|beat| BeatOnset {
+                time: beat.timestamp,
+                strength: beat.energy * if beat.dominant_freq < BASS_ONSET_FREQ { BASS_ONSET_BOOST } else { 1.0 },
+            })
+            .collect();
+        let beat_grid = BeatGrid::fit(&onsets, bpm, spectrogram.duration);
+        if let Some(grid) = &beat_grid {
+            tracing::info!("Beat grid fitted - {} beats, first downbeat at {:.3}s", grid.beats.len(), grid.first_downbeat);
+        }
+
+        // Step 7: Find where the music starts and ends within the beat grid
+        let structure = Self::detect_structure(spectrogram, &analysis_cache, beat_grid.as_ref());
+        let descriptors = Self::describe(spectrogram, &beats, beat_grid.as_ref());
+
+        Ok(SpectrogramBpmResult {
+            bpm: Some(bpm),
+            confidence: estimate.confidence,
+            candidates: estimate.candidates,
+            spectrogram_path,
+            visualization_path,
+            beat_grid,
+            structure,
+            descriptors,
+        })
     }
 
-    /// Download and analyze a remote audio file with spectrogram
-    pub async fn analyze_remote_file_spectrogram(&self, url: &str) -> Result<(f32, String, String)> {
-        tracing::info!("Starting remote spectrogram analysis for URL: {}", url);
-        
-        // Create a temporary file
-        let temp_dir = std::env::temp_dir();
-        let temp_file = temp_dir.join(format!("musestruct_spectrogram_{}.tmp", uuid::Uuid::new_v4()));
-        let temp_path = temp_file.to_string_lossy().to_string();
-        
-        tracing::debug!("Created temporary file for spectrogram analysis: {}", temp_path);
-
-        tracing::debug!("Downloading file from URL: {}", url);
-        // Download the file
-        let response = reqwest::get(url).await
-            .map_err(|e| {
-                let error_msg = format!("Failed to download file from URL '{}': {}", url, e);
-                tracing::error!("{}", error_msg);
-                anyhow!(error_msg)
-            })?;
-        
-        let content_length = response.content_length();
-        tracing::debug!("Download response received - Content length: {:?}", content_length);
-        
-        if !response.status().is_success() {
-            let error_msg = format!("HTTP error when downloading file from '{}': {}", url, response.status());
-            tracing::error!("{}", error_msg);
-            return Err(anyhow!(error_msg));
-        }
-        
-        let bytes = response.bytes().await
-            .map_err(|e| {
-                let error_msg = format!("Failed to read response bytes from URL '{}': {}", url, e);
-                tracing::error!("{}", error_msg);
-                anyhow!(error_msg)
-            })?;
-        tracing::info!("File downloaded successfully - Size: {} bytes", bytes.len());
-        
-        // Write to temporary file
-        tokio::fs::write(&temp_file, bytes).await?;
-        tracing::debug!("File written to temporary location: {}", temp_path);
-
-        // Analyze the temporary file with spectrogram
-        let result = self.analyze_bpm_with_spectrogram(&temp_path).await;
-
-        // Clean up temporary file
-        match tokio::fs::remove_file(&temp_file).await {
-            Ok(_) => tracing::debug!("Temporary file cleaned up: {}", temp_path),
-            Err(e) => tracing::warn!("Failed to clean up temporary file {}: {}", temp_path, e),
-        }
+    /// Track descriptors from the frame summaries and detected beats
+    fn describe(spectrogram: &Spectrogram, beats: &[SpectrogramBeat], beat_grid: Option<&BeatGrid>) -> TrackDescriptors {
+        let frames: Vec<DescriptorFrame> = spectrogram
+            .frames
+            .iter()
+            .map(|frame| DescriptorFrame { rms: frame.rms, centroid: frame.centroid })
+            .collect();
+        let onsets: Vec<f32> = beats.iter().map(|beat| beat.timestamp).collect();
+        let descriptors = TrackDescriptors::compute(&frames, &onsets, beat_grid, spectrogram.duration);
+        tracing::info!("Track descriptors - energy {:.2}, danceability {:.2}, brightness {:.0} Hz, dynamic range {:.1} dB, acousticness {:.2}",
+                       descriptors.energy, descriptors.danceability, descriptors.brightness,
+                       descriptors.dynamic_range, descriptors.acousticness);
+        descriptors
+    }
 
-        result
+    /// Silence, fades, intro/outro and hot cues from the energy sections of the beat detection
+    fn detect_structure(spectrogram: &Spectrogram, analysis_cache: &AnalysisCache, beat_grid: Option<&BeatGrid>) -> TrackStructure {
+        let frames: Vec<(f32, f32)> = analysis_cache
+            .frame_energies
+            .iter()
+            .map(|&(_, timestamp, energy)| (timestamp, energy))
+            .collect();
+        let sections: Vec<EnergySection> = analysis_cache
+            .section_thresholds
+            .iter()
+            .map(|&(section_start, section_end, threshold)| EnergySection {
+                start: spectrogram.frames[section_start].time,
+                end: spectrogram.frames.get(section_end).map_or(spectrogram.duration, |frame| frame.time),
+                level: threshold,
+            })
+            .collect();
+        let structure = TrackStructure::detect(&frames, &sections, beat_grid, spectrogram.duration);
+        tracing::info!("Track structure - audio {:.2}s to {:.2}s, intro {:?} bars, outro {:?} bars, {} hot cues",
+                       structure.audio_start, structure.audio_end,
+                       structure.intro.map(|intro| intro.bars), structure.outro.map(|outro| outro.bars),
+                       structure.hot_cues.len());
+        structure
     }
 
-    /// Generate full-song spectrogram
-    fn generate_spectrogram(samples: &[f32], sample_rate: u32) -> Result<Spectrogram> {
-        let mut spectrogram_data = Vec::new();
-        let time_resolution = SPECTROGRAM_HOP_SIZE as f32 / sample_rate as f32;
-        let freq_resolution = sample_rate as f32 / SPECTROGRAM_WINDOW_SIZE as f32;
-        let duration = samples.len() as f32 / sample_rate as f32;
+    /// Magnitudes of one analysis frame in the beat-relevant frequency range, and its spectral centroid
+    fn frame_spectrum(samples: &[f32], sample_rate: u32) -> Option<(Vec<f32>, f32)> {
+        // Apply Hann window
+        let windowed_samples = hann_window(samples);
         
-        // Process entire song in windows
-        for window_start in (0..samples.len()).step_by(SPECTROGRAM_HOP_SIZE) {
-            let window_end = (window_start + SPECTROGRAM_WINDOW_SIZE).min(samples.len());
-            if window_end - window_start < SPECTROGRAM_WINDOW_SIZE / 2 {
-                break; // Skip incomplete windows at the end
+        // Calculate spectrum
+        let spectrum_result = samples_fft_to_spectrum(
+            &windowed_samples,
+            sample_rate,
+            FrequencyLimit::All,
+            Some(&divide_by_N_sqrt),
+        );
+        
+        match spectrum_result {
+            // Extract magnitude data
+            Ok(spectrum) => {
+                let (weighted, total) = spectrum
+                    .data()
+                    .iter()
+                    .fold((0.0, 0.0), |(weighted, total), (freq, magnitude)| (weighted + freq.val() * magnitude.val(), total + magnitude.val()));
+                let centroid = if total > 0.0 { weighted / total } else { 0.0 };
+                let magnitudes = spectrum
+                    .data()
+                    .iter()
+                    .filter(|(freq, _)| (LOW_FREQ_CUTOFF..=HIGH_FREQ_CUTOFF).contains(&freq.val()))
+                    .map(|(_, magnitude)| magnitude.val())
+                    .collect();
+                Some((magnitudes, centroid))
             }
-            
-            // Extract and pad window
-            let mut window_samples = samples[window_start..window_end].to_vec();
-            window_samples.resize(SPECTROGRAM_WINDOW_SIZE, 0.0);
-            
-            // Apply Hann window
-            let windowed_samples = hann_window(&window_samples);
-            
-            // Calculate spectrum
-            let spectrum_result = samples_fft_to_spectrum(
-                &windowed_samples,
-                sample_rate,
-                FrequencyLimit::Range(LOW_FREQ_CUTOFF, HIGH_FREQ_CUTOFF),
-                Some(&divide_by_N_sqrt),
-            );
-            
-            match spectrum_result {
-                Ok(spectrum) => {
-                    // Extract magnitude data
-                    let magnitudes: Vec<f32> = spectrum
-                        .data()
-                        .iter()
-                        .map(|(_, magnitude)| magnitude.val())
-                        .collect();
-                    
-                    spectrogram_data.push(magnitudes);
-                }
-                Err(e) => {
-                    tracing::debug!("FFT failed for window starting at {}: {}", window_start, e);
-                    continue;
-                }
+            Err(e) => {
+                tracing::debug!("FFT failed for spectrogram window: {}", e);
+                None
             }
         }
-        
-        if spectrogram_data.is_empty() {
-            return Err(anyhow!("Failed to generate spectrogram data"));
-        }
-        
-        Ok(Spectrogram {
-            data: spectrogram_data,
-            time_resolution,
-            freq_resolution,
-            min_freq: LOW_FREQ_CUTOFF,
-            max_freq: HIGH_FREQ_CUTOFF,
-            duration,
-        })
     }
 
     /// Save spectrogram as an image file
-    fn save_spectrogram_image(spectrogram: &Spectrogram, original_file_path: &str) -> Result<String> {
-        // Generate output path first
-        let original_path = Path::new(original_file_path);
-        let file_stem = original_path.file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("unknown");
-        
-        let output_path = format!("./cache/spectrograms/spectrogram_{}.png", file_stem);
-        
-        // Check if we should skip generation
-        if !GENERATE_SPECTROGRAM_IMAGE {
-            tracing::debug!("Spectrogram image generation disabled, returning placeholder path");
-            return Ok(output_path);
-        }
-        
-        // Check if file exists and we shouldn't override
-        if !OVERRIDE_EXISTING_IMAGES && Path::new(&output_path).exists() {
-            tracing::info!("Spectrogram image already exists and override disabled: {}", output_path);
-            return Ok(output_path);
-        }
-        let width = spectrogram.data.len();
-        let height = spectrogram.data.first().map_or(0, |f| f.len());
+    fn save_spectrogram_image(spectrogram: &Spectrogram, output_path: &Path) -> Result<()> {
+        let width = spectrogram.columns.len();
+        let height = spectrogram.columns.first().map_or(0, |f| f.len());
         
         if width == 0 || height == 0 {
             return Err(anyhow!("Invalid spectrogram dimensions"));
@@ -316,7 +274,7 @@ impl SpectrogramBpmAnalysisService {
         let mut min_val = f32::INFINITY;
         let mut max_val = f32::NEG_INFINITY;
         
-        for frame in &spectrogram.data {
+        for frame in &spectrogram.columns {
             for &magnitude in frame {
                 min_val = min_val.min(magnitude);
                 max_val = max_val.max(magnitude);
@@ -329,7 +287,7 @@ impl SpectrogramBpmAnalysisService {
         }
         
         // Generate image with color mapping (blue to red via green)
-        for (x, frame) in spectrogram.data.iter().enumerate() {
+        for (x, frame) in spectrogram.columns.iter().enumerate() {
             for (y, &magnitude) in frame.iter().enumerate() {
                 // Normalize to 0-1 range
                 let normalized = (magnitude - min_val) / range;
@@ -353,17 +311,12 @@ impl SpectrogramBpmAnalysisService {
             }
         }
         
-        // Create directory if it doesn't exist
-        if let Some(parent) = Path::new(&output_path).parent() {
-            std::fs::create_dir_all(parent)?;
-        }
-        
         // Save image
-        img.save(&output_path)
+        Self::save_png(&img, output_path)
             .map_err(|e| anyhow!("Failed to save spectrogram image: {}", e))?;
         
-        tracing::info!("Spectrogram image saved: {} ({}x{} pixels)", output_path, width, height);
-        Ok(output_path)
+        tracing::info!("Spectrogram image saved: {:?} ({}x{} pixels)", output_path, width, height);
+        Ok(())
     }
 
     /// Create analysis visualization showing spectrogram with overlaid beats and analysis data
@@ -371,31 +324,12 @@ impl SpectrogramBpmAnalysisService {
         beats: &[SpectrogramBeat], 
         spectrogram: &Spectrogram, 
         analysis_cache: &AnalysisCache,
-        original_file_path: &str, 
-        detected_bpm: f32
-    ) -> Result<String> {
-        // Generate output path first
-        let original_path = Path::new(original_file_path);
-        let file_stem = original_path.file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("unknown");
-        
-        let output_path = format!("./cache/spectrograms/analysis_{}_{}bpm.png", file_stem, detected_bpm as u32);
-        
-        // Check if we should skip generation
-        if !GENERATE_ANALYSIS_VISUALIZATION {
-            tracing::debug!("Analysis visualization generation disabled, returning placeholder path");
-            return Ok(output_path);
-        }
-        
-        // Check if file exists and we shouldn't override
-        if !OVERRIDE_EXISTING_IMAGES && Path::new(&output_path).exists() {
-            tracing::info!("Analysis visualization already exists and override disabled: {}", output_path);
-            return Ok(output_path);
-        }
+        output_path: &Path,
+    ) -> Result<()> {
         // Use actual spectrogram dimensions (same as normal spectrogram)
-        let img_width = spectrogram.data.len() as u32;
-        let img_height = spectrogram.data.first().map_or(0, |f| f.len()) as u32;
+        let img_width = spectrogram.columns.len() as u32;
+        let img_height = spectrogram.columns.first().map_or(0, |f| f.len()) as u32;
+        let column = |frame_idx: usize| (frame_idx / spectrogram.frames_per_column) as u32;
         
         if img_width == 0 || img_height == 0 {
             return Err(anyhow!("Invalid spectrogram dimensions for analysis visualization"));
@@ -412,7 +346,7 @@ impl SpectrogramBpmAnalysisService {
         let mut min_val = f32::INFINITY;
         let mut max_val = f32::NEG_INFINITY;
         
-        for frame in &spectrogram.data {
+        for frame in &spectrogram.columns {
             for &magnitude in frame {
                 min_val = min_val.min(magnitude);
                 max_val = max_val.max(magnitude);
@@ -422,7 +356,7 @@ impl SpectrogramBpmAnalysisService {
         let range = max_val - min_val;
         if range > 0.0 {
             // Draw spectrogram using direct pixel mapping (same as normal spectrogram)
-            for (x, frame) in spectrogram.data.iter().enumerate() {
+            for (x, frame) in spectrogram.columns.iter().enumerate() {
                 for (y, &magnitude) in frame.iter().enumerate() {
                     // Normalize and apply logarithmic scaling
                     let normalized = (magnitude - min_val) / range;
@@ -453,7 +387,7 @@ impl SpectrogramBpmAnalysisService {
             if max_energy > 0.0 {
                 // 1. Draw energy plot as white pixels
                 for (frame_idx, _, energy) in frame_energies {
-                    let x = *frame_idx as u32;
+                    let x = column(*frame_idx);
                     if x < img_width {
                         let normalized_energy = energy / max_energy;
                         let y = ((1.0 - normalized_energy) * (img_height - 1) as f32) as u32;
@@ -478,7 +412,7 @@ impl SpectrogramBpmAnalysisService {
                     let threshold_y = ((1.0 - normalized_threshold) * (img_height - 1) as f32) as u32;
                     
                     if threshold_y < img_height {
-                        for x in section_start as u32..section_end as u32 {
+                        for x in column(section_start)..column(section_end) {
                             if x < img_width {
                                 img.put_pixel(x, threshold_y, Rgb([128, 128, 128])); // Gray for threshold
                             }
@@ -493,8 +427,8 @@ impl SpectrogramBpmAnalysisService {
                     }
                     
                     // Find cluster bounds
-                    let cluster_start_frame = group.first().unwrap().0 as u32;
-                    let cluster_end_frame = group.last().unwrap().0 as u32;
+                    let cluster_start_frame = column(group.first().unwrap().0);
+                    let cluster_end_frame = column(group.last().unwrap().0);
                     
                     // Find min/max energy in cluster for box height
                     let mut min_cluster_energy = f32::INFINITY;
@@ -534,11 +468,8 @@ impl SpectrogramBpmAnalysisService {
             
             // Overlay detected beats as vertical lines
             if !beats.is_empty() {
-                let duration = spectrogram.duration;
-                
                 for beat in beats {
-                    let relative_time = beat.timestamp / duration;
-                    let x = (relative_time * img_width as f32) as u32;
+                    let x = column(beat.frame);
                     
                     // Draw bright vertical line for beat
                     for y in 0..img_height {
@@ -551,18 +482,24 @@ impl SpectrogramBpmAnalysisService {
             }
         }
         
-        // Create directory if it doesn't exist
-        if let Some(parent) = Path::new(&output_path).parent() {
-            std::fs::create_dir_all(parent)?;
-        }
-        
         // Save image
-        img.save(&output_path)
+        Self::save_png(&img, output_path)
             .map_err(|e| anyhow!("Failed to save analysis visualization: {}", e))?;
         
-        tracing::info!("Analysis visualization saved: {} ({}x{} pixels, {} beats)", 
+        tracing::info!("Analysis visualization saved: {:?} ({}x{} pixels, {} beats)", 
                        output_path, img_width, img_height, beats.len());
-        Ok(output_path)
+        Ok(())
+    }
+
+    /// Write a PNG next to its destination first, so readers never see a partial image
+    fn save_png(img: &RgbImage, output_path: &Path) -> Result<()> {
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let partial_path = output_path.with_extension("png.partial");
+        img.save_with_format(&partial_path, ImageFormat::Png)?;
+        std::fs::rename(&partial_path, output_path)?;
+        Ok(())
     }
 
     /// Detect beats from spectrogram using adaptive clustering approach
@@ -572,21 +509,10 @@ impl SpectrogramBpmAnalysisService {
         let mut frame_energies_for_cache = Vec::new();
         let mut max_energy = 0.0f32;
         
-        for (frame_idx, frame_data) in spectrogram.data.iter().enumerate() {
-            let timestamp = frame_idx as f32 * spectrogram.time_resolution;
-            let total_energy: f32 = frame_data.iter().sum();
-            let avg_energy = total_energy / frame_data.len() as f32;
+        for (frame_idx, frame) in spectrogram.frames.iter().enumerate() {
+            let SpectrogramFrame { time: timestamp, energy: avg_energy, dominant_freq, .. } = *frame;
             max_energy = max_energy.max(avg_energy);
             
-            // Find dominant frequency
-            let dominant_freq_idx = frame_data
-                .iter()
-                .enumerate()
-                .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(std::cmp::Ordering::Equal))
-                .map(|(idx, _)| idx)
-                .unwrap_or(0);
-            let dominant_freq = spectrogram.min_freq + (dominant_freq_idx as f32 * spectrogram.freq_resolution);
-            
             frame_energies.push((frame_idx, timestamp, avg_energy, dominant_freq));
             frame_energies_for_cache.push((frame_idx, timestamp, avg_energy));
         }
@@ -683,10 +609,11 @@ impl SpectrogramBpmAnalysisService {
                 }
             }
             
-            let (_, timestamp, energy, dominant_freq, threshold) = *best_frame;
+            let (frame, timestamp, energy, dominant_freq, threshold) = *best_frame;
             let confidence = (energy / threshold).min(2.0); // Confidence based on how much above threshold
             
             beats.push(SpectrogramBeat {
+                frame,
                 timestamp,
                 energy,
                 dominant_freq,
@@ -729,8 +656,8 @@ impl SpectrogramBpmAnalysisService {
         debounced
     }
 
-    /// Calculate BPM using histogram-based interval analysis
-    fn calculate_bpm_histogram(beats: &[SpectrogramBeat]) -> Result<f32> {
+    /// Calculate BPM using histogram-based interval analysis; also returns the intervals used
+    fn calculate_bpm_histogram(beats: &[SpectrogramBeat]) -> Result<(f32, Vec<f32>)> {
         if beats.len() < 3 {
             return Err(anyhow!("Not enough beats for histogram BPM calculation"));
         }
@@ -849,279 +776,121 @@ impl SpectrogramBpmAnalysisService {
                                if included { "[INCLUDED]" } else { "" });
             }
             
-            // Check for subdivision patterns
-            if averaged_bpm > 160.0 {
-                let half_bpm = averaged_bpm / 2.0;
-                let third_bpm = averaged_bpm / 3.0;
-                
-                if half_bpm >= 80.0 && half_bpm <= 160.0 {
-                    tracing::info!("Using half-time subdivision: {:.1} BPM", half_bpm);
-                    return Ok(half_bpm);
-                } else if third_bpm >= 80.0 && third_bpm <= 160.0 {
-                    tracing::info!("Using third-time subdivision: {:.1} BPM", third_bpm);
-                    return Ok(third_bpm);
-                }
-            }
-            
-            Ok(averaged_bpm)
+            Ok((averaged_bpm, filtered_intervals))
         } else {
             Err(anyhow!("No peaks found in histogram analysis"))
         }
     }
+}
 
-    /// Read audio file (reused from main service with minor modifications)
-    fn read_audio_file(file_path: &str) -> Result<AudioTrack> {
-        // Remove file:// prefix if present
-        let clean_path = if file_path.starts_with("file://") {
-            &file_path[7..]
-        } else {
-            file_path
-        };
-
-        let path = Path::new(clean_path);
-        let extension = path.extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("unknown")
-            .to_lowercase();
-
-        tracing::debug!("Reading audio file for spectrogram: {} (format: {})", clean_path, extension);
-
-        // Try symphonia first, fallback to hound for WAV
-        match Self::read_with_symphonia(clean_path) {
-            Ok(track) => {
-                tracing::info!("Successfully read audio file with symphonia: {}", clean_path);
-                Ok(track)
-            },
-            Err(symphonia_err) => {
-                tracing::debug!("Symphonia failed: {}", symphonia_err);
-                
-                if extension == "wav" {
-                    tracing::debug!("Falling back to hound for WAV file");
-                    Self::read_wav_with_hound(clean_path)
-                } else {
-                    Err(anyhow!(
-                        "Failed to read audio file '{}': {}",
-                        clean_path, symphonia_err
-                    ))
-                }
-            }
+impl Spectrogram {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            frames: Vec::new(),
+            columns: Vec::new(),
+            frames_per_column: 1,
+            pending_column: None,
+            freq_resolution: sample_rate as f32 / SPECTROGRAM_WINDOW_SIZE as f32,
+            min_freq: LOW_FREQ_CUTOFF,
+            max_freq: HIGH_FREQ_CUTOFF,
+            duration: 0.0,
         }
     }
 
-    /// Read with symphonia (simplified version)
-    fn read_with_symphonia(file_path: &str) -> Result<AudioTrack> {
-        let file = File::open(file_path)
-            .map_err(|e| anyhow!("Failed to open file '{}': {}", file_path, e))?;
-        
-        let mss = MediaSourceStream::new(Box::new(file), Default::default());
-
-        let mut hint = Hint::new();
-        if let Some(extension) = Path::new(file_path).extension() {
-            if let Some(ext_str) = extension.to_str() {
-                hint.with_extension(ext_str);
-            }
-        }
-
-        let meta_opts: MetadataOptions = Default::default();
-        let fmt_opts: FormatOptions = Default::default();
-
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &fmt_opts, &meta_opts)
-            .map_err(|e| anyhow!("Failed to probe audio format: {}", e))?;
-
-        let mut format = probed.format;
-
-        let track = format
-            .tracks()
+    /// Summarize a frame for beat detection and pool it into the image columns
+    fn push_frame(&mut self, magnitudes: Vec<f32>, time: f32, centroid: f32, rms: f32) {
+        let energy = magnitudes.iter().sum::<f32>() / magnitudes.len() as f32;
+        let dominant_freq_idx = magnitudes
             .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .ok_or_else(|| anyhow!("No supported audio tracks found"))?;
-
-        let dec_opts: DecoderOptions = Default::default();
-        let mut decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &dec_opts)
-            .map_err(|e| anyhow!("Failed to create decoder: {}", e))?;
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(idx, _)| idx)
+            .unwrap_or(0);
+        let dominant_freq = self.min_freq + (dominant_freq_idx as f32 * self.freq_resolution);
+        self.frames.push(SpectrogramFrame { time, energy, dominant_freq, centroid, rms });
 
-        let track_id = track.id;
-        let sample_rate = track.codec_params.sample_rate
-            .ok_or_else(|| anyhow!("Sample rate not found in track"))?;
-
-        let mut samples = Vec::new();
-        let mut channel_count = None;
+        let (column, count) = self.pending_column.get_or_insert_with(|| (vec![0.0; magnitudes.len()], 0));
+        for (pooled, magnitude) in column.iter_mut().zip(magnitudes) {
+            *pooled = pooled.max(magnitude);
+        }
+        *count += 1;
+        if *count == self.frames_per_column {
+            self.flush_column();
+        }
+    }
 
-        loop {
-            let packet = match format.next_packet() {
-                Ok(packet) => packet,
-                Err(SymphoniaError::ResetRequired) => {
-                    return Err(anyhow!("Track list changed during decoding"));
-                }
-                Err(SymphoniaError::IoError(err)) => {
-                    if err.kind() == std::io::ErrorKind::UnexpectedEof {
-                        break;
-                    } else {
-                        return Err(anyhow!("IO error during decoding: {}", err));
-                    }
-                }
-                Err(err) => {
-                    return Err(anyhow!("Decoding error: {}", err));
-                }
-            };
+    fn flush_column(&mut self) {
+        let Some((column, _)) = self.pending_column.take() else {
+            return;
+        };
+        self.columns.push(column);
+
+        // Halve the image resolution instead of growing past the column limit
+        if self.columns.len() == MAX_IMAGE_COLUMNS {
+            self.columns = self
+                .columns
+                .chunks(2)
+                .map(|pair| pair[0].iter().zip(&pair[1]).map(|(a, b)| a.max(*b)).collect())
+                .collect();
+            self.frames_per_column *= 2;
+        }
+    }
+}
 
-            while !format.metadata().is_latest() {
-                format.metadata().pop();
-            }
+/// Streaming spectrogram generation for the BPM analysis
+pub struct SpectrogramBpmAnalyzer {
+    window: FrameWindow,
+    spectrogram: Spectrogram,
+    images: SpectrogramImages,
+}
 
-            if packet.track_id() != track_id {
-                continue;
-            }
+impl Default for SpectrogramBpmAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            match decoder.decode(&packet) {
-                Ok(decoded) => {
-                    if channel_count.is_none() {
-                        channel_count = Some(decoded.spec().channels.count());
-                    }
+impl SpectrogramBpmAnalyzer {
+    pub fn new() -> Self {
+        Self::with_images(SpectrogramImages::default())
+    }
 
-                    match decoded {
-                        AudioBufferRef::F32(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend_from_slice(plane);
-                            }
-                        }
-                        AudioBufferRef::U8(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| (s as f32 - 128.0) / 128.0));
-                            }
-                        }
-                        AudioBufferRef::U16(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| (s as f32 - 32768.0) / 32768.0));
-                            }
-                        }
-                        AudioBufferRef::U24(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| (s.into_u32() as f32 - 8388608.0) / 8388608.0));
-                            }
-                        }
-                        AudioBufferRef::U32(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| (s as f32 - 2147483648.0) / 2147483648.0));
-                            }
-                        }
-                        AudioBufferRef::S8(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| s as f32 / 128.0));
-                            }
-                        }
-                        AudioBufferRef::S16(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| s as f32 / 32768.0));
-                            }
-                        }
-                        AudioBufferRef::S24(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| s.inner() as f32 / 8388608.0));
-                            }
-                        }
-                        AudioBufferRef::S32(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| s as f32 / 2147483648.0));
-                            }
-                        }
-                        AudioBufferRef::F64(buf) => {
-                            for plane in buf.planes().planes() {
-                                samples.extend(plane.iter().map(|&s| s as f32));
-                            }
-                        }
-                    }
-                }
-                Err(SymphoniaError::IoError(_)) => continue,
-                Err(SymphoniaError::DecodeError(_)) => continue,
-                Err(err) => {
-                    return Err(anyhow!("Decode error: {}", err));
-                }
-            }
+    pub fn with_images(images: SpectrogramImages) -> Self {
+        Self {
+            window: FrameWindow::new(SPECTROGRAM_WINDOW_SIZE, SPECTROGRAM_HOP_SIZE, ANALYSIS_SAMPLE_RATE),
+            spectrogram: Spectrogram::new(ANALYSIS_SAMPLE_RATE),
+            images,
         }
+    }
 
-        if samples.is_empty() {
-            return Err(anyhow!("No audio samples decoded"));
+    /// Detect beats, estimate the BPM and fit the beat grid over all audio pushed
+    pub fn finish(mut self) -> Result<SpectrogramBpmResult> {
+        self.window.finish(collect_frames(&mut self.spectrogram));
+        self.spectrogram.flush_column();
+        if self.spectrogram.frames.is_empty() {
+            return Err(anyhow!("Failed to generate spectrogram data"));
         }
+        SpectrogramBpmAnalysisService::analyze_spectrogram(&self.spectrogram, &self.images)
+    }
+}
 
-        // Convert to mono
-        let mono_samples = if let Some(channels) = channel_count {
-            if channels == 1 {
-                samples
-            } else {
-                samples
-                    .chunks(channels)
-                    .map(|chunk| chunk.iter().sum::<f32>() / chunk.len() as f32)
-                    .collect()
-            }
-        } else {
-            samples
-        };
+fn collect_frames(spectrogram: &mut Spectrogram) -> impl FnMut(&[f32], f64) + '_ {
+    move |samples, time| {
+        if let Some((magnitudes, centroid)) = SpectrogramBpmAnalysisService::frame_spectrum(samples, ANALYSIS_SAMPLE_RATE) {
+            let rms = (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt();
+            spectrogram.push_frame(magnitudes, time as f32, centroid, rms);
+        }
+    }
+}
 
-        Ok(AudioTrack {
-            samples: mono_samples,
-            sample_rate,
-        })
+impl AudioSink for SpectrogramBpmAnalyzer {
+    fn start_span(&mut self, time: f64) {
+        self.window.start_span(time, collect_frames(&mut self.spectrogram));
     }
 
-    /// Read WAV with hound (simplified version)
-    fn read_wav_with_hound(file_path: &str) -> Result<AudioTrack> {
-        let mut reader = hound::WavReader::open(file_path)
-            .map_err(|e| anyhow!("Failed to open WAV file '{}': {}", file_path, e))?;
-        
-        let spec = reader.spec();
-        
-        let samples: Vec<f32> = match spec.sample_format {
-            hound::SampleFormat::Int => {
-                match spec.bits_per_sample {
-                    16 => {
-                        reader
-                            .samples::<i16>()
-                            .map(|s| s.map(|sample| sample as f32 / i16::MAX as f32))
-                            .collect::<Result<Vec<f32>, HoundError>>()
-                            .map_err(|e| anyhow!("Failed to read 16-bit samples: {}", e))?
-                    }
-                    24 => {
-                        reader
-                            .samples::<i32>()
-                            .map(|s| s.map(|sample| sample as f32 / (1 << 23) as f32))
-                            .collect::<Result<Vec<f32>, HoundError>>()
-                            .map_err(|e| anyhow!("Failed to read 24-bit samples: {}", e))?
-                    }
-                    32 => {
-                        reader
-                            .samples::<i32>()
-                            .map(|s| s.map(|sample| sample as f32 / i32::MAX as f32))
-                            .collect::<Result<Vec<f32>, HoundError>>()
-                            .map_err(|e| anyhow!("Failed to read 32-bit samples: {}", e))?
-                    }
-                    _ => return Err(anyhow!("Unsupported bit depth: {} bits", spec.bits_per_sample)),
-                }
-            }
-            hound::SampleFormat::Float => {
-                reader
-                    .samples::<f32>()
-                    .collect::<Result<Vec<f32>, HoundError>>()
-                    .map_err(|e| anyhow!("Failed to read float samples: {}", e))?
-            }
-        };
-        
-        // Convert to mono
-        let mono_samples = if spec.channels == 1 {
-            samples
-        } else {
-            samples
-                .chunks(spec.channels as usize)
-                .map(|chunk| chunk.iter().sum::<f32>() / chunk.len() as f32)
-                .collect()
-        };
-        
-        Ok(AudioTrack {
-            samples: mono_samples,
-            sample_rate: spec.sample_rate,
-        })
+    fn push_mono(&mut self, samples: &[f32]) {
+        self.window.push(samples, collect_frames(&mut self.spectrogram));
+        self.spectrogram.duration = self.window.end_time() as f32;
     }
 }
 
@@ -12,6 +12,9 @@ use std::fs::File;
 use id3::{Tag, TagLike};
 use sha2::{Sha256, Digest};
 
+use super::local_index::LocalLibraryIndex;
+use crate::services::search_query::{TrackAnalysisInfo, TrackQuery};
+use crate::services::track_analysis_store::TrackAnalysisStore;
 use super::{StreamingService, SearchResults, StreamingTrack, StreamingAlbum, StreamingPlaylist, ServiceCredentials, AuthResult};
 
 #[derive(Debug, Clone)]
@@ -23,12 +26,30 @@ struct TrackMetadata {
     pub cover_url: Option<String>,
     pub track_number: Option<u32>,
     pub year: Option<u32>,
+    pub isrc: Option<String>,
 }
 
+/// The directory local library tracks are served from
+pub fn default_music_dir() -> PathBuf {
+    std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")).join("own_music")
+}
+
+/// The canonical path of a file inside `music_dir`; None for anything outside it, including
+/// paths escaping it with `..` or symlinks, and for files that do not exist
+pub fn confine_to_music_dir(music_dir: &Path, path: &Path) -> Option<PathBuf> {
+    let canonical_music_dir = music_dir.canonicalize().ok()?;
+    let canonical_file = path.canonicalize().ok()?;
+    (canonical_file.starts_with(&canonical_music_dir) && canonical_file.is_file()).then_some(canonical_file)
+}
+
+// Track analyses looked up per query, keeping the statement's parameter count bounded
+const ANALYSIS_LOOKUP_CHUNK: usize = 1000;
+
 #[derive(Debug, Clone)]
 pub struct LocalMusicService {
     music_dir: PathBuf,
     cache_dir: PathBuf,
+    db: Option<sea_orm::DatabaseConnection>, // Analysis results for the analysis-based filters
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +63,7 @@ pub struct LocalTrack {
     pub cover_url: Option<String>,
     pub track_number: Option<u32>,
     pub year: Option<u32>,
+    pub isrc: Option<String>,
 }
 
 impl LocalMusicService {
@@ -59,6 +81,96 @@ impl LocalMusicService {
         Self { 
             music_dir,
             cache_dir,
+            db: None,
+        }
+    }
+
+    /// Look up analysis results so searches can filter on BPM, key and descriptors
+    pub fn with_analysis(mut self, db: sea_orm::DatabaseConnection) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Analysis results of the tracks passing the query's other filters, by track id.
+    /// Nothing is loaded unless the query filters on analysis results.
+    async fn analysis_for(
+        &self,
+        index: &tokio::sync::RwLock<LocalLibraryIndex>,
+        query: &TrackQuery,
+    ) -> Result<HashMap<String, TrackAnalysisInfo>> {
+        let Some(db) = self.db.as_ref().filter(|_| query.needs_analysis()) else {
+            return Ok(HashMap::new());
+        };
+        let keys: Vec<_> = index
+            .read()
+            .await
+            .filtered_track_ids(&query.without_analysis_filters())
+            .into_iter()
+            .map(|track_id| ("server".to_string(), track_id))
+            .collect();
+
+        let mut analysis = HashMap::new();
+        for chunk in keys.chunks(ANALYSIS_LOOKUP_CHUNK) {
+            let analyses = TrackAnalysisStore::find_many(db, chunk).await?;
+            analysis.extend(analyses.into_iter().map(|((_, track_id), analysis)| (track_id, TrackAnalysisInfo::from(&analysis))));
+        }
+        Ok(analysis)
+    }
+
+    /// Bring the shared search index up to date, re-reading only new or modified files
+    async fn refreshed_index(&self) -> Result<std::sync::Arc<tokio::sync::RwLock<LocalLibraryIndex>>, String> {
+        let index = LocalLibraryIndex::shared(&self.music_dir);
+        if !index.read().await.needs_refresh() {
+            return Ok(index);
+        }
+
+        if !self.music_dir.exists() {
+            fs::create_dir_all(&self.music_dir).await
+                .map_err(|e| format!("Failed to create music directory: {}", e))?;
+        }
+
+        let mut guard = index.write().await;
+        // Another request may have refreshed while we waited for the lock
+        if guard.needs_refresh() {
+            let music_dir = self.music_dir.clone();
+            let files = tokio::task::spawn_blocking(move || LocalLibraryIndex::list_audio_files(&music_dir))
+                .await
+                .map_err(|e| format!("Failed to scan music directory: {}", e))?;
+            let known = guard.track_count();
+            let stale = guard.stale_files(&files);
+            let changed = !stale.is_empty() || guard.track_count() != known;
+
+            for file in &stale {
+                let track = self.read_local_track(&file.path).await;
+                guard.upsert(file, track);
+            }
+            guard.finish_refresh(changed);
+        }
+        drop(guard);
+
+        Ok(index)
+    }
+
+    async fn read_local_track(&self, path: &Path) -> LocalTrack {
+        let file_name = path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        // Extract metadata from file tags and directory structure
+        let metadata = self.extract_metadata(path).await;
+
+        LocalTrack {
+            file_path: path.to_path_buf(),
+            title: metadata.title,
+            artist: metadata.artist,
+            album: metadata.album,
+            duration: metadata.duration,
+            file_name,
+            cover_url: metadata.cover_url,
+            track_number: metadata.track_number,
+            year: metadata.year,
+            isrc: metadata.isrc,
         }
     }
 
@@ -90,29 +202,8 @@ impl LocalMusicService {
                 let path = entry.path();
                 
                 if path.is_file() {
-                    if let Some(extension) = path.extension() {
-                        let ext = extension.to_string_lossy().to_lowercase();
-                        if matches!(ext.as_str(), "mp3" | "flac" | "wav" | "m4a" | "ogg") {
-                            let file_name = path.file_name()
-                                .unwrap_or_default()
-                                .to_string_lossy()
-                                .to_string();
-                            
-                            // Extract metadata from file tags and directory structure
-                            let metadata = self.extract_metadata(&path).await;
-                            
-                            tracks.push(LocalTrack {
-                                file_path: path.clone(),
-                                title: metadata.title,
-                                artist: metadata.artist,
-                                album: metadata.album,
-                                duration: metadata.duration,
-                                file_name,
-                                cover_url: metadata.cover_url,
-                                track_number: metadata.track_number,
-                                year: metadata.year,
-                            });
-                        }
+                    if LocalLibraryIndex::is_audio_file(&path) {
+                        tracks.push(self.read_local_track(&path).await);
                     }
                 } else if path.is_dir() {
                     // Recursively scan subdirectories
@@ -159,6 +250,10 @@ impl LocalMusicService {
         let album = tag.album().map(|s| s.to_string());
         let track_number = tag.track();
         let year = tag.year();
+        let isrc = tag.get("TSRC")
+            .and_then(|frame| frame.content().text())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty());
         
         println!("ID3 metadata - title: {:?}, artist: {:?}, album: {:?}, track: {:?}, year: {:?}", 
                  title, artist, album, track_number, year);
@@ -188,6 +283,7 @@ impl LocalMusicService {
             cover_url,
             track_number,
             year: year.map(|y| y as u32), // Convert i32 to u32
+            isrc,
         };
         
         println!("Final ID3 metadata - title: '{}', artist: '{}', album: '{}'", 
@@ -397,6 +493,29 @@ impl LocalMusicService {
         None
     }
     
+    /// Read the ISRC from container tags (Vorbis comments, MP4 atoms, ...) using Symphonia
+    fn extract_isrc_with_symphonia(&self, file_path: &std::path::Path) -> Option<String> {
+        let file = File::open(file_path).ok()?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+        let mut hint = Hint::new();
+        if let Some(ext_str) = file_path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext_str);
+        }
+
+        let mut probed = symphonia::default::get_probe()
+            .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+            .ok()?;
+
+        let metadata_rev = probed.format.metadata().current().cloned()
+            .or_else(|| probed.metadata.get().and_then(|m| m.current().cloned()))?;
+
+        metadata_rev.tags().iter()
+            .find(|tag| matches!(tag.std_key, Some(StandardTagKey::IdentIsrc)))
+            .map(|tag| tag.value.to_string().trim().to_string())
+            .filter(|isrc| !isrc.is_empty())
+    }
+
     /// Detect image format from raw bytes
     fn detect_image_format(&self, data: &[u8]) -> &'static str {
         if data.len() < 12 {
@@ -489,6 +608,7 @@ impl LocalMusicService {
             cover_url: self.find_cover_image(file_path),
             track_number: None,
             year: None,
+            isrc: self.extract_isrc_with_symphonia(file_path),
         }
     }
 
@@ -573,21 +693,8 @@ impl LocalMusicService {
         }
     }
 
-    fn search_tracks(&self, tracks: &[LocalTrack], query: &str) -> Vec<StreamingTrack> {
-        let query_lower = query.to_lowercase();
-        
+    fn to_streaming_tracks(&self, tracks: &[LocalTrack]) -> Vec<StreamingTrack> {
         tracks.iter()
-            .filter(|track| {
-                // If query is empty, return all tracks
-                if query.trim().is_empty() {
-                    true
-                } else {
-                    track.title.to_lowercase().contains(&query_lower) ||
-                    track.artist.to_lowercase().contains(&query_lower) ||
-                    track.album.to_lowercase().contains(&query_lower) ||
-                    track.file_name.to_lowercase().contains(&query_lower)
-                }
-            })
             .map(|track| StreamingTrack {
                 id: format!("server_{}", track.file_path.to_string_lossy()),
                 title: track.title.clone(),
@@ -601,32 +708,27 @@ impl LocalMusicService {
                 bitrate: None,
                 sample_rate: None,
                 bit_depth: None,
+                isrc: track.isrc.clone(),
             })
             .collect()
     }
 
-    fn search_albums(&self, tracks: &[LocalTrack], query: &str) -> Vec<StreamingAlbum> {
-        let query_lower = query.to_lowercase();
+    // Group matched tracks into albums, keeping the order in which albums first appear
+    fn group_albums(&self, tracks: &[LocalTrack]) -> Vec<StreamingAlbum> {
+        let mut album_order: Vec<String> = Vec::new();
         let mut albums: HashMap<String, Vec<&LocalTrack>> = HashMap::new();
         
-        // Group tracks by album using metadata
         for track in tracks {
-            // If query is empty, include all albums
-            let matches = if query.trim().is_empty() {
-                true
-            } else {
-                track.album.to_lowercase().contains(&query_lower) ||
-                track.artist.to_lowercase().contains(&query_lower)
-            };
-            
-            if matches {
-                let album_key = format!("{}_{}", track.artist, track.album);
-                albums.entry(album_key).or_insert_with(Vec::new).push(track);
+            let album_key = format!("{}_{}", track.artist, track.album);
+            if !albums.contains_key(&album_key) {
+                album_order.push(album_key.clone());
             }
+            albums.entry(album_key).or_default().push(track);
         }
         
-        albums.into_iter()
-            .map(|(_, album_tracks)| {
+        album_order.into_iter()
+            .filter_map(|key| albums.remove(&key))
+            .map(|album_tracks| {
                 let first_track = album_tracks[0];
                 
                 // Sort tracks by track number if available, otherwise by filename
@@ -672,8 +774,10 @@ impl LocalMusicService {
                         bitrate: None,
                         sample_rate: None,
                         bit_depth: None,
+                        isrc: track.isrc.clone(),
                     }).collect(),
                     source: "server".to_string(),
+                    upc: None,
                 }
             })
             .collect()
@@ -851,6 +955,7 @@ impl LocalMusicService {
                 cover_url: track.cover_url.clone(),
                 track_number: track.track_number,
                 year: track.year,
+                isrc: track.isrc.clone(),
             };
             
             StreamingTrack {
@@ -866,6 +971,7 @@ impl LocalMusicService {
                 bitrate: None,
                 sample_rate: None,
                 bit_depth: None,
+                isrc: track.isrc,
             }
         }).collect())
     }
@@ -874,12 +980,19 @@ impl LocalMusicService {
 #[async_trait]
 impl StreamingService for LocalMusicService {
     async fn search(&self, query: &str, limit: Option<u32>, offset: Option<u32>) -> Result<SearchResults> {
-        let tracks = self.scan_music_files().await.map_err(|e| anyhow!(e))?;
+        let parsed_query = TrackQuery::parse(query)?;
+        let index = self.refreshed_index().await.map_err(|e| anyhow!(e))?;
+        let analysis = self.analysis_for(&index, &parsed_query).await?;
+        let matched_tracks = index.read().await.search(&parsed_query, &analysis);
         let playlists = self.scan_playlists().await.map_err(|e| anyhow!(e))?;
         
-        let found_tracks = self.search_tracks(&tracks, query);
-        let found_albums = self.search_albums(&tracks, query);
-        let found_playlists = self.search_playlists(&playlists, query);
+        let found_tracks = self.to_streaming_tracks(&matched_tracks);
+        let found_albums = self.group_albums(&matched_tracks);
+        let found_playlists = if parsed_query.filters.is_empty() {
+            self.search_playlists(&playlists, &parsed_query.free_text)
+        } else {
+            Vec::new()
+        };
         
         let limit = limit.unwrap_or(20) as usize;
         let offset = offset.unwrap_or(0) as usize;
@@ -982,6 +1095,7 @@ impl StreamingService for LocalMusicService {
                         bitrate: None,
                         sample_rate: None,
                         bit_depth: None,
+                        isrc: track.isrc.clone(),
                     })
                     .collect();
                 
@@ -1025,8 +1139,45 @@ impl StreamingService for LocalMusicService {
     }
 
     async fn get_track(&self, track_id: &str) -> Result<StreamingTrack> {
-        // For now, return error - could be implemented to get specific track details
-        Err(anyhow!("Get track not implemented for server source"))
+        // Extract file path from track_id (format: "server_/path/to/file")
+        let file_path_str = track_id.strip_prefix("server_")
+            .ok_or_else(|| anyhow!("Invalid track ID for server source"))?;
+        let file_path = PathBuf::from(file_path_str);
+
+        // Only resolve files that live inside the music directory
+        if confine_to_music_dir(&self.music_dir, &file_path).is_none() {
+            return Err(anyhow!("Track not found: {}", track_id));
+        }
+
+        let metadata = self.extract_metadata(&file_path).await;
+        let track = LocalTrack {
+            file_name: file_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            file_path,
+            title: metadata.title,
+            artist: metadata.artist,
+            album: metadata.album,
+            duration: metadata.duration,
+            cover_url: metadata.cover_url,
+            track_number: metadata.track_number,
+            year: metadata.year,
+            isrc: metadata.isrc,
+        };
+
+        Ok(StreamingTrack {
+            id: format!("server_{}", track.file_path.to_string_lossy()),
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            album: track.album.clone(),
+            duration: track.duration.map(|d| d as i32),
+            stream_url: Some(self.get_stream_url_for_track(&track)),
+            cover_url: track.cover_url.clone(),
+            source: "server".to_string(),
+            quality: Some("Original".to_string()),
+            bitrate: None,
+            sample_rate: None,
+            bit_depth: None,
+            isrc: track.isrc,
+        })
     }
 
     async fn authenticate(&self, _credentials: &ServiceCredentials) -> Result<AuthResult> {
@@ -1053,3 +1204,24 @@ impl StreamingService for LocalMusicService {
         self.search(query, limit, offset).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confine_to_music_dir() {
+        let root = std::env::temp_dir().join(format!("musestruct_confine_test_{}", uuid::Uuid::new_v4()));
+        let music_dir = root.join("own_music");
+        std::fs::create_dir_all(music_dir.join("Artist")).unwrap();
+        std::fs::write(music_dir.join("Artist").join("track.flac"), b"").unwrap();
+        std::fs::write(root.join("secret.flac"), b"").unwrap();
+
+        assert!(confine_to_music_dir(&music_dir, &music_dir.join("Artist/track.flac")).is_some());
+        assert!(confine_to_music_dir(&music_dir, &music_dir.join("Artist/../../secret.flac")).is_none());
+        assert!(confine_to_music_dir(&music_dir, &root.join("secret.flac")).is_none());
+        assert!(confine_to_music_dir(&music_dir, &music_dir.join("Artist")).is_none());
+        assert!(confine_to_music_dir(&music_dir, &music_dir.join("missing.flac")).is_none());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
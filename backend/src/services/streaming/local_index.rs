@@ -0,0 +1,474 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+use super::local::LocalTrack;
+use crate::services::search_query::{FilterSubject, TrackAnalysisInfo, TrackQuery};
+use crate::services::track_matching::TrackMatchingService;
+
+pub const AUDIO_EXTENSIONS: [&str; 5] = ["mp3", "flac", "wav", "m4a", "ogg"];
+
+// Index configuration
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10); // Minimum time between directory walks
+const TITLE_WEIGHT: f32 = 3.0;
+const ARTIST_WEIGHT: f32 = 2.0;
+const ALBUM_WEIGHT: f32 = 1.5;
+const FILE_NAME_WEIGHT: f32 = 0.5;
+const PREFIX_MATCH_SCORE: f32 = 0.8;
+const FUZZY_MATCH_SCORE: f32 = 0.7;
+
+static INDEXES: OnceLock<Mutex<HashMap<PathBuf, Arc<RwLock<LocalLibraryIndex>>>>> = OnceLock::new();
+
+/// File on disk as seen by the last directory walk
+#[derive(Debug, Clone)]
+pub struct LibraryFile {
+    pub path: PathBuf,
+    pub modified_secs: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedTrack {
+    track: LocalTrack,
+    modified_secs: u64,
+    size: u64,
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Title,
+    Artist,
+    Album,
+    FileName,
+}
+
+impl Field {
+    fn weight(self) -> f32 {
+        match self {
+            Field::Title => TITLE_WEIGHT,
+            Field::Artist => ARTIST_WEIGHT,
+            Field::Album => ALBUM_WEIGHT,
+            Field::FileName => FILE_NAME_WEIGHT,
+        }
+    }
+}
+
+/// In-memory inverted index over the local library with fuzzy, diacritic-insensitive matching.
+/// It is refreshed incrementally: only files whose modification time or size changed are re-read.
+pub struct LocalLibraryIndex {
+    music_dir: PathBuf,
+    documents: Vec<IndexedTrack>,
+    positions: HashMap<PathBuf, usize>, // Index into `documents` by file path
+    postings: HashMap<String, Vec<(usize, Field)>>,
+    terms: Vec<String>, // Sorted, for prefix lookups
+    terms_by_length: HashMap<usize, Vec<usize>>, // Indexes into `terms` by length in chars, for fuzzy lookups
+    last_refresh: Option<Instant>,
+}
+
+impl LocalLibraryIndex {
+    fn new(music_dir: PathBuf) -> Self {
+        let mut index = Self {
+            music_dir,
+            documents: Vec::new(),
+            positions: HashMap::new(),
+            postings: HashMap::new(),
+            terms: Vec::new(),
+            terms_by_length: HashMap::new(),
+            last_refresh: None,
+        };
+        index.load_snapshot();
+        index
+    }
+
+    /// The shared index for a music directory
+    pub fn shared(music_dir: &Path) -> Arc<RwLock<LocalLibraryIndex>> {
+        let registry = INDEXES.get_or_init(|| Mutex::new(HashMap::new()));
+        let mut registry = registry.lock().unwrap_or_else(|e| e.into_inner());
+        registry
+            .entry(music_dir.to_path_buf())
+            .or_insert_with(|| Arc::new(RwLock::new(LocalLibraryIndex::new(music_dir.to_path_buf()))))
+            .clone()
+    }
+
+    pub fn needs_refresh(&self) -> bool {
+        self.last_refresh.is_none_or(|at| at.elapsed() >= REFRESH_INTERVAL)
+    }
+
+    /// Recursively list the audio files of the music directory with their modification times
+    pub fn list_audio_files(music_dir: &Path) -> Vec<LibraryFile> {
+        let mut files = Vec::new();
+        let mut pending = vec![music_dir.to_path_buf()];
+
+        while let Some(dir) = pending.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Ok(metadata) = entry.metadata() else { continue };
+                if metadata.is_dir() {
+                    pending.push(path);
+                } else if Self::is_audio_file(&path) {
+                    let modified_secs = metadata
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    files.push(LibraryFile { path, modified_secs, size: metadata.len() });
+                }
+            }
+        }
+
+        files
+    }
+
+    pub fn is_audio_file(path: &Path) -> bool {
+        path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.as_str()))
+    }
+
+    /// Drop removed files and return the files that are new or changed since the last refresh
+    pub fn stale_files(&mut self, files: &[LibraryFile]) -> Vec<LibraryFile> {
+        let current: HashMap<&Path, &LibraryFile> = files.iter().map(|f| (f.path.as_path(), f)).collect();
+        self.documents.retain(|doc| current.contains_key(doc.track.file_path.as_path()));
+        self.rebuild_positions();
+
+        files
+            .iter()
+            .filter(|file| match self.positions.get(&file.path) {
+                Some(&position) => {
+                    let doc = &self.documents[position];
+                    doc.modified_secs != file.modified_secs || doc.size != file.size
+                }
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Insert or replace the entry of a (re-)read file
+    pub fn upsert(&mut self, file: &LibraryFile, track: LocalTrack) {
+        let entry = IndexedTrack {
+            track,
+            modified_secs: file.modified_secs,
+            size: file.size,
+        };
+        match self.positions.get(&file.path) {
+            Some(&position) => self.documents[position] = entry,
+            None => {
+                self.positions.insert(file.path.clone(), self.documents.len());
+                self.documents.push(entry);
+            }
+        }
+    }
+
+    /// Rebuild the postings after a refresh and persist the index when anything changed
+    pub fn finish_refresh(&mut self, changed: bool) {
+        self.last_refresh = Some(Instant::now());
+        if changed || self.postings.is_empty() {
+            self.documents.sort_by(|a, b| a.track.file_path.cmp(&b.track.file_path));
+            self.rebuild_positions();
+            self.rebuild_postings();
+        }
+        if changed {
+            self.save_snapshot();
+        }
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.documents.len()
+    }
+
+    fn rebuild_positions(&mut self) {
+        self.positions = self
+            .documents
+            .iter()
+            .enumerate()
+            .map(|(position, doc)| (doc.track.file_path.clone(), position))
+            .collect();
+    }
+
+    fn rebuild_postings(&mut self) {
+        let mut postings: HashMap<String, Vec<(usize, Field)>> = HashMap::new();
+        for (doc_id, doc) in self.documents.iter().enumerate() {
+            let file_stem = doc.track.file_path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let fields = [
+                (Field::Title, doc.track.title.as_str()),
+                (Field::Artist, doc.track.artist.as_str()),
+                (Field::Album, doc.track.album.as_str()),
+                (Field::FileName, file_stem.as_str()),
+            ];
+            for (field, text) in fields {
+                for token in TrackMatchingService::normalize_text(text).split(' ').filter(|t| !t.is_empty()) {
+                    postings.entry(token.to_string()).or_default().push((doc_id, field));
+                }
+            }
+        }
+        self.terms = postings.keys().cloned().collect();
+        self.terms.sort();
+        self.terms_by_length = HashMap::new();
+        for (term_id, term) in self.terms.iter().enumerate() {
+            self.terms_by_length.entry(term.chars().count()).or_default().push(term_id);
+        }
+        self.postings = postings;
+    }
+
+    fn max_edits(token: &str) -> usize {
+        match token.chars().count() {
+            0..=3 => 0,
+            4..=6 => 1,
+            _ => 2,
+        }
+    }
+
+    /// Best weighted score of every document for one query token
+    fn score_token(&self, token: &str) -> HashMap<usize, f32> {
+        // Match quality of every term close enough to the token; exact beats prefix beats fuzzy
+        let mut qualities: HashMap<&str, f32> = HashMap::new();
+        if self.postings.contains_key(token) {
+            qualities.insert(token, 1.0);
+        }
+        let token_chars = token.chars().count();
+        if token_chars >= 2 {
+            let first = self.terms.partition_point(|term| term.as_str() < token);
+            for term in self.terms[first..].iter().take_while(|term| term.starts_with(token)) {
+                qualities.entry(term).or_insert(PREFIX_MATCH_SCORE);
+            }
+        }
+        // Only terms whose length is within the edit budget can be close enough
+        let max_edits = Self::max_edits(token);
+        if max_edits > 0 {
+            for length in token_chars.saturating_sub(max_edits)..=token_chars + max_edits {
+                for &term_id in self.terms_by_length.get(&length).into_iter().flatten() {
+                    let term = self.terms[term_id].as_str();
+                    if qualities.contains_key(term) {
+                        continue;
+                    }
+                    let distance = strsim::levenshtein(term, token);
+                    if distance <= max_edits {
+                        qualities.insert(term, FUZZY_MATCH_SCORE - 0.1 * (distance - 1) as f32);
+                    }
+                }
+            }
+        }
+
+        let document_count = self.documents.len().max(1) as f32;
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        for (term, quality) in qualities {
+            let postings = &self.postings[term];
+            let idf = (1.0 + document_count / postings.len() as f32).ln();
+            for (doc_id, field) in postings {
+                let score = quality * idf * field.weight();
+                let best = scores.entry(*doc_id).or_insert(0.0);
+                if score > *best {
+                    *best = score;
+                }
+            }
+        }
+
+        scores
+    }
+
+    /// Track ids of the tracks passing the query's filters, ignoring its free text
+    pub fn filtered_track_ids(&self, query: &TrackQuery) -> Vec<String> {
+        self.documents
+            .iter()
+            .filter_map(|document| {
+                let track_id = track_id(&document.track);
+                passes_filters(&document.track, &track_id, query, &HashMap::new()).then_some(track_id)
+            })
+            .collect()
+    }
+
+    /// Tracks matching the query, most relevant first. All free-text words have to match
+    /// (fuzzily); if that yields nothing, tracks matching any word are returned instead.
+    pub fn search(&self, query: &TrackQuery, analysis: &HashMap<String, TrackAnalysisInfo>) -> Vec<LocalTrack> {
+        let candidates: Vec<usize> = (0..self.documents.len())
+            .filter(|&doc_id| {
+                let track = &self.documents[doc_id].track;
+                passes_filters(track, &track_id(track), query, analysis)
+            })
+            .collect();
+
+        let normalized = TrackMatchingService::normalize_text(&query.free_text);
+        let tokens: Vec<&str> = normalized.split(' ').filter(|t| !t.is_empty()).collect();
+
+        if tokens.is_empty() {
+            let mut tracks: Vec<LocalTrack> = candidates.into_iter().map(|id| self.documents[id].track.clone()).collect();
+            tracks.sort_by(|a, b| {
+                (&a.artist, &a.album, a.track_number, &a.file_name).cmp(&(&b.artist, &b.album, b.track_number, &b.file_name))
+            });
+            return tracks;
+        }
+
+        let token_scores: Vec<HashMap<usize, f32>> = tokens.iter().map(|token| self.score_token(token)).collect();
+        let mut all_words = Vec::new();
+        let mut any_word = Vec::new();
+        for doc_id in candidates {
+            let matched: Vec<f32> = token_scores.iter().filter_map(|scores| scores.get(&doc_id).copied()).collect();
+            if matched.is_empty() {
+                continue;
+            }
+            let score: f32 = matched.iter().sum();
+            if matched.len() == tokens.len() {
+                all_words.push((doc_id, score));
+            } else {
+                any_word.push((doc_id, score * matched.len() as f32 / tokens.len() as f32));
+            }
+        }
+
+        let mut ranked = if all_words.is_empty() { any_word } else { all_words };
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        ranked.into_iter().map(|(doc_id, _)| self.documents[doc_id].track.clone()).collect()
+    }
+
+    fn snapshot_path(&self) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(self.music_dir.to_string_lossy().as_bytes());
+        let hash = format!("{:x}", hasher.finalize());
+        std::env::current_dir()
+            .unwrap_or_else(|_| PathBuf::from("."))
+            .join("cache")
+            .join(format!("library_index_{}.json", &hash[..16]))
+    }
+
+    fn load_snapshot(&mut self) {
+        let Ok(data) = std::fs::read(self.snapshot_path()) else { return };
+        match serde_json::from_slice::<Vec<IndexedTrack>>(&data) {
+            Ok(documents) => {
+                self.documents = documents;
+                self.rebuild_positions();
+                self.rebuild_postings();
+            }
+            Err(e) => tracing::warn!("Ignoring unreadable library index snapshot: {}", e),
+        }
+    }
+
+    fn save_snapshot(&self) {
+        let path = self.snapshot_path();
+        let result = serde_json::to_vec(&self.documents)
+            .map_err(|e| e.to_string())
+            .and_then(|data| {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                }
+                std::fs::write(&path, data).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            tracing::warn!("Failed to save library index snapshot: {}", e);
+        }
+    }
+}
+
+// Track id of a library file, as used by the server source
+fn track_id(track: &LocalTrack) -> String {
+    format!("server_{}", track.file_path.to_string_lossy())
+}
+
+fn passes_filters(track: &LocalTrack, track_id: &str, query: &TrackQuery, analysis: &HashMap<String, TrackAnalysisInfo>) -> bool {
+    query.matches(&FilterSubject {
+        title: Some(&track.title),
+        artist: &track.artist,
+        album: &track.album,
+        year: track.year,
+        source: "server",
+        analysis: analysis.get(track_id),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_track(title: &str, artist: &str, album: &str) -> LocalTrack {
+        LocalTrack {
+            file_path: PathBuf::from(format!("/music/{} - {}.mp3", artist, title)),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album: album.to_string(),
+            duration: None,
+            file_name: format!("{} - {}.mp3", artist, title),
+            cover_url: None,
+            track_number: None,
+            year: Some(1997),
+            isrc: None,
+        }
+    }
+
+    fn index_with(tracks: Vec<LocalTrack>) -> LocalLibraryIndex {
+        let mut index = LocalLibraryIndex {
+            music_dir: PathBuf::from("/music"),
+            documents: Vec::new(),
+            positions: HashMap::new(),
+            postings: HashMap::new(),
+            terms: Vec::new(),
+            terms_by_length: HashMap::new(),
+            last_refresh: None,
+        };
+        for track in tracks {
+            let file = LibraryFile { path: track.file_path.clone(), modified_secs: 1, size: 1 };
+            index.upsert(&file, track);
+        }
+        index.rebuild_postings();
+        index
+    }
+
+    #[test]
+    fn test_fuzzy_diacritic_and_word_order() {
+        let index = index_with(vec![
+            local_track("Jóga", "Björk", "Homogenic"),
+            local_track("Around the World", "Daft Punk", "Homework"),
+        ]);
+        let analysis = HashMap::new();
+
+        let results = index.search(&TrackQuery::parse("bjork joga").unwrap(), &analysis);
+        assert_eq!(results[0].title, "Jóga");
+
+        let results = index.search(&TrackQuery::parse("world arround punk").unwrap(), &analysis);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title, "Around the World");
+    }
+
+    #[test]
+    fn test_field_filters() {
+        let index = index_with(vec![
+            local_track("Jóga", "Björk", "Homogenic"),
+            local_track("Around the World", "Daft Punk", "Homework"),
+        ]);
+        let mut analysis = HashMap::new();
+        analysis.insert(
+            "server_/music/Daft Punk - Around the World.mp3".to_string(),
+            TrackAnalysisInfo { bpm: Some(121.0), key_name: Some("Am".to_string()), camelot: Some("8A".to_string()), descriptors: None },
+        );
+
+        let results = index.search(&TrackQuery::parse("bpm:120-128 key:8a").unwrap(), &analysis);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].artist, "Daft Punk");
+
+        assert!(index.search(&TrackQuery::parse("artist:bjork year:2000-2010").unwrap(), &analysis).is_empty());
+
+        // Analyses are only looked up for the tracks passing the other filters
+        let query = TrackQuery::parse("artist:daft bpm:120-128 around").unwrap().without_analysis_filters();
+        assert_eq!(index.filtered_track_ids(&query), vec!["server_/music/Daft Punk - Around the World.mp3".to_string()]);
+    }
+
+    #[test]
+    fn test_upsert_replaces_and_edits_count_chars() {
+        let mut index = index_with(vec![local_track("Группа крови", "Кино", "Группа крови")]);
+        let mut retitled = local_track("Группа крови", "Кино", "Группа крови");
+        retitled.album = "Звезда по имени Солнце".to_string();
+        let file = LibraryFile { path: retitled.file_path.clone(), modified_secs: 2, size: 1 };
+        index.upsert(&file, retitled);
+        index.rebuild_postings();
+        assert_eq!(index.track_count(), 1);
+
+        // One letter short, though two bytes short in UTF-8
+        let results = index.search(&TrackQuery::parse("група").unwrap(), &HashMap::new());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].album, "Звезда по имени Солнце");
+    }
+}
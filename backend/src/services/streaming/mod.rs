@@ -1,12 +1,14 @@
 pub mod qobuz;
 pub mod spotify;
 pub mod local;
+pub mod local_index;
 pub mod interface;
 
 pub use interface::*;
 pub use qobuz::*;
 pub use spotify::*;
 pub use local::*;
+pub use local_index::*;
 
 use serde::{Deserialize, Serialize};
 
@@ -24,6 +26,8 @@ pub struct StreamingTrack {
     pub bitrate: Option<i32>,      // in kbps
     pub sample_rate: Option<i32>,  // in Hz
     pub bit_depth: Option<i32>,    // in bits
+    #[serde(default)]
+    pub isrc: Option<String>,      // International Standard Recording Code
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +39,8 @@ pub struct StreamingAlbum {
     pub cover_url: Option<String>,
     pub tracks: Vec<StreamingTrack>,
     pub source: String,
+    #[serde(default)]
+    pub upc: Option<String>,       // Universal Product Code
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -146,6 +146,7 @@ impl StreamingService for QobuzService {
                 bitrate,
                 sample_rate,
                 bit_depth,
+                isrc: track.isrc,
             }
         }).collect();
 
@@ -157,6 +158,7 @@ impl StreamingService for QobuzService {
             cover_url: album.image.as_ref().and_then(|i| i.large.clone()),
             tracks: vec![], // Tracks would be fetched separately
             source: "qobuz".to_string(),
+            upc: album.upc,
         }).collect();
 
         Ok(SearchResults {
@@ -238,6 +240,7 @@ impl StreamingService for QobuzService {
                 bitrate,
                 sample_rate,
                 bit_depth,
+                isrc: track.isrc,
                 // Note: Position tracking would need to be added to StreamingTrack struct if needed
             })
         }).collect();
@@ -279,6 +282,7 @@ impl StreamingService for QobuzService {
                 bitrate,
                 sample_rate,
                 bit_depth,
+                isrc: track.isrc,
             })
         }).collect();
 
@@ -328,6 +332,7 @@ impl StreamingService for QobuzService {
             bitrate,
             sample_rate,
             bit_depth,
+            isrc: track.isrc,
         })
     }
 
@@ -529,6 +534,7 @@ impl QobuzService {
                 bitrate,
                 sample_rate,
                 bit_depth,
+                isrc: track.isrc,
             })
         }).collect();
 
@@ -572,6 +578,7 @@ impl QobuzService {
                 cover_url: album.image.as_ref().and_then(|i| i.large.clone()),
                 tracks: vec![],
                 source: "qobuz".to_string(),
+                upc: album.upc,
             })
         }).collect();
 
@@ -654,6 +661,7 @@ struct QobuzTrack {
     duration: Option<i32>,
     performer: Option<QobuzArtist>,
     album: Option<QobuzAlbum>,
+    isrc: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -663,6 +671,7 @@ struct QobuzAlbum {
     artist: Option<QobuzArtist>,
     released_at: Option<i64>, // Can be negative (dates before 1970)
     image: Option<QobuzImage>,
+    upc: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
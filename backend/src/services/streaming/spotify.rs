@@ -96,6 +96,7 @@ impl StreamingService for SpotifyService {
         let response: SpotifySearchResponse = self.make_request("search", &params).await?;
 
         let tracks = response.tracks.items.into_iter().map(|track| {
+            let isrc = track.external_ids.as_ref().and_then(|ids| ids.isrc.clone());
             let album_name = track.album.name;
             let artist_name = if let Some(artist) = track.artists.first() {
                 artist.name.clone()
@@ -117,6 +118,7 @@ impl StreamingService for SpotifyService {
                 bitrate: Some(160), // Spotify previews are typically 160kbps
                 sample_rate: Some(44100), // Standard CD sample rate
                 bit_depth: None, // Not specified for MP3 previews
+                isrc,
             }
         }).collect();
 
@@ -136,6 +138,7 @@ impl StreamingService for SpotifyService {
                 cover_url,
                 tracks: vec![], // Would need separate API call to get tracks
                 source: "spotify".to_string(),
+                upc: album.external_ids.and_then(|ids| ids.upc),
             }
         }).collect();
 
@@ -198,6 +201,7 @@ impl StreamingService for SpotifyService {
                     bitrate: Some(320),
                     sample_rate: Some(44100),
                     bit_depth: Some(16),
+                    isrc: track.external_ids.and_then(|ids| ids.isrc),
                 }
             })
             .collect();
@@ -232,6 +236,7 @@ impl StreamingService for SpotifyService {
                     bitrate: Some(160), // Spotify previews are typically 160kbps
                     sample_rate: Some(44100), // Standard CD sample rate
                     bit_depth: None, // Not specified for MP3 previews
+                    isrc: None, // Simplified track objects carry no external ids
                 }
             })
             .collect();
@@ -255,6 +260,7 @@ impl StreamingService for SpotifyService {
             "Unknown Artist".to_string()
         };
         let cover_url = track.album.images.first().map(|img| img.url.clone());
+        let isrc = track.external_ids.as_ref().and_then(|ids| ids.isrc.clone());
 
         Ok(StreamingTrack {
             id: track.id,
@@ -269,6 +275,7 @@ impl StreamingService for SpotifyService {
             bitrate: Some(160), // Spotify previews are typically 160kbps
             sample_rate: Some(44100), // Standard CD sample rate
             bit_depth: None, // Not specified for MP3 previews
+            isrc,
         })
     }
 
@@ -353,6 +360,7 @@ struct SpotifyTrack {
     album: SpotifyAlbum,
     duration_ms: u32,
     preview_url: Option<String>,
+    external_ids: Option<SpotifyExternalIds>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -362,6 +370,13 @@ struct SpotifyAlbum {
     artists: Vec<SpotifyArtist>,
     release_date: String,
     images: Vec<SpotifyImage>,
+    external_ids: Option<SpotifyExternalIds>, // Only present on full album objects
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyExternalIds {
+    isrc: Option<String>,
+    upc: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -20,6 +20,7 @@ use serde::Deserialize;
 #[derive(Debug, Clone)]
 pub struct CachedTrack {
     pub id: String,
+    pub track_id: String, // Id of the track at its source
     pub file_path: PathBuf,
     pub duration: u64,
     pub size: u64,
@@ -39,8 +40,12 @@ pub struct StreamingService {
     cached_tracks: Arc<RwLock<HashMap<String, CachedTrack>>>,
     max_cache_size: u64, // in bytes
     max_age: Duration,
+    preview_renders: std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>, // Previews being rendered, by key
 }
 
+// Rendered previews are kept for repeated range requests, up to this many
+const MAX_CACHED_PREVIEWS: usize = 16;
+
 impl StreamingService {
     pub fn new(cache_dir: PathBuf) -> Self {
         Self {
@@ -48,6 +53,7 @@ impl StreamingService {
             cached_tracks: Arc::new(RwLock::new(HashMap::new())),
             max_cache_size: 5 * 1024 * 1024 * 1024, // 5GB
             max_age: Duration::from_secs(24 * 60 * 60), // 24 hours
+            preview_renders: std::sync::Mutex::new(HashMap::new()),
         }
     }
 
@@ -57,10 +63,95 @@ impl StreamingService {
         
         // Clean up old cached files on startup
         self.cleanup_old_cache().await?;
+
+        // Previews are rendered again on demand
+        let preview_dir = self.preview_dir();
+        if preview_dir.exists() {
+            fs::remove_dir_all(&preview_dir).await?;
+        }
+        fs::create_dir_all(&preview_dir).await?;
         
         Ok(())
     }
 
+    /// The canonical path of a file in the cache if it holds a cached track
+    pub async fn cached_file(&self, path: &Path) -> Option<PathBuf> {
+        let canonical = path.canonicalize().ok()?;
+        let cached_tracks = self.cached_tracks.read().await;
+        cached_tracks
+            .values()
+            .any(|track| track.file_path.canonicalize().is_ok_and(|cached| cached == canonical))
+            .then_some(canonical)
+    }
+
+    /// A cached track by its stream id
+    pub async fn cached_track(&self, id: &str) -> Option<CachedTrack> {
+        let cached_tracks = self.cached_tracks.read().await;
+        cached_tracks.values().find(|track| track.id == id).cloned()
+    }
+
+    fn preview_dir(&self) -> PathBuf {
+        self.cache_dir.join("previews")
+    }
+
+    /// Where the preview rendered with `key` is kept
+    pub fn preview_path(&self, key: &str) -> PathBuf {
+        self.preview_dir().join(format!("{}.wav", key))
+    }
+
+    /// The preview with `key`, rendered by `render` into the path it is given unless it is kept already.
+    /// Players asking for the same preview at once wait for a single render.
+    pub async fn preview<F>(&self, key: &str, render: F) -> anyhow::Result<PathBuf>
+    where
+        F: FnOnce(PathBuf) -> anyhow::Result<()> + Send + 'static,
+    {
+        let path = self.preview_path(key);
+        let lock_previews = || self.preview_renders.lock().unwrap_or_else(|e| e.into_inner());
+        let render_lock = lock_previews().entry(key.to_string()).or_default().clone();
+        let rendered = {
+            let _rendering = render_lock.lock().await;
+            if path.exists() {
+                Ok(())
+            } else {
+                // Pruned before rendering, so the new preview is not removed before it is served
+                if let Err(e) = self.prune_previews().await {
+                    tracing::warn!("Failed to prune cached previews: {}", e);
+                }
+                let target = path.clone();
+                tokio::task::spawn_blocking(move || render(target))
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|rendered| rendered)
+            }
+        };
+        // The last one done with a key forgets it; clones are only taken under the same lock
+        let mut renders = lock_previews();
+        if Arc::strong_count(&render_lock) == 2 {
+            renders.remove(key);
+        }
+        rendered.map(|_| path)
+    }
+
+    /// Remove the least recently rendered previews beyond the ones kept
+    async fn prune_previews(&self) -> anyhow::Result<()> {
+        let mut entries = fs::read_dir(self.preview_dir()).await?;
+        let mut previews = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            // Partial files belong to renders in progress
+            if entry.path().extension().is_none_or(|extension| extension != "wav") {
+                continue;
+            }
+            let modified = entry.metadata().await?.modified().unwrap_or(UNIX_EPOCH);
+            previews.push((entry.path(), modified));
+        }
+
+        previews.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+        for (path, _) in previews.into_iter().skip(MAX_CACHED_PREVIEWS) {
+            let _ = fs::remove_file(path).await;
+        }
+        Ok(())
+    }
+
     pub async fn get_stream_url(&self, track_id: &str, source: &str, original_url: &str, title: Option<&str>, artist: Option<&str>) -> anyhow::Result<String> {
         // Create deterministic cache key based on source, artist, and title
         let cache_key = if let (Some(title), Some(artist)) = (title, artist) {
@@ -165,6 +256,7 @@ impl StreamingService {
         
         let cached_track = CachedTrack {
             id: cache_id,
+            track_id: track_id.to_string(),
             file_path,
             duration,
             size: metadata.len(),
@@ -290,76 +382,7 @@ impl StreamingService {
         }
         
         println!("File exists, reading content...");
-
-        // Get file metadata
-        let file_metadata = match fs::metadata(&cached_track.file_path).await {
-            Ok(metadata) => metadata,
-            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-        };
-
-        let file_size = file_metadata.len() as usize;
-        println!("File size: {} bytes", file_size);
-
-        // Parse range header if present
-        let (start, end) = if let Some(range) = range_header {
-            if let Some((start_str, end_str)) = range.strip_prefix("bytes=").and_then(|r| r.split_once('-')) {
-                let start = start_str.parse::<usize>().unwrap_or(0);
-                let end = if end_str.is_empty() {
-                    file_size - 1
-                } else {
-                    end_str.parse::<usize>().unwrap_or(file_size - 1).min(file_size - 1)
-                };
-                (start, end)
-            } else {
-                (0, file_size - 1)
-            }
-        } else {
-            (0, file_size - 1)
-        };
-
-        println!("Range: {} - {} (length: {})", start, end, end - start + 1);
-
-        // Read the requested range
-        let mut file = match fs::File::open(&cached_track.file_path).await {
-            Ok(file) => file,
-            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-        };
-
-        // Seek to start position
-        use tokio::io::AsyncSeekExt;
-        if let Err(_) = file.seek(std::io::SeekFrom::Start(start as u64)).await {
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-
-        // Read the range
-        let mut buffer = vec![0u8; end - start + 1];
-        let bytes_read = match file.read_exact(&mut buffer).await {
-            Ok(_) => buffer.len(),
-            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-        };
-
-        let content = buffer[..bytes_read].to_vec();
-
-        // Create response with proper headers for range requests
-        let mut response_builder = Response::builder()
-            .header(header::CONTENT_TYPE, "audio/mpeg")
-            .header(header::ACCEPT_RANGES, "bytes")
-            .header(header::CACHE_CONTROL, "public, max-age=3600")
-            .header(header::CONTENT_LENGTH, content.len());
-
-        if range_header.is_some() {
-            response_builder = response_builder
-                .status(StatusCode::PARTIAL_CONTENT)
-                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, file_size));
-        } else {
-            response_builder = response_builder.status(StatusCode::OK);
-        }
-
-        let response = response_builder
-            .body(content.into())
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        Ok(response)
+        serve_file(&cached_track.file_path, "audio/mpeg", range_header).await
     }
 
     pub fn router(streaming_service: Arc<StreamingService>) -> Router<AppState> {
@@ -384,3 +407,95 @@ async fn stream_track_handler(
 ) -> Result<Response, StatusCode> {
     streaming_service.stream_track(&track_id, range_header.as_deref()).await
 }
+
+/// Serve a file, or the byte range a player asks for
+pub async fn serve_file(path: &Path, content_type: &str, range_header: Option<&str>) -> Result<Response, StatusCode> {
+    let mut file = fs::File::open(path).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let file_size = file.metadata().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?.len();
+
+    let range = match range_header {
+        Some(range) => match byte_range(range, file_size) {
+            Some(range) => Some(range),
+            None => {
+                tracing::debug!("Unsatisfiable range {} of {:?} ({} bytes)", range, path, file_size);
+                return Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", file_size))
+                    .body(axum::body::Body::empty())
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        },
+        None => None,
+    };
+    let (start, length) = range.map_or((0, file_size), |(start, end)| (start, end - start + 1));
+    tracing::debug!("Serving {} bytes from {} of {:?}", length, start, path);
+
+    use tokio::io::AsyncSeekExt;
+    file.seek(std::io::SeekFrom::Start(start)).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // Streamed in chunks, tracks and previews can be large
+    let chunks = futures_util::stream::unfold((file, length), |(mut file, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+        let mut buffer = vec![0u8; remaining.min(256 * 1024) as usize];
+        match file.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((Ok::<_, std::io::Error>(buffer), (file, remaining - read as u64)))
+            }
+            Err(e) => Some((Err(e), (file, 0))),
+        }
+    });
+
+    let mut response_builder = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, "public, max-age=3600")
+        .header(header::CONTENT_LENGTH, length);
+    response_builder = match range {
+        Some((start, end)) => response_builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, file_size)),
+        None => response_builder.status(StatusCode::OK),
+    };
+    response_builder
+        .body(axum::body::Body::from_stream(chunks))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// The first and last byte of a `bytes=` range header, None when it is malformed or outside the file
+fn byte_range(range: &str, file_size: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let last = file_size.checked_sub(1)?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // The last `end` bytes
+        ("", suffix) => (file_size.saturating_sub(suffix.parse::<u64>().ok().filter(|n| *n > 0)?), last),
+        (start, "") => (start.parse().ok()?, last),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(last)),
+    };
+    (start <= end).then_some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_range() {
+        assert_eq!(byte_range("bytes=0-", 100), Some((0, 99)));
+        assert_eq!(byte_range("bytes=10-19", 100), Some((10, 19)));
+        assert_eq!(byte_range("bytes=90-200", 100), Some((90, 99)));
+        assert_eq!(byte_range("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(byte_range("bytes=-500", 100), Some((0, 99)));
+
+        // Unsatisfiable or malformed
+        assert_eq!(byte_range("bytes=100-", 100), None);
+        assert_eq!(byte_range("bytes=20-10", 100), None);
+        assert_eq!(byte_range("bytes=0-", 0), None);
+        assert_eq!(byte_range("bytes=-0", 100), None);
+        assert_eq!(byte_range("items=0-10", 100), None);
+        assert_eq!(byte_range("bytes=a-10", 100), None);
+    }
+}
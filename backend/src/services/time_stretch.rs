@@ -0,0 +1,380 @@
+use crate::services::harmonic_mixing::Camelot;
+
+// Accepted preview settings
+pub const MIN_TEMPO: f32 = 0.5; // Half speed
+pub const MAX_TEMPO: f32 = 2.0; // Double speed
+pub const MAX_SEMITONES: i32 = 12;
+
+// WSOLA configuration, in frames at 44.1 kHz
+const CHANNELS: usize = 2;
+const FRAME: usize = 2048; // ~46ms grains
+const HOP: usize = FRAME / 2; // Hann windows at half overlap sum to one
+const SEEK: usize = 512; // How far a grain may move from its nominal position to line up with the previous one
+const DECIMATION: usize = 4; // The coarse similarity search runs on every 4th frame of a mono mix
+
+/// How a track is played back for a preview
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StretchSettings {
+    pub tempo: f32, // Playback speed, the target over the original tempo
+    pub key_lock: bool, // Keep the key at any speed; without it the pitch follows the speed like a turntable
+    pub semitones: i32, // Pitch shift on top
+}
+
+impl Default for StretchSettings {
+    fn default() -> Self {
+        Self { tempo: 1.0, key_lock: true, semitones: 0 }
+    }
+}
+
+impl StretchSettings {
+    /// Factor all frequencies are multiplied by
+    pub fn pitch_ratio(&self) -> f32 {
+        let speed_pitch = if self.key_lock { 1.0 } else { self.tempo };
+        speed_pitch * 2f32.powf(self.semitones as f32 / 12.0)
+    }
+
+    /// Pitch change in semitones, fractional when it follows the speed without key lock
+    pub fn pitch_shift(&self) -> f32 {
+        12.0 * self.pitch_ratio().log2()
+    }
+
+    /// The key a track sounds in after the pitch change, to the nearest semitone
+    pub fn played_key(&self, key: Camelot) -> Camelot {
+        key.transpose(self.pitch_shift().round() as i32)
+    }
+}
+
+/// Cubic Hermite interpolation between `y[1]` and `y[2]` at `t` from 0.0 to 1.0
+pub fn hermite(y: [f32; 4], t: f32) -> f32 {
+    let c1 = 0.5 * (y[2] - y[0]);
+    let c2 = y[0] - 2.5 * y[1] + 2.0 * y[2] - 0.5 * y[3];
+    let c3 = 0.5 * (y[3] - y[0]) + 1.5 * (y[1] - y[2]);
+    ((c3 * t + c2) * t + c1) * t + y[1]
+}
+
+/// Time-stretches and pitch-shifts interleaved stereo chunk by chunk; the length changes by the inverse of
+/// the tempo. Output is returned as soon as it is final, so memory use does not grow with the track length.
+pub struct Stretcher {
+    wsola: Option<Wsola>, // None when the speed is unchanged
+    resampler: Option<Resampler>, // None when the pitch is unchanged
+}
+
+impl Stretcher {
+    pub fn new(settings: &StretchSettings) -> Self {
+        let pitch = settings.pitch_ratio() as f64;
+        // Resampling by the pitch ratio speeds up by it too, so the stretch makes up the rest of the tempo
+        let speed = settings.tempo as f64 / pitch;
+        Self {
+            wsola: ((speed - 1.0).abs() >= 1e-4).then(|| Wsola::new(speed)),
+            resampler: ((pitch - 1.0).abs() >= 1e-4).then(|| Resampler::new(pitch)),
+        }
+    }
+
+    /// Stretch the next interleaved stereo samples
+    pub fn push(&mut self, samples: &[f32]) -> Vec<f32> {
+        let stretched = match &mut self.wsola {
+            Some(wsola) => wsola.push(samples),
+            None => samples.to_vec(),
+        };
+        match &mut self.resampler {
+            Some(resampler) => resampler.push(&stretched),
+            None => stretched,
+        }
+    }
+
+    /// The rest of the output after the last samples
+    pub fn finish(&mut self) -> Vec<f32> {
+        let stretched = self.wsola.as_mut().map(Wsola::finish).unwrap_or_default();
+        match &mut self.resampler {
+            Some(resampler) => {
+                let mut output = resampler.push(&stretched);
+                output.extend(resampler.finish());
+                output
+            }
+            None => stretched,
+        }
+    }
+}
+
+// Changes the speed keeping the pitch: windowed grains are read `speed` times further apart than they
+// are written, each moved within SEEK to where it best continues the waveform of the previous grain.
+// A grain is placed once all input it may read is in; input and output are dropped once no grain uses them.
+struct Wsola {
+    speed: f64,
+    window: Vec<f32>,
+    input: Vec<f32>, // Interleaved, from source frame `offset` on
+    mono: Vec<f32>,
+    coarse: Vec<f32>, // Averages of DECIMATION mono frames
+    offset: usize, // Multiple of DECIMATION, so the coarse averages line up with the whole track's
+    grain: usize, // Next grain
+    previous: usize, // Source frame the last grain started at
+    output: Vec<f32>, // Overlap-added grains, from output frame `emitted` on
+    weights: Vec<f32>,
+    emitted: usize,
+}
+
+impl Wsola {
+    fn new(speed: f64) -> Self {
+        let window = (0..FRAME)
+            .map(|n| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / FRAME as f32).cos())
+            .collect();
+        Self {
+            speed,
+            window,
+            input: Vec::new(),
+            mono: Vec::new(),
+            coarse: Vec::new(),
+            offset: 0,
+            grain: 0,
+            previous: 0,
+            output: Vec::new(),
+            weights: Vec::new(),
+            emitted: 0,
+        }
+    }
+
+    fn push(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.input.extend_from_slice(samples);
+        self.mono.extend(samples.chunks_exact(CHANNELS).map(|frame| 0.5 * (frame[0] + frame[1])));
+        for group in self.coarse.len()..self.mono.len() / DECIMATION {
+            let chunk = &self.mono[group * DECIMATION..(group + 1) * DECIMATION];
+            self.coarse.push(chunk.iter().sum::<f32>() / DECIMATION as f32);
+        }
+        self.run(false)
+    }
+
+    fn finish(&mut self) -> Vec<f32> {
+        let rest = &self.mono[self.coarse.len() * DECIMATION..];
+        if !rest.is_empty() {
+            self.coarse.push(rest.iter().sum::<f32>() / rest.len() as f32);
+        }
+        self.run(true)
+    }
+
+    fn nominal(&self, grain: usize) -> usize {
+        (grain as f64 * HOP as f64 * self.speed).round() as usize
+    }
+
+    fn run(&mut self, finished: bool) -> Vec<f32> {
+        let frames = self.offset + self.mono.len();
+        // The output length is known at the end; until then it is at least what the input so far gives
+        let output_frames = (frames as f64 / self.speed).round() as usize;
+        while self.grain * HOP < output_frames {
+            let nominal = self.nominal(self.grain);
+            if !finished && nominal + SEEK + FRAME > frames {
+                break;
+            }
+            let start = if self.grain == 0 {
+                0
+            } else {
+                self.offset + best_start(&self.mono, &self.coarse, self.previous + HOP - self.offset, nominal - self.offset)
+            };
+
+            let written = self.grain * HOP - self.emitted;
+            if self.weights.len() < written + FRAME {
+                self.output.resize((written + FRAME) * CHANNELS, 0.0);
+                self.weights.resize(written + FRAME, 0.0);
+            }
+            for (n, &weight) in self.window.iter().enumerate().take(frames.saturating_sub(start)) {
+                let (source, target) = ((start - self.offset + n) * CHANNELS, (written + n) * CHANNELS);
+                self.output[target] += weight * self.input[source];
+                self.output[target + 1] += weight * self.input[source + 1];
+                self.weights[written + n] += weight;
+            }
+            self.previous = start;
+            self.grain += 1;
+        }
+
+        // Frames before the next grain are complete
+        let ready = if finished { output_frames } else { (self.grain * HOP).min(output_frames) };
+        let count = ready.saturating_sub(self.emitted);
+        if self.weights.len() < count {
+            self.output.resize(count * CHANNELS, 0.0);
+            self.weights.resize(count, 0.0);
+        }
+        let mut output: Vec<f32> = self.output.drain(..count * CHANNELS).collect();
+        for (frame, weight) in output.chunks_exact_mut(CHANNELS).zip(self.weights.drain(..count)) {
+            if weight > 1e-3 {
+                frame.iter_mut().for_each(|sample| *sample /= weight);
+            }
+        }
+        self.emitted += count;
+
+        // Input before anything the next grain may read is not needed anymore
+        if !finished {
+            let natural = if self.grain == 0 { 0 } else { self.previous + HOP };
+            let keep = natural.min(self.nominal(self.grain).saturating_sub(SEEK)).min(frames) / DECIMATION * DECIMATION;
+            if keep > self.offset {
+                let dropped = keep - self.offset;
+                self.input.drain(..dropped * CHANNELS);
+                self.mono.drain(..dropped);
+                self.coarse.drain(..dropped / DECIMATION);
+                self.offset = keep;
+            }
+        }
+        output
+    }
+}
+
+// Start of the grain near `nominal` whose opening is most similar to the natural continuation at
+// `natural`, found on the decimated mix and refined at full rate
+fn best_start(mono: &[f32], coarse: &[f32], natural: usize, nominal: usize) -> usize {
+    let last = mono.len().saturating_sub(FRAME);
+    let (low, high) = (nominal.saturating_sub(SEEK), (nominal + SEEK).min(last));
+    if low >= high || natural + HOP > mono.len() {
+        return nominal;
+    }
+
+    let span = HOP / DECIMATION;
+    let reference = &coarse[natural / DECIMATION..natural / DECIMATION + span];
+    let coarse_best = (low / DECIMATION..=high / DECIMATION)
+        .filter(|&candidate| candidate + span <= coarse.len())
+        .max_by(|&a, &b| {
+            similarity(reference, &coarse[a..a + span]).total_cmp(&similarity(reference, &coarse[b..b + span]))
+        })
+        .map_or(nominal, |candidate| candidate * DECIMATION);
+
+    let reference = &mono[natural..natural + HOP];
+    (coarse_best.saturating_sub(DECIMATION).max(low)..=(coarse_best + DECIMATION).min(high))
+        .max_by(|&a, &b| similarity(reference, &mono[a..a + HOP]).total_cmp(&similarity(reference, &mono[b..b + HOP])))
+        .unwrap_or(coarse_best)
+}
+
+// Cross-correlation normalized by the candidate's energy, so loud passages are not favored
+fn similarity(reference: &[f32], candidate: &[f32]) -> f32 {
+    let (dot, energy) = reference
+        .iter()
+        .zip(candidate)
+        .fold((0.0, 0.0), |(dot, energy), (&r, &c)| (dot + r * c, energy + c * c));
+    dot / (energy + 1e-9f32).sqrt()
+}
+
+// Plays interleaved stereo `ratio` times faster, which raises the pitch by the same factor
+struct Resampler {
+    ratio: f64,
+    input: Vec<f32>, // Interleaved, from frame `offset` on
+    offset: usize,
+    produced: usize, // Output frames so far
+}
+
+impl Resampler {
+    fn new(ratio: f64) -> Self {
+        Self { ratio, input: Vec::new(), offset: 0, produced: 0 }
+    }
+
+    fn push(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.input.extend_from_slice(samples);
+        self.run(false)
+    }
+
+    fn finish(&mut self) -> Vec<f32> {
+        self.run(true)
+    }
+
+    fn run(&mut self, finished: bool) -> Vec<f32> {
+        let frames = self.offset + self.input.len() / CHANNELS;
+        let output_frames = if finished { (frames as f64 / self.ratio).floor() as usize } else { usize::MAX };
+        let (input, offset) = (&self.input, self.offset);
+        let sample = |frame: isize, channel: usize| input[(frame.clamp(0, frames as isize - 1) as usize - offset) * CHANNELS + channel];
+
+        let mut output = Vec::new();
+        while self.produced < output_frames {
+            let position = self.produced as f64 * self.ratio;
+            let index = position.floor() as isize;
+            // Until the end, the interpolation waits for the two frames after the position
+            if !finished && index + 2 >= frames as isize {
+                break;
+            }
+            let t = (position - index as f64) as f32;
+            for channel in 0..CHANNELS {
+                let y = [sample(index - 1, channel), sample(index, channel), sample(index + 1, channel), sample(index + 2, channel)];
+                output.push(hermite(y, t));
+            }
+            self.produced += 1;
+        }
+
+        // Frames before the one left of the next position are not read again
+        let keep = ((self.produced as f64 * self.ratio).floor() as usize).saturating_sub(1).min(frames);
+        if keep > self.offset {
+            self.input.drain(..(keep - self.offset) * CHANNELS);
+            self.offset = keep;
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 44100.0;
+
+    fn stretch(samples: &[f32], settings: &StretchSettings) -> Vec<f32> {
+        let mut stretcher = Stretcher::new(settings);
+        let mut output = stretcher.push(samples);
+        output.extend(stretcher.finish());
+        output
+    }
+
+    fn sine(frequency: f32, seconds: f32) -> Vec<f32> {
+        (0..(seconds * RATE) as usize)
+            .map(|n| 0.5 * (2.0 * std::f32::consts::PI * frequency * n as f32 / RATE).sin())
+            .flat_map(|sample| [sample, sample])
+            .collect()
+    }
+
+    // Frequency of the left channel from its rising zero crossings, away from the edges
+    fn frequency(samples: &[f32]) -> f32 {
+        let left: Vec<f32> = samples.iter().step_by(CHANNELS).copied().collect();
+        let middle = &left[left.len() / 4..left.len() * 3 / 4];
+        let crossings = middle.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count();
+        crossings as f32 * RATE / middle.len() as f32
+    }
+
+    #[test]
+    fn test_stretch_and_shift() {
+        let input = sine(440.0, 2.0);
+        let frames = |samples: &[f32]| (samples.len() / CHANNELS) as f32 / RATE;
+
+        // Key lock keeps the pitch at a faster tempo
+        let locked = stretch(&input, &StretchSettings { tempo: 1.25, ..Default::default() });
+        assert!((frames(&locked) - 1.6).abs() < 0.01, "{}", frames(&locked));
+        assert!((frequency(&locked) - 440.0).abs() < 5.0, "{}", frequency(&locked));
+
+        // Without it the pitch follows the speed
+        let varispeed = stretch(&input, &StretchSettings { tempo: 1.25, key_lock: false, semitones: 0 });
+        assert!((frames(&varispeed) - 1.6).abs() < 0.01);
+        assert!((frequency(&varispeed) - 550.0).abs() < 5.0, "{}", frequency(&varispeed));
+
+        // An octave up at the same tempo
+        let shifted = stretch(&input, &StretchSettings { semitones: 12, ..Default::default() });
+        assert!((frames(&shifted) - 2.0).abs() < 0.01);
+        assert!((frequency(&shifted) - 880.0).abs() < 8.0, "{}", frequency(&shifted));
+    }
+
+    #[test]
+    fn test_chunked_matches_whole_buffer() {
+        let input = sine(440.0, 1.0);
+        let settings = StretchSettings { tempo: 1.1, semitones: 3, ..Default::default() };
+        let whole = stretch(&input, &settings);
+
+        let mut stretcher = Stretcher::new(&settings);
+        let mut streamed = Vec::new();
+        for chunk in input.chunks(333 * CHANNELS) {
+            streamed.extend(stretcher.push(chunk));
+        }
+        streamed.extend(stretcher.finish());
+
+        assert_eq!(streamed.len(), whole.len());
+        assert!(streamed.iter().zip(&whole).all(|(a, b)| (a - b).abs() < 1e-5));
+    }
+
+    #[test]
+    fn test_played_key() {
+        let key = Camelot::parse("8A").unwrap();
+        assert_eq!(StretchSettings { tempo: 1.06, ..Default::default() }.played_key(key), key);
+        // 6% faster without key lock is a semitone up
+        assert_eq!(StretchSettings { tempo: 1.06, key_lock: false, semitones: 0 }.played_key(key).to_string(), "3A");
+        assert_eq!(StretchSettings { semitones: -2, ..Default::default() }.played_key(key).to_string(), "6A");
+    }
+}
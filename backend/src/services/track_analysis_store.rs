@@ -0,0 +1,251 @@
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{ActiveModelBehavior, ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, Set};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use crate::models::track_analysis::{
+    ActiveModel, BPM_ALGORITHM_VERSION, FINGERPRINT_ALGORITHM_VERSION, KEY_ALGORITHM_VERSION, LOUDNESS_ALGORITHM_VERSION,
+};
+use crate::services::beat_grid::BeatGrid;
+use crate::services::bpm_estimate::BpmCandidate;
+use crate::services::chord_analysis::ChordSegment;
+use crate::services::fingerprint::TrackFingerprint;
+use crate::services::key_analysis::{KeyCandidate, KeyProfile, KeySegment};
+use crate::services::loudness_analysis::TrackLoudness;
+use crate::services::track_descriptors::TrackDescriptors;
+use crate::services::track_structure::TrackStructure;
+use crate::models::{TrackAnalysisColumn, TrackAnalysisEntity, TrackAnalysisModel};
+
+/// Key of a track across sources
+pub type TrackKey = (String, String); // (source, track_id)
+
+/// Fields written by one analysis run
+#[derive(Debug, Clone)]
+pub enum AnalysisUpdate {
+    Bpm {
+        bpm: Option<f32>, // None when the tempo could not be determined
+        confidence: f32,
+        candidates: Vec<BpmCandidate>,
+        beat_grid: Option<BeatGrid>,
+        structure: TrackStructure,
+        descriptors: TrackDescriptors,
+    },
+    Key {
+        key_name: String,
+        camelot: String,
+        confidence: f32,
+        profile: KeyProfile,
+        runner_up: Option<KeyCandidate>,
+        timeline: Vec<KeySegment>,
+        chords: Vec<ChordSegment>,
+    },
+    Loudness(TrackLoudness),
+    Fingerprint(TrackFingerprint),
+}
+
+/// User-independent storage of analysis results, keyed by (source, track_id) and content hash
+pub struct TrackAnalysisStore;
+
+impl TrackAnalysisStore {
+    pub async fn find(db: &DatabaseConnection, source: &str, track_id: &str) -> Result<Option<TrackAnalysisModel>, DbErr> {
+        TrackAnalysisEntity::find()
+            .filter(TrackAnalysisColumn::Source.eq(source))
+            .filter(TrackAnalysisColumn::TrackId.eq(track_id))
+            .one(db)
+            .await
+    }
+
+    /// Most recent analysis of the same audio content, possibly under another source or track id
+    pub async fn find_by_content_hash(db: &DatabaseConnection, content_hash: &str) -> Result<Option<TrackAnalysisModel>, DbErr> {
+        TrackAnalysisEntity::find()
+            .filter(TrackAnalysisColumn::ContentHash.eq(content_hash))
+            .order_by_desc(TrackAnalysisColumn::UpdatedAt)
+            .one(db)
+            .await
+    }
+
+    /// Analyses of many tracks at once, for joining onto lists of tracks
+    pub async fn find_many(db: &DatabaseConnection, keys: &[TrackKey]) -> Result<HashMap<TrackKey, TrackAnalysisModel>, DbErr> {
+        if keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let analyses = TrackAnalysisEntity::find()
+            .filter(
+                Expr::tuple([Expr::col(TrackAnalysisColumn::Source).into(), Expr::col(TrackAnalysisColumn::TrackId).into()])
+                    .in_tuples(keys.iter().cloned()),
+            )
+            .all(db)
+            .await?;
+
+        Ok(analyses
+            .into_iter()
+            .map(|analysis| ((analysis.source.clone(), analysis.track_id.clone()), analysis))
+            .collect())
+    }
+
+    pub async fn find_by_source(db: &DatabaseConnection, source: &str) -> Result<Vec<TrackAnalysisModel>, DbErr> {
+        TrackAnalysisEntity::find()
+            .filter(TrackAnalysisColumn::Source.eq(source))
+            .all(db)
+            .await
+    }
+
+    /// Store the result of an analysis run, creating the track's entry if needed. Only the columns
+    /// of this run are written, so concurrent runs of other kinds on the same track do not clobber each other.
+    pub async fn save(
+        db: &DatabaseConnection,
+        source: &str,
+        track_id: &str,
+        content_hash: Option<String>,
+        update: AnalysisUpdate,
+    ) -> Result<TrackAnalysisModel, DbErr> {
+        let mut active = <ActiveModel as ActiveModelBehavior>::new();
+        active.source = Set(source.to_string());
+        active.track_id = Set(track_id.to_string());
+        let mut columns = vec![TrackAnalysisColumn::UpdatedAt];
+
+        let now = chrono::Utc::now().naive_utc();
+        if content_hash.is_some() {
+            active.content_hash = Set(content_hash);
+            columns.push(TrackAnalysisColumn::ContentHash);
+        }
+        match update {
+            AnalysisUpdate::Bpm { bpm, confidence, candidates, beat_grid, structure, descriptors } => {
+                active.bpm = Set(bpm);
+                active.bpm_confidence = Set(Some(confidence));
+                active.bpm_candidates = Set(serde_json::to_string(&candidates).ok());
+                active.beat_grid = Set(beat_grid.map(|grid| grid.to_compact_json()));
+                active.structure = Set(serde_json::to_string(&structure).ok());
+                active.energy = Set(Some(descriptors.energy));
+                active.danceability = Set(Some(descriptors.danceability));
+                active.pulse_clarity = Set(Some(descriptors.pulse_clarity));
+                active.brightness = Set(Some(descriptors.brightness));
+                active.dynamic_range = Set(Some(descriptors.dynamic_range));
+                active.acousticness = Set(Some(descriptors.acousticness));
+                active.bpm_analyzed_at = Set(Some(now));
+                active.bpm_version = Set(Some(BPM_ALGORITHM_VERSION));
+                columns.extend([
+                    TrackAnalysisColumn::Bpm,
+                    TrackAnalysisColumn::BpmConfidence,
+                    TrackAnalysisColumn::BpmCandidates,
+                    TrackAnalysisColumn::BeatGrid,
+                    TrackAnalysisColumn::Structure,
+                    TrackAnalysisColumn::Energy,
+                    TrackAnalysisColumn::Danceability,
+                    TrackAnalysisColumn::PulseClarity,
+                    TrackAnalysisColumn::Brightness,
+                    TrackAnalysisColumn::DynamicRange,
+                    TrackAnalysisColumn::Acousticness,
+                    TrackAnalysisColumn::BpmAnalyzedAt,
+                    TrackAnalysisColumn::BpmVersion,
+                ]);
+            }
+            AnalysisUpdate::Key { key_name, camelot, confidence, profile, runner_up, timeline, chords } => {
+                active.key_name = Set(Some(key_name));
+                active.camelot = Set(Some(camelot));
+                active.key_confidence = Set(Some(confidence));
+                active.key_profile = Set(Some(profile.name().to_string()));
+                active.key_runner_up = Set(runner_up.and_then(|runner_up| serde_json::to_string(&runner_up).ok()));
+                active.key_timeline = Set(serde_json::to_string(&timeline).ok());
+                active.chord_timeline = Set(serde_json::to_string(&chords).ok());
+                active.key_analyzed_at = Set(Some(now));
+                active.key_version = Set(Some(KEY_ALGORITHM_VERSION));
+                columns.extend([
+                    TrackAnalysisColumn::KeyName,
+                    TrackAnalysisColumn::Camelot,
+                    TrackAnalysisColumn::KeyConfidence,
+                    TrackAnalysisColumn::KeyProfile,
+                    TrackAnalysisColumn::KeyRunnerUp,
+                    TrackAnalysisColumn::KeyTimeline,
+                    TrackAnalysisColumn::ChordTimeline,
+                    TrackAnalysisColumn::KeyAnalyzedAt,
+                    TrackAnalysisColumn::KeyVersion,
+                ]);
+            }
+            AnalysisUpdate::Loudness(loudness) => {
+                active.integrated_loudness = Set(loudness.integrated_lufs);
+                active.loudness_range = Set(Some(loudness.loudness_range));
+                active.true_peak = Set(Some(loudness.true_peak));
+                active.loudness_histogram = Set(Some(loudness.histogram.to_compact_json()));
+                active.loudness_analyzed_at = Set(Some(now));
+                active.loudness_version = Set(Some(LOUDNESS_ALGORITHM_VERSION));
+                columns.extend([
+                    TrackAnalysisColumn::IntegratedLoudness,
+                    TrackAnalysisColumn::LoudnessRange,
+                    TrackAnalysisColumn::TruePeak,
+                    TrackAnalysisColumn::LoudnessHistogram,
+                    TrackAnalysisColumn::LoudnessAnalyzedAt,
+                    TrackAnalysisColumn::LoudnessVersion,
+                ]);
+            }
+            AnalysisUpdate::Fingerprint(fingerprint) => {
+                active.fingerprint = Set(Some(fingerprint.fingerprint));
+                active.duration = Set(Some(fingerprint.duration));
+                active.codec = Set(Some(fingerprint.quality.codec));
+                active.sample_rate = Set(Some(fingerprint.quality.sample_rate as i32));
+                active.bit_depth = Set(fingerprint.quality.bit_depth.map(|bits| bits as i32));
+                active.bitrate = Set(fingerprint.quality.bitrate.map(|kbps| kbps as i32));
+                active.fingerprint_version = Set(Some(FINGERPRINT_ALGORITHM_VERSION));
+                columns.extend([
+                    TrackAnalysisColumn::Fingerprint,
+                    TrackAnalysisColumn::Duration,
+                    TrackAnalysisColumn::Codec,
+                    TrackAnalysisColumn::SampleRate,
+                    TrackAnalysisColumn::BitDepth,
+                    TrackAnalysisColumn::Bitrate,
+                    TrackAnalysisColumn::FingerprintVersion,
+                ]);
+            }
+        }
+        active.updated_at = Set(now);
+
+        TrackAnalysisEntity::insert(active)
+            .on_conflict(
+                OnConflict::columns([TrackAnalysisColumn::Source, TrackAnalysisColumn::TrackId])
+                    .update_columns(columns)
+                    .to_owned(),
+            )
+            .exec_with_returning(db)
+            .await
+    }
+
+    /// Record the loudness of the album on each of its analyzed tracks
+    pub async fn save_album_loudness(
+        db: &DatabaseConnection,
+        analyses: Vec<TrackAnalysisModel>,
+        album_loudness: Option<f32>,
+        album_peak: f32,
+    ) -> Result<Vec<TrackAnalysisModel>, DbErr> {
+        let mut saved = Vec::with_capacity(analyses.len());
+        for analysis in analyses {
+            if analysis.album_loudness == album_loudness && analysis.album_peak == Some(album_peak) {
+                saved.push(analysis);
+                continue;
+            }
+            let mut active: ActiveModel = analysis.into();
+            active.album_loudness = Set(album_loudness);
+            active.album_peak = Set(Some(album_peak));
+            active.updated_at = Set(chrono::Utc::now().naive_utc());
+            saved.push(active.update(db).await?);
+        }
+        Ok(saved)
+    }
+
+    /// SHA-256 of a file's content, read in blocks
+    pub fn content_hash_of_file(path: &Path) -> std::io::Result<String> {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
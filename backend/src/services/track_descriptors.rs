@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+
+use crate::services::beat_grid::BeatGrid;
+
+// Descriptor configuration
+const SILENT_FRAME_RMS: f32 = 1e-4; // -80 dBFS, frames below are left out
+const LOUDNESS_FLOOR_DB: f32 = -30.0; // Mean RMS mapped to 0..1 energy between these levels
+const LOUDNESS_CEILING_DB: f32 = -6.0;
+const BUSY_ONSETS_PER_SEC: f32 = 4.0; // Onset rate that counts as fully energetic
+const BRIGHT_CENTROID_HZ: f32 = 4000.0; // Spectral centroid that counts as fully bright
+const WIDE_DYNAMIC_RANGE_DB: f32 = 20.0; // Dynamic range that counts as fully uncompressed
+const PULSE_TOLERANCE_SECS: f32 = 0.07; // How far an onset may be off a grid beat to support it
+const DANCE_TEMPO: (f32, f32) = (100.0, 130.0); // Tempos that are fully danceable
+const DANCE_TEMPO_LIMITS: (f32, f32) = (60.0, 180.0); // Tempos at which danceability from tempo reaches zero
+
+/// Spectral summary of an analysis frame
+#[derive(Debug, Clone, Copy)]
+pub struct DescriptorFrame {
+    pub rms: f32, // Linear, of the mono samples
+    pub centroid: f32, // Hz
+}
+
+/// Track-level descriptors for sorting and filtering, e.g. DJ sets by energy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackDescriptors {
+    pub energy: f32, // 0.0 to 1.0, from loudness, onset rate and brightness
+    pub danceability: f32, // 0.0 to 1.0, from pulse clarity and tempo
+    pub pulse_clarity: f32, // 0.0 to 1.0, share of beat grid beats backed by an onset
+    pub brightness: f32, // Mean spectral centroid in Hz
+    pub dynamic_range: f32, // dB between loud and quiet passages
+    pub acousticness: f32, // 0.0 to 1.0, from dynamic range, brightness and pulse
+}
+
+/// A descriptor as a search field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Descriptor {
+    Energy,
+    Danceability,
+    PulseClarity,
+    Brightness,
+    DynamicRange,
+    Acousticness,
+}
+
+impl Descriptor {
+    pub const ALL: [Descriptor; 6] = [
+        Descriptor::Energy,
+        Descriptor::Danceability,
+        Descriptor::PulseClarity,
+        Descriptor::Brightness,
+        Descriptor::DynamicRange,
+        Descriptor::Acousticness,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Descriptor::Energy => "energy",
+            Descriptor::Danceability => "danceability",
+            Descriptor::PulseClarity => "pulse_clarity",
+            Descriptor::Brightness => "brightness",
+            Descriptor::DynamicRange => "dynamic_range",
+            Descriptor::Acousticness => "acousticness",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Descriptor> {
+        Self::ALL.into_iter().find(|descriptor| descriptor.name() == name)
+    }
+
+    pub fn value(&self, descriptors: &TrackDescriptors) -> f32 {
+        match self {
+            Descriptor::Energy => descriptors.energy,
+            Descriptor::Danceability => descriptors.danceability,
+            Descriptor::PulseClarity => descriptors.pulse_clarity,
+            Descriptor::Brightness => descriptors.brightness,
+            Descriptor::DynamicRange => descriptors.dynamic_range,
+            Descriptor::Acousticness => descriptors.acousticness,
+        }
+    }
+}
+
+impl TrackDescriptors {
+    /// Compute the descriptors from the frames of a track, its detected onsets and beat grid
+    pub fn compute(frames: &[DescriptorFrame], onsets: &[f32], grid: Option<&BeatGrid>, duration: f32) -> TrackDescriptors {
+        let audible: Vec<DescriptorFrame> = frames.iter().copied().filter(|frame| frame.rms >= SILENT_FRAME_RMS).collect();
+        if audible.is_empty() || duration <= 0.0 {
+            return TrackDescriptors::default();
+        }
+
+        let mean_power = audible.iter().map(|frame| frame.rms * frame.rms).sum::<f32>() / audible.len() as f32;
+        let loudness = scale(10.0 * mean_power.log10(), LOUDNESS_FLOOR_DB, LOUDNESS_CEILING_DB);
+        let brightness = audible.iter().map(|frame| frame.centroid).sum::<f32>() / audible.len() as f32;
+        let bright = scale(brightness, 0.0, BRIGHT_CENTROID_HZ);
+        let busy = scale(onsets.len() as f32 / duration, 0.0, BUSY_ONSETS_PER_SEC);
+
+        // Spread between loud and quiet frames
+        let mut levels: Vec<f32> = audible.iter().map(|frame| 20.0 * frame.rms.log10()).collect();
+        levels.sort_by(f32::total_cmp);
+        let percentile = |p: f32| levels[((levels.len() - 1) as f32 * p).round() as usize];
+        let dynamic_range = percentile(0.95) - percentile(0.1);
+
+        let pulse_clarity = grid.map_or(0.0, |grid| pulse_clarity(onsets, grid));
+        let danceability = grid.map_or(0.0, |grid| 0.7 * pulse_clarity + 0.3 * tempo_danceability(grid.bpm));
+
+        TrackDescriptors {
+            energy: 0.5 * loudness + 0.3 * busy + 0.2 * bright,
+            danceability,
+            pulse_clarity,
+            brightness,
+            dynamic_range,
+            acousticness: 0.4 * scale(dynamic_range, 0.0, WIDE_DYNAMIC_RANGE_DB) + 0.3 * (1.0 - bright) + 0.3 * (1.0 - pulse_clarity),
+        }
+    }
+}
+
+/// Share of grid beats with an onset close by
+fn pulse_clarity(onsets: &[f32], grid: &BeatGrid) -> f32 {
+    if grid.beats.is_empty() {
+        return 0.0;
+    }
+    let mut onsets = onsets.to_vec();
+    onsets.sort_by(f32::total_cmp);
+    let backed = grid
+        .beats
+        .iter()
+        .filter(|&&beat| {
+            let next = onsets.partition_point(|&onset| onset < beat);
+            let near = |index: usize| onsets.get(index).is_some_and(|onset| (onset - beat).abs() <= PULSE_TOLERANCE_SECS);
+            near(next) || (next > 0 && near(next - 1))
+        })
+        .count();
+    backed as f32 / grid.beats.len() as f32
+}
+
+fn tempo_danceability(bpm: f32) -> f32 {
+    if bpm < DANCE_TEMPO.0 {
+        scale(bpm, DANCE_TEMPO_LIMITS.0, DANCE_TEMPO.0)
+    } else {
+        1.0 - scale(bpm, DANCE_TEMPO.1, DANCE_TEMPO_LIMITS.1)
+    }
+}
+
+// Linear map of value from floor..ceiling onto 0..1
+fn scale(value: f32, floor: f32, ceiling: f32) -> f32 {
+    ((value - floor) / (ceiling - floor)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_club_track_versus_acoustic_track() {
+        let duration = 60.0;
+        let beats: Vec<f32> = (0..128).map(|i| i as f32 * 60.0 / 128.0).collect();
+        let grid = BeatGrid {
+            bpm: 128.0,
+            beats_per_bar: 4,
+            first_downbeat: 0.0,
+            bar_phase: 0,
+            beats: beats.clone(),
+            tempo_curve: Vec::new(),
+        };
+
+        // Loud, compressed and bright with an onset on every beat
+        let club_frames: Vec<DescriptorFrame> = (0..600u32)
+            .map(|i| DescriptorFrame { rms: if i.is_multiple_of(2) { 0.4 } else { 0.35 }, centroid: 3000.0 })
+            .collect();
+        let club = TrackDescriptors::compute(&club_frames, &beats, Some(&grid), duration);
+
+        // Quiet swells with few onsets, off the grid
+        let acoustic_frames: Vec<DescriptorFrame> = (0..600)
+            .map(|i| DescriptorFrame { rms: 0.005 + 0.1 * (i as f32 / 60.0).sin().abs(), centroid: 900.0 })
+            .collect();
+        let acoustic_onsets: Vec<f32> = (0..30).map(|i| i as f32 * 2.0 + 0.23).collect();
+        let acoustic = TrackDescriptors::compute(&acoustic_frames, &acoustic_onsets, Some(&grid), duration);
+
+        assert!((club.pulse_clarity - 1.0).abs() < 0.01);
+        assert!((club.brightness - 3000.0).abs() < 0.1);
+        assert!(club.energy > 0.7, "club energy {}", club.energy);
+        assert!(club.energy > acoustic.energy + 0.3);
+        assert!(club.danceability > acoustic.danceability + 0.3);
+        assert!(acoustic.dynamic_range > club.dynamic_range + 10.0);
+        assert!(acoustic.acousticness > club.acousticness + 0.3);
+
+        assert_eq!(TrackDescriptors::compute(&[], &beats, Some(&grid), duration), TrackDescriptors::default());
+        assert_eq!(Descriptor::from_name("dynamic_range"), Some(Descriptor::DynamicRange));
+    }
+}
@@ -0,0 +1,339 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+use super::streaming::{StreamingService, StreamingTrack};
+
+// Matching configuration
+const DURATION_TOLERANCE_SECS: i32 = 3; // Same recording on different services
+const DURATION_MAX_DEVIATION_SECS: i32 = 15; // Beyond this the durations are considered unrelated
+const MIN_METADATA_CONFIDENCE: f32 = 0.75; // Minimum score for a metadata-only match
+const TITLE_WEIGHT: f32 = 0.5;
+const ARTIST_WEIGHT: f32 = 0.35;
+const DURATION_WEIGHT: f32 = 0.15;
+const CANDIDATE_SEARCH_LIMIT: u32 = 10; // Results requested per provider query
+
+// Words in brackets/after a dash that describe the release rather than the recording
+const VERSION_NOISE: [&str; 6] = ["remaster", "remastered", "deluxe", "explicit", "mono", "stereo"];
+
+/// How two tracks were linked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMethod {
+    Isrc,
+    Metadata,
+}
+
+/// A candidate track that is considered equivalent to a source track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackMatch {
+    pub track: StreamingTrack,
+    pub method: MatchMethod,
+    pub confidence: f32, // 0.0 to 1.0, ISRC matches are always 1.0
+}
+
+/// Links equivalent tracks across Qobuz, Spotify and the local library
+pub struct TrackMatchingService;
+
+impl TrackMatchingService {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Normalize an ISRC ("US-RC1-76-07839" -> "USRC17607839"); returns None for malformed codes
+    pub fn normalize_isrc(isrc: &str) -> Option<String> {
+        let normalized: String = isrc
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+
+        if normalized.len() == 12 && normalized[..2].chars().all(|c| c.is_ascii_alphabetic()) {
+            Some(normalized)
+        } else {
+            None
+        }
+    }
+
+    /// Lowercase, strip diacritics and punctuation, and collapse whitespace
+    pub fn normalize_text(text: &str) -> String {
+        let folded: String = text
+            .nfkd()
+            .filter(|c| !is_combining_mark(*c))
+            .flat_map(|c| c.to_lowercase())
+            .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+            .collect();
+
+        folded.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// Normalize a title, dropping featured artists and release-only suffixes
+    /// ("Get Lucky (feat. Pharrell Williams) - Radio Edit" keeps "radio edit",
+    /// "Heroes - 2017 Remaster" becomes "heroes")
+    pub fn normalize_title(title: &str) -> String {
+        let mut kept = String::new();
+        let mut depth = 0usize;
+        let mut bracketed = String::new();
+
+        for c in title.chars() {
+            match c {
+                '(' | '[' => {
+                    if depth == 0 {
+                        bracketed.clear();
+                    } else {
+                        bracketed.push(c);
+                    }
+                    depth += 1;
+                }
+                ')' | ']' if depth > 0 => {
+                    depth -= 1;
+                    if depth == 0 {
+                        if !Self::is_noise_segment(&bracketed) {
+                            kept.push(' ');
+                            kept.push_str(&bracketed);
+                        }
+                    } else {
+                        bracketed.push(c);
+                    }
+                }
+                _ if depth > 0 => bracketed.push(c),
+                _ => kept.push(c),
+            }
+        }
+
+        let main = match kept.split_once(" - ") {
+            Some((head, tail)) if Self::is_noise_segment(tail) => head.to_string(),
+            _ => kept,
+        };
+
+        Self::normalize_text(&main)
+    }
+
+    /// Normalize an artist string, keeping only the primary artist
+    pub fn normalize_artist(artist: &str) -> String {
+        let normalized = Self::normalize_text(artist);
+        let separators = [" feat ", " ft ", " featuring ", " x ", " and ", " with "];
+
+        let primary = separators.iter().fold(normalized.as_str(), |acc, sep| {
+            acc.split_once(sep).map(|(head, _)| head).unwrap_or(acc)
+        });
+        let primary = primary.split([',', '&', ';']).next().unwrap_or(primary);
+
+        primary.trim().strip_prefix("the ").unwrap_or(primary.trim()).to_string()
+    }
+
+    fn is_noise_segment(segment: &str) -> bool {
+        let normalized = Self::normalize_text(segment);
+        normalized.starts_with("feat ")
+            || normalized.starts_with("ft ")
+            || normalized.starts_with("featuring ")
+            || normalized.starts_with("with ")
+            || normalized.split(' ').any(|word| VERSION_NOISE.contains(&word))
+    }
+
+    /// Similarity of two normalized strings, tolerant of word order
+    fn text_similarity(a: &str, b: &str) -> f32 {
+        if a.is_empty() || b.is_empty() {
+            return 0.0;
+        }
+        if a == b {
+            return 1.0;
+        }
+
+        let mut a_words: Vec<&str> = a.split(' ').collect();
+        let mut b_words: Vec<&str> = b.split(' ').collect();
+        a_words.sort_unstable();
+        b_words.sort_unstable();
+
+        let direct = strsim::normalized_levenshtein(a, b);
+        let sorted = strsim::normalized_levenshtein(&a_words.join(" "), &b_words.join(" "));
+
+        direct.max(sorted) as f32
+    }
+
+    fn duration_similarity(a: Option<i32>, b: Option<i32>) -> Option<f32> {
+        let (a, b) = (a?, b?);
+        if a <= 0 || b <= 0 {
+            return None;
+        }
+
+        let diff = (a - b).abs();
+        if diff <= DURATION_TOLERANCE_SECS {
+            Some(1.0)
+        } else if diff >= DURATION_MAX_DEVIATION_SECS {
+            Some(0.0)
+        } else {
+            let span = (DURATION_MAX_DEVIATION_SECS - DURATION_TOLERANCE_SECS) as f32;
+            Some(1.0 - (diff - DURATION_TOLERANCE_SECS) as f32 / span)
+        }
+    }
+
+    /// Score a candidate against the source track; returns None if they are not the same recording
+    pub fn match_track(&self, source: &StreamingTrack, candidate: &StreamingTrack) -> Option<TrackMatch> {
+        // ISRC identifies the recording, so it wins over any metadata
+        let source_isrc = source.isrc.as_deref().and_then(Self::normalize_isrc);
+        let candidate_isrc = candidate.isrc.as_deref().and_then(Self::normalize_isrc);
+        if let (Some(a), Some(b)) = (&source_isrc, &candidate_isrc)
+            && a == b
+        {
+            return Some(TrackMatch {
+                track: candidate.clone(),
+                method: MatchMethod::Isrc,
+                confidence: 1.0,
+            });
+        }
+
+        let title_score = Self::text_similarity(
+            &Self::normalize_title(&source.title),
+            &Self::normalize_title(&candidate.title),
+        );
+        let artist_score = Self::text_similarity(
+            &Self::normalize_artist(&source.artist),
+            &Self::normalize_artist(&candidate.artist),
+        );
+
+        let confidence = match Self::duration_similarity(source.duration, candidate.duration) {
+            // Clearly different lengths: a live version, an edit or a different song
+            Some(0.0) => return None,
+            Some(duration_score) => {
+                title_score * TITLE_WEIGHT + artist_score * ARTIST_WEIGHT + duration_score * DURATION_WEIGHT
+            }
+            None => {
+                (title_score * TITLE_WEIGHT + artist_score * ARTIST_WEIGHT) / (TITLE_WEIGHT + ARTIST_WEIGHT)
+            }
+        };
+
+        // Two different ISRCs with near-identical metadata are usually re-releases; keep them but lower
+        let confidence = if source_isrc.is_some() && candidate_isrc.is_some() {
+            confidence * 0.9
+        } else {
+            confidence
+        };
+
+        if confidence >= MIN_METADATA_CONFIDENCE {
+            Some(TrackMatch {
+                track: candidate.clone(),
+                method: MatchMethod::Metadata,
+                confidence,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Rank all candidates that match the source track, best first
+    pub fn find_matches(&self, source: &StreamingTrack, candidates: &[StreamingTrack]) -> Vec<TrackMatch> {
+        let mut matches: Vec<TrackMatch> = candidates
+            .iter()
+            .filter(|candidate| !(candidate.source == source.source && candidate.id == source.id))
+            .filter_map(|candidate| self.match_track(source, candidate))
+            .collect();
+
+        matches.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap_or(std::cmp::Ordering::Equal));
+        matches
+    }
+
+    /// Best match for the source track among the candidates, if any
+    pub fn best_match(&self, source: &StreamingTrack, candidates: &[StreamingTrack]) -> Option<TrackMatch> {
+        self.find_matches(source, candidates).into_iter().next()
+    }
+
+    /// Free-text query used to look up the source track on another provider
+    pub fn search_query_for(track: &StreamingTrack) -> String {
+        format!("{} {}", Self::normalize_artist(&track.artist), Self::normalize_title(&track.title))
+    }
+
+    /// Search a provider for the source track and return its matches, best first
+    pub async fn find_on_service(&self, service: &dyn StreamingService, source: &StreamingTrack) -> Result<Vec<TrackMatch>> {
+        let mut candidates = Vec::new();
+
+        // Local files carry no ISRC, the fuzzy library index finds them by artist and title
+        if service.service_name() != "server"
+            && let Some(isrc) = source.isrc.as_deref().and_then(Self::normalize_isrc)
+        {
+            let isrc_query = match service.service_name() {
+                "spotify" => format!("isrc:{}", isrc),
+                _ => isrc,
+            };
+            // An ISRC lookup failing is not fatal, the metadata search below still runs
+            if let Ok(results) = service.search(&isrc_query, Some(CANDIDATE_SEARCH_LIMIT), None).await {
+                candidates.extend(results.tracks);
+            }
+        }
+
+        let has_isrc_match = candidates.iter().any(|candidate| {
+            matches!(self.match_track(source, candidate), Some(TrackMatch { method: MatchMethod::Isrc, .. }))
+        });
+        if !has_isrc_match {
+            let query = Self::search_query_for(source);
+            candidates.extend(service.search(&query, Some(CANDIDATE_SEARCH_LIMIT), None).await?.tracks);
+        }
+
+        Ok(self.find_matches(source, &candidates))
+    }
+}
+
+impl Default for TrackMatchingService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(source: &str, title: &str, artist: &str, duration: Option<i32>, isrc: Option<&str>) -> StreamingTrack {
+        StreamingTrack {
+            id: format!("{}-{}", source, title),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album: "Album".to_string(),
+            duration,
+            stream_url: None,
+            cover_url: None,
+            quality: None,
+            source: source.to_string(),
+            bitrate: None,
+            sample_rate: None,
+            bit_depth: None,
+            isrc: isrc.map(|s| s.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_normalization() {
+        assert_eq!(TrackMatchingService::normalize_text("Björk"), "bjork");
+        assert_eq!(TrackMatchingService::normalize_title("Heroes - 2017 Remaster"), "heroes");
+        assert_eq!(TrackMatchingService::normalize_title("Get Lucky (feat. Pharrell Williams)"), "get lucky");
+        assert_eq!(TrackMatchingService::normalize_artist("Daft Punk feat. Pharrell Williams"), "daft punk");
+        assert_eq!(TrackMatchingService::normalize_isrc("us-rc1-76-07839"), Some("USRC17607839".to_string()));
+        assert_eq!(TrackMatchingService::normalize_isrc("not an isrc"), None);
+    }
+
+    #[test]
+    fn test_isrc_match_wins() {
+        let service = TrackMatchingService::new();
+        let source = track("spotify", "Song", "Artist", Some(200), Some("USRC17607839"));
+        let candidate = track("qobuz", "Completely Different", "Someone", Some(100), Some("US-RC1-76-07839"));
+
+        let result = service.match_track(&source, &candidate).unwrap();
+        assert_eq!(result.method, MatchMethod::Isrc);
+        assert_eq!(result.confidence, 1.0);
+    }
+
+    #[test]
+    fn test_metadata_match() {
+        let service = TrackMatchingService::new();
+        let source = track("spotify", "Jóga - Remastered", "Björk", Some(305), None);
+        let same = track("server", "Joga", "Bjork", Some(306), None);
+        let live = track("qobuz", "Joga", "Bjork", Some(420), None);
+        let other = track("qobuz", "Hyperballad", "Bjork", Some(305), None);
+
+        let matches = service.find_matches(&source, &[other, live, same]);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].track.source, "server");
+        assert_eq!(matches[0].method, MatchMethod::Metadata);
+    }
+}
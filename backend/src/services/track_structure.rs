@@ -0,0 +1,312 @@
+use serde::{Deserialize, Serialize};
+
+use crate::services::beat_grid::BeatGrid;
+
+// Structure detection configuration
+const SILENCE_DB: f32 = -40.0; // Frames this far below the typical level of the track are silent
+const FADE_DEPTH_DB: f32 = 12.0; // Rise needed from the edge of the audio to count as a fade
+const FADE_PLATEAU_DB: f32 = -3.0; // A fade ends once within this of the level it is heading for
+const FADE_LOOKAHEAD_SECS: f32 = 8.0; // How far ahead the level a fade is heading for is looked up
+const FADE_STEADINESS: f32 = 0.25; // Share of the rise a fade must have covered halfway through
+const MIN_FADE_SECS: f32 = 1.0;
+const INTRO_LEVEL_DB: f32 = -4.0; // Bars quieter than this relative to the typical level belong to the intro/outro
+const PHRASE_BARS: usize = 8;
+const CUE_CHANGE_DB: f32 = 3.0; // Level change between phrases that suggests a hot cue
+const MAX_HOT_CUES: usize = 8;
+
+/// Energy level of a stretch of the track, e.g. a section of the adaptive beat detection
+#[derive(Debug, Clone, Copy)]
+pub struct EnergySection {
+    pub start: f32,
+    pub end: f32,
+    pub level: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimeRange {
+    pub start: f32,
+    pub end: f32,
+}
+
+/// Intro or outro in whole bars of the beat grid
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BarSpan {
+    pub start: f32,
+    pub end: f32,
+    pub bars: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HotCueKind {
+    Start,
+    IntroEnd,
+    Drop, // Energy rises at a phrase boundary
+    Breakdown, // Energy falls at a phrase boundary
+    OutroStart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HotCue {
+    pub time: f32,
+    pub bar: u32, // Counted from the first downbeat of the beat grid
+    pub kind: HotCueKind,
+}
+
+/// Where the music of a track starts and ends, for mixing
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackStructure {
+    pub audio_start: f32, // End of leading silence
+    pub audio_end: f32, // Start of trailing silence
+    pub fade_in: Option<TimeRange>,
+    pub fade_out: Option<TimeRange>,
+    pub intro: Option<BarSpan>, // None without a beat grid
+    pub outro: Option<BarSpan>,
+    pub hot_cues: Vec<HotCue>, // Sorted by time
+}
+
+impl TrackStructure {
+    /// Detect silence, fades, intro and outro from frame energies (time, energy) and the
+    /// energy sections of a track. Intro, outro and hot cues need the beat grid.
+    pub fn detect(frames: &[(f32, f32)], sections: &[EnergySection], grid: Option<&BeatGrid>, duration: f32) -> TrackStructure {
+        let mut structure = TrackStructure {
+            audio_start: 0.0,
+            audio_end: duration,
+            ..Default::default()
+        };
+        let Some(typical) = typical_level(sections) else {
+            return structure;
+        };
+
+        // Leading and trailing silence
+        let silence = typical * from_db(SILENCE_DB);
+        let Some(first) = frames.iter().position(|&(_, energy)| energy > silence) else {
+            return structure;
+        };
+        let last = frames.iter().rposition(|&(_, energy)| energy > silence).unwrap_or(first);
+        structure.audio_start = frames[first].0;
+        structure.audio_end = frames.get(last + 1).map_or(duration, |&(time, _)| time);
+
+        // Fades, walking away from either edge of the audio
+        let audible: Vec<(f32, f32)> = sections
+            .iter()
+            .filter(|section| section.end > structure.audio_start && section.start < structure.audio_end)
+            .map(|section| ((section.start + section.end) / 2.0, section.level))
+            .collect();
+        structure.fade_in = find_fade(structure.audio_start, audible.iter().copied())
+            .map(|end| TimeRange { start: structure.audio_start, end });
+        structure.fade_out = find_fade(structure.audio_end, audible.iter().rev().copied())
+            .map(|start| TimeRange { start, end: structure.audio_end });
+
+        if let Some(grid) = grid {
+            structure.detect_sections(sections, grid, typical);
+        }
+        structure
+    }
+
+    /// Intro and outro as the quiet bars at either end, hot cues where the energy changes between phrases
+    fn detect_sections(&mut self, sections: &[EnergySection], grid: &BeatGrid, typical: f32) {
+        let bars: Vec<(usize, f32, f32, f32)> = bars(grid, self.audio_end)
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, (start, end))| end > self.audio_start && start < self.audio_end)
+            .map(|(index, (start, end))| (index, start, end, level_between(sections, start, end)))
+            .collect();
+        let loud = typical * from_db(INTRO_LEVEL_DB);
+        let (Some(first_loud), Some(last_loud)) = (
+            bars.iter().position(|bar| bar.3 >= loud),
+            bars.iter().rposition(|bar| bar.3 >= loud),
+        ) else {
+            return;
+        };
+        let (first, last) = (bars[0], bars[bars.len() - 1]);
+        let intro = BarSpan {
+            start: first.1,
+            end: bars[first_loud].1,
+            bars: first_loud as u32,
+        };
+        let outro = BarSpan {
+            start: bars[last_loud].2,
+            end: last.2,
+            bars: (bars.len() - 1 - last_loud) as u32,
+        };
+        self.intro = Some(intro);
+        self.outro = Some(outro);
+
+        // Cues at the edges of the intro and outro take precedence over phrase boundaries
+        let mut candidates = vec![(f32::INFINITY, HotCue { time: first.1, bar: first.0 as u32, kind: HotCueKind::Start })];
+        if intro.bars > 0 {
+            let bar = bars[first_loud];
+            candidates.push((f32::INFINITY, HotCue { time: bar.1, bar: bar.0 as u32, kind: HotCueKind::IntroEnd }));
+        }
+        if outro.bars > 0 {
+            let bar = bars[last_loud + 1];
+            candidates.push((f32::INFINITY, HotCue { time: bar.1, bar: bar.0 as u32, kind: HotCueKind::OutroStart }));
+        }
+
+        // Phrases are counted from the first downbeat; only audible bars add to their level
+        let mut phrases: Vec<(usize, f32, f32, u32)> = Vec::new(); // (first bar, start, level sum, bar count)
+        for &(index, start, _, level) in &bars {
+            match phrases.last_mut() {
+                Some(phrase) if phrase.0 / PHRASE_BARS == index / PHRASE_BARS => {
+                    phrase.2 += level;
+                    phrase.3 += 1;
+                }
+                _ => phrases.push((index, start, level, 1)),
+            }
+        }
+        for pair in phrases.windows(2) {
+            let (previous, phrase) = (pair[0], pair[1]);
+            if !phrase.0.is_multiple_of(PHRASE_BARS) || phrase.1 < intro.end || phrase.1 > outro.start {
+                continue;
+            }
+            let change = to_db(phrase.2 / phrase.3 as f32) - to_db(previous.2 / previous.3 as f32);
+            if change.abs() >= CUE_CHANGE_DB {
+                let kind = if change > 0.0 { HotCueKind::Drop } else { HotCueKind::Breakdown };
+                candidates.push((change.abs(), HotCue { time: phrase.1, bar: phrase.0 as u32, kind }));
+            }
+        }
+
+        // Strongest cues first, one per bar
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        for (_, cue) in candidates {
+            if self.hot_cues.len() < MAX_HOT_CUES && self.hot_cues.iter().all(|chosen| chosen.bar != cue.bar) {
+                self.hot_cues.push(cue);
+            }
+        }
+        self.hot_cues.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+}
+
+/// Median level of the non-silent sections
+fn typical_level(sections: &[EnergySection]) -> Option<f32> {
+    let mut levels: Vec<f32> = sections.iter().map(|section| section.level).filter(|&level| level > 0.0).collect();
+    if levels.is_empty() {
+        return None;
+    }
+    levels.sort_by(f32::total_cmp);
+    Some(levels[levels.len() / 2])
+}
+
+/// End of a fade starting at the edge of the audio, given (time, level) points ordered away from the edge.
+/// A fade rises steadily; a quiet passage followed by a sudden entry is not one.
+fn find_fade(edge: f32, points: impl Iterator<Item = (f32, f32)>) -> Option<f32> {
+    let points: Vec<(f32, f32)> = points.collect();
+    let start_level = to_db(points.first()?.1);
+
+    // The fade ends where the level reaches the one it is heading for
+    let end = (0..points.len()).find(|&i| {
+        let (time, level) = points[i];
+        let ahead = points[i..]
+            .iter()
+            .take_while(|(other, _)| (other - time).abs() <= FADE_LOOKAHEAD_SECS)
+            .map(|&(_, level)| level)
+            .fold(level, f32::max);
+        to_db(level) >= to_db(ahead) + FADE_PLATEAU_DB
+    })?;
+    let (end_time, end_level) = points[end];
+    let rise = to_db(end_level) - start_level;
+    if rise < FADE_DEPTH_DB || (end_time - edge).abs() < MIN_FADE_SECS {
+        return None;
+    }
+
+    let covered = (to_db(points[end / 2].1) - start_level) / rise;
+    (covered >= FADE_STEADINESS).then_some(end_time)
+}
+
+/// (start, end) of every bar from the first downbeat
+fn bars(grid: &BeatGrid, duration: f32) -> Vec<(f32, f32)> {
+    let beats_per_bar = grid.beats_per_bar.max(1) as usize;
+    let bar_length = beats_per_bar as f32 * 60.0 / grid.bpm;
+    let downbeats: Vec<f32> = grid.beats.iter().skip(grid.bar_phase as usize).step_by(beats_per_bar).copied().collect();
+    downbeats
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| (start, downbeats.get(i + 1).copied().unwrap_or((start + bar_length).min(duration))))
+        .filter(|(start, end)| end > start)
+        .collect()
+}
+
+/// Average level over a time range, weighted by how much of it each section covers
+fn level_between(sections: &[EnergySection], start: f32, end: f32) -> f32 {
+    let (weighted, covered) = sections.iter().fold((0.0, 0.0), |(weighted, covered), section| {
+        let overlap = (section.end.min(end) - section.start.max(start)).max(0.0);
+        (weighted + section.level * overlap, covered + overlap)
+    });
+    if covered > 0.0 { weighted / covered } else { 0.0 }
+}
+
+fn to_db(level: f32) -> f32 {
+    20.0 * level.max(1e-9).log10()
+}
+
+fn from_db(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_structure() {
+        // 120 BPM with 2 s bars: silence, fade-in, intro, body, breakdown, body, outro, fade-out, silence
+        let level = |t: f32| match t {
+            t if t < 2.0 => 0.0,
+            t if t < 6.0 => 0.01 * 30f32.powf((t - 2.0) / 4.0),
+            t if t < 16.0 => 0.3,
+            t if t < 48.0 => 1.0,
+            t if t < 64.0 => 0.4,
+            t if t < 96.0 => 1.0,
+            t if t < 112.0 => 0.3,
+            t if t < 116.0 => 0.3 * (1.0 / 30f32).powf((t - 112.0) / 4.0),
+            _ => 0.0,
+        };
+        let duration = 120.0;
+        let frames: Vec<(f32, f32)> = (0..2400).map(|i| i as f32 * 0.05).map(|t| (t, level(t))).collect();
+        let sections: Vec<EnergySection> = (0..400)
+            .map(|i| i as f32 * 0.3)
+            .map(|start| EnergySection {
+                start,
+                end: start + 0.6,
+                level: frames.iter().filter(|(t, _)| *t >= start && *t < start + 0.6).map(|&(_, e)| e).fold(0.0, f32::max),
+            })
+            .collect();
+        let grid = BeatGrid {
+            bpm: 120.0,
+            beats_per_bar: 4,
+            beats: (0..240).map(|i| i as f32 * 0.5).collect(),
+            first_downbeat: 0.0,
+            bar_phase: 0,
+            tempo_curve: Vec::new(),
+        };
+
+        let structure = TrackStructure::detect(&frames, &sections, Some(&grid), duration);
+        assert!((structure.audio_start - 2.0).abs() < 0.1, "audio start {}", structure.audio_start);
+        assert!((structure.audio_end - 116.0).abs() < 0.1, "audio end {}", structure.audio_end);
+
+        let fade_in = structure.fade_in.unwrap();
+        assert!(fade_in.end > 4.5 && fade_in.end < 6.5, "fade in {:?}", fade_in);
+        let fade_out = structure.fade_out.unwrap();
+        assert!(fade_out.start > 111.5 && fade_out.start < 113.5, "fade out {:?}", fade_out);
+
+        let intro = structure.intro.unwrap();
+        assert_eq!(intro.bars, 7);
+        assert!((intro.end - 16.0).abs() < 0.01);
+        let outro = structure.outro.unwrap();
+        assert_eq!(outro.bars, 10);
+        assert!((outro.start - 96.0).abs() < 0.01);
+
+        let cues: Vec<(u32, HotCueKind)> = structure.hot_cues.iter().map(|cue| (cue.bar, cue.kind)).collect();
+        assert_eq!(
+            cues,
+            vec![
+                (1, HotCueKind::Start),
+                (8, HotCueKind::IntroEnd),
+                (24, HotCueKind::Breakdown),
+                (32, HotCueKind::Drop),
+                (48, HotCueKind::OutroStart),
+            ]
+        );
+    }
+}
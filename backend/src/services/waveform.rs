@@ -0,0 +1,542 @@
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::task;
+
+use crate::services::audio_decode::{downmix, AnalysisSpan, AudioSink, AudioStream};
+use crate::services::loudness_analysis::Biquad;
+use crate::services::track_analysis_store::TrackAnalysisStore;
+
+// Waveform configuration
+pub const WAVEFORM_BASE_SAMPLES_PER_PIXEL: u32 = 256; // Finest resolution, the audiowaveform default
+const LOW_BAND_MAX_HZ: f64 = 250.0; // Kicks and bass
+const HIGH_BAND_MIN_HZ: f64 = 4000.0; // Hi-hats and cymbals
+
+// Disk cache format
+const CACHE_MAGIC: &[u8; 4] = b"MSWF";
+const CACHE_VERSION: u32 = 1;
+const CACHE_HEADER_LEN: usize = 20;
+const CACHE_GC_INTERVAL: Duration = Duration::from_secs(6 * 3600);
+const DEFAULT_CACHE_MAX_AGE_DAYS: u64 = 30;
+const DEFAULT_CACHE_MAX_TOTAL_MB: u64 = 512;
+
+// audiowaveform data format
+const AUDIOWAVEFORM_VERSION: i32 = 2;
+const AUDIOWAVEFORM_FLAG_8_BIT: u32 = 1;
+
+/// Frequency band of a waveform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaveformBand {
+    Full,
+    Low,
+    Mid,
+    High,
+}
+
+impl WaveformBand {
+    pub const ALL: [WaveformBand; 4] = [WaveformBand::Full, WaveformBand::Low, WaveformBand::Mid, WaveformBand::High];
+    pub const SPLIT: [WaveformBand; 3] = [WaveformBand::Low, WaveformBand::Mid, WaveformBand::High];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WaveformBand::Full => "full",
+            WaveformBand::Low => "low",
+            WaveformBand::Mid => "mid",
+            WaveformBand::High => "high",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<WaveformBand> {
+        Self::ALL.into_iter().find(|band| band.name().eq_ignore_ascii_case(name))
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Peak summary of one pixel, with amplitudes between -1 and 1
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WaveformPeak {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+/// Waveform of a track at the base resolution, for every band
+#[derive(Debug, Clone)]
+pub struct Waveform {
+    pub sample_rate: u32,
+    pub samples_per_pixel: u32,
+    bands: Vec<Vec<WaveformPeak>>, // Indexed like WaveformBand::ALL
+}
+
+/// audiowaveform JSON data (version 2), with the RMS of every pixel as an extension
+#[derive(Debug, Serialize)]
+pub struct WaveformJson {
+    pub version: i32,
+    pub channels: usize, // One per band
+    pub sample_rate: u32,
+    pub samples_per_pixel: u32,
+    pub bits: u8,
+    pub length: usize,
+    pub data: Vec<i32>, // min, max per channel for every pixel
+    pub rms: Vec<i32>, // Per channel for every pixel
+    pub bands: Vec<&'static str>,
+}
+
+impl Waveform {
+    /// Number of pixels at the base resolution
+    pub fn length(&self) -> usize {
+        self.bands[0].len()
+    }
+
+    /// Resolution for a requested zoom level or image width, a multiple of the base resolution
+    pub fn resolution_for(&self, samples_per_pixel: Option<u32>, pixels: Option<u32>) -> u32 {
+        let requested = match (samples_per_pixel, pixels) {
+            (Some(samples_per_pixel), _) => samples_per_pixel,
+            (None, Some(pixels)) if pixels > 0 => {
+                let samples = self.length() as u64 * self.samples_per_pixel as u64;
+                samples.div_ceil(pixels as u64).min(u32::MAX as u64) as u32
+            }
+            _ => self.samples_per_pixel,
+        };
+        // Beyond one pixel for the whole track zooming out has no effect, and the product has to fit
+        let max_factor = (self.length() as u32).min(u32::MAX / self.samples_per_pixel).max(1);
+        requested.div_ceil(self.samples_per_pixel).clamp(1, max_factor) * self.samples_per_pixel
+    }
+
+    /// Peaks of a band at a resolution that is a multiple of the base resolution
+    pub fn peaks(&self, band: WaveformBand, samples_per_pixel: u32) -> Vec<WaveformPeak> {
+        let factor = (samples_per_pixel / self.samples_per_pixel).max(1) as usize;
+        self.bands[band.index()]
+            .chunks(factor)
+            .map(|pixels| WaveformPeak {
+                min: pixels.iter().map(|peak| peak.min).fold(f32::INFINITY, f32::min),
+                max: pixels.iter().map(|peak| peak.max).fold(f32::NEG_INFINITY, f32::max),
+                rms: (pixels.iter().map(|peak| peak.rms * peak.rms).sum::<f32>() / pixels.len() as f32).sqrt(),
+            })
+            .collect()
+    }
+
+    /// audiowaveform binary data (version 2) with the bands as channels
+    pub fn to_dat(&self, bands: &[WaveformBand], samples_per_pixel: u32, bits: u8) -> Vec<u8> {
+        let peaks: Vec<Vec<WaveformPeak>> = bands.iter().map(|band| self.peaks(*band, samples_per_pixel)).collect();
+        let length = peaks.first().map_or(0, |peaks| peaks.len());
+        let sample_bytes = if bits == 8 { 1 } else { 2 };
+
+        let mut data = Vec::with_capacity(24 + length * bands.len() * 2 * sample_bytes);
+        data.extend_from_slice(&AUDIOWAVEFORM_VERSION.to_le_bytes());
+        data.extend_from_slice(&(if bits == 8 { AUDIOWAVEFORM_FLAG_8_BIT } else { 0 }).to_le_bytes());
+        data.extend_from_slice(&(self.sample_rate as i32).to_le_bytes());
+        data.extend_from_slice(&(samples_per_pixel as i32).to_le_bytes());
+        data.extend_from_slice(&(length as u32).to_le_bytes());
+        data.extend_from_slice(&(bands.len() as i32).to_le_bytes());
+        for pixel in 0..length {
+            for band in &peaks {
+                for value in [band[pixel].min, band[pixel].max] {
+                    let value = quantize(value, bits);
+                    if bits == 8 {
+                        data.push(value as i8 as u8);
+                    } else {
+                        data.extend_from_slice(&(value as i16).to_le_bytes());
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    /// audiowaveform JSON data (version 2) with the bands as channels
+    pub fn to_json(&self, bands: &[WaveformBand], samples_per_pixel: u32, bits: u8) -> WaveformJson {
+        let peaks: Vec<Vec<WaveformPeak>> = bands.iter().map(|band| self.peaks(*band, samples_per_pixel)).collect();
+        let length = peaks.first().map_or(0, |peaks| peaks.len());
+
+        let mut data = Vec::with_capacity(length * bands.len() * 2);
+        let mut rms = Vec::with_capacity(length * bands.len());
+        for pixel in 0..length {
+            for band in &peaks {
+                data.push(quantize(band[pixel].min, bits));
+                data.push(quantize(band[pixel].max, bits));
+                rms.push(quantize(band[pixel].rms, bits));
+            }
+        }
+
+        WaveformJson {
+            version: AUDIOWAVEFORM_VERSION,
+            channels: bands.len(),
+            sample_rate: self.sample_rate,
+            samples_per_pixel,
+            bits,
+            length,
+            data,
+            rms,
+            bands: bands.iter().map(|band| band.name()).collect(),
+        }
+    }
+
+    fn to_cache_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(CACHE_HEADER_LEN + self.length() * self.bands.len() * 6);
+        data.extend_from_slice(CACHE_MAGIC);
+        data.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+        data.extend_from_slice(&self.sample_rate.to_le_bytes());
+        data.extend_from_slice(&self.samples_per_pixel.to_le_bytes());
+        data.extend_from_slice(&(self.length() as u32).to_le_bytes());
+        for band in &self.bands {
+            for peak in band {
+                for value in [peak.min, peak.max, peak.rms] {
+                    data.extend_from_slice(&(quantize(value, 16) as i16).to_le_bytes());
+                }
+            }
+        }
+        data
+    }
+
+    fn from_cache_bytes(data: &[u8]) -> Option<Waveform> {
+        let header = data.get(..CACHE_HEADER_LEN)?;
+        let word = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        if &header[..4] != CACHE_MAGIC || word(4) != CACHE_VERSION {
+            return None;
+        }
+        let length = word(16) as usize;
+        let body = &data[CACHE_HEADER_LEN..];
+        if body.len() != length * WaveformBand::ALL.len() * 6 {
+            return None;
+        }
+
+        let values: Vec<f32> = body
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / i16::MAX as f32)
+            .collect();
+        let bands = (0..WaveformBand::ALL.len())
+            .map(|band| {
+                values[band * length * 3..(band + 1) * length * 3]
+                    .chunks_exact(3)
+                    .map(|v| WaveformPeak { min: v[0], max: v[1], rms: v[2] })
+                    .collect()
+            })
+            .collect();
+        Some(Waveform {
+            sample_rate: word(8),
+            samples_per_pixel: word(12),
+            bands,
+        })
+    }
+}
+
+// Amplitude as a signed integer of the given bit depth
+fn quantize(value: f32, bits: u8) -> i32 {
+    let scale = if bits == 8 { i8::MAX as f32 } else { i16::MAX as f32 };
+    (value.clamp(-1.0, 1.0) * scale).round() as i32
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PeakAccumulator {
+    min: f32,
+    max: f32,
+    sum_squares: f64,
+}
+
+impl Default for PeakAccumulator {
+    fn default() -> Self {
+        Self { min: f32::INFINITY, max: f32::NEG_INFINITY, sum_squares: 0.0 }
+    }
+}
+
+/// Streaming peak computation of the downmixed signal and its low, mid and high bands
+pub struct WaveformBuilder {
+    sample_rate: u32,
+    channels: usize,
+    low: [Biquad; 2],
+    mid: [Biquad; 4],
+    high: [Biquad; 2],
+    pixel: [PeakAccumulator; 4],
+    pixel_samples: u32,
+    bands: Vec<Vec<WaveformPeak>>,
+}
+
+impl WaveformBuilder {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let low_pass = || Biquad::low_pass(sample_rate, LOW_BAND_MAX_HZ);
+        let high_pass = || Biquad::high_pass(sample_rate, HIGH_BAND_MIN_HZ);
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            low: [low_pass(), low_pass()],
+            mid: [
+                Biquad::high_pass(sample_rate, LOW_BAND_MAX_HZ),
+                Biquad::high_pass(sample_rate, LOW_BAND_MAX_HZ),
+                Biquad::low_pass(sample_rate, HIGH_BAND_MIN_HZ),
+                Biquad::low_pass(sample_rate, HIGH_BAND_MIN_HZ),
+            ],
+            high: [high_pass(), high_pass()],
+            pixel: [PeakAccumulator::default(); 4],
+            pixel_samples: 0,
+            bands: vec![Vec::new(); WaveformBand::ALL.len()],
+        }
+    }
+
+    fn push_samples(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            let x = sample as f64;
+            let values = [
+                x,
+                self.low.iter_mut().fold(x, |y, filter| filter.process(y)),
+                self.mid.iter_mut().fold(x, |y, filter| filter.process(y)),
+                self.high.iter_mut().fold(x, |y, filter| filter.process(y)),
+            ];
+            for (pixel, value) in self.pixel.iter_mut().zip(values) {
+                pixel.min = pixel.min.min(value as f32);
+                pixel.max = pixel.max.max(value as f32);
+                pixel.sum_squares += value * value;
+            }
+            self.pixel_samples += 1;
+            if self.pixel_samples == WAVEFORM_BASE_SAMPLES_PER_PIXEL {
+                self.finish_pixel();
+            }
+        }
+    }
+
+    fn finish_pixel(&mut self) {
+        for (band, pixel) in self.bands.iter_mut().zip(&mut self.pixel) {
+            band.push(WaveformPeak {
+                min: pixel.min,
+                max: pixel.max,
+                rms: (pixel.sum_squares / self.pixel_samples as f64).sqrt() as f32,
+            });
+            *pixel = PeakAccumulator::default();
+        }
+        self.pixel_samples = 0;
+    }
+
+    pub fn finish(mut self) -> Waveform {
+        if self.pixel_samples > 0 {
+            self.finish_pixel();
+        }
+        Waveform {
+            sample_rate: self.sample_rate,
+            samples_per_pixel: WAVEFORM_BASE_SAMPLES_PER_PIXEL,
+            bands: self.bands,
+        }
+    }
+}
+
+impl AudioSink for WaveformBuilder {
+    fn push_native(&mut self, samples: &[f32]) {
+        let mono = downmix(samples, self.channels);
+        self.push_samples(&mono);
+    }
+
+    fn wants_mono(&self) -> bool {
+        false
+    }
+}
+
+/// Computes waveforms and caches their peaks on disk. Cache files are removed once older than
+/// WAVEFORM_CACHE_MAX_AGE_DAYS, oldest first when exceeding WAVEFORM_CACHE_MAX_MB.
+#[derive(Debug, Clone)]
+pub struct WaveformService {
+    cache_dir: PathBuf,
+    max_age: Duration,
+    max_total_bytes: u64,
+}
+
+impl WaveformService {
+    pub fn new(cache_dir: PathBuf, max_age: Duration, max_total_bytes: u64) -> Self {
+        Self { cache_dir, max_age, max_total_bytes }
+    }
+
+    pub fn from_env() -> Self {
+        let number = |name: &str, default: u64| {
+            std::env::var(name).ok().and_then(|value| value.parse::<u64>().ok()).unwrap_or(default)
+        };
+        let cache_dir = std::env::var("WAVEFORM_CACHE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")).join("cache").join("waveforms"));
+        Self::new(
+            cache_dir,
+            Duration::from_secs(number("WAVEFORM_CACHE_MAX_AGE_DAYS", DEFAULT_CACHE_MAX_AGE_DAYS) * 24 * 3600),
+            number("WAVEFORM_CACHE_MAX_MB", DEFAULT_CACHE_MAX_TOTAL_MB) * 1024 * 1024,
+        )
+    }
+
+    /// Waveform of a local file, computed once per file content and cached on disk
+    pub async fn waveform(&self, file_path: &str) -> Result<Waveform> {
+        let file_path = file_path.strip_prefix("file://").unwrap_or(file_path).to_string();
+        let service = self.clone();
+        task::spawn_blocking(move || service.load_or_compute(&file_path)).await?
+    }
+
+    fn load_or_compute(&self, file_path: &str) -> Result<Waveform> {
+        let content_hash = TrackAnalysisStore::content_hash_of_file(Path::new(file_path))
+            .map_err(|e| anyhow!("Cannot read audio file '{}': {}", file_path, e))?;
+        let cache_path = self.cache_dir.join(format!("{}.peaks", content_hash));
+        if let Ok(data) = std::fs::read(&cache_path) {
+            match Waveform::from_cache_bytes(&data) {
+                Some(waveform) => return Ok(waveform),
+                None => tracing::warn!("Ignoring unreadable waveform cache file {:?}", cache_path),
+            }
+        }
+
+        let waveform = Self::compute(file_path)?;
+        let result = std::fs::create_dir_all(&self.cache_dir)
+            .and_then(|_| std::fs::write(&cache_path, waveform.to_cache_bytes()));
+        if let Err(e) = result {
+            tracing::warn!("Failed to cache waveform of {}: {}", file_path, e);
+        }
+        Ok(waveform)
+    }
+
+    /// Blocking peak computation over the whole track
+    pub fn compute(file_path: &str) -> Result<Waveform> {
+        tracing::info!("Computing waveform for file: {}", file_path);
+        let start_time = std::time::Instant::now();
+
+        let mut stream = AudioStream::open(file_path)?;
+        let mut builder = WaveformBuilder::new(stream.sample_rate, stream.channels);
+        // Waveforms always cover the whole track, whatever the analysis duration limit
+        stream.run(AnalysisSpan::Full, &mut [&mut builder])?;
+        let waveform = builder.finish();
+
+        tracing::info!("Waveform computed for file: {} - {} pixels - Duration: {:?}",
+                       file_path, waveform.length(), start_time.elapsed());
+        Ok(waveform)
+    }
+
+    /// Collect garbage in the cache now and then in the background
+    pub fn start(&self) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let gc = service.clone();
+                match task::spawn_blocking(move || gc.collect_garbage()).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(removed)) => tracing::info!("Removed {} old waveform cache files", removed),
+                    Ok(Err(e)) => tracing::warn!("Waveform cache cleanup failed: {}", e),
+                    Err(e) => tracing::warn!("Waveform cache cleanup panicked: {}", e),
+                }
+                tokio::time::sleep(CACHE_GC_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Remove expired cache files, then the oldest ones while over the size limit. Returns the number removed.
+    pub fn collect_garbage(&self) -> std::io::Result<usize> {
+        let now = SystemTime::now();
+        let mut files: Vec<(PathBuf, SystemTime, u64)> = Vec::new();
+        let mut removed = 0;
+
+        let entries = match std::fs::read_dir(&self.cache_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let modified = metadata.modified().unwrap_or(now);
+            if now.duration_since(modified).unwrap_or_default() > self.max_age {
+                std::fs::remove_file(entry.path())?;
+                removed += 1;
+            } else {
+                files.push((entry.path(), modified, metadata.len()));
+            }
+        }
+
+        files.sort_by_key(|(_, modified, _)| *modified);
+        let mut total: u64 = files.iter().map(|(_, _, size)| size).sum();
+        for (path, _, size) in files {
+            if total <= self.max_total_bytes {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            total -= size;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_SAMPLE_RATE: u32 = 44100;
+
+    fn sine_waveform(frequency: f32, amplitude: f32, seconds: f32) -> Waveform {
+        let mut builder = WaveformBuilder::new(TEST_SAMPLE_RATE, 1);
+        let samples: Vec<f32> = (0..(seconds * TEST_SAMPLE_RATE as f32) as usize)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * frequency * i as f32 / TEST_SAMPLE_RATE as f32).sin())
+            .collect();
+        builder.push_native(&samples);
+        builder.finish()
+    }
+
+    #[test]
+    fn test_peaks_bands_and_resolutions() {
+        let waveform = sine_waveform(60.0, 0.5, 2.0);
+        assert_eq!(waveform.length(), (2 * TEST_SAMPLE_RATE).div_ceil(WAVEFORM_BASE_SAMPLES_PER_PIXEL) as usize);
+
+        // One pixel per 4096 samples holds more than a full period of 60 Hz
+        let full = waveform.peaks(WaveformBand::Full, 4096);
+        let middle = full[full.len() / 2];
+        assert!((middle.max - 0.5).abs() < 0.01 && (middle.min + 0.5).abs() < 0.01);
+        assert!((middle.rms - 0.5 / 2f32.sqrt()).abs() < 0.02, "rms {}", middle.rms);
+
+        // A bass tone stays in the low band
+        let low = waveform.peaks(WaveformBand::Low, 4096)[full.len() / 2];
+        let high = waveform.peaks(WaveformBand::High, 4096)[full.len() / 2];
+        assert!(low.max > 0.4, "low {:?}", low);
+        assert!(high.max < 0.01, "high {:?}", high);
+
+        assert_eq!(waveform.resolution_for(Some(1000), None), 1024);
+        assert_eq!(waveform.resolution_for(None, Some(100)), 1024);
+        // Zooming out stops at one pixel for the whole track instead of overflowing
+        let whole = waveform.length() as u32 * WAVEFORM_BASE_SAMPLES_PER_PIXEL;
+        assert_eq!(waveform.resolution_for(Some(u32::MAX), None), whole);
+    }
+
+    #[test]
+    fn test_audiowaveform_and_cache_formats() {
+        let waveform = sine_waveform(1000.0, 0.25, 1.0);
+
+        let dat = waveform.to_dat(&WaveformBand::SPLIT, 512, 8);
+        let word = |i: usize| i32::from_le_bytes(dat[i..i + 4].try_into().unwrap());
+        let length = waveform.peaks(WaveformBand::Mid, 512).len();
+        assert_eq!((word(0), word(4), word(8), word(12)), (2, 1, TEST_SAMPLE_RATE as i32, 512));
+        assert_eq!((word(16) as usize, word(20)), (length, 3));
+        assert_eq!(dat.len(), 24 + length * 3 * 2);
+
+        let json = waveform.to_json(&[WaveformBand::Full], 512, 16);
+        assert_eq!(json.data.len(), 2 * json.length);
+        assert!((json.data[2 * (json.length / 2) + 1] - 8192).abs() < 100);
+
+        let cached = Waveform::from_cache_bytes(&waveform.to_cache_bytes()).unwrap();
+        assert_eq!(cached.length(), waveform.length());
+        let (a, b) = (cached.peaks(WaveformBand::High, 256), waveform.peaks(WaveformBand::High, 256));
+        assert!(a.iter().zip(&b).all(|(a, b)| (a.max - b.max).abs() < 1e-4 && (a.rms - b.rms).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_cache_garbage_collection() {
+        let dir = std::env::temp_dir().join(format!("musestruct_waveforms_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["1", "2", "3"] {
+            std::fs::write(dir.join(format!("{}.peaks", name)), [0u8; 60]).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        // Over the size limit, the oldest file goes first
+        assert_eq!(WaveformService::new(dir.clone(), Duration::from_secs(3600), 150).collect_garbage().unwrap(), 1);
+        let exists = |name: &str| dir.join(format!("{}.peaks", name)).exists();
+        assert!(!exists("1") && exists("2") && exists("3"));
+
+        // Expired files are removed regardless of size
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(WaveformService::new(dir.clone(), Duration::ZERO, u64::MAX).collect_garbage().unwrap(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
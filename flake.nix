@@ -106,6 +106,8 @@
             rustfmt
             clippy
             rust-analyzer
+            # Mix export encoding (FLAC, MP3, Opus)
+            ffmpeg
             
             # Chrome for web development and testing
             google-chrome
@@ -8,30 +8,15 @@ class AudioAnalysisService {
 
   AudioAnalysisService(this._apiService);
 
-  /// Analyze BPM of a track using spectrogram approach
-  Future<SpectrogramBpmAnalysisResult> analyzeBpmSpectrogram(Track track) async {
+  /// Analyze BPM of a track using spectrogram approach, optionally rendering its images
+  Future<SpectrogramBpmAnalysisResult> analyzeBpmSpectrogram(Track track, {bool images = false}) async {
     try {
-      final response = await _apiService.post(
-        '/audio/analyze-bpm-spectrogram',
-        queryParameters: {
-          'track_id': track.id,
-          'source': track.source,
-          if (track.streamUrl != null) 'stream_url': track.streamUrl!,
-        },
-        timeout: BaseApiService.analysisTimeout, // Use longer timeout for analysis
-      );
-
-      if (response.statusCode == 200) {
-        final data = json.decode(response.body);
-        if (data['success'] == true && data['data'] != null) {
-          return SpectrogramBpmAnalysisResult.fromJson(data['data']);
-        } else {
-          throw Exception(data['message'] ?? 'Spectrogram BPM analysis failed');
-        }
-      } else {
-        final data = json.decode(response.body);
-        throw Exception(data['message'] ?? 'Spectrogram BPM analysis request failed');
-      }
+      final data = await _requestAnalysis('/audio/analyze-bpm-spectrogram', {
+        'track_id': track.id,
+        'source': track.source,
+        if (images) 'images': 'true',
+      });
+      return SpectrogramBpmAnalysisResult.fromJson(data);
     } catch (e) {
       throw Exception('Failed to analyze BPM with spectrogram: $e');
     }
@@ -40,27 +25,11 @@ class AudioAnalysisService {
   /// Analyze BPM of a track (legacy windowed approach)
   Future<BpmAnalysisResult> analyzeBpm(Track track) async {
     try {
-      final response = await _apiService.post(
-        '/audio/analyze-bpm',
-        queryParameters: {
-          'track_id': track.id,
-          'source': track.source,
-          if (track.streamUrl != null) 'stream_url': track.streamUrl!,
-        },
-        timeout: BaseApiService.analysisTimeout, // Use longer timeout for analysis
-      );
-
-      if (response.statusCode == 200) {
-        final data = json.decode(response.body);
-        if (data['success'] == true && data['data'] != null) {
-          return BpmAnalysisResult.fromJson(data['data']);
-        } else {
-          throw Exception(data['message'] ?? 'BPM analysis failed');
-        }
-      } else {
-        final data = json.decode(response.body);
-        throw Exception(data['message'] ?? 'BPM analysis request failed');
-      }
+      final data = await _requestAnalysis('/audio/analyze-bpm', {
+        'track_id': track.id,
+        'source': track.source,
+      });
+      return BpmAnalysisResult.fromJson(data);
     } catch (e) {
       throw Exception('Failed to analyze BPM: $e');
     }
@@ -69,32 +38,55 @@ class AudioAnalysisService {
   /// Analyze key of a track
   Future<KeyAnalysisResult> analyzeKey(Track track) async {
     try {
-      final response = await _apiService.post(
-        '/audio/analyze-key',
-        queryParameters: {
-          'track_id': track.id,
-          'source': track.source,
-          if (track.streamUrl != null) 'stream_url': track.streamUrl!,
-        },
-        timeout: BaseApiService.analysisTimeout, // Use longer timeout for analysis
-      );
-
-      if (response.statusCode == 200) {
-        final data = json.decode(response.body);
-        if (data['success'] == true && data['data'] != null) {
-          return KeyAnalysisResult.fromJson(data['data']);
-        } else {
-          throw Exception(data['message'] ?? 'Key analysis failed');
-        }
-      } else {
-        final data = json.decode(response.body);
-        throw Exception(data['message'] ?? 'Key analysis request failed');
-      }
+      final data = await _requestAnalysis('/audio/analyze-key', {
+        'track_id': track.id,
+        'source': track.source,
+      });
+      return KeyAnalysisResult.fromJson(data);
     } catch (e) {
       throw Exception('Failed to analyze key: $e');
     }
   }
 
+  /// Request an analysis result. Tracks not analyzed yet are queued by the server (202 with the job),
+  /// so wait for the job and ask again.
+  Future<Map<String, dynamic>> _requestAnalysis(String endpoint, Map<String, String> queryParameters) async {
+    final deadline = DateTime.now().add(BaseApiService.analysisTimeout);
+    var response = await _apiService.post(endpoint, queryParameters: queryParameters);
+    while (response.statusCode == 202) {
+      final job = json.decode(response.body)['data'];
+      await _waitForJob(job['id'], deadline);
+      response = await _apiService.post(endpoint, queryParameters: queryParameters);
+    }
+
+    final data = json.decode(response.body);
+    if (response.statusCode == 200 && data['success'] == true && data['data'] != null) {
+      return data['data'];
+    }
+    throw Exception(data['message'] ?? 'Analysis request failed');
+  }
+
+  /// Poll an analysis job until it completed
+  Future<void> _waitForJob(String jobId, DateTime deadline) async {
+    while (DateTime.now().isBefore(deadline)) {
+      await Future.delayed(const Duration(seconds: 2));
+      final response = await _apiService.get('/audio/jobs/$jobId');
+      final data = json.decode(response.body);
+      if (response.statusCode != 200 || data['success'] != true) {
+        throw Exception(data['message'] ?? 'Analysis job not found');
+      }
+      switch (data['data']['status']) {
+        case 'completed':
+          return;
+        case 'failed':
+          throw Exception(data['data']['last_error'] ?? 'Analysis failed');
+        case 'cancelled':
+          throw Exception('Analysis was cancelled');
+      }
+    }
+    throw Exception('Analysis is taking too long, try again later');
+  }
+
   /// Get BPM for a track if it has been analyzed
   Future<double?> getBpm(Track track) async {
     try {
@@ -156,28 +148,34 @@ class KeyAnalysisResult {
 class SpectrogramBpmAnalysisResult {
   final String trackId;
   final String source;
-  final double bpm;
+  final double? bpm; // null when the tempo could not be determined
+  final double confidence;
+  final List<BpmCandidate> candidates;
   final int analysisTimeMs;
-  final String spectrogramPath;
-  final String analysisVisualizationPath;
+  final String? spectrogramUrl; // Backend path of the rendered image, only set when images were requested
+  final String? analysisVisualizationUrl;
 
   SpectrogramBpmAnalysisResult({
     required this.trackId,
     required this.source,
-    required this.bpm,
+    this.bpm,
+    required this.confidence,
+    required this.candidates,
     required this.analysisTimeMs,
-    required this.spectrogramPath,
-    required this.analysisVisualizationPath,
+    this.spectrogramUrl,
+    this.analysisVisualizationUrl,
   });
 
   factory SpectrogramBpmAnalysisResult.fromJson(Map<String, dynamic> json) {
     return SpectrogramBpmAnalysisResult(
       trackId: json['track_id'] as String,
       source: json['source'] as String,
-      bpm: (json['bpm'] as num).toDouble(),
+      bpm: json['bpm'] != null ? (json['bpm'] as num).toDouble() : null,
+      confidence: (json['confidence'] as num?)?.toDouble() ?? 0.0,
+      candidates: BpmCandidate.listFromJson(json['candidates']),
       analysisTimeMs: json['analysis_time_ms'] as int,
-      spectrogramPath: json['spectrogram_path'] as String,
-      analysisVisualizationPath: json['analysis_visualization_path'] as String,
+      spectrogramUrl: json['spectrogram_url'] as String?,
+      analysisVisualizationUrl: json['analysis_visualization_url'] as String?,
     );
   }
 }
@@ -185,13 +183,17 @@ class SpectrogramBpmAnalysisResult {
 class BpmAnalysisResult {
   final String trackId;
   final String source;
-  final double bpm;
+  final double? bpm; // null when the tempo could not be determined
+  final double confidence;
+  final List<BpmCandidate> candidates;
   final int analysisTimeMs;
 
   BpmAnalysisResult({
     required this.trackId,
     required this.source,
-    required this.bpm,
+    this.bpm,
+    required this.confidence,
+    required this.candidates,
     required this.analysisTimeMs,
   });
 
@@ -199,7 +201,9 @@ class BpmAnalysisResult {
     return BpmAnalysisResult(
       trackId: json['track_id'] as String,
       source: json['source'] as String,
-      bpm: (json['bpm'] as num).toDouble(),
+      bpm: json['bpm'] != null ? (json['bpm'] as num).toDouble() : null,
+      confidence: (json['confidence'] as num?)?.toDouble() ?? 0.0,
+      candidates: BpmCandidate.listFromJson(json['candidates']),
       analysisTimeMs: json['analysis_time_ms'] as int,
     );
   }
@@ -224,3 +228,26 @@ class BpmResponse {
     );
   }
 }
+
+/// Alternative tempo (half, double or triplet) with its support in the analysis
+class BpmCandidate {
+  final double bpm;
+  final double score;
+
+  BpmCandidate({
+    required this.bpm,
+    required this.score,
+  });
+
+  factory BpmCandidate.fromJson(Map<String, dynamic> json) {
+    return BpmCandidate(
+      bpm: (json['bpm'] as num).toDouble(),
+      score: (json['score'] as num).toDouble(),
+    );
+  }
+
+  static List<BpmCandidate> listFromJson(dynamic json) {
+    if (json is! List) return [];
+    return json.map((c) => BpmCandidate.fromJson(c as Map<String, dynamic>)).toList();
+  }
+}
@@ -961,18 +961,32 @@ class ExpandedMusicPlayer extends StatelessWidget {
       analysisService.analyzeBpmSpectrogram(track).then((result) {
         // Update the track with the new BPM value
         if (context.mounted) {
+          final bpm = result.bpm;
+          if (bpm == null) {
+            ScaffoldMessenger.of(context).showSnackBar(
+              const SnackBar(
+                content: Text('BPM could not be determined for this track'),
+                backgroundColor: Colors.orange,
+                behavior: SnackBarBehavior.floating,
+                margin: EdgeInsets.only(bottom: 100, left: 16, right: 16),
+                duration: Duration(seconds: 3),
+              ),
+            );
+            return;
+          }
+
           final musicProvider = Provider.of<MusicProvider>(context, listen: false);
-          musicProvider.updateTrackBpm(track.id, result.bpm);
+          musicProvider.updateTrackBpm(track.id, bpm);
           
           // Also update saved tracks if this track is saved
           final savedTracksProvider = Provider.of<SavedTracksProvider>(context, listen: false);
-          savedTracksProvider.updateTrackBpm(track.id, track.source, result.bpm);
+          savedTracksProvider.updateTrackBpm(track.id, track.source, bpm);
           
           // Show success message when analysis completes
           ScaffoldMessenger.of(context).showSnackBar(
             SnackBar(
               content: Text(
-                'Spectrogram BPM analysis complete: ${result.bpm.toStringAsFixed(1)} BPM\nSpectrogram: ${result.spectrogramPath.split('/').last}\nVisualization: ${result.analysisVisualizationPath.split('/').last}',
+                'Spectrogram BPM analysis complete: ${bpm.toStringAsFixed(1)} BPM (confidence ${(result.confidence * 100).round()}%)',
               ),
               backgroundColor: Colors.green,
               behavior: SnackBarBehavior.floating,
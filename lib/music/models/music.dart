@@ -684,19 +684,72 @@ class SaveAlbumRequest {
   }
 }
 
+class TrackLoudness {
+  final double? integratedLufs;
+  final double loudnessRange;
+  final double truePeak;
+  final double truePeakDbtp;
+  final double? trackGain;
+  final double? albumLoudness;
+  final double? albumGain;
+  final double? albumPeak;
+
+  TrackLoudness({
+    this.integratedLufs,
+    required this.loudnessRange,
+    required this.truePeak,
+    required this.truePeakDbtp,
+    this.trackGain,
+    this.albumLoudness,
+    this.albumGain,
+    this.albumPeak,
+  });
+
+  factory TrackLoudness.fromJson(Map<String, dynamic> json) {
+    return TrackLoudness(
+      integratedLufs: (json['integrated_lufs'] as num?)?.toDouble(),
+      loudnessRange: (json['loudness_range'] as num).toDouble(),
+      truePeak: (json['true_peak'] as num).toDouble(),
+      truePeakDbtp: (json['true_peak_dbtp'] as num).toDouble(),
+      trackGain: (json['track_gain'] as num?)?.toDouble(),
+      albumLoudness: (json['album_loudness'] as num?)?.toDouble(),
+      albumGain: (json['album_gain'] as num?)?.toDouble(),
+      albumPeak: (json['album_peak'] as num?)?.toDouble(),
+    );
+  }
+
+  Map<String, dynamic> toJson() {
+    return {
+      'integrated_lufs': integratedLufs,
+      'loudness_range': loudnessRange,
+      'true_peak': truePeak,
+      'true_peak_dbtp': truePeakDbtp,
+      'track_gain': trackGain,
+      'album_loudness': albumLoudness,
+      'album_gain': albumGain,
+      'album_peak': albumPeak,
+    };
+  }
+}
+
 class BackendStreamUrlResponse {
   final String streamUrl;
   final bool isCached;
+  final TrackLoudness? loudness;
 
   BackendStreamUrlResponse({
     required this.streamUrl,
     required this.isCached,
+    this.loudness,
   });
 
   factory BackendStreamUrlResponse.fromJson(Map<String, dynamic> json) {
     return BackendStreamUrlResponse(
       streamUrl: json['stream_url'] as String,
       isCached: json['is_cached'] as bool,
+      loudness: json['loudness'] != null
+          ? TrackLoudness.fromJson(json['loudness'] as Map<String, dynamic>)
+          : null,
     );
   }
 
@@ -704,6 +757,7 @@ class BackendStreamUrlResponse {
     return {
       'stream_url': streamUrl,
       'is_cached': isCached,
+      'loudness': loudness?.toJson(),
     };
   }
 }